@@ -0,0 +1,70 @@
+use std::{error::Error, ffi::OsStr, io, iter, os::windows::ffi::OsStrExt, ptr};
+
+use log::{Level, Log, Metadata, Record};
+use winapi::um::{
+    winbase::{RegisterEventSourceW, ReportEventW},
+    winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, HANDLE},
+};
+
+/// Event ID of messages in EventCreate.exe that consist of just the inserted
+/// string. `remote-uci service install` registers it as the message file.
+const EVENT_ID: u32 = 1;
+
+/// Logger that writes to the Windows event log.
+struct EventLog {
+    handle: HANDLE,
+    level: Level,
+}
+
+// Event log handles can be used from any thread.
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(iter::once(0)).collect()
+}
+
+pub fn init(source: &str, level: Level) -> Result<(), Box<dyn Error>> {
+    let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(source).as_ptr()) };
+    if handle.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+    log::set_logger(Box::leak(Box::new(EventLog { handle, level })))
+        .map_err(|err| err.to_string())?;
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
+
+impl Log for EventLog {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let event_type = match record.level() {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(&record.args().to_string());
+        let mut strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                EVENT_ID,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -1,3 +1,5 @@
+mod eventlog;
+
 use std::{error::Error, ffi::OsString, sync::Arc, time::Duration};
 
 use clap::Parser;
@@ -34,7 +36,10 @@ fn service_status(state: ServiceState, wait_hint: Duration) -> ServiceStatus {
 
 #[tokio::main(flavor = "current_thread")]
 async fn service_main(_args: Vec<OsString>) {
-    let _ = simple_logging::log_to_file("remote-uci.log", log::LevelFilter::Warn);
+    // The event source is registered by `remote-uci service install`.
+    if eventlog::init("remote-uci", log::Level::Warn).is_err() {
+        let _ = simple_logging::log_to_file("remote-uci.log", log::LevelFilter::Warn);
+    }
 
     if let Err(err) = service_run().await {
         log::error!("Fatal error: {err}");
@@ -60,7 +65,7 @@ async fn service_run() -> Result<(), Box<dyn Error>> {
         Duration::from_secs(60),
     ))?;
 
-    let (_spec, server) = make_server(Opts::try_parse()?, ListenFd::empty()).await?;
+    let (server, handle) = make_server(Opts::try_parse()?, ListenFd::empty()).await?;
 
     server
         .with_graceful_shutdown(async {
@@ -77,9 +82,12 @@ async fn service_run() -> Result<(), Box<dyn Error>> {
                     Duration::from_secs(60),
                 ))
                 .expect("set stop pending");
+            handle.request_shutdown();
         })
         .await?;
 
+    handle.shutdown().await;
+
     status_handle.set_service_status(service_status(ServiceState::Stopped, Duration::default()))?;
 
     Ok(())
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use remote_uci::uci::{decode_line, UciIn};
+
+fuzz_target!(|data: &[u8]| {
+    let s = decode_line(data);
+    if let Ok(Some(uci_in)) = UciIn::from_line(&s) {
+        let line = uci_in.to_string();
+        assert!(!line.chars().any(|c| c.is_control() && c != '\t'));
+        let uci_in_rountripped = UciIn::from_line(&line).unwrap().unwrap();
+        assert_eq!(uci_in, uci_in_rountripped);
+    }
+});
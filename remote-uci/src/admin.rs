@@ -0,0 +1,259 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::EngineStatus,
+    reload::Reloader,
+    tokens::{self, Permissions},
+    ws::{Secret, SecretStore, SharedEngine},
+    ExternalWorkerOpts,
+};
+
+/// Operator API for inspecting and controlling engines at runtime.
+pub struct Admin {
+    secret: Secret,
+    endpoints: Vec<Endpoint>,
+    reloader: Arc<Reloader>,
+}
+
+/// An engine served at a socket path.
+pub struct Endpoint {
+    pub path: String,
+    pub engine: Arc<SharedEngine>,
+    pub secrets: Arc<SecretStore>,
+    pub spec: ExternalWorkerOpts,
+}
+
+#[derive(Serialize)]
+pub struct EngineEntry {
+    path: String,
+    worker: usize,
+    #[serde(flatten)]
+    status: EngineStatus,
+}
+
+#[derive(Deserialize)]
+pub struct EngineQuery {
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SecretQuery {
+    #[serde(flatten)]
+    endpoint: EngineQuery,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct SecretEntry {
+    name: String,
+    #[serde(flatten)]
+    permissions: Permissions,
+}
+
+#[derive(Deserialize)]
+pub struct Upgrade {
+    /// Engine executable to switch to.
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+pub struct Killed {
+    sessions: Vec<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Rotated {
+    registration_url: String,
+}
+
+impl Admin {
+    pub fn new(secret: Secret, endpoints: Vec<Endpoint>, reloader: Arc<Reloader>) -> Admin {
+        Admin {
+            secret,
+            endpoints,
+            reloader,
+        }
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if Secret(token.to_owned()) == self.secret => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    fn endpoint(&self, query: &EngineQuery) -> Result<&Endpoint, StatusCode> {
+        let path = query.path.as_deref().unwrap_or("/socket");
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.path == path)
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+pub async fn status(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<EngineEntry>>, StatusCode> {
+    admin.authorize(&headers)?;
+    Ok(Json(
+        admin
+            .endpoints
+            .iter()
+            .flat_map(|endpoint| {
+                endpoint
+                    .engine
+                    .status()
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(worker, status)| EngineEntry {
+                        path: endpoint.path.clone(),
+                        worker,
+                        status,
+                    })
+            })
+            .collect(),
+    ))
+}
+
+pub async fn kill_session(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<EngineQuery>,
+) -> Result<Json<Killed>, StatusCode> {
+    admin.authorize(&headers)?;
+    Ok(Json(Killed {
+        sessions: admin.endpoint(&query)?.engine.kill_sessions(),
+    }))
+}
+
+pub async fn restart_engine(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<EngineQuery>,
+) -> Result<StatusCode, StatusCode> {
+    admin.authorize(&headers)?;
+    admin
+        .endpoint(&query)?
+        .engine
+        .restart_engines()
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to restart engine: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn upgrade_engine(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<EngineQuery>,
+    Json(upgrade): Json<Upgrade>,
+) -> Result<StatusCode, StatusCode> {
+    admin.authorize(&headers)?;
+    admin
+        .endpoint(&query)?
+        .engine
+        .upgrade_engines(upgrade.path)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to upgrade engine: {err}");
+            StatusCode::UNPROCESSABLE_ENTITY
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn rotate_secret(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<EngineQuery>,
+) -> Result<Json<Rotated>, StatusCode> {
+    admin.authorize(&headers)?;
+    let endpoint = admin.endpoint(&query)?;
+    let secret = endpoint.secrets.rotate().await;
+    tracing::warn!("Rotated secret of {}", endpoint.path);
+    Ok(Json(Rotated {
+        registration_url: endpoint.spec.with_secret(secret).registration_url(),
+    }))
+}
+
+pub async fn reload(admin: Arc<Admin>, headers: HeaderMap) -> Result<StatusCode, StatusCode> {
+    admin.authorize(&headers)?;
+    admin.reloader.reload().await.map_err(|err| {
+        tracing::error!("Failed to reload configuration: {err}");
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_secrets(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<EngineQuery>,
+) -> Result<Json<Vec<SecretEntry>>, StatusCode> {
+    admin.authorize(&headers)?;
+    Ok(Json(
+        admin
+            .endpoint(&query)?
+            .secrets
+            .named()
+            .into_iter()
+            .map(|named| SecretEntry {
+                name: named.name,
+                permissions: named.permissions,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn add_secret(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<SecretQuery>,
+    Json(permissions): Json<Permissions>,
+) -> Result<Json<Rotated>, StatusCode> {
+    admin.authorize(&headers)?;
+    let endpoint = admin.endpoint(&query.endpoint)?;
+    if !tokens::is_valid_name(&query.name) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let secret = endpoint
+        .secrets
+        .add(query.name.clone(), permissions.clone())
+        .await
+        .ok_or(StatusCode::CONFLICT)?;
+    tracing::warn!("Added secret {} for {}", query.name, endpoint.path);
+    Ok(Json(Rotated {
+        registration_url: endpoint
+            .spec
+            .with_secret(secret)
+            .restricted(&permissions)
+            .registration_url(),
+    }))
+}
+
+pub async fn revoke_secret(
+    admin: Arc<Admin>,
+    headers: HeaderMap,
+    Query(query): Query<SecretQuery>,
+) -> Result<StatusCode, StatusCode> {
+    admin.authorize(&headers)?;
+    let endpoint = admin.endpoint(&query.endpoint)?;
+    if !endpoint.secrets.revoke(&query.name).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    tracing::warn!("Revoked secret {} for {}", query.name, endpoint.path);
+    Ok(StatusCode::NO_CONTENT)
+}
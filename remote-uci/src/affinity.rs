@@ -0,0 +1,145 @@
+use std::{fmt, str::FromStr};
+
+use tokio::process::Command;
+
+/// Processors on which engines may run, like 0-27 or 0,2,4-7.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuSet(Vec<usize>);
+
+impl CpuSet {
+    pub fn count(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<CpuSet, &'static str> {
+        let mut cpus = Vec::new();
+        for part in s.split(',') {
+            let (start, end) = match part.split_once('-') {
+                Some((start, end)) => (start, end),
+                None => (part, part),
+            };
+            let start: usize = start
+                .trim()
+                .parse()
+                .map_err(|_| "expected cpus like 0-3,6")?;
+            let end: usize = end.trim().parse().map_err(|_| "expected cpus like 0-3,6")?;
+            if start > end {
+                return Err("expected ascending cpu range");
+            }
+            if end >= MAX_CPUS {
+                return Err("cpu number too large");
+            }
+            cpus.extend(start..=end);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuSet(cpus))
+    }
+}
+
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut cpus = self.0.iter().copied().peekable();
+        while let Some(start) = cpus.next() {
+            let mut end = start;
+            while cpus.peek() == Some(&(end + 1)) {
+                end += 1;
+                cpus.next();
+            }
+            if !first {
+                f.write_str(",")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{start}")?;
+            } else {
+                write!(f, "{start}-{end}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Largest number of processors that can be selected.
+const MAX_CPUS: usize = 1024;
+
+/// Arranges for the engine process to run only on the given processors.
+pub fn apply(command: &mut Command, cpus: &CpuSet) {
+    imp::apply(command, cpus);
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{io, mem};
+
+    use tokio::process::Command;
+
+    use super::CpuSet;
+
+    pub fn apply(command: &mut Command, cpus: &CpuSet) {
+        let mut set = unsafe { mem::zeroed::<libc::cpu_set_t>() };
+        for &cpu in &cpus.0 {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        unsafe {
+            command.pre_exec(move || {
+                if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use tokio::process::Command;
+
+    use super::CpuSet;
+
+    pub fn apply(_command: &mut Command, _cpus: &CpuSet) {
+        tracing::warn!("Ignoring --engine-cpus: not supported on this platform");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpus(s: &str) -> CpuSet {
+        s.parse().expect("cpus")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(cpus("0-3,8"), CpuSet(vec![0, 1, 2, 3, 8]));
+        assert_eq!(cpus("0-3,8").to_string(), "0-3,8");
+        assert_eq!(cpus("0-3,8").count(), 5);
+        assert_eq!(cpus("5").to_string(), "5");
+        assert_eq!(cpus(" 2 - 4 , 0 ").to_string(), "0,2-4");
+        assert_eq!(cpus("1023").count(), 1);
+    }
+
+    #[test]
+    fn test_overlapping() {
+        assert_eq!(cpus("0-4,2-6,5").to_string(), "0-6");
+        assert_eq!(cpus("8,0-3,3,2").to_string(), "0-3,8");
+        assert_eq!(cpus("3-3"), CpuSet(vec![3]));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for invalid in [
+            "", ",", "0,", "-", "3-0", "0-3-5", "a", "-1", "1024", "0-1024",
+        ] {
+            assert!(invalid.parse::<CpuSet>().is_err(), "{invalid}");
+        }
+        assert_eq!("3-0".parse::<CpuSet>(), Err("expected ascending cpu range"));
+    }
+}
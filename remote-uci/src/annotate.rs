@@ -0,0 +1,436 @@
+use std::{error::Error, fs, io, path::Path, time::Duration};
+
+use shakmaty::{
+    fen::Fen,
+    san::{San, SanPlus},
+    uci::Uci,
+    CastlingMode, Chess, Color, Position,
+};
+
+use crate::{
+    engine::{Engine, Session},
+    gpu::GpuBackend,
+    uci::{Eval, UciIn, UciOptionName, UciOut},
+    Opts,
+};
+
+/// Search depth if no limit is given.
+const DEFAULT_DEPTH: u32 = 20;
+
+/// Width of movetext lines in the annotated PGN.
+const LINE_WIDTH: usize = 80;
+
+struct Game {
+    headers: Vec<(String, String)>,
+    /// Comments before the first move.
+    comments: Vec<String>,
+    moves: Vec<PgnMove>,
+    result: String,
+}
+
+struct PgnMove {
+    san: SanPlus,
+    nags: Vec<String>,
+    comments: Vec<String>,
+}
+
+impl Game {
+    fn new() -> Game {
+        Game {
+            headers: Vec::new(),
+            comments: Vec::new(),
+            moves: Vec::new(),
+            result: "*".to_owned(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.comments.is_empty() && self.moves.is_empty()
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(other, _)| other == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn comments(&mut self) -> &mut Vec<String> {
+        match self.moves.last_mut() {
+            Some(m) => &mut m.comments,
+            None => &mut self.comments,
+        }
+    }
+}
+
+/// Reads the games of a PGN file. Variations are skipped.
+fn parse(pgn: &str) -> Result<Vec<Game>, String> {
+    let mut games = Vec::new();
+    let mut game = Game::new();
+    let mut chars = pgn.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            '%' if line_start => {
+                // Escaped line.
+                chars.by_ref().take_while(|c| *c != '\n').for_each(drop);
+            }
+            '[' => {
+                if !game.moves.is_empty() {
+                    games.push(game);
+                    game = Game::new();
+                }
+                let tag: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                let (name, value) = tag
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or_default();
+                game.headers.push((
+                    name.to_owned(),
+                    value
+                        .trim()
+                        .trim_matches('"')
+                        .replace("\\\"", "\"")
+                        .replace("\\\\", "\\"),
+                ));
+            }
+            '{' => {
+                let comment: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                game.comments().push(comment.trim().to_owned());
+            }
+            ';' => {
+                let comment: String = chars.by_ref().take_while(|c| *c != '\n').collect();
+                game.comments().push(comment.trim().to_owned());
+                line_start = true;
+                continue;
+            }
+            '(' => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some('{') => chars.by_ref().take_while(|c| *c != '}').for_each(drop),
+                        Some(_) => (),
+                        None => return Err("unterminated variation".to_owned()),
+                    }
+                }
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}()[];".contains(c) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        game.result = token;
+                        games.push(game);
+                        game = Game::new();
+                    }
+                    nag if nag.starts_with('$') => {
+                        if let Some(m) = game.moves.last_mut() {
+                            m.nags.push(nag.to_owned());
+                        }
+                    }
+                    _ => {
+                        let san =
+                            token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                        let (san, suffix) = san.split_at(san.trim_end_matches(['!', '?']).len());
+                        if san.is_empty() {
+                            continue;
+                        }
+                        game.moves.push(PgnMove {
+                            san: san.parse().map_err(|_| format!("invalid move {token}"))?,
+                            nags: suffix_nag(suffix).into_iter().collect(),
+                            comments: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        line_start = false;
+    }
+    if !game.is_empty() {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+fn suffix_nag(suffix: &str) -> Option<String> {
+    Some(
+        match suffix {
+            "!" => "$1",
+            "?" => "$2",
+            "!!" => "$3",
+            "??" => "$4",
+            "!?" => "$5",
+            "?!" => "$6",
+            _ => return None,
+        }
+        .to_owned(),
+    )
+}
+
+/// Evaluation in the `[%eval]` format of lichess, from the point of view of
+/// white.
+fn format_eval(eval: &Eval, turn: Color) -> String {
+    match *eval {
+        Eval::Cp(cp) => format!("{:.2}", turn.fold_wb(cp, -cp) as f64 / 100.0),
+        Eval::Mate(mate) => format!("#{}", turn.fold_wb(mate, -mate)),
+    }
+}
+
+struct Analysis {
+    eval: Option<Eval>,
+    bestmove: Option<Uci>,
+}
+
+/// Searches a position and returns the final evaluation of the best line.
+async fn analyse(
+    engine: &mut Engine,
+    session: Session,
+    position: UciIn,
+    go: UciIn,
+) -> io::Result<Analysis> {
+    engine.send(session, position).await?;
+    engine.send(session, go).await?;
+    let mut eval = None;
+    loop {
+        match engine.recv(session).await? {
+            UciOut::Info {
+                multipv,
+                score: Some(score),
+                ..
+            } if multipv.map_or(true, |multipv| multipv.get() == 1)
+                && !score.lowerbound
+                && !score.upperbound =>
+            {
+                eval = Some(score.eval);
+            }
+            UciOut::Bestmove { m, .. } => return Ok(Analysis { eval, bestmove: m }),
+            _ => (),
+        }
+    }
+}
+
+/// Appends tokens to movetext, breaking lines at the line width.
+struct Movetext {
+    text: String,
+    line: usize,
+}
+
+impl Movetext {
+    fn push(&mut self, token: &str) {
+        if self.line > 0 && self.line + 1 + token.len() > LINE_WIDTH {
+            self.text.push('\n');
+            self.line = 0;
+        } else if self.line > 0 {
+            self.text.push(' ');
+            self.line += 1;
+        }
+        self.text.push_str(token);
+        self.line += token.len();
+    }
+}
+
+async fn annotate_game(
+    engine: &mut Engine,
+    session: Session,
+    game: &Game,
+    go: &UciIn,
+) -> Result<String, Box<dyn Error>> {
+    let castling_mode = CastlingMode::from_chess960(
+        game.header("Variant")
+            .map_or(false, |variant| variant.eq_ignore_ascii_case("chess960")),
+    );
+    if let Some(variant) = game.header("Variant").filter(|variant| {
+        !variant.eq_ignore_ascii_case("standard") && !variant.eq_ignore_ascii_case("chess960")
+    }) {
+        return Err(format!("variant {variant} is not supported").into());
+    }
+    let fen: Option<Fen> = game.header("FEN").map(str::parse).transpose()?;
+    let start: Chess = match fen {
+        Some(ref fen) => fen.clone().into_position(castling_mode)?,
+        None => Chess::default(),
+    };
+
+    engine.send(session, UciIn::Ucinewgame).await?;
+    let chess960 = UciOptionName("UCI_Chess960".to_owned());
+    if engine.has_option(&chess960) {
+        engine
+            .send(
+                session,
+                UciIn::Setoption {
+                    name: chess960,
+                    value: Some(castling_mode.is_chess960().to_string()),
+                },
+            )
+            .await?;
+    }
+
+    // Analyse the position before each move and after the last move.
+    let mut pos = start.clone();
+    let mut moves = Vec::new();
+    let mut analyses = Vec::new();
+    for (i, pgn_move) in game.moves.iter().enumerate() {
+        analyses.push(if pos.is_game_over() {
+            None
+        } else {
+            Some(
+                analyse(
+                    engine,
+                    session,
+                    UciIn::Position {
+                        fen: fen.clone(),
+                        moves: moves.clone(),
+                    },
+                    go.clone(),
+                )
+                .await?,
+            )
+        });
+        let m = pgn_move
+            .san
+            .san
+            .to_move(&pos)
+            .map_err(|_| format!("illegal move {} at ply {}", pgn_move.san, i + 1))?;
+        moves.push(Uci::from_move(&m, castling_mode));
+        pos.play_unchecked(&m);
+    }
+    analyses.push(if pos.is_game_over() {
+        None
+    } else {
+        Some(analyse(engine, session, UciIn::Position { fen, moves }, go.clone()).await?)
+    });
+
+    let mut pgn = String::new();
+    for (name, value) in &game.headers {
+        if name != "Annotator" {
+            pgn.push_str(&format!(
+                "[{name} \"{}\"]\n",
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            ));
+        }
+    }
+    pgn.push_str("[Annotator \"remote-uci\"]\n\n");
+
+    let mut movetext = Movetext {
+        text: String::new(),
+        line: 0,
+    };
+    for comment in &game.comments {
+        movetext.push(&format!("{{ {comment} }}"));
+    }
+    let mut pos = start;
+    let mut black_needs_number = true;
+    for (i, pgn_move) in game.moves.iter().enumerate() {
+        let turn = pos.turn();
+        let fullmoves = pos.fullmoves();
+        let m = pgn_move.san.san.to_move(&pos)?;
+        let best = analyses[i]
+            .as_ref()
+            .and_then(|analysis| analysis.bestmove.as_ref())
+            .and_then(|uci| uci.to_move(&pos).ok())
+            .filter(|best| *best != m)
+            .map(|best| San::from_move(&pos, &best));
+        pos.play_unchecked(&m);
+
+        if turn == Color::White {
+            movetext.push(&format!("{fullmoves}."));
+        } else if black_needs_number {
+            movetext.push(&format!("{fullmoves}..."));
+        }
+        movetext.push(&pgn_move.san.to_string());
+        for nag in &pgn_move.nags {
+            movetext.push(nag);
+        }
+
+        let mut comment = Vec::new();
+        if let Some(eval) = analyses[i + 1]
+            .as_ref()
+            .and_then(|analysis| analysis.eval.as_ref())
+        {
+            comment.push(format!("[%eval {}]", format_eval(eval, pos.turn())));
+        }
+        if let Some(best) = best {
+            comment.push(format!("Best: {best}."));
+        }
+        comment.extend(pgn_move.comments.iter().cloned());
+        black_needs_number = !comment.is_empty();
+        if !comment.is_empty() {
+            movetext.push(&format!("{{ {} }}", comment.join(" ")));
+        }
+    }
+    movetext.push(&game.result);
+    pgn.push_str(&movetext.text);
+    pgn.push_str("\n\n");
+    Ok(pgn)
+}
+
+/// Runs each position of the games in a PGN file through the configured
+/// engine, and writes the games with evaluations and best moves.
+pub async fn annotate(
+    opts: Opts,
+    pgn: &Path,
+    output: Option<&Path>,
+    depth: Option<u32>,
+    movetime: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let games = parse(&fs::read_to_string(pgn)?)?;
+
+    let opts = opts.with_config_file()?;
+    let gpu = GpuBackend::detect();
+    let addr = opts
+        .engine
+        .clone()
+        .best(gpu)
+        .await
+        .ok_or("no engine configured")?;
+    let mut engine = Engine::new(addr, opts.local_engine_parameters(gpu)).await?;
+
+    let session = Session(1);
+    engine.ensure_newgame(session).await?;
+    let go = UciIn::Go {
+        searchmoves: None,
+        ponder: false,
+        wtime: None,
+        btime: None,
+        winc: None,
+        binc: None,
+        movestogo: None,
+        depth: depth.or_else(|| movetime.is_none().then_some(DEFAULT_DEPTH)),
+        nodes: None,
+        mate: None,
+        movetime,
+        infinite: false,
+    };
+
+    let mut annotated = String::new();
+    for (i, game) in games.iter().enumerate() {
+        tracing::info!(
+            "Annotating game {} of {} ({} moves) ...",
+            i + 1,
+            games.len(),
+            game.moves.len()
+        );
+        annotated.push_str(
+            &annotate_game(&mut engine, session, game, &go)
+                .await
+                .map_err(|err| format!("game {}: {err}", i + 1))?,
+        );
+    }
+    match output {
+        Some(output) => fs::write(output, annotated)?,
+        None => print!("{annotated}"),
+    }
+    Ok(())
+}
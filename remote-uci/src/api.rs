@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeMap, convert::Infallible, io, num::NonZeroU32, sync::Arc, time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{FromRequest as _, Query, RequestParts},
+    http::{header, HeaderMap, Request, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use futures_util::{stream, Stream, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use shakmaty::fen::Fen;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{
+    engine::{AnnouncedOption, Observation},
+    tokens::Permissions,
+    uci::{Eval, UciIn, UciOptionName, UciOut},
+    ws::{self, Secret, SecretStore, SharedEngine, SocketOpts},
+};
+
+#[derive(Deserialize)]
+pub struct Params {
+    secret: Option<Secret>,
+}
+
+/// Analysis of a single position, for scripts and other tools that do not
+/// want to speak UCI over a websocket.
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalyseRequest {
+    #[serde_as(as = "DisplayFromStr")]
+    fen: Fen,
+    multipv: Option<NonZeroU32>,
+    /// Search time in milliseconds.
+    movetime: Option<u64>,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct AnalyseResponse {
+    bestmove: Option<String>,
+    lines: Vec<Line>,
+}
+
+/// Final evaluation and principal variation of one of the best moves.
+#[derive(Serialize)]
+pub struct Line {
+    multipv: u32,
+    depth: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mate: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wdl: Option<[u32; 3]>,
+    nodes: Option<u64>,
+    pv: Vec<String>,
+}
+
+type Rejection = (StatusCode, String);
+
+pub async fn analyse(
+    engine: Arc<SharedEngine>,
+    secrets: Arc<SecretStore>,
+    opts: SocketOpts,
+    Query(params): Query<Params>,
+    req: Request<Body>,
+) -> Result<Json<AnalyseResponse>, Response> {
+    let ip = opts.ip_filter.client_ip(&req);
+    let secret = params.secret.or_else(|| bearer(req.headers()));
+    let grant = ws::authorize(&engine, &secrets, &opts, ip, secret.as_ref())
+        .map_err(IntoResponse::into_response)?;
+    let Json(request) = Json::<AnalyseRequest>::from_request(&mut RequestParts::new(req))
+        .await
+        .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response())?;
+
+    // Finish the analysis even if the client goes away, so that the engine
+    // is not left searching.
+    tokio::spawn(async move { run(&engine, &grant.permissions, request).await })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+        .map(Json)
+        .map_err(|rejection| rejection.into_response())
+}
+
+/// Lists the options of the engine, with their types and bounds, so that
+/// clients can offer them for configuration.
+pub async fn options(
+    engine: Arc<SharedEngine>,
+    secrets: Arc<SecretStore>,
+    opts: SocketOpts,
+    Query(params): Query<Params>,
+    req: Request<Body>,
+) -> Result<Json<Vec<AnnouncedOption>>, Response> {
+    let ip = opts.ip_filter.client_ip(&req);
+    let secret = params.secret.or_else(|| bearer(req.headers()));
+    ws::authorize(&engine, &secrets, &opts, ip, secret.as_ref())
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(engine.options()))
+}
+
+/// Streams the positions and analysis of the engines as server-sent events,
+/// so that other devices can follow along without controlling the engine.
+pub async fn watch(
+    engine: Arc<SharedEngine>,
+    secrets: Arc<SecretStore>,
+    opts: SocketOpts,
+    Query(params): Query<Params>,
+    req: Request<Body>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let ip = opts.ip_filter.client_ip(&req);
+    let secret = params.secret.or_else(|| bearer(req.headers()));
+    ws::authorize(&engine, &secrets, &opts, ip, secret.as_ref())
+        .map_err(IntoResponse::into_response)?;
+    let mut guard = engine
+        .shutdown_guard()
+        .ok_or_else(|| (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response())?;
+
+    let observations = stream::select_all(
+        engine
+            .observe()
+            .into_iter()
+            .map(|receiver| stream::unfold(receiver, next_observation).boxed()),
+    );
+    Ok(Sse::new(
+        observations
+            .map(|observation| {
+                Ok(match observation {
+                    Observation::Position(position) => Event::default()
+                        .event("position")
+                        .data(position.to_string()),
+                    Observation::Info(info) => {
+                        Event::default().event("info").data(info.to_string())
+                    }
+                })
+            })
+            .take_until(async move { guard.requested().await }),
+    )
+    .keep_alive(KeepAlive::default()))
+}
+
+async fn next_observation(
+    mut receiver: Receiver<Observation>,
+) -> Option<(Observation, Receiver<Observation>)> {
+    loop {
+        match receiver.recv().await {
+            Ok(observation) => return Some((observation, receiver)),
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::debug!("spectator skipped {skipped} observations");
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> Option<Secret> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Secret(token.to_owned()))
+}
+
+async fn run(
+    shared_engine: &SharedEngine,
+    permissions: &Permissions,
+    request: AnalyseRequest,
+) -> Result<AnalyseResponse, Rejection> {
+    if request.movetime.is_none() && request.depth.is_none() && request.nodes.is_none() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "expected movetime, depth or nodes".to_owned(),
+        ));
+    }
+    let _guard = shared_engine
+        .shutdown_guard()
+        .ok_or_else(|| (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_owned()))?;
+    if let Some(until) = shared_engine.unavailable_until() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("unavailable until {until}"),
+        ));
+    }
+    let position = UciIn::Position {
+        fen: Some(request.fen),
+        moves: Vec::new(),
+    };
+    let go = UciIn::Go {
+        searchmoves: None,
+        ponder: false,
+        wtime: None,
+        btime: None,
+        winc: None,
+        binc: None,
+        movestogo: None,
+        depth: request.depth,
+        nodes: request.nodes,
+        mate: None,
+        movetime: request.movetime.map(Duration::from_millis),
+        infinite: false,
+    };
+
+    let (session, mut engine) = shared_engine.acquire().await;
+    tracing::warn!("{}: analysing for api", session.0);
+    engine
+        .validate_position(&position)
+        .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))?;
+
+    let res = async {
+        engine.start_session(session).await?;
+        for command in permissions.session_commands(&engine) {
+            engine.send(session, command).await?;
+        }
+        engine
+            .send(
+                session,
+                UciIn::Setoption {
+                    name: UciOptionName("MultiPV".to_owned()),
+                    value: Some(request.multipv.map_or(1, NonZeroU32::get).to_string()),
+                },
+            )
+            .await?;
+        engine.send(session, position).await?;
+        engine.send(session, go).await?;
+
+        let mut lines = BTreeMap::new();
+        loop {
+            let notified = engine.notified();
+            tokio::select! {
+                command = engine.recv(session) => match command? {
+                    UciOut::Info {
+                        multipv,
+                        depth,
+                        score: Some(score),
+                        wdl,
+                        nodes,
+                        pv: Some(pv),
+                        ..
+                    } => {
+                        let multipv = multipv.map_or(1, NonZeroU32::get);
+                        let (cp, mate) = match score.eval {
+                            Eval::Cp(cp) => (Some(cp), None),
+                            Eval::Mate(mate) => (None, Some(mate)),
+                        };
+                        lines.insert(multipv, Line {
+                            multipv,
+                            depth,
+                            cp,
+                            mate,
+                            wdl: wdl.map(|wdl| [wdl.wins, wdl.draws, wdl.losses]),
+                            nodes,
+                            pv: pv.iter().map(ToString::to_string).collect(),
+                        });
+                    }
+                    UciOut::Bestmove { m, .. } => {
+                        break Ok::<_, io::Error>(AnalyseResponse {
+                            bestmove: m.map(|m| m.to_string()),
+                            lines: lines.into_values().collect(),
+                        });
+                    }
+                    _ => (),
+                },
+                _ = notified => {
+                    if (engine.is_preempted(session) || engine.is_killed(session))
+                        && engine.is_searching()
+                    {
+                        engine.send(session, UciIn::Stop).await?;
+                    }
+                }
+            }
+        }
+    }
+    .await;
+    res.map_err(|err| {
+        tracing::error!("{}: failed to analyse for api: {err}", session.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    })
+}
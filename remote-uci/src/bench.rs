@@ -0,0 +1,84 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use tokio::{process::Command, time::timeout};
+
+/// Maximum time for a single benchmark run.
+const BENCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs a short benchmark of each engine build, one after another, and
+/// returns the fastest. Falls back to the first candidate if no benchmark
+/// succeeds.
+pub async fn fastest(candidates: Vec<PathBuf>) -> Option<PathBuf> {
+    let mut fastest: Option<(PathBuf, u64)> = None;
+    for path in &candidates {
+        tracing::info!("Benchmarking {path:?} ...");
+        match nps(path).await {
+            Ok(nps) => {
+                tracing::info!("{path:?} searched {nps} nodes/second");
+                if fastest.as_ref().map_or(true, |(_, best)| nps > *best) {
+                    fastest = Some((path.clone(), nps));
+                }
+            }
+            Err(err) => tracing::warn!("Could not benchmark {path:?}: {err}"),
+        }
+    }
+    match fastest {
+        Some((path, _)) => {
+            tracing::info!("Selected {path:?} as the fastest engine build");
+            Some(path)
+        }
+        None => candidates.into_iter().next(),
+    }
+}
+
+/// Runs `bench` with a small hash table, one thread and a low depth, like
+/// Stockfish supports, and parses the reported speed.
+pub async fn nps(path: &Path) -> io::Result<u64> {
+    summary(path, &["bench", "16", "1", "10"], "Nodes/second").await
+}
+
+/// Runs `bench` with default settings, and parses the number of nodes
+/// searched. Stockfish releases publish this as their bench signature.
+pub async fn signature(path: &Path) -> io::Result<u64> {
+    summary(path, &["bench"], "Nodes searched").await
+}
+
+/// Runs the engine with the given arguments, and parses a value from the
+/// summary it prints.
+async fn summary(path: &Path, args: &[&str], key: &str) -> io::Result<u64> {
+    let output = timeout(
+        BENCH_TIMEOUT,
+        Command::new(path)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "bench timed out"))??;
+    // Stockfish prints the summary to stderr.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stderr
+        .lines()
+        .chain(stdout.lines())
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim() == key {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bench did not report {key}"),
+            )
+        })
+}
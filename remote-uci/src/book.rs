@@ -0,0 +1,294 @@
+use std::{fs, io, num::NonZeroU32, path::Path};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use shakmaty::{uci::Uci, zobrist::ZobristHash, CastlingMode, Chess, Position, Role, Square};
+
+use crate::uci::UciOut;
+
+/// How book moves are used.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BookMode {
+    /// Report book moves as info string, and let the engine search anyway.
+    #[default]
+    Advisory,
+    /// Answer searches with book moves instead of starting the engine,
+    /// unless the search is infinite or pondering.
+    Authoritative,
+}
+
+/// Entry of a Polyglot book, which is a file of 16 byte entries sorted by
+/// key.
+struct Entry {
+    key: u64,
+    raw_move: u16,
+    weight: u16,
+}
+
+/// Polyglot opening book, loaded into memory.
+pub struct OpeningBook {
+    entries: Vec<Entry>,
+    /// Consult the book only up to this many moves into the game.
+    depth: Option<u32>,
+    pub mode: BookMode,
+}
+
+/// Move from the book, with its share of the weights of all book moves in
+/// the position, in percent.
+pub struct BookMove {
+    pub m: Uci,
+    pub percent: u32,
+}
+
+impl OpeningBook {
+    pub fn open(path: &Path, depth: Option<u32>, mode: BookMode) -> io::Result<OpeningBook> {
+        let data = fs::read(path)?;
+        if data.len() % 16 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path:?} is not a Polyglot book"),
+            ));
+        }
+        let entries: Vec<Entry> = data
+            .chunks_exact(16)
+            .map(|chunk| Entry {
+                key: u64::from_be_bytes(chunk[0..8].try_into().expect("key")),
+                raw_move: u16::from_be_bytes(chunk[8..10].try_into().expect("move")),
+                weight: u16::from_be_bytes(chunk[10..12].try_into().expect("weight")),
+            })
+            .collect();
+        tracing::info!("Loaded {} book entries from {path:?}", entries.len());
+        Ok(OpeningBook {
+            entries,
+            depth,
+            mode,
+        })
+    }
+
+    /// Book moves for the position at the game ply, from the highest to the
+    /// lowest weight. Empty if the position is not in the book.
+    pub fn moves(&self, pos: &Chess, ply: u32, castling_mode: CastlingMode) -> Vec<BookMove> {
+        if self
+            .depth
+            .map_or(false, |depth| ply >= depth.saturating_mul(2))
+        {
+            return Vec::new();
+        }
+        let key: u64 = pos.zobrist_hash();
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let entries: Vec<&Entry> = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter(|entry| entry.weight > 0)
+            .collect();
+        let total: u32 = entries.iter().map(|entry| u32::from(entry.weight)).sum();
+        let legals = pos.legal_moves();
+        let mut moves: Vec<(u16, BookMove)> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let (from, to, promotion) = decode(entry.raw_move);
+                // Castling is encoded as the king capturing its own rook.
+                let m = legals.iter().find(|m| {
+                    m.from() == Some(from) && m.to() == to && m.promotion() == promotion
+                })?;
+                Some((
+                    entry.weight,
+                    BookMove {
+                        m: Uci::from_move(m, castling_mode),
+                        percent: u32::from(entry.weight) * 100 / total,
+                    },
+                ))
+            })
+            .collect();
+        moves.sort_by(|(a, _), (b, _)| b.cmp(a));
+        moves.into_iter().map(|(_, m)| m).collect()
+    }
+}
+
+fn decode(raw_move: u16) -> (Square, Square, Option<Role>) {
+    let to = Square::new(u32::from(raw_move & 0o77));
+    let from = Square::new(u32::from((raw_move >> 6) & 0o77));
+    let promotion = match (raw_move >> 12) & 0o7 {
+        1 => Some(Role::Knight),
+        2 => Some(Role::Bishop),
+        3 => Some(Role::Rook),
+        4 => Some(Role::Queen),
+        _ => None,
+    };
+    (from, to, promotion)
+}
+
+/// Book moves as an info string, like `book e2e4 45% d2d4 35% c2c4 20%`.
+pub fn info_string(moves: &[BookMove]) -> UciOut {
+    let mut string = "book".to_owned();
+    for m in moves {
+        string.push_str(&format!(" {} {}%", m.m, m.percent));
+    }
+    UciOut::info_string(string)
+}
+
+/// Output for a search answered from the book: a line for each of the
+/// best `multipv` book moves, and the best move.
+pub fn answer(moves: &[BookMove], multipv: u32) -> Vec<UciOut> {
+    let mut output: Vec<UciOut> = moves
+        .iter()
+        .zip(1..=multipv)
+        .map(|(m, i)| {
+            let mut info = UciOut::info_string(format!("book {}%", m.percent));
+            if let UciOut::Info {
+                ref mut multipv,
+                ref mut depth,
+                ref mut pv,
+                ..
+            } = info
+            {
+                *multipv = NonZeroU32::new(i);
+                *depth = Some(1);
+                *pv = Some(vec![m.m.clone()]);
+            }
+            info
+        })
+        .collect();
+    output.push(UciOut::Bestmove {
+        m: moves.first().map(|m| m.m.clone()),
+        ponder: None,
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+
+    fn raw_move(from: Square, to: Square, promotion: u16) -> u16 {
+        promotion << 12 | u16::from(from) << 6 | u16::from(to)
+    }
+
+    /// Writes a book with the entries, which must be sorted by key, and
+    /// loads it.
+    fn book(name: &str, entries: &[(u64, u16, u16)], depth: Option<u32>) -> OpeningBook {
+        let mut data = Vec::new();
+        for &(key, raw_move, weight) in entries {
+            data.extend(key.to_be_bytes());
+            data.extend(raw_move.to_be_bytes());
+            data.extend(weight.to_be_bytes());
+            data.extend(0u32.to_be_bytes()); // learn
+        }
+        let path =
+            std::env::temp_dir().join(format!("remote-uci-test-{}-{name}.bin", std::process::id()));
+        fs::write(&path, data).expect("write book");
+        let book = OpeningBook::open(&path, depth, BookMode::Advisory).expect("open book");
+        let _ = fs::remove_file(path);
+        book
+    }
+
+    fn position(fen: &str) -> Chess {
+        fen.parse::<Fen>()
+            .expect("fen")
+            .into_position(CastlingMode::Standard)
+            .expect("position")
+    }
+
+    fn moves(book: &OpeningBook, pos: &Chess, ply: u32, mode: CastlingMode) -> Vec<String> {
+        book.moves(pos, ply, mode)
+            .into_iter()
+            .map(|m| format!("{} {}%", m.m, m.percent))
+            .collect()
+    }
+
+    #[test]
+    fn test_start_position_key() {
+        assert_eq!(Chess::default().zobrist_hash::<u64>(), 0x463b96181691fc9c);
+    }
+
+    #[test]
+    fn test_weights() {
+        let key = Chess::default().zobrist_hash::<u64>();
+        let book = book(
+            "weights",
+            &[
+                (key - 1, raw_move(Square::A2, Square::A3, 0), 10),
+                (key, raw_move(Square::D2, Square::D4, 0), 1),
+                (key, raw_move(Square::E2, Square::E4, 0), 2),
+                (key, raw_move(Square::G1, Square::F3, 0), 0),
+                (key, raw_move(Square::C2, Square::C4, 0), 1),
+                // Not legal.
+                (key, raw_move(Square::E2, Square::E5, 0), 1),
+                (key + 1, raw_move(Square::B2, Square::B3, 0), 10),
+            ],
+            None,
+        );
+        assert_eq!(
+            moves(&book, &Chess::default(), 0, CastlingMode::Standard),
+            vec!["e2e4 40%", "d2d4 20%", "c2c4 20%"]
+        );
+        assert_eq!(
+            info_string(&book.moves(&Chess::default(), 0, CastlingMode::Standard)).to_string(),
+            "info string book e2e4 40% d2d4 20% c2c4 20%"
+        );
+    }
+
+    #[test]
+    fn test_castling_and_promotion() {
+        let castling = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let promotion = position("8/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+        let mut entries = vec![
+            (
+                castling.zobrist_hash::<u64>(),
+                raw_move(Square::E1, Square::H1, 0),
+                1,
+            ),
+            (
+                castling.zobrist_hash::<u64>(),
+                raw_move(Square::E1, Square::A1, 0),
+                1,
+            ),
+            (
+                promotion.zobrist_hash::<u64>(),
+                raw_move(Square::E7, Square::E8, 4),
+                3,
+            ),
+            (
+                promotion.zobrist_hash::<u64>(),
+                raw_move(Square::E7, Square::E8, 1),
+                1,
+            ),
+        ];
+        entries.sort_by_key(|&(key, _, _)| key);
+        let book = book("special", &entries, None);
+        assert_eq!(
+            moves(&book, &castling, 0, CastlingMode::Standard),
+            vec!["e1g1 50%", "e1c1 50%"]
+        );
+        assert_eq!(
+            moves(&book, &castling, 0, CastlingMode::Chess960),
+            vec!["e1h1 50%", "e1a1 50%"]
+        );
+        assert_eq!(
+            moves(&book, &promotion, 0, CastlingMode::Standard),
+            vec!["e7e8q 75%", "e7e8n 25%"]
+        );
+        assert_eq!(
+            decode(raw_move(Square::B7, Square::A8, 3)),
+            (Square::B7, Square::A8, Some(Role::Rook))
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        let key = Chess::default().zobrist_hash::<u64>();
+        let book = book(
+            "depth",
+            &[(key, raw_move(Square::E2, Square::E4, 0), 1)],
+            Some(1),
+        );
+        assert_eq!(
+            moves(&book, &Chess::default(), 1, CastlingMode::Standard),
+            vec!["e2e4 100%"]
+        );
+        assert!(moves(&book, &Chess::default(), 2, CastlingMode::Standard).is_empty());
+    }
+}
@@ -0,0 +1,24 @@
+/// Opens the URL in the default browser, unless there is no graphical
+/// session. Failures are logged, so that the printed URL can be used
+/// instead.
+pub fn open_browser(url: &str) {
+    if is_headless() {
+        tracing::debug!("No graphical session, not opening browser");
+        return;
+    }
+    match open::that(url) {
+        Ok(()) => tracing::info!("Opened registration URL in browser"),
+        Err(err) => tracing::warn!("Could not open browser: {err}"),
+    }
+}
+
+/// Whether there is no graphical session to show windows in.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn is_headless() -> bool {
+    std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none()
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn is_headless() -> bool {
+    false
+}
@@ -0,0 +1,776 @@
+use std::{
+    cmp::max,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener},
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use listenfd::ListenFd;
+
+use crate::{
+    admin::{self, Admin, Endpoint},
+    advertised_base_url,
+    book::OpeningBook,
+    cache::{self, AnalysisCache},
+    engine::{EngineAddr, EngineParameters},
+    error::RemoteUciError,
+    get_external_protocol,
+    gpu::GpuBackend,
+    health::{self, Health},
+    ipfilter::{self, IpFilter},
+    lichess::{self, LichessClient},
+    listen::{self, Incoming, Listener, PeerAddr},
+    load_secrets,
+    metrics::{self, Metrics},
+    nnue, official,
+    online_tb::OnlineTablebase,
+    origin::OriginPolicy,
+    reload::{self, Reloader},
+    rotate_secret, route_engine,
+    sandbox::SandboxPolicy,
+    shutdown::Shutdown,
+    start_pool,
+    stats::{self, Stats},
+    thermal::ThermalGuard,
+    throttle::SecretThrottle,
+    tui::{self, LogBuffer},
+    tunnel, variant,
+    ws::{SecretStorage, SecretStore, SharedEngine, SocketOpts},
+    EngineSpec, ExternalWorkerOpts, Opts, VariantEngine,
+};
+
+/// Sets up engine endpoints for embedding in another application, which
+/// serves the returned router itself.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let (router, handle) = remote_uci::RemoteUciBuilder::new()
+///     .engine("/usr/bin/stockfish")
+///     .base_url("wss://example.com")
+///     .build()
+///     .await?;
+/// for spec in handle.specs() {
+///     println!("{}", spec.registration_url());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Serve the router with `into_make_service_with_connect_info::<SocketAddr>`
+/// to make client addresses available for `allow_ip` and `deny_ip`.
+#[derive(Debug, Default, Clone)]
+pub struct RemoteUciBuilder {
+    opts: Opts,
+    base_url: Option<String>,
+    /// The base URL is the address of a listener, not published.
+    local_base_url: bool,
+}
+
+impl RemoteUciBuilder {
+    pub fn new() -> RemoteUciBuilder {
+        RemoteUciBuilder::default()
+    }
+
+    /// Starts from options as parsed from the command line. The config file
+    /// named in the options is read when building.
+    pub fn from_opts(opts: Opts) -> RemoteUciBuilder {
+        RemoteUciBuilder {
+            opts,
+            ..RemoteUciBuilder::default()
+        }
+    }
+
+    /// UCI engine executable.
+    pub fn engine(mut self, path: impl Into<PathBuf>) -> RemoteUciBuilder {
+        self.opts.engine.engine = Some(path.into());
+        self
+    }
+
+    /// Engine served over TCP on HOST:PORT, instead of an executable.
+    pub fn engine_tcp(mut self, addr: impl Into<String>) -> RemoteUciBuilder {
+        self.opts.engine.engine_tcp = Some(addr.into());
+        self
+    }
+
+    /// Additional engine, served under `/socket/{name}`.
+    pub fn engine_spec(
+        mut self,
+        name: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> RemoteUciBuilder {
+        self.opts.engine_spec.push(EngineSpec {
+            name: name.into(),
+            path: path.into(),
+        });
+        self
+    }
+
+    /// Engine name to announce to lichess.
+    pub fn name(mut self, name: impl Into<String>) -> RemoteUciBuilder {
+        self.opts.name = Some(name.into());
+        self
+    }
+
+    pub fn max_threads(mut self, max_threads: u32) -> RemoteUciBuilder {
+        self.opts.max_threads = Some(max_threads);
+        self
+    }
+
+    /// Maximum hash table size in MiB.
+    pub fn max_hash(mut self, max_hash: u32) -> RemoteUciBuilder {
+        self.opts.max_hash = Some(max_hash);
+        self
+    }
+
+    /// File to load the secret from, or to create with a random secret.
+    /// Without a secret file, a new random secret is used.
+    pub fn secret_file(mut self, path: impl Into<PathBuf>) -> RemoteUciBuilder {
+        self.opts.secret_file = Some(path.into());
+        self
+    }
+
+    /// Public websocket URL of the router, like `wss://example.com`, for
+    /// registration URLs. Takes precedence over `advertise_url` and
+    /// `publish_addr` of the options. The pages that show the secret are
+    /// then only served to clients on this machine.
+    pub fn base_url(mut self, url: impl Into<String>) -> RemoteUciBuilder {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    fn local_base_url(mut self, url: String) -> RemoteUciBuilder {
+        self.base_url = Some(url);
+        self.local_base_url = true;
+        self
+    }
+
+    fn resolve_base_url(&self, opts: &Opts) -> Result<String, RemoteUciError> {
+        if let Some(ref url) = self.base_url {
+            return Ok(url.trim_end_matches('/').to_owned());
+        }
+        if let Some(ref url) = opts.advertise_url {
+            return advertised_base_url(url).map_err(RemoteUciError::AdvertiseUrl);
+        }
+        match opts.publish_addr {
+            Some(ref publish_addr) => Ok(format!(
+                "{}://{publish_addr}",
+                get_external_protocol(opts.publish_addr_tls.unwrap_or(false))
+            )),
+            None => Err(RemoteUciError::NoPublicUrl),
+        }
+    }
+
+    /// Starts the engines and background tasks, and returns the routes of
+    /// all endpoints.
+    pub async fn build(self) -> Result<(Router, EngineHandle), RemoteUciError> {
+        let cli_opts = self.opts.clone();
+        let opts = self
+            .opts
+            .clone()
+            .with_config_file()
+            .map_err(|err| RemoteUciError::Config(err.to_string()))?;
+        let base_url = self.resolve_base_url(&opts)?;
+        let gpu = GpuBackend::detect();
+        if let Some(gpu) = gpu {
+            tracing::info!("Detected {gpu} capable GPU");
+        }
+        let default_engine = opts.engine.clone().best(gpu).await;
+        if default_engine.is_none() && opts.engine_spec.is_empty() {
+            return Err(RemoteUciError::NoEngine);
+        }
+
+        let pool_size = opts.pool_size.map_or(1, NonZeroUsize::get);
+        if let Some(ref eval_file) = opts.eval_file {
+            nnue::fetch(eval_file)
+                .await
+                .map_err(|err| RemoteUciError::EvalFile(err.to_string()))?;
+        }
+        let (options, tablebases) = opts
+            .engine_options()
+            .map_err(|err| RemoteUciError::Config(err.to_string()))?;
+        let mut sandbox_paths = opts.syzygy_paths();
+        sandbox_paths.extend(opts.weights.clone());
+        sandbox_paths.extend(opts.eval_file.as_deref().and_then(nnue::locate));
+        let stats = Arc::new(Stats::new(opts.stats_file.clone()).map_err(RemoteUciError::Stats)?);
+
+        let params = EngineParameters {
+            max_threads: opts.max_threads(),
+            max_hash: max(
+                opts.max_hash() / u32::try_from(pool_size).unwrap_or(u32::MAX),
+                1,
+            ),
+            options,
+            gpu,
+            limits: opts.search_limits(),
+            protocol: opts.protocol.unwrap_or_default(),
+            sandbox: SandboxPolicy {
+                mode: opts.sandbox.unwrap_or_default(),
+                paths: sandbox_paths,
+            },
+            launch: opts.launch(),
+            priority: opts.engine_priority(),
+            cpus: opts.engine_cpus.clone(),
+            numa: opts.numa,
+            battery: opts.on_battery,
+            engine_timeout: opts.engine_timeout.map(Into::into),
+            move_overhead: opts.move_overhead.map(Into::into),
+            max_multipv: opts.max_multipv.map(NonZeroU32::get),
+            default_multipv: opts.default_multipv.map(NonZeroU32::get),
+            init_timeout: opts.init_timeout.map(Into::into),
+            init_retries: opts.init_retries.unwrap_or(0),
+            thermal: opts.thermal_limit.map(|limit| {
+                let thermal = Arc::new(ThermalGuard::new(limit, opts.thermal_threads.unwrap_or(1)));
+                tokio::spawn(Arc::clone(&thermal).monitor());
+                thermal
+            }),
+            option_policy: opts.option_policy(),
+            variants: opts.variants.clone(),
+            validate_pv: opts.validate_pv.unwrap_or(false),
+            notify: opts.notify.unwrap_or(false),
+            wdl: opts.wdl.unwrap_or(false),
+            normalize_scores: opts.normalize_scores.unwrap_or(false),
+            hash_policy: opts.hash_policy.unwrap_or_default(),
+            deterministic: opts.deterministic.unwrap_or(false),
+            cache: match (opts.analysis_cache, opts.analysis_cache_file.clone()) {
+                (None, None) => None,
+                (capacity, path) => Some(Arc::new(
+                    AnalysisCache::new(capacity.unwrap_or(cache::DEFAULT_CAPACITY), path)
+                        .map_err(RemoteUciError::AnalysisCache)?,
+                )),
+            },
+            compare: opts.engine_b.clone().map(EngineAddr::Process),
+            standby: opts.standby.unwrap_or(false),
+            split_multipv: opts.split_multipv,
+            book: match opts.book {
+                Some(ref path) => Some(Arc::new(
+                    OpeningBook::open(path, opts.book_depth, opts.book_mode.unwrap_or_default())
+                        .map_err(RemoteUciError::Book)?,
+                )),
+                None => None,
+            },
+            online_tb: opts
+                .online_tb
+                .unwrap_or(false)
+                .then(|| Arc::new(OnlineTablebase::new(tablebases))),
+            stats: Some(Arc::clone(&stats)),
+        };
+
+        let policy = opts.session_policy.unwrap_or_default();
+        let idle_timeout = opts.idle_timeout.map(Into::into);
+        let only_when_idle = opts.only_when_idle.map(Into::into);
+        let secret_rotate_interval: Option<Duration> = opts.secret_rotate_interval.map(Into::into);
+        let secret_storage = opts
+            .secret_storage()
+            .map_err(|err| RemoteUciError::Config(err.to_string()))?;
+        if let Some(ref name) = opts.default_profile {
+            if !opts.profile.contains_key(name) {
+                return Err(RemoteUciError::Config(format!(
+                    "--default-profile {name} is not defined in the config file"
+                )));
+            }
+        }
+        let ping_interval = opts
+            .ws_ping_interval
+            .map_or(Duration::from_secs(10), Into::into);
+        let socket_opts = SocketOpts {
+            compression: opts.ws_compression.unwrap_or(false),
+            info_interval: opts.info_interval_ms.map(Duration::from_millis),
+            session_log_dir: opts.session_log_dir.map(Arc::from),
+            origin_policy: Arc::new(OriginPolicy::new(&opts.allow_origin, &base_url)),
+            ip_filter: Arc::new(IpFilter::new(
+                &opts.allow_ip,
+                &opts.deny_ip,
+                opts.trust_proxy.unwrap_or(false),
+            )),
+            local_pages: !self.local_base_url,
+            throttle: Arc::new(SecretThrottle::new(
+                opts.secret_ban_after,
+                opts.secret_ban_duration
+                    .map_or(Duration::from_secs(60 * 60), Into::into),
+            )),
+            ping_interval,
+            ping_timeout: opts.ws_timeout.map_or(ping_interval, Into::into),
+            strict_uci: opts.strict_uci.unwrap_or(false),
+            report_latency: opts.report_latency.unwrap_or(false),
+            resume_window: opts.resume_window.map(Into::into),
+            profiles: Arc::new(opts.profile.clone()),
+            default_profile: opts.default_profile.clone(),
+        };
+        let shutdown = Arc::new(Shutdown::new(
+            opts.shutdown_timeout
+                .map_or(Duration::from_secs(10), Into::into),
+        ));
+
+        let lichess = opts.lichess_token.map(|token| {
+            Arc::new(LichessClient::new(
+                token,
+                opts.lichess_url
+                    .unwrap_or_else(|| "https://lichess.org".to_owned()),
+                opts.lichess_engine_url
+                    .unwrap_or_else(|| "https://engine.lichess.ovh".to_owned()),
+            ))
+        });
+
+        let mut specs = Vec::new();
+        let mut engines = Vec::new();
+        let mut endpoints = Vec::new();
+        let mut app = Router::new();
+
+        if let Some(addr) = default_engine {
+            let pool = start_pool(addr.clone(), &params, pool_size).await?;
+            let mut variant_engines: Vec<(Vec<String>, SharedEngine)> = Vec::new();
+            let mut variant_paths: Vec<(PathBuf, Vec<String>)> = Vec::new();
+            for VariantEngine { variant, path } in opts.variant_engine {
+                match variant_paths.iter_mut().find(|(other, _)| *other == path) {
+                    Some((_, variants)) => variants.push(variant),
+                    None => variant_paths.push((path, vec![variant])),
+                }
+            }
+            for (path, variants) in variant_paths {
+                let params = EngineParameters {
+                    variants: variants.clone(),
+                    ..params.clone()
+                };
+                let variant_pool =
+                    start_pool(EngineAddr::Process(path), &params, pool_size).await?;
+                variant_engines.push((
+                    variants,
+                    SharedEngine::new(variant_pool, policy, idle_timeout, Arc::clone(&shutdown)),
+                ));
+            }
+            let engine = &pool[0];
+            let mut variants = engine.variants();
+            if !variant_engines.is_empty() {
+                if variants.is_empty() {
+                    variants.push("chess".to_owned());
+                }
+                for variant in variant_engines.iter().flat_map(|(variants, _)| variants) {
+                    let variant = variant::lichess_name(variant);
+                    if !variants.iter().any(|served| served == variant) {
+                        variants.push(variant.to_owned());
+                    }
+                }
+            }
+            let official_stockfish = opts.promise_official_stockfish.unwrap_or(false)
+                && match official::verify(&addr, engine.name()).await {
+                    Ok(version) => {
+                        tracing::info!("Verified official Stockfish {version}");
+                        true
+                    }
+                    Err(err) => {
+                        tracing::warn!("Not promising official Stockfish: {err}");
+                        false
+                    }
+                };
+            let (secret, named) =
+                load_secrets(secret_storage.as_ref()).map_err(RemoteUciError::SecretFile)?;
+            let secrets = Arc::new(SecretStore::new(secret, named, secret_storage.clone()));
+            let spec = ExternalWorkerOpts {
+                url: format!("{base_url}/socket"),
+                secret: secrets.active(),
+                max_threads: engine.max_threads(),
+                max_hash: engine.max_hash(),
+                variants,
+                name: opts
+                    .name
+                    .unwrap_or_else(|| engine.name().unwrap_or("remote-uci").to_owned()),
+                official_stockfish,
+                tablebases,
+                network: engine.network(),
+            };
+            let engine = Arc::new(
+                SharedEngine::new(pool, policy, idle_timeout, Arc::clone(&shutdown))
+                    .with_variant_engines(variant_engines)
+                    .with_schedule(opts.available.clone()),
+            );
+            tokio::spawn(Arc::clone(&engine).suspend_when_idle());
+            tokio::spawn(Arc::clone(&engine).enforce_schedule());
+            if let Some(threshold) = only_when_idle {
+                tokio::spawn(Arc::clone(&engine).pause_while_user_active(threshold));
+            }
+            engines.push(("/socket".to_owned(), engine.metrics()));
+            if let Some(ref lichess) = lichess {
+                tokio::spawn(lichess::provide(
+                    Arc::clone(lichess),
+                    spec.clone(),
+                    Arc::clone(&engine),
+                ));
+            }
+            if let Some(interval) = secret_rotate_interval {
+                tokio::spawn(rotate_secret(spec.clone(), Arc::clone(&secrets), interval));
+            }
+            let endpoint = Endpoint {
+                path: "/socket".to_owned(),
+                engine,
+                secrets,
+                spec: spec.clone(),
+            };
+            app = route_engine(app, "", &endpoint, socket_opts.clone());
+            endpoints.push(endpoint);
+            specs.push(spec);
+        }
+
+        for EngineSpec { name, path } in opts.engine_spec {
+            let pool = start_pool(EngineAddr::Process(path), &params, pool_size).await?;
+            let engine = &pool[0];
+            let storage = secret_storage.as_ref().map(|storage| storage.scoped(&name));
+            let (secret, named) =
+                load_secrets(storage.as_ref()).map_err(RemoteUciError::SecretFile)?;
+            let secrets = Arc::new(SecretStore::new(secret, named, storage));
+            let spec = ExternalWorkerOpts {
+                url: format!("{base_url}/socket/{name}"),
+                secret: secrets.active(),
+                max_threads: engine.max_threads(),
+                max_hash: engine.max_hash(),
+                variants: engine.variants(),
+                name: engine.name().unwrap_or(&name).to_owned(),
+                official_stockfish: false,
+                tablebases,
+                network: engine.network(),
+            };
+            let socket_path = format!("/socket/{name}");
+            let engine = Arc::new(
+                SharedEngine::new(pool, policy, idle_timeout, Arc::clone(&shutdown))
+                    .with_schedule(opts.available.clone()),
+            );
+            tokio::spawn(Arc::clone(&engine).suspend_when_idle());
+            tokio::spawn(Arc::clone(&engine).enforce_schedule());
+            if let Some(threshold) = only_when_idle {
+                tokio::spawn(Arc::clone(&engine).pause_while_user_active(threshold));
+            }
+            engines.push((socket_path.clone(), engine.metrics()));
+            if let Some(ref lichess) = lichess {
+                tokio::spawn(lichess::provide(
+                    Arc::clone(lichess),
+                    spec.clone(),
+                    Arc::clone(&engine),
+                ));
+            }
+            if let Some(interval) = secret_rotate_interval {
+                tokio::spawn(rotate_secret(spec.clone(), Arc::clone(&secrets), interval));
+            }
+            let endpoint = Endpoint {
+                path: socket_path,
+                engine,
+                secrets,
+                spec: spec.clone(),
+            };
+            app = route_engine(
+                app,
+                &format!("/engine/{name}"),
+                &endpoint,
+                socket_opts.clone(),
+            );
+            endpoints.push(endpoint);
+            specs.push(spec);
+        }
+
+        let metrics = Arc::new(Metrics::new(engines));
+        app = app
+            .route("/metrics", get(move || metrics::handler(metrics)))
+            .route("/stats", get(move || stats::handler(stats)));
+
+        let secrets = endpoints
+            .iter()
+            .map(|endpoint| Arc::clone(&endpoint.secrets))
+            .collect();
+        let engines = endpoints
+            .iter()
+            .map(|endpoint| Arc::clone(&endpoint.engine))
+            .collect();
+        let health = Arc::new(Health::new(
+            endpoints
+                .iter()
+                .map(|endpoint| (endpoint.path.clone(), Arc::clone(&endpoint.engine)))
+                .collect(),
+        ));
+        let readiness = Arc::clone(&health);
+        let watchdog = Arc::clone(&health);
+        app = app
+            .route("/healthz", get(move || health::healthz(health)))
+            .route("/readyz", get(move || health::readyz(readiness)));
+
+        let reloader = Arc::new(Reloader::new(
+            cli_opts,
+            endpoints
+                .iter()
+                .map(|endpoint| (Arc::clone(&endpoint.engine), Arc::clone(&endpoint.secrets)))
+                .collect(),
+        ));
+
+        if let Some(ref admin_secret_file) = opts.admin_secret_file {
+            let admin = Arc::new(Admin::new(
+                load_secrets(Some(&SecretStorage::File(admin_secret_file.clone())))
+                    .map_err(RemoteUciError::SecretFile)?
+                    .0,
+                endpoints,
+                Arc::clone(&reloader),
+            ));
+            let (status, kill_session, restart_engine, upgrade_engine, rotate_secret, reload) = (
+                Arc::clone(&admin),
+                Arc::clone(&admin),
+                Arc::clone(&admin),
+                Arc::clone(&admin),
+                Arc::clone(&admin),
+                Arc::clone(&admin),
+            );
+            let (list_secrets, add_secret, revoke_secret) =
+                (Arc::clone(&admin), Arc::clone(&admin), admin);
+            app = app
+                .route(
+                    "/admin/status",
+                    get(move |headers| admin::status(status, headers)),
+                )
+                .route(
+                    "/admin/kill-session",
+                    post(move |headers, query| admin::kill_session(kill_session, headers, query)),
+                )
+                .route(
+                    "/admin/restart-engine",
+                    post(move |headers, query| {
+                        admin::restart_engine(restart_engine, headers, query)
+                    }),
+                )
+                .route(
+                    "/admin/upgrade-engine",
+                    post(move |headers, query, body| {
+                        admin::upgrade_engine(upgrade_engine, headers, query, body)
+                    }),
+                )
+                .route(
+                    "/admin/rotate-secret",
+                    post(move |headers, query| admin::rotate_secret(rotate_secret, headers, query)),
+                )
+                .route(
+                    "/admin/reload",
+                    post(move |headers| admin::reload(reload, headers)),
+                )
+                .route(
+                    "/admin/secrets",
+                    get(move |headers, query| admin::list_secrets(list_secrets, headers, query))
+                        .post(move |headers, query, body| {
+                            admin::add_secret(add_secret, headers, query, body)
+                        })
+                        .delete(move |headers, query| {
+                            admin::revoke_secret(revoke_secret, headers, query)
+                        }),
+                );
+        }
+
+        let ip_filter = socket_opts.ip_filter;
+        if ip_filter.is_active() {
+            app = app.layer(middleware::from_fn(move |req, next| {
+                ipfilter::middleware(Arc::clone(&ip_filter), req, next)
+            }));
+        }
+
+        Ok((
+            app,
+            EngineHandle {
+                specs,
+                secrets,
+                engines,
+                shutdown,
+                reloader,
+                health: watchdog,
+            },
+        ))
+    }
+}
+
+/// Controls the engines behind a router built with [`RemoteUciBuilder`].
+pub struct EngineHandle {
+    specs: Vec<ExternalWorkerOpts>,
+    secrets: Vec<Arc<SecretStore>>,
+    engines: Vec<Arc<SharedEngine>>,
+    shutdown: Arc<Shutdown>,
+    reloader: Arc<Reloader>,
+    health: Arc<Health>,
+}
+
+impl EngineHandle {
+    /// Registration details of each engine endpoint.
+    pub fn specs(&self) -> &[ExternalWorkerOpts] {
+        &self.specs
+    }
+
+    /// Reads the config file and secret files again.
+    pub async fn reload(&self) -> Result<(), RemoteUciError> {
+        self.reloader
+            .reload()
+            .await
+            .map_err(|err| RemoteUciError::Config(err.to_string()))
+    }
+
+    /// Stops accepting sessions and ends the current ones, or starts
+    /// accepting sessions again. Clients are told to come back later.
+    pub fn hold(&self, on_hold: bool) {
+        for engine in &self.engines {
+            engine.hold(on_hold);
+        }
+    }
+
+    /// Shows the state of the engines in the terminal, until shutdown is
+    /// requested.
+    pub async fn dashboard(&self, logs: LogBuffer) {
+        tui::run(
+            &self.specs,
+            &self.secrets,
+            &self.engines,
+            &self.shutdown,
+            logs,
+        )
+        .await;
+    }
+
+    /// Shows an icon in the system tray. Returns when the user picks Quit
+    /// from its menu.
+    #[cfg(feature = "tray")]
+    pub async fn tray(&self) {
+        if let Err(err) = crate::tray::run(&self.specs, &self.secrets, &self.engines).await {
+            tracing::error!("System tray: {err}");
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Asks sessions to finish, and ends streams to spectators. Call this
+    /// before waiting for the HTTP server to shut down gracefully.
+    pub fn request_shutdown(&self) {
+        self.shutdown.request();
+    }
+
+    /// Waits until all sessions are done or the shutdown timeout elapses.
+    pub async fn shutdown(&self) {
+        self.shutdown.drain().await;
+    }
+}
+
+/// Binds the listeners configured in the options, and builds the server
+/// with all engine endpoints.
+pub async fn make_server(
+    opts: Opts,
+    mut listen_fds: ListenFd,
+) -> Result<
+    (
+        hyper::Server<Incoming, IntoMakeServiceWithConnectInfo<Router, PeerAddr>>,
+        EngineHandle,
+    ),
+    RemoteUciError,
+> {
+    let config = opts
+        .clone()
+        .with_config_file()
+        .map_err(|err| RemoteUciError::Config(err.to_string()))?;
+
+    let tcp_listeners = if config.bind.is_empty() {
+        let mut listeners = Vec::new();
+        for index in 0..listen_fds.len() {
+            if let Some(listener) =
+                listen_fds
+                    .take_tcp_listener(index)
+                    .map_err(|source| RemoteUciError::Bind {
+                        addr: format!("inherited socket {index}"),
+                        source,
+                    })?
+            {
+                listeners.push(listener);
+            }
+        }
+        if listeners.is_empty() && config.bind_unix.is_none() {
+            listeners.push(TcpListener::bind("localhost:9670").map_err(|source| {
+                RemoteUciError::Bind {
+                    addr: "localhost:9670".to_owned(),
+                    source,
+                }
+            })?);
+        }
+        listeners
+    } else {
+        let only_v6 = config.bind.len() > 1;
+        config
+            .bind
+            .iter()
+            .map(|addr| {
+                listen::bind(*addr, only_v6).map_err(|source| RemoteUciError::Bind {
+                    addr: addr.to_string(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let local_addr = tcp_listeners
+        .first()
+        .and_then(|listener| listener.local_addr().ok());
+    let mut listeners = tcp_listeners
+        .into_iter()
+        .map(|listener| {
+            let addr = listener
+                .local_addr()
+                .map_or_else(|_| "tcp socket".to_owned(), |addr| addr.to_string());
+            Listener::tcp(listener).map_err(|source| RemoteUciError::Bind { addr, source })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(ref path) = config.bind_unix {
+        listeners.push(
+            listen::bind_unix(path).map_err(|source| RemoteUciError::Bind {
+                addr: path.display().to_string(),
+                source,
+            })?,
+        );
+    }
+
+    let mut builder = RemoteUciBuilder::from_opts(opts);
+    if let Some(tunnel) = config.tunnel {
+        let mut target = local_addr.ok_or(RemoteUciError::NoTcpListener)?;
+        if target.ip().is_unspecified() {
+            target.set_ip(match target.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let url = tunnel::start(tunnel, target, config.tunnel_command.as_deref())
+            .await
+            .map_err(RemoteUciError::Tunnel)?;
+        tracing::info!("Tunnel is available at {url}");
+        // All clients of the tunnel connect from loopback. Tell them apart
+        // by the address the tunnel forwards, so that one client guessing
+        // secrets does not get all of them throttled.
+        builder.opts.trust_proxy = Some(true);
+        builder = builder.base_url(format!(
+            "{}://{}",
+            get_external_protocol(true),
+            url.trim_start_matches("https://")
+        ));
+    } else if config.advertise_url.is_none() && config.publish_addr.is_none() {
+        let local_addr = local_addr.ok_or(RemoteUciError::NoPublicUrl)?;
+        builder = builder.local_base_url(format!(
+            "{}://{local_addr}",
+            get_external_protocol(config.publish_addr_tls.unwrap_or(false))
+        ));
+    }
+
+    let (app, handle) = builder.build().await?;
+
+    #[cfg(target_os = "linux")]
+    tokio::spawn(crate::systemd::watchdog(Arc::clone(&handle.health)));
+    #[cfg(unix)]
+    tokio::spawn(reload::reload_on_sighup(Arc::clone(&handle.reloader)));
+
+    // All engines have answered uciok by now.
+    #[cfg(target_os = "linux")]
+    crate::systemd::notify("READY=1");
+
+    Ok((
+        axum::Server::builder(Incoming::new(listeners))
+            .serve(app.into_make_service_with_connect_info::<PeerAddr>()),
+        handle,
+    ))
+}
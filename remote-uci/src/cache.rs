@@ -0,0 +1,293 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use shakmaty::{fen::Fen, variant::VariantPosition, EnPassantMode};
+
+use crate::uci::UciOut;
+
+/// Number of analyses to keep if only a cache file is given.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Identifies a search that gives the same result every time.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Engine name and the options that influence its analysis.
+    pub engine: String,
+    pub fen: String,
+    pub multipv: u32,
+    pub depth: u32,
+}
+
+impl CacheKey {
+    /// Key for a search of the position. Positions reached by different
+    /// moves share a key, as long as the FEN is the same.
+    pub fn new(engine: String, pos: &VariantPosition, multipv: u32, depth: u32) -> CacheKey {
+        CacheKey {
+            engine,
+            fen: Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string(),
+            multipv,
+            depth,
+        }
+    }
+}
+
+/// Final output of searches with a fixed depth, so that repeated analysis
+/// of the same positions, like common openings, does not need the engine.
+pub struct AnalysisCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    file: Option<Mutex<File>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    output: HashMap<CacheKey, Vec<UciOut>>,
+    /// Keys in order of insertion, to evict the oldest analysis first.
+    order: VecDeque<CacheKey>,
+}
+
+impl Entries {
+    fn insert(&mut self, capacity: usize, key: CacheKey, output: Vec<UciOut>) {
+        if self.output.insert(key.clone(), output).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.output.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl AnalysisCache {
+    /// Creates a cache, loading previous analysis from the file if given.
+    /// New analysis is appended to the file.
+    pub fn new(capacity: usize, path: Option<PathBuf>) -> io::Result<AnalysisCache> {
+        let mut entries = Entries::default();
+        let file = match path {
+            Some(path) => {
+                let stored = load(&path, capacity, &mut entries)?;
+                if stored > entries.order.len() {
+                    // Drop evicted and duplicate analysis.
+                    compact(&path, &entries)?;
+                }
+                tracing::info!(
+                    "Loaded {} cached analyses from {path:?}",
+                    entries.order.len()
+                );
+                Some(Mutex::new(
+                    OpenOptions::new().create(true).append(true).open(&path)?,
+                ))
+            }
+            None => None,
+        };
+        Ok(AnalysisCache {
+            capacity,
+            entries: Mutex::new(entries),
+            file,
+        })
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<UciOut>> {
+        self.entries
+            .lock()
+            .expect("analysis cache")
+            .output
+            .get(key)
+            .cloned()
+    }
+
+    pub fn insert(&self, key: CacheKey, output: Vec<UciOut>) {
+        if let Some(ref file) = self.file {
+            let mut file = file.lock().expect("analysis cache file");
+            if let Err(err) = writeln!(file, "{}", format_entry(&key, &output)) {
+                tracing::error!("Failed to write analysis cache: {err}");
+            }
+        }
+        self.entries
+            .lock()
+            .expect("analysis cache")
+            .insert(self.capacity, key, output);
+    }
+}
+
+/// Entries are stored one per line, with tab separated fields: engine,
+/// FEN, multipv, depth, and the lines of engine output.
+fn format_entry(key: &CacheKey, output: &[UciOut]) -> String {
+    let mut fields = vec![
+        key.engine.replace('\t', " "),
+        key.fen.clone(),
+        key.multipv.to_string(),
+        key.depth.to_string(),
+    ];
+    fields.extend(output.iter().map(ToString::to_string));
+    fields.join("\t")
+}
+
+fn parse_entry(line: &str) -> Option<(CacheKey, Vec<UciOut>)> {
+    let mut fields = line.split('\t');
+    let key = CacheKey {
+        engine: fields.next()?.to_owned(),
+        fen: fields.next()?.to_owned(),
+        multipv: fields.next()?.parse().ok()?,
+        depth: fields.next()?.parse().ok()?,
+    };
+    let output = fields
+        .map(|line| UciOut::from_line(line).ok().flatten())
+        .collect::<Option<Vec<_>>>()?;
+    matches!(output.last(), Some(UciOut::Bestmove { .. })).then(|| (key, output))
+}
+
+/// Reads the cache file, and returns the number of stored entries.
+fn load(path: &Path, capacity: usize, entries: &mut Entries) -> io::Result<usize> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut stored = 0;
+    for (i, line) in content.lines().enumerate() {
+        stored += 1;
+        match parse_entry(line) {
+            Some((key, output)) => entries.insert(capacity, key, output),
+            None => tracing::warn!("Ignoring invalid line {} of {path:?}", i + 1),
+        }
+    }
+    Ok(stored)
+}
+
+fn compact(path: &Path, entries: &Entries) -> io::Result<()> {
+    let mut content = String::new();
+    for key in &entries.order {
+        if let Some(output) = entries.output.get(key) {
+            content.push_str(&format_entry(key, output));
+            content.push('\n');
+        }
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{variant::Variant, CastlingMode};
+
+    use super::*;
+    use crate::uci::UciIn;
+
+    fn key(fen: &str) -> CacheKey {
+        CacheKey {
+            engine: "Stockfish 16".to_owned(),
+            fen: fen.to_owned(),
+            multipv: 1,
+            depth: 20,
+        }
+    }
+
+    fn output(bestmove: &str) -> Vec<UciOut> {
+        [
+            format!("info depth 20 score cp 30 pv {bestmove}"),
+            format!("bestmove {bestmove}"),
+        ]
+        .iter()
+        .map(|line| UciOut::from_line(line).expect("output").expect("not empty"))
+        .collect()
+    }
+
+    fn position(line: &str) -> VariantPosition {
+        UciIn::from_line(line)
+            .expect("command")
+            .expect("not empty")
+            .to_position(Variant::Chess, CastlingMode::Standard)
+            .expect("legal")
+            .expect("position")
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("remote-uci-test-{}-{name}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = AnalysisCache::new(2, None).expect("cache");
+        cache.insert(key("a"), output("e2e4"));
+        cache.insert(key("b"), output("d2d4"));
+        // Replacing does not count as another entry.
+        cache.insert(key("a"), output("c2c4"));
+        assert_eq!(cache.get(&key("a")), Some(output("c2c4")));
+        cache.insert(key("c"), output("g1f3"));
+        assert_eq!(cache.get(&key("a")), None, "oldest");
+        assert_eq!(cache.get(&key("b")), Some(output("d2d4")));
+        assert_eq!(cache.get(&key("c")), Some(output("g1f3")));
+        assert_eq!(
+            cache.get(&CacheKey {
+                depth: 21,
+                ..key("c")
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let new = |line: &str| CacheKey::new("Stockfish 16".to_owned(), &position(line), 1, 20);
+        assert_eq!(
+            new("position startpos moves e2e4 e7e5 d2d4 d7d5"),
+            new("position startpos moves d2d4 d7d5 e2e4 e7e5")
+        );
+        // En passant squares only matter if the capture is legal.
+        assert_eq!(
+            new("position startpos moves e2e4"),
+            new("position fen rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+        );
+        assert_ne!(
+            new("position startpos moves e2e4 d7d5 e4e5 f7f5"),
+            new("position fen rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3")
+        );
+        assert_ne!(
+            new("position startpos moves g1f3"),
+            new("position startpos moves e2e4")
+        );
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = temp_file("persistence");
+        {
+            let cache = AnalysisCache::new(10, Some(path.clone())).expect("cache");
+            cache.insert(key("a"), output("e2e4"));
+            cache.insert(key("b"), output("d2d4"));
+        }
+        let mut content = fs::read_to_string(&path).expect("cache file");
+        content.push_str("not an entry\n");
+        fs::write(&path, content).expect("write cache file");
+
+        let cache = AnalysisCache::new(10, Some(path.clone())).expect("cache");
+        assert_eq!(cache.get(&key("a")), Some(output("e2e4")));
+        cache.insert(key("d"), output("g1f3"));
+        drop(cache);
+
+        // Only the newest analyses are kept when loading into a smaller
+        // cache, and the file is compacted.
+        let cache = AnalysisCache::new(1, Some(path.clone())).expect("cache");
+        assert_eq!(cache.get(&key("a")), None);
+        assert_eq!(cache.get(&key("d")), Some(output("g1f3")));
+        assert_eq!(
+            fs::read_to_string(&path)
+                .expect("cache file")
+                .lines()
+                .count(),
+            1
+        );
+        let _ = fs::remove_file(path);
+    }
+}
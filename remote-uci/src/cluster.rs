@@ -0,0 +1,157 @@
+use std::{fmt, io, str::FromStr, time::Duration};
+
+use futures_util::{SinkExt as _, StreamExt as _};
+use rand::random;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader, DuplexStream},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+
+/// How long to wait for a worker to report its load.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Websocket endpoint of another remote-uci instance, like
+/// `ws://192.168.1.20:9670/socket?secret=...`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct WorkerUrl(Url);
+
+impl FromStr for WorkerUrl {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<WorkerUrl, &'static str> {
+        const EXPECTED: &str = "expected ws://HOST:PORT/PATH?secret=SECRET";
+        let url = Url::parse(s).map_err(|_| EXPECTED)?;
+        if url.scheme() != "ws"
+            || url.host_str().is_none()
+            || !url.query_pairs().any(|(key, _)| key == "secret")
+        {
+            return Err(EXPECTED);
+        }
+        Ok(WorkerUrl(url))
+    }
+}
+
+impl TryFrom<String> for WorkerUrl {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<WorkerUrl, &'static str> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for WorkerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Without the secret.
+        let mut url = self.0.clone();
+        url.set_query(None);
+        write!(f, "{url}")
+    }
+}
+
+impl WorkerUrl {
+    /// Number of sessions the worker is serving on the endpoint, from its
+    /// metrics.
+    async fn load(&self, http: &Client) -> reqwest::Result<Option<u64>> {
+        let mut url = self.0.clone();
+        url.set_query(None);
+        let path = url.path().to_owned();
+        url.set_path("/metrics");
+        let _ = url.set_scheme("http");
+        let metrics = http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let prefix = format!("remote_uci_active_sessions{{path=\"{path}\"}} ");
+        Ok(metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix))
+            .and_then(|sessions| sessions.trim().parse().ok()))
+    }
+
+    fn socket_url(&self) -> Url {
+        let mut url = self.0.clone();
+        if !url.query_pairs().any(|(key, _)| key == "session") {
+            url.query_pairs_mut()
+                .append_pair("session", &format!("cluster-{:016x}", random::<u64>()));
+        }
+        url
+    }
+}
+
+/// Connects to the worker that is serving the fewest sessions, and forwards
+/// the engine protocol over its websocket. Workers that do not respond are
+/// skipped.
+pub async fn connect(workers: &[WorkerUrl]) -> io::Result<DuplexStream> {
+    let http = Client::builder()
+        .timeout(LOAD_TIMEOUT)
+        .build()
+        .expect("cluster client");
+    let mut least_loaded = None;
+    for worker in workers {
+        match worker.load(&http).await {
+            Ok(Some(load)) => {
+                if least_loaded.map_or(true, |(_, least)| load < least) {
+                    least_loaded = Some((worker, load));
+                }
+            }
+            Ok(None) => tracing::warn!("Worker {worker} does not serve the engine endpoint"),
+            Err(err) => tracing::warn!("Could not get load of worker {worker}: {err}"),
+        }
+    }
+    let (worker, load) = least_loaded
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no worker is available"))?;
+    tracing::info!("Connecting to worker {worker} with {load} session(s) ...");
+    let (socket, _) = connect_async(worker.socket_url())
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::ConnectionRefused, err))?;
+    let (client, bridge) = tokio::io::duplex(64 * 1024);
+    let worker = worker.clone();
+    tokio::spawn(async move {
+        if let Err(err) = forward(socket, bridge).await {
+            tracing::error!("Connection to worker {worker} failed: {err}");
+        }
+    });
+    Ok(client)
+}
+
+/// Sends each line of the engine protocol as a text message, and the other
+/// way around, until either side closes.
+async fn forward(
+    mut socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    bridge: DuplexStream,
+) -> io::Result<()> {
+    let (read, mut write) = tokio::io::split(bridge);
+    let mut lines = BufReader::new(read).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => socket
+                    .send(Message::Text(line))
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?,
+                None => {
+                    let _ = socket.close(None).await;
+                    return Ok(());
+                }
+            },
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    write.write_all(text.as_bytes()).await?;
+                    write.write_all(b"\n").await?;
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => (),
+                Some(Err(err)) => return Err(io::Error::new(io::ErrorKind::BrokenPipe, err)),
+            },
+        }
+    }
+}
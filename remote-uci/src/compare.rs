@@ -0,0 +1,109 @@
+use tokio::sync::mpsc;
+
+use crate::{
+    engine::{Engine, EngineAddr, EngineParameters, Session},
+    uci::{UciIn, UciOut},
+};
+
+/// Second engine that analyses the same positions as the engine of a
+/// session, so that clients can compare evaluations. Its lines are
+/// delivered as info strings, tagged with its name.
+pub struct Comparison {
+    commands: mpsc::UnboundedSender<UciIn>,
+    pub output: mpsc::UnboundedReceiver<UciOut>,
+}
+
+impl Comparison {
+    /// Starts the engine in the background. It gets the same resource
+    /// limits, but none of the options of the first engine.
+    pub fn spawn(addr: EngineAddr, params: &EngineParameters) -> Comparison {
+        let params = EngineParameters {
+            options: Vec::new(),
+            compare: None,
+            standby: false,
+            cache: None,
+            book: None,
+            online_tb: None,
+            stats: None,
+            notify: false,
+            ..params.clone()
+        };
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (output_tx, output) = mpsc::unbounded_channel();
+        tokio::spawn(run(addr, params, commands_rx, output_tx));
+        Comparison { commands, output }
+    }
+
+    /// Passes on a command that was sent to the first engine, if it is
+    /// relevant for the analysis.
+    pub fn send(&self, command: &UciIn) {
+        let relevant = match command {
+            UciIn::Position { .. }
+            | UciIn::Go { .. }
+            | UciIn::Stop
+            | UciIn::Ponderhit
+            | UciIn::Ucinewgame => true,
+            UciIn::Setoption { name, .. } => *name == "MultiPV" || *name == "UCI_Chess960",
+            _ => false,
+        };
+        if relevant {
+            let _ = self.commands.send(command.clone());
+        }
+    }
+
+    /// Drops output that was not delivered before the search of the first
+    /// engine ended.
+    pub fn discard(&mut self) {
+        while self.output.try_recv().is_ok() {}
+    }
+}
+
+async fn run(
+    addr: EngineAddr,
+    params: EngineParameters,
+    mut commands: mpsc::UnboundedReceiver<UciIn>,
+    output: mpsc::UnboundedSender<UciOut>,
+) {
+    let session = Session(0);
+    let mut engine = match Engine::new(addr.clone(), params).await {
+        Ok(engine) => engine,
+        Err(err) => {
+            tracing::error!("Could not start engine {addr} for comparison: {err}");
+            return;
+        }
+    };
+    let name = engine.name().unwrap_or("engine-b").to_owned();
+    loop {
+        let res = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => {
+                    if engine.is_searching() && !matches!(command, UciIn::Stop | UciIn::Ponderhit) {
+                        // Still finishing the previous search.
+                        if let Err(err) = engine.ensure_idle(session).await {
+                            tracing::error!("Engine {addr} for comparison failed: {err}");
+                            return;
+                        }
+                    }
+                    engine.send(session, command).await
+                }
+                None => return,
+            },
+            out = engine.recv(session) => match out {
+                Ok(ref info @ UciOut::Info { score: Some(_), pv: Some(_), string: None, .. }) => {
+                    let line = info.to_string();
+                    let line = line.strip_prefix("info ").unwrap_or(&line);
+                    if output.send(UciOut::info_string(format!("{name}: {line}"))).is_err() {
+                        return;
+                    }
+                    Ok(())
+                }
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = res {
+            tracing::error!("Engine {addr} for comparison failed: {err}");
+            return;
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use std::{
+    cmp::max,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures_util::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Largest frame or decompressed message accepted from clients.
+const MAX_MESSAGE_SIZE: usize = 16 << 20;
+
+/// Empty block at the end of each compressed message, which is not
+/// transmitted (RFC 7692, section 7.2.1).
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const OPCODE: u8 = 0x0f;
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+
+/// Negotiated parameters of the permessage-deflate extension.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DeflateConfig {
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl DeflateConfig {
+    /// Accepts the first permessage-deflate offer in a
+    /// Sec-WebSocket-Extensions request header that can be supported.
+    pub fn negotiate(header: &str) -> Option<DeflateConfig> {
+        header.split(',').find_map(|offer| {
+            let mut params = offer.split(';').map(str::trim);
+            if params.next() != Some("permessage-deflate") {
+                return None;
+            }
+            let mut config = DeflateConfig {
+                server_no_context_takeover: false,
+                client_no_context_takeover: false,
+            };
+            for param in params {
+                match param.split_once('=').map_or(param, |(name, _)| name.trim()) {
+                    "server_no_context_takeover" => config.server_no_context_takeover = true,
+                    "client_no_context_takeover" => config.client_no_context_takeover = true,
+                    // Decompression always works with the largest window.
+                    "client_max_window_bits" => (),
+                    // Compressing with a smaller window is not supported.
+                    _ => return None,
+                }
+            }
+            Some(config)
+        })
+    }
+
+    /// Sec-WebSocket-Extensions response header.
+    pub fn response_header(&self) -> String {
+        let mut header = "permessage-deflate".to_owned();
+        if self.server_no_context_takeover {
+            header.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            header.push_str("; client_no_context_takeover");
+        }
+        header
+    }
+}
+
+/// A websocket frame, with the payload unmasked.
+struct Frame {
+    head: u8,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Parses the next complete frame from the buffer, returning it along
+    /// with the number of bytes it occupied.
+    fn parse(buf: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let masked = buf[1] & 0x80 != 0;
+        let (len, mut offset) = match buf[1] & 0x7f {
+            126 if buf.len() >= 4 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+            127 if buf.len() >= 10 => {
+                let mut len = [0; 8];
+                len.copy_from_slice(&buf[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            126 | 127 => return Ok(None),
+            len => (u64::from(len), 2),
+        };
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_MESSAGE_SIZE)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "websocket frame too large")
+            })?;
+        let mask = if masked {
+            if buf.len() < offset + 4 {
+                return Ok(None);
+            }
+            offset += 4;
+            Some([
+                buf[offset - 4],
+                buf[offset - 3],
+                buf[offset - 2],
+                buf[offset - 1],
+            ])
+        } else {
+            None
+        };
+        if buf.len() < offset + len {
+            return Ok(None);
+        }
+        let mut payload = buf[offset..offset + len].to_vec();
+        if let Some(mask) = mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        Ok(Some((
+            Frame {
+                head: buf[0],
+                payload,
+            },
+            offset + len,
+        )))
+    }
+
+    fn opcode(&self) -> u8 {
+        self.head & OPCODE
+    }
+
+    fn is_fin(&self) -> bool {
+        self.head & FIN != 0
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.head & RSV1 != 0
+    }
+
+    /// Serializes the frame. Client frames are masked with an all-zero key,
+    /// which leaves the payload unchanged.
+    fn write(&self, masked: bool, out: &mut Vec<u8>) {
+        out.push(self.head);
+        let mask_bit = if masked { 0x80 } else { 0 };
+        match self.payload.len() {
+            len if len < 126 => out.push(mask_bit | len as u8),
+            len if len <= usize::from(u16::MAX) => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        if masked {
+            out.extend_from_slice(&[0; 4]);
+        }
+        out.extend_from_slice(&self.payload);
+    }
+}
+
+struct Codec {
+    config: DeflateConfig,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Codec {
+    fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let before = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - before) as usize;
+            out.reserve(max(64, data.len() - consumed));
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            if (self.compress.total_in() - before) as usize == data.len()
+                && out.len() < out.capacity()
+            {
+                break;
+            }
+        }
+        if out.ends_with(&TRAILER) {
+            out.truncate(out.len() - TRAILER.len());
+        }
+        if self.config.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+
+    fn decompress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = Vec::with_capacity(data.len() + TRAILER.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TRAILER);
+        let mut out = Vec::with_capacity(data.len() * 2 + 64);
+        let before = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - before) as usize;
+            let produced = out.len();
+            out.reserve(max(256, input.len() - consumed));
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if out.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decompressed message too large",
+                ));
+            }
+            let done = (self.decompress.total_in() - before) as usize == input.len();
+            if (done && out.len() < out.capacity())
+                || (out.len() == produced
+                    && (self.decompress.total_in() - before) as usize == consumed)
+            {
+                break;
+            }
+        }
+        if self.config.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+}
+
+/// Applies permessage-deflate to the raw frames of a server-side websocket
+/// connection. Incoming compressed messages are decompressed before they
+/// reach the websocket implementation, and outgoing data frames are
+/// compressed. Without a negotiated config, bytes are passed through as is.
+pub struct DeflateStream<S> {
+    inner: S,
+    codec: Option<Codec>,
+    read_raw: Vec<u8>,
+    read_out: Vec<u8>,
+    read_pos: usize,
+    fragments: Option<Frame>,
+    write_raw: Vec<u8>,
+    write_out: Vec<u8>,
+    write_pos: usize,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S, config: Option<DeflateConfig>) -> DeflateStream<S> {
+        DeflateStream {
+            inner,
+            codec: config.map(|config| Codec {
+                config,
+                compress: Compress::new(Compression::default(), false),
+                decompress: Decompress::new(false),
+            }),
+            read_raw: Vec::new(),
+            read_out: Vec::new(),
+            read_pos: 0,
+            fragments: None,
+            write_raw: Vec::new(),
+            write_out: Vec::new(),
+            write_pos: 0,
+        }
+    }
+
+    /// Decompresses an incoming frame, or buffers it until the message is
+    /// complete.
+    fn inbound(&mut self, frame: Frame, raw: &[u8]) -> io::Result<()> {
+        let codec = self.codec.as_mut().expect("codec");
+        let mut message = match (frame.opcode(), self.fragments.take()) {
+            (TEXT | BINARY, None) if frame.is_compressed() => frame,
+            (CONTINUATION, Some(mut message)) => {
+                if message.payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "compressed message too large",
+                    ));
+                }
+                message.payload.extend_from_slice(&frame.payload);
+                message.head = (message.head & !FIN) | (frame.head & FIN);
+                message
+            }
+            (_, fragments) => {
+                // Control frames may be interleaved with fragments.
+                self.fragments = fragments;
+                self.read_out.extend_from_slice(raw);
+                return Ok(());
+            }
+        };
+        if !message.is_fin() {
+            self.fragments = Some(message);
+            return Ok(());
+        }
+        message.payload = codec.decompress(&message.payload)?;
+        message.head &= !RSV1;
+        message.write(true, &mut self.read_out);
+        Ok(())
+    }
+
+    /// Compresses an outgoing frame, unless it is a control frame or part of
+    /// a fragmented message.
+    fn outbound(&mut self, mut frame: Frame, raw: &[u8]) -> io::Result<()> {
+        let codec = self.codec.as_mut().expect("codec");
+        if frame.is_fin() && matches!(frame.opcode(), TEXT | BINARY) {
+            frame.payload = codec.compress(&frame.payload)?;
+            frame.head |= RSV1;
+            frame.write(false, &mut self.write_out);
+        } else {
+            self.write_out.extend_from_slice(raw);
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_out.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.write_out[self.write_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_out.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.codec.is_none() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        loop {
+            if this.read_pos < this.read_out.len() {
+                let n = (this.read_out.len() - this.read_pos).min(buf.remaining());
+                buf.put_slice(&this.read_out[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            this.read_out.clear();
+            this.read_pos = 0;
+
+            if let Some((frame, len)) = Frame::parse(&this.read_raw)? {
+                let raw: Vec<u8> = this.read_raw.drain(..len).collect();
+                this.inbound(frame, &raw)?;
+                continue;
+            }
+
+            let mut chunk = [0; 4096];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.read_raw.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.codec.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        // Apply backpressure once the client falls behind.
+        if this.write_out.len() - this.write_pos >= 64 * 1024 {
+            ready!(this.poll_drain(cx))?;
+        }
+        this.write_raw.extend_from_slice(buf);
+        while let Some((frame, len)) = Frame::parse(&this.write_raw)? {
+            let raw: Vec<u8> = this.write_raw.drain(..len).collect();
+            this.outbound(frame, &raw)?;
+        }
+        if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    use super::*;
+
+    fn codec(config: DeflateConfig) -> Codec {
+        Codec {
+            config,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let default = DeflateConfig {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        };
+        assert_eq!(
+            DeflateConfig::negotiate("permessage-deflate"),
+            Some(default.clone())
+        );
+        assert_eq!(
+            DeflateConfig::negotiate("permessage-deflate; client_max_window_bits"),
+            Some(default.clone())
+        );
+        assert_eq!(
+            DeflateConfig::negotiate("permessage-deflate; client_max_window_bits=10"),
+            Some(default.clone())
+        );
+        assert_eq!(
+            DeflateConfig::negotiate(
+                "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+            ),
+            Some(DeflateConfig {
+                server_no_context_takeover: true,
+                client_no_context_takeover: true,
+            })
+        );
+        assert_eq!(
+            DeflateConfig::negotiate("permessage-deflate; server_max_window_bits=10"),
+            None
+        );
+        assert_eq!(
+            DeflateConfig::negotiate(
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate; client_no_context_takeover"
+            ),
+            Some(DeflateConfig {
+                server_no_context_takeover: false,
+                client_no_context_takeover: true,
+            })
+        );
+        assert_eq!(DeflateConfig::negotiate("x-webkit-deflate-frame"), None);
+        assert_eq!(DeflateConfig::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_response_header() {
+        let config = DeflateConfig::negotiate(
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover",
+        )
+        .expect("supported");
+        assert_eq!(
+            config.response_header(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+        let config = DeflateConfig::negotiate("permessage-deflate; client_max_window_bits")
+            .expect("supported");
+        assert_eq!(config.response_header(), "permessage-deflate");
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let message =
+            b"info depth 20 seldepth 28 multipv 1 score cp 31 nodes 1234567 pv e2e4 e7e5 g1f3";
+        for no_context_takeover in [false, true] {
+            let config = DeflateConfig {
+                server_no_context_takeover: no_context_takeover,
+                client_no_context_takeover: no_context_takeover,
+            };
+            let (mut server, mut client) = (codec(config.clone()), codec(config));
+            let first = server.compress(message)?;
+            assert!(!first.ends_with(&TRAILER));
+            let second = server.compress(message)?;
+            if no_context_takeover {
+                // Each message is compressed on its own.
+                assert_eq!(first, second);
+            } else {
+                // The second message refers back to the first.
+                assert!(second.len() < first.len());
+            }
+            assert_eq!(client.decompress(&first)?, message);
+            assert_eq!(client.decompress(&second)?, message);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_too_large() {
+        let mut compress = Compress::new(Compression::best(), false);
+        let mut data = Vec::with_capacity(MAX_MESSAGE_SIZE);
+        compress
+            .compress_vec(
+                &vec![b'a'; MAX_MESSAGE_SIZE + 1],
+                &mut data,
+                FlushCompress::Sync,
+            )
+            .expect("compress");
+        let mut codec = codec(DeflateConfig::negotiate("permessage-deflate").expect("config"));
+        assert!(codec.decompress(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> io::Result<()> {
+        let config = DeflateConfig::negotiate("permessage-deflate").expect("config");
+        let (inner, mut client) = tokio::io::duplex(64 * 1024);
+        let mut stream = DeflateStream::new(inner, Some(config.clone()));
+        let mut peer = codec(config);
+
+        // Outgoing text frames are compressed.
+        let mut raw = Vec::new();
+        Frame {
+            head: FIN | TEXT,
+            payload: b"bestmove e2e4".to_vec(),
+        }
+        .write(false, &mut raw);
+        stream.write_all(&raw).await?;
+        stream.flush().await?;
+        let mut buf = vec![0; 1024];
+        let n = client.read(&mut buf).await?;
+        let (frame, len) = Frame::parse(&buf[..n])?.expect("complete frame");
+        assert_eq!(len, n);
+        assert!(frame.is_compressed());
+        assert_eq!(peer.decompress(&frame.payload)?, b"bestmove e2e4");
+
+        // Incoming compressed messages are decompressed, also when they
+        // are fragmented.
+        let payload = peer.compress(b"go infinite")?;
+        let (head, tail) = payload.split_at(payload.len() / 2);
+        let mut raw = Vec::new();
+        Frame {
+            head: RSV1 | TEXT,
+            payload: head.to_vec(),
+        }
+        .write(true, &mut raw);
+        Frame {
+            head: FIN | CONTINUATION,
+            payload: tail.to_vec(),
+        }
+        .write(true, &mut raw);
+        client.write_all(&raw).await?;
+        let n = stream.read(&mut buf).await?;
+        let (frame, _) = Frame::parse(&buf[..n])?.expect("complete frame");
+        assert!(frame.is_fin() && !frame.is_compressed());
+        assert_eq!(frame.opcode(), TEXT);
+        assert_eq!(frame.payload, b"go infinite");
+        Ok(())
+    }
+}
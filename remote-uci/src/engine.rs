@@ -1,11 +1,57 @@
-use std::{collections::HashMap, io, path::PathBuf, process::Stdio};
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt, future, io, mem,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    sync::{atomic::Ordering, Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    uci::Uci,
+    variant::{Variant, VariantPosition},
+    CastlingMode, Chess, Position as _,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
-    process::{ChildStdin, ChildStdout, Command},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadBuf},
+    net::TcpStream,
+    process::{Child, Command},
+    sync::broadcast,
+    task::JoinHandle,
+    time::{sleep, timeout},
 };
 
-use crate::uci::{UciIn, UciOption, UciOptionName, UciOut};
+use crate::{
+    affinity::{self, CpuSet},
+    book::{self, BookMode, BookMove, OpeningBook},
+    cache::{AnalysisCache, CacheKey},
+    cluster::{self, WorkerUrl},
+    compare::Comparison,
+    gpu::GpuBackend,
+    metrics::EngineMetrics,
+    mock::MockEngine,
+    notify,
+    numa::{self, NumaPolicy},
+    online_tb::OnlineTablebase,
+    power::{self, BatteryLimits},
+    priority::{self, ProcessPriority},
+    sandbox::{self, SandboxPolicy},
+    split::SplitPool,
+    stats::{Progress, Stats},
+    thermal::ThermalGuard,
+    uci::{
+        self, OptionPolicy, ProtocolError, SearchLimits, UciIn, UciOption, UciOptionName, UciOut,
+    },
+    variant, wdl,
+    xboard::{Protocol, Xboard},
+};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Session(pub u64);
@@ -17,120 +63,1057 @@ pub struct Engine {
     options: HashMap<UciOptionName, UciOption>,
     name: Option<String>,
     params: EngineParameters,
-    stdin: BufWriter<ChildStdin>,
-    stdout: BufReader<ChildStdout>,
+    addr: EngineAddr,
+    process: Option<Child>,
+    stdin: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    stdout: BufReader<Box<dyn AsyncRead + Send + Unpin>>,
+    /// Partial line from stdout, kept if reading is interrupted.
+    stdout_buf: Vec<u8>,
+    xboard: Option<Xboard>,
+    replay: Replay,
+    /// Position of the current analysis, to validate principal variations.
+    pv_position: Option<VariantPosition>,
+    /// Game ply of the current analysis, to estimate win/draw/loss
+    /// statistics.
+    ply: u32,
+    /// Output of a cached analysis, still to be delivered.
+    cached: VecDeque<UciOut>,
+    /// Search whose final output will be added to the cache.
+    recording: Option<Recording>,
+    /// Book moves and online tablebase results, still to be delivered
+    /// before the output of the engine.
+    notes: VecDeque<UciOut>,
+    metrics: Arc<EngineMetrics>,
+    status: Arc<Mutex<EngineStatus>>,
+    observers: broadcast::Sender<Observation>,
+    suspended: bool,
+    last_active: Instant,
+    /// Whether battery limits are in effect.
+    on_battery: bool,
+    /// Whether threads are reduced because the processor is too hot.
+    throttled: bool,
+    /// Threads requested while the processor was too hot.
+    restore_threads: Option<UciIn>,
+    /// Task that collects diagnostics from the stderr of the process.
+    stderr_reader: Option<JoinHandle<()>>,
+    /// Second engine that analyses the same positions.
+    comparison: Option<Comparison>,
+    /// Processes that share MultiPV searches.
+    split: Option<SplitPool>,
+    /// Process that is prepared to replace the engine process.
+    standby: Option<JoinHandle<io::Result<Standby>>>,
+    /// Work of the current search, for statistics.
+    progress: Option<Progress>,
+}
+
+/// What spectators see of the analysis of the current session.
+#[derive(Clone, Debug)]
+pub enum Observation {
+    Position(UciIn),
+    Info(UciOut),
+}
+
+/// Snapshot of the engine state, readable while a session holds the engine.
+#[derive(Default, Clone, Debug, Serialize)]
+pub struct EngineStatus {
+    pub name: Option<String>,
+    pub session: Option<u64>,
+    pub position: Option<String>,
+    pub depth: Option<u32>,
+    pub searching: bool,
+    pub pondering: bool,
+    pub suspended: bool,
+    /// Whether the process is paused while the local user is active.
+    pub paused: bool,
+    /// Whether the engine is a local process that can be paused. Not
+    /// engines in containers, on other machines or under wine, where the
+    /// process is only a client.
+    #[serde(skip)]
+    pub pausable: bool,
+    /// Average round trip time to the client of the session.
+    pub latency_ms: Option<u64>,
+    /// File name of the NNUE network the engine evaluates with.
+    pub network: Option<String>,
+    pub options: Vec<OptionStatus>,
+    /// Last lines the engine printed to stderr.
+    pub stderr: VecDeque<String>,
+    /// Options as announced to clients, in the order of the engine.
+    #[serde(skip)]
+    pub announced: Vec<AnnouncedOption>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OptionStatus {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// An option of the engine, like `{"name": "Clear Hash", "type": "button"}`.
+#[derive(Clone, Debug, Serialize)]
+pub struct AnnouncedOption {
+    pub name: String,
+    #[serde(flatten)]
+    pub option: UciOption,
+    /// Current value, unless the option is a button.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// Whether clients are allowed to set the option.
+    pub settable: bool,
+}
+
+/// When to clear the hash table of the engine.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashPolicy {
+    /// Never clear the hash table, so that positions analysed in earlier
+    /// sessions are analysed faster.
+    Keep,
+    /// Clear the hash table when a session starts.
+    ClearPerSession,
+    /// Clear the hash table when a session starts, and whenever the client
+    /// starts a new game.
+    #[default]
+    ClearPerGame,
 }
 
+#[derive(Clone, Default)]
 pub struct EngineParameters {
     pub max_threads: u32,
     pub max_hash: u32,
+    pub options: Vec<(UciOptionName, Option<String>)>,
+    /// GPU to select for engines with a Backend option, unless the backend
+    /// is configured explicitly.
+    pub gpu: Option<GpuBackend>,
+    /// Limits for searches requested by clients.
+    pub limits: SearchLimits,
+    /// Options that clients may set.
+    pub option_policy: OptionPolicy,
+    /// Variants to serve, by lichess or engine name. Empty to serve all
+    /// variants of the engine.
+    pub variants: Vec<String>,
+    /// Whether to cut principal variations at the first illegal move.
+    pub validate_pv: bool,
+    /// Whether to show desktop notifications about sessions and crashes.
+    pub notify: bool,
+    /// Whether to report win/draw/loss statistics with scores.
+    pub wdl: bool,
+    /// Whether to rescale scores of engines with a known, different scale.
+    pub normalize_scores: bool,
+    /// Final output of previous searches with a fixed depth.
+    pub cache: Option<Arc<AnalysisCache>>,
+    /// Second engine to analyse the same positions, for comparison.
+    pub compare: Option<EngineAddr>,
+    /// Whether to keep a second engine process initialized, to replace the
+    /// engine process without delay if it fails.
+    pub standby: bool,
+    /// Number of additional processes to search the lines of MultiPV
+    /// analysis in parallel.
+    pub split_multipv: Option<usize>,
+    /// Opening book to answer searches from.
+    pub book: Option<Arc<OpeningBook>>,
+    /// Exact results for positions beyond the local tablebases.
+    pub online_tb: Option<Arc<OnlineTablebase>>,
+    /// Statistics of the work done for clients.
+    pub stats: Option<Arc<Stats>>,
+    pub hash_policy: HashPolicy,
+    /// Whether to make every search of the same position give the same
+    /// result, at the cost of speed.
+    pub deterministic: bool,
+    /// Protocol spoken by the engine process.
+    pub protocol: Protocol,
+    /// Restrictions for engine processes started locally.
+    pub sandbox: SandboxPolicy,
+    /// Working directory, arguments and environment of engine processes
+    /// started locally.
+    pub launch: Launch,
+    /// Scheduling priority of engine processes started locally.
+    pub priority: ProcessPriority,
+    /// Processors for engine processes started locally.
+    pub cpus: Option<CpuSet>,
+    /// Placement of engine processes started locally on NUMA machines.
+    pub numa: Option<NumaPolicy>,
+    /// Lower limits while running on battery.
+    pub battery: Option<BatteryLimits>,
+    /// Restart engines that produce no output for this long while they are
+    /// expected to.
+    pub engine_timeout: Option<Duration>,
+    /// Time to leave per move in addition to the network latency, when
+    /// clients play with clock times.
+    pub move_overhead: Option<Duration>,
+    /// Most lines that clients may analyse at the same time.
+    pub max_multipv: Option<u32>,
+    /// Lines to analyse when a session starts.
+    pub default_multipv: Option<u32>,
+    /// How long to wait for the engine to initialize, and how often to
+    /// try again.
+    pub init_timeout: Option<Duration>,
+    pub init_retries: u32,
+    /// Fewer threads while the processor is too hot.
+    pub thermal: Option<Arc<ThermalGuard>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Launch {
+    /// Working directory, instead of the current directory.
+    pub cwd: Option<PathBuf>,
+    pub args: Vec<String>,
+    /// Environment variables in addition to the inherited ones.
+    pub env: Vec<(String, String)>,
+}
+
+impl Launch {
+    fn apply(&self, command: &mut Command) {
+        command.args(&self.args);
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+    }
+}
+
+/// Engine parameters that can be changed at runtime, by reloading the
+/// config file.
+#[derive(Clone)]
+pub struct Reconfiguration {
+    pub options: Vec<(UciOptionName, Option<String>)>,
+    pub limits: SearchLimits,
+    pub option_policy: OptionPolicy,
+}
+
+/// State that is restored after the engine process has been restarted.
+#[derive(Default)]
+struct Replay {
+    setoptions: Vec<UciIn>,
+    position: Option<UciIn>,
+    go: Option<UciIn>,
+    recovering: bool,
+    /// Why the engine was restarted, to inform the client.
+    restarted: Option<&'static str>,
+    /// Last lines of stderr before the restart, to inform the client.
+    crash_report: VecDeque<String>,
+}
+
+/// Engine process that has been started, has answered `uci`, has the
+/// options of the engine set, and has answered `isready`.
+struct Standby {
+    connection: Connection,
+    setoptions: Vec<UciIn>,
+}
+
+impl Standby {
+    async fn prepare(
+        addr: EngineAddr,
+        params: EngineParameters,
+        setoptions: Vec<UciIn>,
+    ) -> io::Result<Standby> {
+        let (process, mut stdin, mut stdout) = connect(&addr, &params).await?;
+        let mut commands = vec![UciIn::Uci.to_string()];
+        commands.extend(setoptions.iter().map(ToString::to_string));
+        commands.push(UciIn::Isready.to_string());
+        for mut buf in commands {
+            buf.push_str("\r\n");
+            stdin.write_all(buf.as_bytes()).await?;
+        }
+        stdin.flush().await?;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if stdout.read_until(b'\n', &mut buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if let Ok(Some(UciOut::Readyok)) = UciOut::from_line(&uci::decode_line(&buf)) {
+                break;
+            }
+        }
+        Ok(Standby {
+            connection: (process, stdin, stdout),
+            setoptions,
+        })
+    }
+}
+
+/// Last info of each line of a search, as sent by the engine.
+struct Recording {
+    key: CacheKey,
+    lines: BTreeMap<u32, UciOut>,
+}
+
+/// Output that arrived while searching.
+enum Read {
+    /// Number of bytes read from the engine process.
+    Engine(usize),
+    /// Line of a split search.
+    Split(Box<UciOut>),
+}
+
+/// Where to find an engine.
+#[derive(Clone, Debug)]
+pub enum EngineAddr {
+    /// Executable to start as a child process.
+    Process(PathBuf),
+    /// Address of a server that speaks the engine protocol over TCP.
+    Tcp(String),
+    /// Executable to start on another machine, by running `ssh`.
+    Ssh { destination: String, path: String },
+    /// Windows executable to start as a child process under wine.
+    Wine(PathBuf),
+    /// Image to run in a Docker container, by running `docker`.
+    Docker(String),
+    /// Other remote-uci instances, of which each session uses the least
+    /// loaded.
+    Cluster(Vec<WorkerUrl>),
+    /// Built-in demo engine.
+    Mock,
+}
+
+impl fmt::Display for EngineAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineAddr::Process(path) => write!(f, "{path:?}"),
+            EngineAddr::Tcp(addr) => write!(f, "tcp://{addr}"),
+            EngineAddr::Ssh { destination, path } => write!(f, "{destination}:{path}"),
+            EngineAddr::Wine(path) => write!(f, "wine {path:?}"),
+            EngineAddr::Docker(image) => write!(f, "docker:{image}"),
+            EngineAddr::Cluster(workers) => write!(f, "cluster of {} workers", workers.len()),
+            EngineAddr::Mock => f.write_str("demo engine"),
+        }
+    }
+}
+
+/// Number of attempts to connect to an engine over TCP, before giving up.
+const TCP_CONNECT_ATTEMPTS: u32 = 5;
+
+/// Memory for engines in containers in addition to the largest hash table
+/// clients may use, in MiB.
+const DOCKER_MEMORY_OVERHEAD_MB: u32 = 256;
+
+/// Number of lines of stderr to keep for diagnostics.
+const STDERR_LINES: usize = 20;
+
+type Connection = (
+    Option<Child>,
+    BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    BufReader<Box<dyn AsyncRead + Send + Unpin>>,
+);
+
+async fn connect(addr: &EngineAddr, params: &EngineParameters) -> io::Result<Connection> {
+    match addr {
+        EngineAddr::Process(path) => {
+            tracing::info!("Starting engine {path:?} ...");
+            // Relative to the current directory, not to --engine-cwd.
+            let path = match params.launch.cwd {
+                Some(_) if path.components().count() > 1 => path.canonicalize()?,
+                _ => path.clone(),
+            };
+            let mut command = Command::new(&path);
+            params.launch.apply(&mut command);
+            sandbox::apply(&mut command, &path, &params.sandbox)?;
+            priority::apply(&mut command, params.priority);
+            if let Some(numa) = params.numa {
+                numa::apply(&mut command, numa)?;
+            }
+            if let Some(ref cpus) = params.cpus {
+                affinity::apply(&mut command, cpus);
+            }
+            spawn(command)
+        }
+        EngineAddr::Ssh { destination, path } => {
+            tracing::info!("Starting engine {path:?} on {destination} ...");
+            let mut command = Command::new("ssh");
+            // Never prompt for passwords or host keys, because stdin is used
+            // for the engine protocol.
+            command
+                .args(["-T", "-o", "BatchMode=yes", "--"])
+                .arg(destination)
+                .arg(path);
+            spawn(command)
+        }
+        EngineAddr::Wine(path) => {
+            tracing::info!("Starting engine {path:?} under wine ...");
+            sandbox::skip(&params.sandbox, "wine")?;
+            // Windows engines look for their files next to the executable.
+            // Line endings are normalized when reading and writing anyway.
+            let path = path.canonicalize()?;
+            let mut command = Command::new("wine");
+            command.arg(&path).env("WINEDEBUG", "-all");
+            if let Some(dir) = path.parent() {
+                command.current_dir(dir);
+            }
+            params.launch.apply(&mut command);
+            priority::apply(&mut command, params.priority);
+            if let Some(numa) = params.numa {
+                numa::apply(&mut command, numa)?;
+            }
+            if let Some(ref cpus) = params.cpus {
+                affinity::apply(&mut command, cpus);
+            }
+            spawn(command)
+        }
+        EngineAddr::Docker(image) => {
+            tracing::info!("Starting engine in container {image} ...");
+            let mut command = Command::new("docker");
+            // No network, no writable files, and no more processors and
+            // memory than clients may use. Tablebases and network files are
+            // mounted read-only at the same paths.
+            command
+                .args([
+                    "run",
+                    "--rm",
+                    "--interactive",
+                    "--init",
+                    "--network=none",
+                    "--read-only",
+                    "--cap-drop=ALL",
+                    "--security-opt=no-new-privileges",
+                ])
+                .arg(format!("--cpus={}", params.max_threads))
+                .arg(format!(
+                    "--memory={}m",
+                    params.max_hash.saturating_add(DOCKER_MEMORY_OVERHEAD_MB)
+                ));
+            for path in &params.sandbox.paths {
+                let path = path
+                    .to_str()
+                    .filter(|path| !path.contains(','))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("can not mount {path:?} into container"),
+                        )
+                    })?;
+                command.arg(format!("--mount=type=bind,src={path},dst={path},readonly"));
+            }
+            // Named, because stopping `docker run` does not stop the
+            // container.
+            let name = format!("remote-uci-{:016x}", random::<u64>());
+            command.arg(format!("--name={name}")).arg(image);
+            let (process, stdin, stdout) = spawn(command)?;
+            Ok((
+                process,
+                stdin,
+                BufReader::new(Box::new(ContainerOutput {
+                    name,
+                    stdout: stdout.into_inner(),
+                })),
+            ))
+        }
+        EngineAddr::Tcp(addr) => {
+            tracing::info!("Connecting to engine at {addr} ...");
+
+            let mut attempt = 1;
+            let stream = loop {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => break stream,
+                    Err(err) if attempt < TCP_CONNECT_ATTEMPTS => {
+                        tracing::warn!("Could not connect to engine (attempt {attempt}): {err}");
+                        sleep(Duration::from_millis(500) * 2u32.pow(attempt - 1)).await;
+                        attempt += 1;
+                    }
+                    Err(err) => return Err(err),
+                }
+            };
+            stream.set_nodelay(true)?;
+            let (read, write) = stream.into_split();
+            Ok((
+                None,
+                BufWriter::new(Box::new(write)),
+                BufReader::new(Box::new(read)),
+            ))
+        }
+        EngineAddr::Cluster(workers) => {
+            let (read, write) = tokio::io::split(cluster::connect(workers).await?);
+            Ok((
+                None,
+                BufWriter::new(Box::new(write)),
+                BufReader::new(Box::new(read)),
+            ))
+        }
+        EngineAddr::Mock => {
+            tracing::info!("Starting demo engine ...");
+            let (read, write) = tokio::io::split(MockEngine::spawn());
+            Ok((
+                None,
+                BufWriter::new(Box::new(write)),
+                BufReader::new(Box::new(read)),
+            ))
+        }
+    }
+}
+
+fn spawn(mut command: Command) -> io::Result<Connection> {
+    let mut process = command
+        .stdout(Stdio::piped())
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdin = process
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "engine stdin closed"))?;
+    let stdout = process
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "engine stdout closed"))?;
+    Ok((
+        Some(process),
+        BufWriter::new(Box::new(stdin)),
+        BufReader::new(Box::new(stdout)),
+    ))
+}
+
+/// Output of an engine in a container. The container is removed when the
+/// output is dropped, like when the engine is restarted or suspended.
+struct ContainerOutput {
+    name: String,
+    stdout: Box<dyn AsyncRead + Send + Unpin>,
+}
+
+impl AsyncRead for ContainerOutput {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl Drop for ContainerOutput {
+    fn drop(&mut self) {
+        tracing::debug!("Removing container {} ...", self.name);
+        let mut command = std::process::Command::new("docker");
+        command
+            .args(["rm", "--force", &self.name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if tokio::runtime::Handle::try_current().is_ok() {
+            // Reaped by the runtime.
+            let _ = Command::from(command).spawn();
+        } else {
+            let _ = command.status();
+        }
+    }
+}
+
+/// Whether the engine process exited or the connection to it was lost.
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
 }
 
 impl Engine {
-    pub async fn new(path: PathBuf, params: EngineParameters) -> io::Result<Engine> {
-        log::info!("Starting engine {path:?} ...");
-
-        let mut process = Command::new(path)
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .spawn()?;
-
-        let mut engine =
-            Engine {
-                pending_uciok: 0,
-                pending_readyok: 0,
-                searching: false,
-                options: HashMap::new(),
-                name: None,
-                params,
-                stdin: BufWriter::new(process.stdin.take().ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::BrokenPipe, "engine stdin closed")
-                })?),
-                stdout: BufReader::new(process.stdout.take().ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::BrokenPipe, "engine stdout closed")
-                })?),
+    /// Starts the engine and waits until it is ready. Engines that take too
+    /// long or exit during initialization are tried again, as configured.
+    pub async fn new(addr: EngineAddr, params: EngineParameters) -> io::Result<Engine> {
+        let mut attempt = 0;
+        loop {
+            let res = match params.init_timeout {
+                Some(limit) => timeout(limit, Engine::start(addr.clone(), params.clone()))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "engine did not initialize within {} (use --init-timeout)",
+                                humantime::format_duration(limit)
+                            ),
+                        ))
+                    }),
+                None => Engine::start(addr.clone(), params.clone()).await,
             };
+            match res {
+                Err(err)
+                    if attempt < params.init_retries
+                        && (err.kind() == io::ErrorKind::TimedOut || is_disconnect(&err)) =>
+                {
+                    attempt += 1;
+                    tracing::warn!("Failed to initialize engine (attempt {attempt}): {err}");
+                }
+                Err(err) if attempt > 0 => {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("{err} (after {} attempts)", attempt + 1),
+                    ))
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn start(addr: EngineAddr, params: EngineParameters) -> io::Result<Engine> {
+        let (process, stdin, stdout) = connect(&addr, &params).await?;
+        let on_battery = params.battery.is_some() && power::on_battery();
+        if on_battery {
+            tracing::info!("Running on battery, reducing engine limits");
+        }
+
+        let mut engine = Engine {
+            pending_uciok: 0,
+            pending_readyok: 0,
+            searching: false,
+            options: HashMap::new(),
+            name: None,
+            params,
+            addr,
+            process,
+            stdin,
+            stdout,
+            stdout_buf: Vec::new(),
+            xboard: None,
+            replay: Replay::default(),
+            pv_position: None,
+            ply: 0,
+            cached: VecDeque::new(),
+            recording: None,
+            notes: VecDeque::new(),
+            metrics: Arc::default(),
+            status: Arc::default(),
+            observers: broadcast::channel(64).0,
+            suspended: false,
+            last_active: Instant::now(),
+            on_battery,
+            throttled: false,
+            restore_threads: None,
+            stderr_reader: None,
+            comparison: None,
+            split: None,
+            standby: None,
+            progress: None,
+        };
+        engine.update_pid();
+        engine.capture_stderr();
+        engine.reset_protocol();
 
         let session = Session(0);
         engine.send(session, UciIn::Uci).await?;
         engine.ensure_idle(session).await?;
+
+        let mut options = engine.params.options.clone();
+        if let Some(backend) = engine.gpu_backend() {
+            tracing::info!("Selecting engine backend {backend}");
+            options.push((UciOptionName("Backend".to_owned()), Some(backend)));
+        }
+        let show_wdl = UciOptionName("UCI_ShowWDL".to_owned());
+        if engine.params.wdl
+            && engine.options.contains_key(&show_wdl)
+            && !options.iter().any(|(name, _)| *name == show_wdl)
+        {
+            options.push((show_wdl, Some("true".to_owned())));
+        }
+        let numa_policy = UciOptionName("NumaPolicy".to_owned());
+        if let Some(numa) = engine.params.numa {
+            if engine.options.contains_key(&numa_policy)
+                && !options.iter().any(|(name, _)| *name == numa_policy)
+            {
+                options.push((numa_policy, Some(numa.engine_option().to_owned())));
+            }
+        }
+        if engine.params.deterministic {
+            options.retain(|(name, value)| {
+                let keep = name.is_deterministic() || *name == "Hash";
+                if !keep {
+                    tracing::warn!("Ignoring option {name} in deterministic mode: {value:?}");
+                }
+                keep
+            });
+            let threads = UciOptionName("Threads".to_owned());
+            if engine.options.contains_key(&threads) {
+                options.push((threads, Some("1".to_owned())));
+            }
+        }
+        for (name, value) in options {
+            if !engine.options.contains_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("engine does not support option {name}"),
+                ));
+            }
+            engine
+                .send_dangerous(session, UciIn::Setoption { name, value })
+                .await?;
+        }
+        engine.send(session, UciIn::Isready).await?;
+        engine.ensure_idle(session).await?;
+
+        if let Some(addr) = engine.params.compare.clone() {
+            engine.comparison = Some(Comparison::spawn(addr, &engine.params));
+        }
+        if let Some(processes) = engine.params.split_multipv.filter(|&n| n > 1) {
+            engine.split = Some(SplitPool::spawn(
+                engine.addr.clone(),
+                &engine.params,
+                processes,
+            ));
+        }
+        engine.prepare_standby();
         Ok(engine)
     }
 
-    pub async fn send(&mut self, session: Session, command: UciIn) -> io::Result<()> {
+    pub async fn send(&mut self, session: Session, mut command: UciIn) -> io::Result<()> {
+        if command.limit_search(&self.params.limits) {
+            tracing::info!(session = session.0, "limited search: {}", command);
+        }
+        if let UciIn::Go { .. } = command {
+            self.apply_thermal(session).await?;
+        }
+        if let UciIn::Setoption {
+            ref name,
+            value: ref mut value @ Some(_),
+        } = command
+        {
+            // Clients may spell values of check and combo options
+            // differently than the engine.
+            if let Some(Ok(valid)) = self.options.get(name).map(|o| o.validate(value.clone())) {
+                *value = Some(valid.to_string());
+            }
+        }
         match command {
-            UciIn::Setoption { ref name, .. } if !name.is_safe() => {
-                log::error!(
-                    "{}: rejected potentially unsafe option: {}",
-                    session.0,
+            UciIn::Ucinewgame => match self.params.hash_policy {
+                HashPolicy::ClearPerGame => self.clear_hash(session).await,
+                HashPolicy::Keep | HashPolicy::ClearPerSession => {
+                    tracing::debug!(session = session.0, "keeping hash for new game");
+                    Ok(())
+                }
+            },
+            UciIn::Setoption { ref name, .. }
+                if self.params.deterministic && !name.is_deterministic() =>
+            {
+                tracing::error!(
+                    session = session.0,
+                    "rejected option in deterministic mode: {}",
+                    command
+                );
+                Ok(())
+            }
+            UciIn::Go { .. } if self.params.deterministic => {
+                // Search from an empty hash table, so that previous searches
+                // do not influence the result.
+                let position = self.replay.position.clone();
+                self.clear_hash(session).await?;
+                if let Some(position) = position {
+                    self.send_dangerous(session, position).await?;
+                }
+                self.send_dangerous(session, command).await
+            }
+            UciIn::Setoption { ref name, .. } if !self.params.option_policy.is_allowed(name) => {
+                tracing::error!(
+                    session = session.0,
+                    "rejected option that is not allowed: {}",
                     command
                 );
                 Ok(())
             }
+            UciIn::Setoption {
+                ref name,
+                value: Some(ref mut value),
+            } if *name == "UCI_Variant" => match self.engine_variant(value) {
+                Some(variant) => {
+                    *value = variant;
+                    self.send_dangerous(session, command).await
+                }
+                None => {
+                    tracing::error!(
+                        session = session.0,
+                        "rejected variant that is not served: {}",
+                        command
+                    );
+                    Ok(())
+                }
+            },
             _ => self.send_dangerous(session, command).await,
         }
     }
 
-    pub async fn send_dangerous(&mut self, session: Session, command: UciIn) -> io::Result<()> {
+    pub async fn send_dangerous(&mut self, session: Session, mut command: UciIn) -> io::Result<()> {
+        self.limit_resources(session, &mut command);
+        self.resume(session).await?;
+        match self.send_inner(session, &command).await {
+            Err(err) if is_disconnect(&err) => {
+                tracing::error!(session = session.0, "failed to write to engine: {}", err);
+                self.restart(session, "engine process exited unexpectedly")
+                    .await?;
+                self.send_inner(session, &command).await
+            }
+            res => res,
+        }
+    }
+
+    async fn send_inner(&mut self, session: Session, command: &UciIn) -> io::Result<()> {
+        if let Some(ref split) = self.split {
+            if split.is_searching() && matches!(command, UciIn::Stop | UciIn::Ponderhit) {
+                if *command == UciIn::Stop {
+                    self.recording = None;
+                }
+                split.send(command);
+                return Ok(());
+            }
+        }
+        let mut split_search = false;
         match command {
-            UciIn::Isready => self.pending_readyok += 1,
-            UciIn::Stop | UciIn::Ponderhit => (),
+            // The engine is not searching while cached output is delivered.
+            UciIn::Stop | UciIn::Ponderhit if !self.cached.is_empty() => return Ok(()),
+            UciIn::Ponderhit if !self.is_pondering() => {
+                tracing::warn!(session = session.0, "ignoring ponderhit: not pondering");
+                return Ok(());
+            }
+            UciIn::Stop => {
+                // Results of interrupted searches are incomplete.
+                self.recording = None;
+            }
+            UciIn::Isready | UciIn::Ponderhit => (),
             _ if self.searching => {
-                log::error!("{}: engine is busy: {}", session.0, command);
+                tracing::error!(session = session.0, "engine is busy: {}", command);
                 return Err(io::Error::new(io::ErrorKind::Other, "engine is busy"));
             }
+            UciIn::Setoption { name, value } => match self.options.get(name) {
+                Some(option) => {
+                    option
+                        .validate(value.clone())
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                }
+                None => {
+                    tracing::warn!(session = session.0, "ignoring unknown option: {}", command);
+                    return Ok(());
+                }
+            },
+            UciIn::Go { .. } => {
+                self.recording = None;
+                self.notes.clear();
+                if let Some((book, moves)) = self.book_moves() {
+                    match (book.mode, command) {
+                        (
+                            BookMode::Authoritative,
+                            UciIn::Go {
+                                ponder: false,
+                                infinite: false,
+                                ..
+                            },
+                        ) => {
+                            tracing::info!(session = session.0, "answered from book");
+                            let multipv = self
+                                .spin_value(&UciOptionName("MultiPV".to_owned()))
+                                .and_then(|multipv| u32::try_from(multipv).ok())
+                                .unwrap_or(1);
+                            self.cached = book::answer(&moves, multipv).into();
+                            self.searching = true;
+                            self.publish_status();
+                            return Ok(());
+                        }
+                        _ => self.notes.push_back(book::info_string(&moves)),
+                    }
+                }
+                if let Some((online_tb, pos)) = self.online_tb_position() {
+                    self.notes
+                        .extend(online_tb.probe(&pos).await.map(UciOut::info_string));
+                }
+                if let Some((cache, key)) = self.cache_key(command) {
+                    match cache.get(&key) {
+                        Some(output) => {
+                            tracing::info!(session = session.0, "analysis cache hit: {}", key.fen);
+                            self.cached = output.into();
+                            self.searching = true;
+                            self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+                            self.publish_status();
+                            return Ok(());
+                        }
+                        None => {
+                            self.recording = Some(Recording {
+                                key,
+                                lines: BTreeMap::new(),
+                            })
+                        }
+                    }
+                }
+                if let Some((multipv, partitions)) = self.split_partitions(command) {
+                    tracing::info!(
+                        session = session.0,
+                        "splitting search across {} processes",
+                        partitions.len()
+                    );
+                    if let Some(ref mut split) = self.split {
+                        split.search(command, multipv, partitions);
+                    }
+                    split_search = true;
+                }
+            }
+            _ => (),
+        }
+
+        if !split_search {
+            let lines = match self.xboard {
+                Some(ref mut xboard) => xboard.write_in(command),
+                None => vec![command.to_string()],
+            };
+            for mut buf in lines {
+                tracing::trace!(session = session.0, "<< {}", buf);
+                buf.push_str("\r\n");
+                self.stdin.write_all(buf.as_bytes()).await?;
+            }
+            self.stdin.flush().await?;
+        }
+        self.last_active = Instant::now();
+
+        match command {
+            UciIn::Isready => self.pending_readyok += 1,
             UciIn::Uci => {
                 self.pending_uciok += 1;
                 self.options.clear();
+                self.status.lock().expect("engine status").announced.clear();
                 self.name.take();
             }
             UciIn::Go { .. } => {
                 self.searching = true;
+                self.status.lock().expect("engine status").depth = None;
+                self.replay.go = Some(command.clone());
+                if !self.replay.recovering {
+                    self.metrics.analyses.fetch_add(1, Ordering::Relaxed);
+                    self.progress = Some(Progress::new());
+                }
             }
-            UciIn::Setoption {
-                ref name,
-                ref value,
-            } => match self.options.get(name) {
-                Some(option) => {
-                    option
-                        .validate(value.clone())
-                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            UciIn::Ponderhit => {
+                // The search continues as a normal search, also if it has to
+                // be resumed after a restart.
+                if let Some(UciIn::Go { ref mut ponder, .. }) = self.replay.go {
+                    *ponder = false;
                 }
-                None => {
-                    log::warn!("{}: ignoring unknown option: {}", session.0, command);
-                    return Ok(());
+            }
+            UciIn::Setoption { name, .. } if self.options.get(name) != Some(&UciOption::Button) => {
+                self.replay.setoptions.retain(
+                    |c| !matches!(c, UciIn::Setoption { name: other, .. } if other == name),
+                );
+                self.replay.setoptions.push(command.clone());
+            }
+            UciIn::Position { fen, moves } => {
+                self.ply = wdl::game_ply(fen.as_ref(), moves.len());
+                if self.params.validate_pv {
+                    self.pv_position = self.rules().and_then(|(variant, castling_mode)| {
+                        command.to_position(variant, castling_mode).ok().flatten()
+                    });
                 }
-            },
+                self.replay.position = Some(command.clone());
+                self.observe(|| Observation::Position(command.clone()));
+            }
+            UciIn::Ucinewgame => {
+                self.ply = 0;
+                self.pv_position = None;
+                self.replay.position = None;
+            }
             _ => (),
         }
+        if let Some(ref comparison) = self.comparison {
+            if !self.replay.recovering {
+                comparison.send(command);
+            }
+        }
+        if let Some(ref split) = self.split {
+            if !self.replay.recovering {
+                split.send(command);
+            }
+        }
+        self.publish_status();
 
-        let mut buf = command.to_string();
-        log::info!("{} << {}", session.0, buf);
-        buf.push_str("\r\n");
-        self.stdin.write_all(buf.as_bytes()).await?;
-        self.stdin.flush().await
+        Ok(())
     }
 
     pub async fn recv(&mut self, session: Session) -> io::Result<UciOut> {
+        if self.suspended {
+            // Nothing to receive until a command wakes up the engine.
+            future::pending::<()>().await;
+        }
         loop {
-            let mut line = String::new();
-            if self.stdout.read_line(&mut line).await? == 0 {
-                return Err(io::ErrorKind::UnexpectedEof.into());
+            if let Some(reason) = self.replay.restarted.take() {
+                return Ok(UciOut::info_string(format!("{reason} and was restarted")));
             }
-            let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
-
-            let mut command = match UciOut::from_line(line) {
-                Err(err) => {
-                    log::error!("{} >> {}", session.0, line);
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            if let Some(line) = self.replay.crash_report.pop_front() {
+                return Ok(UciOut::info_string(format!("engine stderr: {line}")));
+            }
+            if let Some(info) = self.notes.pop_front() {
+                return Ok(info);
+            }
+            let res = match self.params.engine_timeout {
+                Some(limit) if !self.is_idle() => timeout(limit, self.recv_inner(session)).await,
+                _ => Ok(self.recv_inner(session).await),
+            };
+            match res {
+                Ok(Err(err)) if is_disconnect(&err) => {
+                    self.restart(session, "engine process exited unexpectedly")
+                        .await?;
                 }
-                Ok(None) => {
-                    log::warn!("{} >> {}", session.0, line);
-                    continue;
+                Ok(res) => return res,
+                // Paused processes are expected to be quiet.
+                Err(_) if self.status.lock().expect("engine status").paused => (),
+                Err(_) => {
+                    self.restart(session, "engine did not respond").await?;
+                }
+            }
+        }
+    }
+
+    async fn recv_inner(&mut self, session: Session) -> io::Result<UciOut> {
+        loop {
+            // Cached output follows answers to earlier commands.
+            let cached = if self.pending_uciok == 0 && self.pending_readyok == 0 {
+                self.cached.pop_front()
+            } else {
+                None
+            };
+            let mut command = match cached.or_else(|| self.xboard.as_mut().and_then(Xboard::pop)) {
+                Some(command) => command,
+                None => {
+                    let read = match (&mut self.split, &mut self.comparison) {
+                        (Some(split), _) if split.is_searching() => tokio::select! {
+                            read = self.stdout.read_until(b'\n', &mut self.stdout_buf) => Read::Engine(read?),
+                            out = split.recv() => Read::Split(Box::new(out?)),
+                        },
+                        (_, Some(comparison)) if self.searching => tokio::select! {
+                            read = self.stdout.read_until(b'\n', &mut self.stdout_buf) => Read::Engine(read?),
+                            Some(info) = comparison.output.recv() => return Ok(info),
+                        },
+                        _ => {
+                            Read::Engine(self.stdout.read_until(b'\n', &mut self.stdout_buf).await?)
+                        }
+                    };
+                    match read {
+                        // Merged lines of a split search are handled like
+                        // output of the engine.
+                        Read::Split(command) => *command,
+                        Read::Engine(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        Read::Engine(_) => {
+                            let buf = mem::take(&mut self.stdout_buf);
+                            let line = &uci::decode_line(&buf);
+
+                            if let Some(ref mut xboard) = self.xboard {
+                                tracing::trace!(session = session.0, ">> {}", line);
+                                xboard.read_out(line);
+                                continue;
+                            }
+
+                            match UciOut::from_line(line) {
+                                Err(err) => {
+                                    tracing::error!(session = session.0, ">> {}", line);
+                                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                                }
+                                Ok(None) => {
+                                    tracing::warn!(session = session.0, ">> {}", line);
+                                    continue;
+                                }
+                                Ok(Some(command)) => command,
+                            }
+                        }
+                    }
                 }
-                Ok(Some(command)) => command,
             };
 
+            self.record(&command);
+
             match command {
                 UciOut::Info {
                     pv: None,
@@ -139,42 +1122,486 @@ impl Engine {
                     ..
                 } => {
                     // Skip noise.
-                    log::trace!("{} >> {}", session.0, command);
+                    tracing::trace!(session = session.0, ">> {}", command);
                     continue;
                 }
-                UciOut::Info { .. } => log::debug!("{} >> {}", session.0, command),
-                _ => log::info!("{} >> {}", session.0, command),
+                UciOut::Info {
+                    nps, nodes, depth, ..
+                } => {
+                    tracing::trace!(session = session.0, ">> {}", command);
+                    if let Some(ref mut progress) = self.progress {
+                        progress.update(nodes, depth);
+                    }
+                    if let Some(ref pos) = self.pv_position {
+                        if command.truncate_pv(pos) {
+                            tracing::warn!(session = session.0, "truncated illegal pv");
+                        }
+                    }
+                    if let UciOut::Info {
+                        score: Some(ref mut score),
+                        wdl: ref mut reported,
+                        ..
+                    } = command
+                    {
+                        if self.params.normalize_scores {
+                            if let Some(pawn_value) = self.name.as_deref().and_then(wdl::pawn_value)
+                            {
+                                wdl::normalize(&mut score.eval, pawn_value);
+                            }
+                        }
+                        if self.params.wdl && reported.is_none() {
+                            *reported = Some(wdl::estimate(&score.eval, self.ply));
+                        }
+                    }
+                    // Info strings may reveal details of the host.
+                    if let UciOut::Info { string: None, .. } = command {
+                        self.observe(|| Observation::Info(command.clone()));
+                    }
+                    if let Some(nps) = nps {
+                        self.metrics.nps.store(nps, Ordering::Relaxed);
+                    }
+                    if depth.is_some() {
+                        self.status.lock().expect("engine status").depth = depth;
+                    }
+                }
+                _ => tracing::trace!(session = session.0, ">> {}", command),
             }
 
             match command {
                 UciOut::IdName(ref name) => self.name = Some(name.clone()),
                 UciOut::Uciok => self.pending_uciok = self.pending_uciok.saturating_sub(1),
                 UciOut::Readyok => self.pending_readyok = self.pending_readyok.saturating_sub(1),
-                UciOut::Bestmove { .. } => self.searching = false,
+                UciOut::Bestmove { .. } => {
+                    self.searching = false;
+                    self.last_active = Instant::now();
+                    if let Some(ref mut comparison) = self.comparison {
+                        comparison.send(&UciIn::Stop);
+                        comparison.discard();
+                    }
+                    if let (Some(progress), Some(stats)) =
+                        (self.progress.take(), &self.params.stats)
+                    {
+                        stats.searched(session, progress);
+                    }
+                    self.replay.go = None;
+                    self.replay.recovering = false;
+                }
                 UciOut::Option {
                     ref name,
                     ref mut option,
                 } => {
                     // Apply limits set in engine parameters.
+                    let (max_threads, max_hash) = self.resource_limits();
                     if *name == "Threads" {
-                        option.limit_max(self.params.max_threads.into());
+                        option.limit_max(max_threads.into());
                     } else if *name == "Hash" {
-                        option.limit_max(self.params.max_hash.into());
+                        option.limit_max(max_hash.into());
+                    } else if *name == "MultiPV" {
+                        if let UciOption::Spin { default, min, max } = option {
+                            if let Some(lines) = self.params.default_multipv {
+                                *default = i64::from(lines).clamp(*min, *max);
+                            }
+                        }
+                        if let Some(max_multipv) = self.params.max_multipv {
+                            option.limit_max(max_multipv.into());
+                        }
                     }
 
                     self.options.insert(name.clone(), option.clone());
+
+                    // Announce served variants with their lichess names.
+                    if *name == "UCI_Variant" {
+                        if let UciOption::Combo { default, var } = option {
+                            *var = self.variants();
+                            *default = if self.serves_variant(default) {
+                                variant::lichess_name(default).to_owned()
+                            } else {
+                                var.first().cloned().unwrap_or_default()
+                            };
+                        }
+                    }
+
+                    let settable = self.params.option_policy.is_allowed(name)
+                        && (!self.params.deterministic || name.is_deterministic());
+                    self.status
+                        .lock()
+                        .expect("engine status")
+                        .announced
+                        .push(AnnouncedOption {
+                            name: name.to_string(),
+                            value: option.default_value(),
+                            option: option.clone(),
+                            settable,
+                        });
                 }
                 _ => (),
             }
+            if matches!(command, UciOut::IdName(_) | UciOut::Bestmove { .. }) {
+                self.publish_status();
+            }
 
             return Ok(command);
         }
     }
 
+    /// Respawns the engine process after it exited unexpectedly, restores
+    /// options and position, and resumes an interrupted search.
+    async fn restart(&mut self, session: Session, reason: &'static str) -> io::Result<()> {
+        let go = if self.searching {
+            self.replay.go.take()
+        } else {
+            None
+        };
+        self.pending_uciok = 0;
+        self.pending_readyok = 0;
+        self.searching = false;
+        self.recording = None;
+
+        if mem::take(&mut self.replay.recovering) {
+            tracing::error!(session = session.0, "engine failed again while recovering");
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "engine failed again while recovering",
+            ));
+        }
+
+        tracing::error!(session = session.0, "{reason}, restarting ...");
+        if self.params.notify {
+            notify::notify("Engine crashed", reason);
+        }
+        self.disconnect().await;
+        if let Some(reader) = self.stderr_reader.take() {
+            // Give the last words of the process a moment to arrive.
+            let _ = timeout(Duration::from_millis(100), reader).await;
+        }
+        self.replay.crash_report = self.status.lock().expect("engine status").stderr.clone();
+        self.respawn(session).await?;
+        self.metrics.restarts.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(go) = go {
+            self.replay.recovering = true;
+            self.send_inner(session, &go).await?;
+        }
+
+        self.replay.restarted = Some(reason);
+        tracing::warn!(session = session.0, "engine restarted");
+        Ok(())
+    }
+
+    /// Replaces the engine process with a fresh one, and restores options
+    /// and position.
+    async fn respawn(&mut self, session: Session) -> io::Result<()> {
+        self.disconnect().await;
+        let ((process, stdin, stdout), prepared) = match self.standby.take() {
+            Some(standby) => match standby.await.unwrap_or_else(|err| Err(err.into())) {
+                Ok(standby) => {
+                    tracing::warn!(session = session.0, "switching to standby engine");
+                    (standby.connection, standby.setoptions)
+                }
+                Err(err) => {
+                    tracing::error!(session = session.0, "standby engine failed: {err}");
+                    (connect(&self.addr, &self.params).await?, Vec::new())
+                }
+            },
+            None => (connect(&self.addr, &self.params).await?, Vec::new()),
+        };
+        self.process = process;
+        self.stdin = stdin;
+        self.stdout = stdout;
+        self.update_pid();
+        self.capture_stderr();
+        self.reset_protocol();
+
+        self.send_inner(session, &UciIn::Uci).await?;
+        while !self.is_idle() {
+            self.recv_inner(session).await?;
+        }
+        for command in mem::take(&mut self.replay.setoptions) {
+            if prepared.contains(&command) {
+                self.replay.setoptions.push(command);
+            } else {
+                self.send_inner(session, &command).await?;
+            }
+        }
+        if let Some(position) = self.replay.position.take() {
+            self.send_inner(session, &position).await?;
+        }
+        self.send_inner(session, &UciIn::Isready).await?;
+        while !self.is_idle() {
+            self.recv_inner(session).await?;
+        }
+        self.prepare_standby();
+        Ok(())
+    }
+
+    /// Starts preparing a standby process in the background, with the
+    /// options of the engine as they are now.
+    fn prepare_standby(&mut self) {
+        if !self.params.standby || self.standby.is_some() {
+            return;
+        }
+        if self.xboard.is_some() {
+            tracing::warn!("Standby engine is not supported for xboard engines");
+            return;
+        }
+        if matches!(self.addr, EngineAddr::Cluster(_)) {
+            // Workers keep their engines ready anyway.
+            return;
+        }
+        self.standby = Some(tokio::spawn(Standby::prepare(
+            self.addr.clone(),
+            self.params.clone(),
+            self.replay.setoptions.clone(),
+        )));
+    }
+
+    /// Replaces the engine process with one started from another address,
+    /// like a new build of the engine. Options are kept.
+    pub async fn upgrade(&mut self, addr: EngineAddr) -> io::Result<()> {
+        tracing::warn!("Upgrading engine {} to {addr} ...", self.addr);
+        if let Some(standby) = self.standby.take() {
+            standby.abort();
+        }
+        self.addr = addr;
+        self.restart_process().await
+    }
+
+    pub fn params(&self) -> &EngineParameters {
+        &self.params
+    }
+
+    /// Applies reloaded parameters. Options that changed are sent to the
+    /// engine, but options that were removed keep their current values.
+    pub async fn reconfigure(
+        &mut self,
+        session: Session,
+        reconfiguration: Reconfiguration,
+    ) -> io::Result<()> {
+        self.ensure_idle(session).await?;
+        for (name, value) in &reconfiguration.options {
+            if self.params.options.contains(&(name.clone(), value.clone())) {
+                continue;
+            }
+            if !self.options.contains_key(name) {
+                tracing::error!(session = session.0, "engine does not support option {name}");
+                continue;
+            }
+            self.send_dangerous(
+                session,
+                UciIn::Setoption {
+                    name: name.clone(),
+                    value: value.clone(),
+                },
+            )
+            .await?;
+        }
+        self.params.options = reconfiguration.options;
+        self.params.limits = reconfiguration.limits;
+        self.params.option_policy = reconfiguration.option_policy;
+        tracing::info!(session = session.0, "applied reloaded configuration");
+        Ok(())
+    }
+
+    /// Stops the engine process if it has not been used for the given
+    /// duration. It will be started again when needed.
+    pub async fn suspend_if_idle(&mut self, timeout: Duration) -> io::Result<()> {
+        if self.suspended || !self.is_idle() || self.last_active.elapsed() < timeout {
+            return Ok(());
+        }
+        tracing::warn!("Stopping idle engine ...");
+        self.disconnect().await;
+        if let Some(standby) = self.standby.take() {
+            standby.abort();
+        }
+        self.suspended = true;
+        self.update_pid();
+        self.publish_status();
+        Ok(())
+    }
+
+    /// Checks that the engine responds to isready within the time limit.
+    /// Suspended engines are not woken up, and the check does not count as
+    /// activity.
+    pub async fn ping(&mut self, limit: Duration) -> io::Result<()> {
+        if self.suspended {
+            return Ok(());
+        }
+        let last_active = self.last_active;
+        let session = Session(0);
+        let res = timeout(limit, async {
+            self.send(session, UciIn::Isready).await?;
+            self.ensure_idle(session).await
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "engine did not respond to isready",
+            ))
+        });
+        self.last_active = last_active;
+        res
+    }
+
+    /// Replaces the engine process on request of an operator, even if it is
+    /// unresponsive. Options are kept.
+    pub async fn restart_process(&mut self) -> io::Result<()> {
+        tracing::warn!("Restarting engine ...");
+        self.pending_uciok = 0;
+        self.pending_readyok = 0;
+        self.searching = false;
+        self.suspended = false;
+        self.cached.clear();
+        self.recording = None;
+        self.notes.clear();
+        self.replay.go = None;
+        self.replay.recovering = false;
+        self.respawn(Session(0)).await
+    }
+
+    async fn resume(&mut self, session: Session) -> io::Result<()> {
+        if self.suspended {
+            tracing::warn!(session = session.0, "resuming idle engine ...");
+            self.suspended = false;
+            self.respawn(session).await?;
+        }
+        Ok(())
+    }
+
+    /// Kills the engine process, or closes the connection to the engine.
+    async fn disconnect(&mut self) {
+        match self.process {
+            Some(ref mut process) => {
+                let _ = process.kill().await;
+                self.stdout = BufReader::new(Box::new(tokio::io::empty()));
+            }
+            None => {
+                let _ = self.stdin.shutdown().await;
+            }
+        }
+    }
+
+    /// Starts translating from scratch for a new engine process.
+    fn reset_protocol(&mut self) {
+        self.stdout_buf.clear();
+        self.xboard = match self.params.protocol {
+            Protocol::Uci => None,
+            Protocol::Xboard => Some(Xboard::default()),
+        };
+    }
+
+    /// Logs what the engine process prints to stderr, and keeps the last
+    /// lines for the status.
+    fn capture_stderr(&mut self) {
+        let stderr = match self
+            .process
+            .as_mut()
+            .and_then(|process| process.stderr.take())
+        {
+            Some(stderr) => stderr,
+            None => return,
+        };
+        let status = Arc::clone(&self.status);
+        self.stderr_reader = Some(tokio::spawn(async move {
+            let mut stderr = BufReader::new(stderr);
+            let mut buf = Vec::new();
+            while stderr
+                .read_until(b'\n', &mut buf)
+                .await
+                .map_or(false, |n| n > 0)
+            {
+                let line = uci::decode_line(&mem::take(&mut buf));
+                let mut status = status.lock().expect("engine status");
+                match status.session {
+                    Some(session) => tracing::info!(session, "engine stderr: {line}"),
+                    None => tracing::info!("engine stderr: {line}"),
+                }
+                if status.stderr.len() >= STDERR_LINES {
+                    status.stderr.pop_front();
+                }
+                status.stderr.push_back(line);
+            }
+        }));
+    }
+
+    fn update_pid(&self) {
+        let pid = if self.suspended {
+            None
+        } else {
+            self.process.as_ref().and_then(Child::id)
+        };
+        self.metrics.pid.store(pid.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    fn publish_status(&self) {
+        let mut status = self.status.lock().expect("engine status");
+        status.name = self.name.clone();
+        status.searching = self.searching;
+        status.pondering = self.is_pondering();
+        status.suspended = self.suspended;
+        status.pausable = matches!(self.addr, EngineAddr::Process(_));
+        status.position = self.replay.position.as_ref().map(ToString::to_string);
+        status.network = self.network();
+        status.options = self
+            .replay
+            .setoptions
+            .iter()
+            .filter_map(|command| match command {
+                UciIn::Setoption { name, value } => Some(OptionStatus {
+                    name: name.to_string(),
+                    value: value.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        let EngineStatus {
+            options, announced, ..
+        } = &mut *status;
+        for announced in announced {
+            announced.value = options
+                .iter()
+                .find(|option| option.name.eq_ignore_ascii_case(&announced.name))
+                .and_then(|option| option.value.clone())
+                .or_else(|| announced.option.default_value());
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<EngineMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    pub fn status(&self) -> Arc<Mutex<EngineStatus>> {
+        Arc::clone(&self.status)
+    }
+
+    pub fn observers(&self) -> broadcast::Sender<Observation> {
+        self.observers.clone()
+    }
+
+    /// Passes analysis on to spectators, if any.
+    fn observe(&self, observation: impl FnOnce() -> Observation) {
+        if self.observers.receiver_count() > 0 {
+            let _ = self.observers.send(observation());
+        }
+    }
+
     pub fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
 
+    /// File name of the NNUE network, from the EvalFile option.
+    pub fn network(&self) -> Option<String> {
+        let eval_file = self.option_value(&UciOptionName("EvalFile".to_owned()))?;
+        Path::new(&eval_file)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| name != "<empty>")
+    }
+
+    pub fn has_option(&self, name: &UciOptionName) -> bool {
+        self.options.contains_key(name)
+    }
+
     pub fn max_threads(&self) -> i64 {
         self.options
             .get(&UciOptionName("Threads".to_owned()))
@@ -189,17 +1616,338 @@ impl Engine {
             .unwrap_or(16)
     }
 
-    pub fn variants(&self) -> &[String] {
+    /// Current value of an option, as last set by the operator or a client.
+    fn option_value(&self, name: &UciOptionName) -> Option<String> {
+        self.replay
+            .setoptions
+            .iter()
+            .find_map(|command| match command {
+                UciIn::Setoption { name: other, value } if other == name => value.clone(),
+                _ => None,
+            })
+            .or_else(|| match self.options.get(name)? {
+                UciOption::Check { default } => Some(default.to_string()),
+                UciOption::Spin { default, .. } => Some(default.to_string()),
+                UciOption::Combo { default, .. } | UciOption::String { default } => {
+                    Some(default.clone())
+                }
+                UciOption::Button => None,
+            })
+    }
+
+    /// Current value of a spin option, as last set by the operator or a
+    /// client.
+    pub fn spin_value(&self, name: &UciOptionName) -> Option<i64> {
+        self.option_value(name)?.parse().ok()
+    }
+
+    /// Rules of the selected variant, unless the variant is unknown to
+    /// shakmaty.
+    fn rules(&self) -> Option<(Variant, CastlingMode)> {
+        let variant = match self.option_value(&UciOptionName("UCI_Variant".to_owned())) {
+            Some(name) => Variant::from_uci(variant::lichess_name(&name))?,
+            None => Variant::Chess,
+        };
+        let chess960 = self
+            .option_value(&UciOptionName("UCI_Chess960".to_owned()))
+            .map_or(false, |value| value == "true");
+        Some((variant, CastlingMode::from_chess960(chess960)))
+    }
+
+    /// Identifies a search with a fixed depth and no other limits, if
+    /// analysis is cached.
+    fn cache_key(&self, go: &UciIn) -> Option<(Arc<AnalysisCache>, CacheKey)> {
+        let cache = self.params.cache.as_ref()?;
+        let depth = match *go {
+            UciIn::Go {
+                searchmoves: None,
+                ponder: false,
+                wtime: None,
+                btime: None,
+                winc: None,
+                binc: None,
+                movestogo: None,
+                depth: Some(depth),
+                nodes: None,
+                mate: None,
+                movetime: None,
+                infinite: false,
+            } => depth,
+            _ => return None,
+        };
+        let (variant, castling_mode) = self.rules()?;
+        let pos = self
+            .replay
+            .position
+            .as_ref()?
+            .to_position(variant, castling_mode)
+            .ok()??;
+        let mut options: Vec<String> = self
+            .replay
+            .setoptions
+            .iter()
+            .filter(|command| {
+                !matches!(command, UciIn::Setoption { name, .. }
+                    if *name == "Threads" || *name == "Hash" || *name == "MultiPV")
+            })
+            .map(ToString::to_string)
+            .collect();
+        options.sort();
+        Some((
+            Arc::clone(cache),
+            CacheKey::new(
+                format!(
+                    "{} {}",
+                    self.name.as_deref().unwrap_or_default(),
+                    options.join(" ")
+                ),
+                &pos,
+                self.spin_value(&UciOptionName("MultiPV".to_owned()))
+                    .and_then(|multipv| u32::try_from(multipv).ok())
+                    .unwrap_or(1),
+                depth,
+            ),
+        ))
+    }
+
+    /// Moves from the opening book for the current position, if any.
+    fn book_moves(&self) -> Option<(Arc<OpeningBook>, Vec<BookMove>)> {
+        let book = self.params.book.as_ref()?;
+        if self.replay.recovering {
+            return None;
+        }
+        let (variant, castling_mode) = self.rules()?;
+        let pos = match self
+            .replay
+            .position
+            .as_ref()?
+            .to_position(variant, castling_mode)
+            .ok()??
+        {
+            VariantPosition::Chess(pos) => pos,
+            _ => return None,
+        };
+        let moves = book.moves(&pos, self.ply, castling_mode);
+        (!moves.is_empty()).then(|| (Arc::clone(book), moves))
+    }
+
+    /// Current position to look up in the online tablebase, if it is
+    /// enabled and the position is standard chess. Not again when the
+    /// search is resumed after a restart.
+    fn online_tb_position(&self) -> Option<(Arc<OnlineTablebase>, Chess)> {
+        let online_tb = self.params.online_tb.as_ref()?;
+        if self.replay.recovering {
+            return None;
+        }
+        let (variant, castling_mode) = self.rules()?;
+        match self
+            .replay
+            .position
+            .as_ref()?
+            .to_position(variant, castling_mode)
+            .ok()??
+        {
+            VariantPosition::Chess(pos) => Some((Arc::clone(online_tb), pos)),
+            _ => None,
+        }
+    }
+
+    /// Number of lines and root moves for each process of a split search,
+    /// dealt out in turn, if the search is for more than one line. Not when
+    /// the search is resumed after a restart.
+    fn split_partitions(&self, command: &UciIn) -> Option<(u32, Vec<Vec<Uci>>)> {
+        let split = self.split.as_ref()?;
+        if self.replay.recovering || self.xboard.is_some() {
+            return None;
+        }
+        let multipv = self
+            .spin_value(&UciOptionName("MultiPV".to_owned()))
+            .and_then(|multipv| u32::try_from(multipv).ok())
+            .filter(|&multipv| multipv > 1)?;
+        let moves = match command {
+            UciIn::Go {
+                searchmoves: Some(searchmoves),
+                ..
+            } => searchmoves.clone(),
+            UciIn::Go { .. } => {
+                let (variant, castling_mode) = self.rules()?;
+                let pos = self
+                    .replay
+                    .position
+                    .as_ref()?
+                    .to_position(variant, castling_mode)
+                    .ok()??;
+                pos.legal_moves()
+                    .iter()
+                    .map(|m| Uci::from_move(m, castling_mode))
+                    .collect()
+            }
+            _ => return None,
+        };
+        let processes = min(
+            min(usize::try_from(multipv).ok()?, moves.len()),
+            split.processes(),
+        );
+        if processes < 2 {
+            return None;
+        }
+        let mut partitions = vec![Vec::new(); processes];
+        for (i, m) in moves.into_iter().enumerate() {
+            partitions[i % processes].push(m);
+        }
+        Some((multipv, partitions))
+    }
+
+    /// Keeps the final output of a search for the cache.
+    fn record(&mut self, command: &UciOut) {
+        let recording = match self.recording {
+            Some(ref mut recording) => recording,
+            None => return,
+        };
+        match command {
+            UciOut::Info {
+                multipv,
+                score: Some(_),
+                pv: Some(_),
+                string: None,
+                ..
+            } => {
+                recording
+                    .lines
+                    .insert(multipv.map_or(1, NonZeroU32::get), command.clone());
+            }
+            UciOut::Bestmove { .. } => {
+                if let (Some(recording), Some(cache)) = (self.recording.take(), &self.params.cache)
+                {
+                    let mut output: Vec<UciOut> = recording.lines.into_values().collect();
+                    output.push(command.clone());
+                    cache.insert(recording.key, output);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Checks a `position` command against the rules of the selected
+    /// variant. Variants that are unknown to shakmaty are not checked.
+    pub fn validate_position(&self, command: &UciIn) -> Result<(), ProtocolError> {
+        match self.rules() {
+            Some((variant, castling_mode)) => {
+                command.to_position(variant, castling_mode).map(|_| ())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Lc0 backend for the detected GPU.
+    fn gpu_backend(&self) -> Option<String> {
+        let backend = UciOptionName("Backend".to_owned());
+        if self.params.options.iter().any(|(name, _)| *name == backend) {
+            return None;
+        }
+        let available = self.options.get(&backend).and_then(UciOption::var)?;
+        self.params
+            .gpu?
+            .lc0_backend(available)
+            .map(ToOwned::to_owned)
+    }
+
+    fn engine_variants(&self) -> &[String] {
         self.options
             .get(&UciOptionName("UCI_Variant".to_owned()))
             .and_then(UciOption::var)
             .unwrap_or_default()
     }
 
+    /// Variants of the engine that are served, with their lichess names
+    /// where possible.
+    pub fn variants(&self) -> Vec<String> {
+        self.engine_variants()
+            .iter()
+            .filter(|variant| self.serves_variant(variant))
+            .map(|variant| variant::lichess_name(variant).to_owned())
+            .collect()
+    }
+
+    fn serves_variant(&self, name: &str) -> bool {
+        self.params.variants.is_empty()
+            || self
+                .params
+                .variants
+                .iter()
+                .any(|served| variant::same(served, name))
+    }
+
+    /// The `UCI_Variant` value for a variant requested by a client, unless
+    /// the variant is not served.
+    fn engine_variant(&self, name: &str) -> Option<String> {
+        if !self.serves_variant(name) {
+            return None;
+        }
+        Some(
+            variant::engine_name(name, self.engine_variants())
+                .unwrap_or(name)
+                .to_owned(),
+        )
+    }
+
     pub fn is_searching(&self) -> bool {
         self.searching
     }
 
+    /// Leaves time for moves to travel over the network, when clients play
+    /// with clock times. Sets Move Overhead if the engine supports it, and
+    /// otherwise shortens the clock times.
+    pub async fn compensate_latency(
+        &mut self,
+        session: Session,
+        command: &mut UciIn,
+        latency: Duration,
+    ) -> io::Result<()> {
+        let overhead = match (self.params.move_overhead, &mut *command) {
+            (Some(overhead), UciIn::Go { wtime, btime, .. })
+                if wtime.is_some() || btime.is_some() =>
+            {
+                overhead + latency
+            }
+            _ => return Ok(()),
+        };
+        let name = UciOptionName("Move Overhead".to_owned());
+        match self.options.get(&name) {
+            Some(&UciOption::Spin { min, max, .. }) => {
+                let millis = i64::try_from(overhead.as_millis())
+                    .unwrap_or(i64::MAX)
+                    .clamp(min, max)
+                    .to_string();
+                if self.option_value(&name).as_ref() != Some(&millis) {
+                    tracing::debug!(session = session.0, "move overhead: {millis} ms");
+                    self.send_dangerous(
+                        session,
+                        UciIn::Setoption {
+                            name,
+                            value: Some(millis),
+                        },
+                    )
+                    .await?;
+                }
+            }
+            _ => {
+                if let UciIn::Go { wtime, btime, .. } = command {
+                    for time in [wtime, btime].into_iter().flatten() {
+                        *time = time.saturating_sub(overhead);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the engine is searching on the time of the opponent, until
+    /// the client sends `ponderhit` or `stop`.
+    pub fn is_pondering(&self) -> bool {
+        self.searching && matches!(self.replay.go, Some(UciIn::Go { ponder: true, .. }))
+    }
+
     pub fn is_idle(&self) -> bool {
         self.pending_uciok == 0 && self.pending_readyok == 0 && !self.searching
     }
@@ -215,11 +1963,235 @@ impl Engine {
         Ok(())
     }
 
+    /// Starts a new game with an empty hash table, regardless of the hash
+    /// policy.
     pub async fn ensure_newgame(&mut self, session: Session) -> io::Result<()> {
         self.ensure_idle(session).await?;
-        self.send(session, UciIn::Ucinewgame).await?;
+        self.clear_hash(session).await?;
         self.send(session, UciIn::Isready).await?;
         self.ensure_idle(session).await?;
         Ok(())
     }
+
+    /// Prepares the engine for a new session, according to the hash policy.
+    pub async fn start_session(&mut self, session: Session) -> io::Result<()> {
+        if let Some(ref stats) = self.params.stats {
+            stats.start_session(session);
+        }
+        if self.params.notify {
+            notify::notify(
+                "Session started",
+                &format!("A client is using {}", self.display_name()),
+            );
+        }
+        self.update_power(session).await?;
+        match self.params.hash_policy {
+            HashPolicy::Keep => self.ensure_idle(session).await?,
+            HashPolicy::ClearPerSession | HashPolicy::ClearPerGame => {
+                self.ensure_newgame(session).await?
+            }
+        }
+        let multipv = UciOptionName("MultiPV".to_owned());
+        match self.params.default_multipv {
+            Some(lines) if self.options.contains_key(&multipv) => {
+                self.send_dangerous(
+                    session,
+                    UciIn::Setoption {
+                        name: multipv,
+                        value: Some(lines.to_string()),
+                    },
+                )
+                .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts the work done for the session.
+    pub fn end_session(&mut self, session: Session) {
+        if let Some(ref stats) = self.params.stats {
+            stats.end_session(session);
+        }
+        if self.params.notify {
+            notify::notify(
+                "Session ended",
+                &format!("The client is done with {}", self.display_name()),
+            );
+        }
+        self.release_worker();
+    }
+
+    /// Closes the connection to the worker of a cluster, so that the next
+    /// session connects to the least loaded worker at that time.
+    fn release_worker(&mut self) {
+        if !matches!(self.addr, EngineAddr::Cluster(_)) || self.suspended || !self.is_idle() {
+            return;
+        }
+        // Dropping both halves closes the bridge, so that the connection to
+        // the worker ends.
+        self.stdin = BufWriter::new(Box::new(tokio::io::sink()));
+        self.stdout = BufReader::new(Box::new(tokio::io::empty()));
+        self.suspended = true;
+        self.publish_status();
+    }
+
+    fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("the engine")
+    }
+
+    /// Largest number of threads and size of hash table, which are lower
+    /// while running on battery.
+    fn resource_limits(&self) -> (u32, u32) {
+        match self.params.battery {
+            Some(battery) if self.on_battery => (
+                min(self.params.max_threads, battery.threads.unwrap_or(u32::MAX)),
+                min(self.params.max_hash, battery.hash.unwrap_or(u32::MAX)),
+            ),
+            _ => (self.params.max_threads, self.params.max_hash),
+        }
+    }
+
+    /// Reduces threads and hash to the limits on battery or while the
+    /// processor is too hot, rather than rejecting settings that are fine
+    /// otherwise. Also caps MultiPV.
+    fn limit_resources(&mut self, session: Session, command: &mut UciIn) {
+        let (name, value) = match command {
+            UciIn::Setoption {
+                name,
+                value: Some(value),
+            } => (name, value),
+            _ => return,
+        };
+        let mut limit = u32::MAX;
+        if self.on_battery {
+            let (max_threads, max_hash) = self.resource_limits();
+            if *name == "Threads" {
+                limit = max_threads;
+            } else if *name == "Hash" {
+                limit = max_hash;
+            }
+        }
+        if let (true, Some(max_multipv)) = (*name == "MultiPV", self.params.max_multipv) {
+            limit = max_multipv;
+        }
+        if let (true, Some(thermal)) = (self.throttled, &self.params.thermal) {
+            if *name == "Threads" {
+                limit = min(limit, thermal.threads());
+                // Restored when the processor cools down.
+                self.restore_threads = Some(UciIn::Setoption {
+                    name: name.clone(),
+                    value: Some(value.clone()),
+                });
+            }
+        }
+        if value.parse::<u32>().map_or(false, |value| value > limit) {
+            *value = limit.to_string();
+            tracing::info!(session = session.0, "limited option: {}", command);
+        }
+    }
+
+    /// Reduces threads after the processor heated up, or restores them
+    /// after it cooled down. A running search is stopped and started again
+    /// with the new number of threads.
+    pub async fn apply_thermal(&mut self, session: Session) -> io::Result<()> {
+        let hot = match self.params.thermal {
+            Some(ref thermal) => thermal.is_hot(),
+            None => return Ok(()),
+        };
+        if hot == self.throttled || self.suspended || !self.cached.is_empty() {
+            return Ok(());
+        }
+
+        let threads = UciOptionName("Threads".to_owned());
+        let current = self
+            .replay
+            .setoptions
+            .iter()
+            .find(|command| matches!(command, UciIn::Setoption { name, .. } if *name == threads))
+            .cloned()
+            .or_else(|| match self.options.get(&threads) {
+                Some(UciOption::Spin { default, .. }) => Some(UciIn::Setoption {
+                    name: threads.clone(),
+                    value: Some(default.to_string()),
+                }),
+                _ => None,
+            });
+
+        let go = if self.searching {
+            self.replay.go.clone()
+        } else {
+            None
+        };
+        if go.is_some() {
+            self.send_inner(session, &UciIn::Stop).await?;
+            while self.searching {
+                self.recv_inner(session).await?;
+            }
+        }
+
+        self.throttled = hot;
+        let command = if hot {
+            current
+        } else {
+            self.restore_threads.take()
+        };
+        if let Some(command) = command {
+            self.send_dangerous(session, command).await?;
+        }
+        if let Some(go) = go {
+            self.send_dangerous(session, go).await?;
+        }
+        Ok(())
+    }
+
+    /// Switches to the limits of the current power source, if it changed.
+    /// Clients see the new limits when they request the engine options.
+    async fn update_power(&mut self, session: Session) -> io::Result<()> {
+        if self.params.battery.is_none() {
+            return Ok(());
+        }
+        let on_battery = power::on_battery();
+        if on_battery == self.on_battery {
+            return Ok(());
+        }
+        self.on_battery = on_battery;
+        tracing::info!(session = session.0, on_battery, "power source changed");
+
+        self.ensure_idle(session).await?;
+        self.send(session, UciIn::Uci).await?;
+        self.ensure_idle(session).await?;
+        if on_battery {
+            let resources: Vec<UciIn> = self
+                .replay
+                .setoptions
+                .iter()
+                .filter(|command| {
+                    matches!(command, UciIn::Setoption { name, .. } if *name == "Threads" || *name == "Hash")
+                })
+                .cloned()
+                .collect();
+            for command in resources {
+                self.send_dangerous(session, command).await?;
+            }
+            self.send(session, UciIn::Isready).await?;
+            self.ensure_idle(session).await?;
+        }
+        Ok(())
+    }
+
+    async fn clear_hash(&mut self, session: Session) -> io::Result<()> {
+        self.send_dangerous(session, UciIn::Ucinewgame).await?;
+        let clear_hash = UciOptionName("Clear Hash".to_owned());
+        if self.options.get(&clear_hash) == Some(&UciOption::Button) {
+            self.send_dangerous(
+                session,
+                UciIn::Setoption {
+                    name: clear_hash,
+                    value: None,
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,44 @@
+use std::io;
+
+use thiserror::Error;
+
+/// Why the server could not be started.
+#[derive(Error, Debug)]
+pub enum RemoteUciError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error(
+        "no engine configured (use --engine, --engine-tcp, --engine-ssh, --engine-spec or --demo, or run download-stockfish)"
+    )]
+    NoEngine,
+    #[error("could not start engine {engine}: {source}")]
+    EngineSpawn {
+        engine: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not bind server to {addr}: {source}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0}")]
+    SecretFile(String),
+    #[error("could not load analysis cache: {0}")]
+    AnalysisCache(#[source] io::Error),
+    #[error("could not load opening book: {0}")]
+    Book(#[source] io::Error),
+    #[error("could not get network for --eval-file: {0}")]
+    EvalFile(String),
+    #[error("could not load statistics: {0}")]
+    Stats(#[source] io::Error),
+    #[error("tunnel requires a TCP listener (use --bind)")]
+    NoTcpListener,
+    #[error("could not start tunnel: {0}")]
+    Tunnel(#[source] io::Error),
+    #[error("no public URL, use --advertise-url to set it")]
+    NoPublicUrl,
+    #[error("invalid --advertise-url: {0}")]
+    AdvertiseUrl(String),
+}
@@ -0,0 +1,93 @@
+use std::{env, fmt, path::PathBuf};
+
+/// GPU compute APIs that neural network engines like Lc0 can use.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GpuBackend {
+    Cuda,
+    OpenCl,
+}
+
+impl GpuBackend {
+    /// Detects the best available GPU compute API by looking for the driver
+    /// libraries, without loading them.
+    pub fn detect() -> Option<GpuBackend> {
+        if has_library(CUDA_LIBRARY) {
+            Some(GpuBackend::Cuda)
+        } else if has_library(OPENCL_LIBRARY) {
+            Some(GpuBackend::OpenCl)
+        } else {
+            None
+        }
+    }
+
+    /// Picks the matching Lc0 backend from the values of its Backend option.
+    pub fn lc0_backend(self, available: &[String]) -> Option<&str> {
+        let (preferred, prefix) = match self {
+            GpuBackend::Cuda => ("cuda-auto", "cuda"),
+            GpuBackend::OpenCl => ("opencl", "opencl"),
+        };
+        available
+            .iter()
+            .find(|backend| *backend == preferred)
+            .or_else(|| available.iter().find(|backend| backend.starts_with(prefix)))
+            .map(String::as_str)
+    }
+}
+
+impl fmt::Display for GpuBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GpuBackend::Cuda => "CUDA",
+            GpuBackend::OpenCl => "OpenCL",
+        })
+    }
+}
+
+#[cfg(windows)]
+const CUDA_LIBRARY: Option<&str> = Some("nvcuda.dll");
+#[cfg(windows)]
+const OPENCL_LIBRARY: Option<&str> = Some("OpenCL.dll");
+
+#[cfg(target_os = "macos")]
+const CUDA_LIBRARY: Option<&str> = None;
+#[cfg(target_os = "macos")]
+const OPENCL_LIBRARY: Option<&str> = Some("OpenCL.framework");
+
+#[cfg(not(any(windows, target_os = "macos")))]
+const CUDA_LIBRARY: Option<&str> = Some("libcuda.so.1");
+#[cfg(not(any(windows, target_os = "macos")))]
+const OPENCL_LIBRARY: Option<&str> = Some("libOpenCL.so.1");
+
+fn library_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        if let Some(root) = env::var_os("SystemRoot") {
+            dirs.push(PathBuf::from(root).join("System32"));
+        }
+    } else if cfg!(target_os = "macos") {
+        dirs.push(PathBuf::from("/System/Library/Frameworks"));
+    } else {
+        if let Some(paths) = env::var_os("LD_LIBRARY_PATH") {
+            dirs.extend(env::split_paths(&paths));
+        }
+        dirs.extend(
+            [
+                "/usr/lib",
+                "/usr/lib64",
+                "/usr/lib/x86_64-linux-gnu",
+                "/usr/lib/aarch64-linux-gnu",
+                // WSL exposes the Windows driver here.
+                "/usr/lib/wsl/lib",
+            ]
+            .iter()
+            .map(PathBuf::from),
+        );
+    }
+    dirs
+}
+
+fn has_library(name: Option<&str>) -> bool {
+    name.map_or(false, |name| {
+        library_dirs().iter().any(|dir| dir.join(name).exists())
+    })
+}
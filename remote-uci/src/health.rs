@@ -0,0 +1,55 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::http::StatusCode;
+
+use crate::ws::SharedEngine;
+
+/// Time for idle engines to answer isready.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probes for supervisors like Kubernetes and Docker. Unlike the admin API,
+/// they do not require authorization.
+pub struct Health {
+    engines: Vec<(String, Arc<SharedEngine>)>,
+}
+
+impl Health {
+    /// Engines by websocket path.
+    pub fn new(engines: Vec<(String, Arc<SharedEngine>)>) -> Health {
+        Health { engines }
+    }
+
+    /// Sends isready to all idle engines. Returns a description of the
+    /// first engine that does not answer within `limit`.
+    pub async fn ping(&self, limit: Duration) -> Result<(), String> {
+        for (path, engine) in &self.engines {
+            if let Err(err) = engine.ping(limit).await {
+                tracing::error!("Health check of {path} failed: {err}");
+                return Err(format!("engine for {path} is not responding: {err}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Healthy if the server is running and idle engines respond to isready.
+pub async fn healthz(health: Arc<Health>) -> (StatusCode, String) {
+    match health.ping(PING_TIMEOUT).await {
+        Ok(()) => (StatusCode::OK, "ok\n".to_owned()),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, format!("{err}\n")),
+    }
+}
+
+/// Ready if every engine endpoint can start a new session right away, and
+/// the server is not shutting down.
+pub async fn readyz(health: Arc<Health>) -> (StatusCode, String) {
+    for (path, engine) in &health.engines {
+        if !engine.has_capacity() {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("no engine available for {path}\n"),
+            );
+        }
+    }
+    (StatusCode::OK, "ok\n".to_owned())
+}
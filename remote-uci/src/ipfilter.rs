@@ -0,0 +1,261 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+use crate::listen::PeerAddr;
+
+/// IP address or CIDR range, like 192.0.2.1 or 2001:db8::/32.
+#[derive(Copy, Clone, Debug)]
+pub struct IpRange(IpNet);
+
+impl FromStr for IpRange {
+    type Err = ipnet::AddrParseError;
+
+    fn from_str(s: &str) -> Result<IpRange, ipnet::AddrParseError> {
+        match s.parse::<IpAddr>() {
+            Ok(addr) => Ok(IpRange(IpNet::from(addr))),
+            Err(_) => s.parse().map(IpRange),
+        }
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Restricts which clients may connect to the server, before they get to
+/// try a secret.
+#[derive(Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trust_proxy: bool,
+}
+
+impl IpFilter {
+    pub fn new(allow: &[IpRange], deny: &[IpRange], trust_proxy: bool) -> IpFilter {
+        IpFilter {
+            allow: allow.iter().map(|range| range.0).collect(),
+            deny: deny.iter().map(|range| range.0).collect(),
+            trust_proxy,
+        }
+    }
+
+    /// Whether any restrictions are configured.
+    pub fn is_active(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    /// Denied addresses take precedence over allowed addresses. Clients
+    /// with unknown addresses are only allowed if there is no allowlist.
+    fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(&ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+
+    /// Address of the client. Behind a trusted reverse proxy, this is the
+    /// last address in X-Forwarded-For, which was added by the proxy.
+    pub fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        if self.trust_proxy {
+            if let Some(forwarded) = req
+                .headers()
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()
+            {
+                return forwarded.trim().parse().ok().map(canonical);
+            }
+        }
+        peer_ip(req)
+    }
+}
+
+/// Whether the request comes straight from this machine. Tunnels and
+/// reverse proxies also connect from loopback, but add headers with the
+/// address of the actual client.
+pub fn is_local<B>(req: &Request<B>) -> bool {
+    const FORWARDED: [&str; 4] = [
+        "forwarded",
+        "x-forwarded-for",
+        "x-real-ip",
+        "cf-connecting-ip",
+    ];
+    peer_ip(req).map_or(false, |ip| ip.is_loopback())
+        && !FORWARDED
+            .iter()
+            .any(|name| req.headers().contains_key(*name))
+}
+
+fn peer_ip<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ConnectInfo<PeerAddr>>()
+        .and_then(|ConnectInfo(PeerAddr(addr))| *addr)
+        .or_else(|| {
+            // Set by applications that embed the router.
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr)
+        })
+        .map(|addr| canonical(addr.ip()))
+}
+
+/// IPv4 addresses of clients connected to a dual-stack IPv6 listener are
+/// mapped into IPv6.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => v6.to_ipv4().map_or(ip, IpAddr::V4),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+pub async fn middleware<B>(
+    filter: Arc<IpFilter>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let ip = filter.client_ip(&req);
+    if !filter.is_allowed(ip) {
+        match ip {
+            Some(ip) => tracing::warn!("rejected connection from {ip}"),
+            None => tracing::warn!("rejected connection from unknown address"),
+        }
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> IpFilter {
+        let parse = |ranges: &[&str]| -> Vec<IpRange> {
+            ranges
+                .iter()
+                .map(|range| range.parse().expect("valid range"))
+                .collect()
+        };
+        IpFilter::new(&parse(allow), &parse(deny), false)
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().expect("valid address"))
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            "192.0.2.1".parse::<IpRange>().expect("address").to_string(),
+            "192.0.2.1/32"
+        );
+        assert_eq!(
+            "192.0.2.0/24".parse::<IpRange>().expect("v4").to_string(),
+            "192.0.2.0/24"
+        );
+        assert_eq!(
+            "2001:db8::/32".parse::<IpRange>().expect("v6").to_string(),
+            "2001:db8::/32"
+        );
+        assert_eq!(
+            "::1".parse::<IpRange>().expect("address").to_string(),
+            "::1/128"
+        );
+        assert!("192.0.2.0/33".parse::<IpRange>().is_err());
+        assert!("192.0.2".parse::<IpRange>().is_err());
+        assert!("localhost".parse::<IpRange>().is_err());
+        assert!("".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_allow_deny() {
+        let open = filter(&[], &[]);
+        assert!(!open.is_active());
+        assert!(open.is_allowed(ip("203.0.113.5")));
+        assert!(open.is_allowed(None));
+
+        let lan = filter(&["192.168.0.0/16", "2001:db8::/32"], &["192.168.1.13"]);
+        assert!(lan.is_active());
+        assert!(lan.is_allowed(ip("192.168.7.1")));
+        assert!(lan.is_allowed(ip("2001:db8::1")));
+        assert!(!lan.is_allowed(ip("192.168.1.13")), "deny takes precedence");
+        assert!(!lan.is_allowed(ip("203.0.113.5")));
+        assert!(!lan.is_allowed(None), "unknown address with allowlist");
+
+        let blocklist = filter(&[], &["203.0.113.0/24"]);
+        assert!(blocklist.is_active());
+        assert!(!blocklist.is_allowed(ip("203.0.113.5")));
+        assert!(blocklist.is_allowed(ip("198.51.100.1")));
+        assert!(blocklist.is_allowed(None));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let req = |forwarded: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(forwarded) = forwarded {
+                req = req.header("x-forwarded-for", forwarded);
+            }
+            let mut req = req.body(()).expect("request");
+            req.extensions_mut().insert(ConnectInfo(PeerAddr(Some(
+                "[::ffff:192.0.2.1]:1234".parse().expect("socket address"),
+            ))));
+            req
+        };
+        let direct = IpFilter::new(&[], &[], false);
+        assert_eq!(
+            direct.client_ip(&req(Some("198.51.100.1"))),
+            ip("192.0.2.1")
+        );
+        let proxied = IpFilter::new(&[], &[], true);
+        assert_eq!(
+            proxied.client_ip(&req(Some("203.0.113.9, 198.51.100.1"))),
+            ip("198.51.100.1")
+        );
+        assert_eq!(proxied.client_ip(&req(None)), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn test_is_local() {
+        let req = |peer: &str, forwarded: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(forwarded) = forwarded {
+                req = req.header(forwarded, "203.0.113.9");
+            }
+            let mut req = req.body(()).expect("request");
+            req.extensions_mut().insert(ConnectInfo(PeerAddr(Some(
+                peer.parse().expect("socket address"),
+            ))));
+            req
+        };
+        assert!(is_local(&req("127.0.0.1:1234", None)));
+        assert!(is_local(&req("[::1]:1234", None)));
+        assert!(is_local(&req("[::ffff:127.0.0.1]:1234", None)));
+        assert!(!is_local(&req("192.0.2.1:1234", None)));
+        assert!(!is_local(&req("127.0.0.1:1234", Some("x-forwarded-for"))));
+        assert!(!is_local(&req("127.0.0.1:1234", Some("cf-connecting-ip"))));
+        assert!(!is_local(&Request::new(())), "unknown address");
+    }
+}
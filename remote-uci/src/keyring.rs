@@ -0,0 +1,226 @@
+use std::io;
+
+/// Service name of entries in the credential store of the operating system:
+/// the Secret Service on Linux (with `secret-tool`), the macOS Keychain
+/// (with `security`) or the Windows Credential Manager. Entries of different
+/// endpoints are told apart by account name.
+const SERVICE: &str = "remote-uci";
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn read(account: &str) -> io::Result<String> {
+    use std::process::Command;
+
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", SERVICE, "account", account])
+        .output()
+        .map_err(missing_tool("secret-tool"))?;
+    if output.status.success() {
+        String::from_utf8(output.stdout)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    } else if output.stderr.is_empty() {
+        // secret-tool fails silently if there is no such entry.
+        Err(io::ErrorKind::NotFound.into())
+    } else {
+        Err(tool_error("secret-tool", &output.stderr))
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn write(account: &str, content: &str) -> io::Result<()> {
+    use std::{
+        io::Write as _,
+        process::{Command, Stdio},
+    };
+
+    let mut child = Command::new("secret-tool")
+        .args(["store", "--label", &format!("{SERVICE} {account}")])
+        .args(["service", SERVICE, "account", account])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(missing_tool("secret-tool"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(tool_error("secret-tool", &output.stderr))
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn read(account: &str) -> io::Result<String> {
+    use std::process::Command;
+
+    /// Exit status of `security` if there is no such item.
+    const ERR_SEC_ITEM_NOT_FOUND: i32 = 44;
+
+    let output = Command::new("security")
+        .args(["find-generic-password", "-s", SERVICE, "-a", account, "-w"])
+        .output()
+        .map_err(missing_tool("security"))?;
+    if output.status.code() == Some(ERR_SEC_ITEM_NOT_FOUND) {
+        return Err(io::ErrorKind::NotFound.into());
+    } else if !output.status.success() {
+        return Err(tool_error("security", &output.stderr));
+    }
+    let password = String::from_utf8(output.stdout)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let password = password.strip_suffix('\n').unwrap_or(&password);
+    // Passwords with non-printable characters, like the line breaks between
+    // named secrets, are printed as hex.
+    Ok(decode_hex(password)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .filter(|decoded| decoded.contains('\n'))
+        .unwrap_or_else(|| password.to_owned()))
+}
+
+#[cfg(target_os = "macos")]
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+pub fn write(account: &str, content: &str) -> io::Result<()> {
+    use std::{
+        fmt::Write as _,
+        io::Write as _,
+        process::{Command, Stdio},
+    };
+
+    // Commands for interactive mode are read from stdin, so that the
+    // password does not show up in the process list. Hex, because it may
+    // contain line breaks.
+    let mut command = format!(
+        "add-generic-password -U -s {SERVICE} -a \"{account}\" -l \"{SERVICE} {account}\" -X "
+    );
+    for byte in content.bytes() {
+        let _ = write!(command, "{byte:02x}");
+    }
+    command.push('\n');
+    let mut child = Command::new("security")
+        .arg("-i")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(missing_tool("security"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(command.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() && output.stderr.is_empty() {
+        Ok(())
+    } else {
+        Err(tool_error("security", &output.stderr))
+    }
+}
+
+#[cfg(unix)]
+fn missing_tool(program: &'static str) -> impl FnOnce(io::Error) -> io::Error {
+    move |err| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("{program} is required for --secret-keyring: {err}"),
+        )
+    }
+}
+
+#[cfg(unix)]
+fn tool_error(program: &str, stderr: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{program}: {}", String::from_utf8_lossy(stderr).trim()),
+    )
+}
+
+#[cfg(windows)]
+fn target_name(account: &str) -> Vec<u16> {
+    use std::{ffi::OsStr, os::windows::ffi::OsStrExt as _};
+
+    OsStr::new(&format!("{SERVICE}:{account}"))
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn read(account: &str) -> io::Result<String> {
+    use std::{ptr, slice};
+
+    use winapi::{
+        shared::winerror::ERROR_NOT_FOUND,
+        um::wincred::{CredFree, CredReadW, CRED_TYPE_GENERIC, PCREDENTIALW},
+    };
+
+    let target_name = target_name(account);
+    let mut credential: PCREDENTIALW = ptr::null_mut();
+    if unsafe { CredReadW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+        let err = io::Error::last_os_error();
+        return Err(if err.raw_os_error() == Some(ERROR_NOT_FOUND as i32) {
+            io::ErrorKind::NotFound.into()
+        } else {
+            err
+        });
+    }
+    let blob = unsafe {
+        let blob = slice::from_raw_parts(
+            (*credential).CredentialBlob,
+            (*credential).CredentialBlobSize as usize,
+        )
+        .to_vec();
+        CredFree(credential.cast());
+        blob
+    };
+    String::from_utf8(blob).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(windows)]
+pub fn write(account: &str, content: &str) -> io::Result<()> {
+    use std::mem;
+
+    use winapi::um::wincred::{
+        CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+    };
+
+    let mut target_name = target_name(account);
+    let mut blob = content.as_bytes().to_vec();
+    let mut credential: CREDENTIALW = unsafe { mem::zeroed() };
+    credential.Type = CRED_TYPE_GENERIC;
+    credential.TargetName = target_name.as_mut_ptr();
+    credential.CredentialBlobSize = blob.len() as _;
+    credential.CredentialBlob = blob.as_mut_ptr();
+    credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+    if unsafe { CredWriteW(&mut credential, 0) } == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn read(_account: &str) -> io::Result<String> {
+    Err(unsupported())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn write(_account: &str, _content: &str) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--secret-keyring is not supported on this platform",
+    )
+}
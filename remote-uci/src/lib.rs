@@ -1,71 +1,1108 @@
+mod admin;
+mod affinity;
+mod annotate;
+mod api;
+mod bench;
+mod book;
+mod browser;
+mod builder;
+mod cache;
+mod cluster;
+mod compare;
+mod deflate;
 mod engine;
+mod error;
+mod gpu;
+mod health;
+mod ipfilter;
+mod keyring;
+mod lichess;
+mod listen;
+mod logging;
+mod metrics;
+mod mock;
+mod nnue;
+mod notify;
+mod numa;
+mod official;
+mod online_tb;
+mod origin;
+mod power;
+mod presence;
+mod priority;
+mod profile;
+mod qr;
+mod reload;
+mod replay;
+mod sandbox;
+mod schedule;
+#[cfg(windows)]
+mod service;
+mod setup;
+mod shutdown;
+mod split;
+mod stats;
+mod stockfish;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod syzygy;
+mod thermal;
+mod throttle;
+mod tokens;
+mod transcript;
+#[cfg(feature = "tray")]
+mod tray;
+mod tui;
+mod tunnel;
 pub mod uci;
+mod upgrade;
+mod variant;
+mod wdl;
 mod ws;
+mod xboard;
+
+pub use annotate::annotate;
+pub use browser::open_browser;
+pub use builder::{make_server, EngineHandle, RemoteUciBuilder};
+pub use error::RemoteUciError;
+pub use mock::MockEngine;
+pub use qr::terminal_qr_code;
+pub use replay::replay;
+#[cfg(windows)]
+pub use service::{service, ServiceAction};
+pub use setup::setup;
+pub use shutdown::Shutdown;
+pub use stats::print as stats;
+pub use stockfish::download as download_stockfish;
+#[cfg(target_os = "linux")]
+pub use systemd::install as systemd_install;
+pub use syzygy::download as download_tablebases;
+pub use tui::LogBuffer;
+pub use upgrade::request as upgrade;
 
 use std::{
     cmp::min,
+    collections::BTreeMap,
     error::Error,
-    fs, io,
-    net::{SocketAddr, TcpListener},
+    fs, io, mem,
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
     ops::Not,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
     thread,
+    time::Duration,
 };
 
 use axum::{
-    response::Redirect,
-    routing::{get, IntoMakeService},
+    body::Body,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
     Router,
 };
-use clap::Parser;
-use engine::EngineParameters;
-use hyper::server::conn::AddrIncoming;
-use listenfd::ListenFd;
-use serde::Serialize;
-use serde_with::{serde_as, CommaSeparator, DisplayFromStr, StringWithSeparator};
+use clap::{Parser, Subcommand};
+use is_terminal::IsTerminal as _;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, CommaSeparator, DisplayFromStr, OneOrMany, StringWithSeparator};
 use sysinfo::{RefreshKind, System, SystemExt};
+use tokio::time::sleep;
 
 use crate::{
-    engine::Engine,
-    ws::{Secret, SharedEngine},
+    admin::Endpoint,
+    affinity::CpuSet,
+    book::BookMode,
+    cluster::WorkerUrl,
+    engine::{Engine, EngineAddr, EngineParameters, HashPolicy, Launch},
+    gpu::GpuBackend,
+    ipfilter::IpRange,
+    logging::LogFormat,
+    numa::{NumaNode, NumaPolicy},
+    power::BatteryLimits,
+    priority::{PriorityClass, ProcessPriority},
+    profile::Profile,
+    sandbox::{SandboxMode, SandboxPolicy},
+    schedule::Schedule,
+    syzygy::Tablebases,
+    tokens::{parse_secret_file, NamedSecret, Permissions},
+    tunnel::TunnelKind,
+    uci::{OptionPolicy, SearchLimits, UciOptionName},
+    ws::{Secret, SecretStorage, SecretStore, SessionPolicy, SocketOpts},
+    xboard::Protocol,
 };
 
+/// UCI options to set when starting an engine, in order.
+type UciOptions = Vec<(UciOptionName, Option<String>)>;
 
 /// External UCI engine provider for lichess.org.
-#[derive(Debug, Parser)]
+#[serde_as]
+#[derive(Debug, Default, Clone, Parser, Deserialize)]
 #[clap(version)]
+#[serde(default, rename_all = "kebab-case")]
 pub struct Opts {
+    #[clap(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+    /// Read options from a TOML file. Flags given on the command line take
+    /// precedence, like --wdl=false to turn off wdl = true from the file.
+    /// Profiles are merged by name. On SIGHUP or POST /admin/reload, changes of search
+    /// limits, allowed options, UCI options and secret files are applied to
+    /// new sessions.
+    #[clap(long)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
     #[clap(flatten)]
+    #[serde(flatten)]
     engine: EngineOpts,
-    /// Bind server on this socket address.
+    /// Serve an additional engine at /socket/NAME, with its own registration
+    /// URL and secret. May be repeated.
+    #[clap(long, value_name = "NAME=PATH")]
+    engine_spec: Vec<EngineSpec>,
+    /// Bind server on this socket address. May be repeated, for example
+    /// with 0.0.0.0:9670 and [::]:9670 to accept both IPv4 and IPv6.
     #[clap(long)]
-    bind: Option<SocketAddr>,
+    #[serde_as(as = "OneOrMany<_>")]
+    bind: Vec<SocketAddr>,
+    /// Also accept connections on a unix domain socket at this path, for
+    /// example from a reverse proxy on the same machine. Unless --bind is
+    /// given, the server then does not listen on TCP, and the public URL
+    /// must be set with --advertise-url. Not supported on Windows.
+    #[clap(long)]
+    bind_unix: Option<PathBuf>,
     /// The publically accessible address used when registering with lichess
     #[clap(long)]
     publish_addr: Option<String>,
     /// Pass this flag if the public_addr endpoint uses TLS
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    publish_addr_tls: Option<bool>,
+    /// The public URL of the server used when registering with lichess, for
+    /// example wss://engine.example.com/socket behind a reverse proxy with
+    /// TLS. The server still binds locally. Additional engines are served
+    /// at the same URL with /NAME appended. Takes precedence over
+    /// --publish-addr and --publish-addr-tls.
+    #[clap(long)]
+    advertise_url: Option<String>,
+    /// Also allow web pages from this origin to connect, in addition to
+    /// https://lichess.org and pages served from localhost. Use * to allow
+    /// any origin. May be repeated.
+    #[clap(long, value_name = "ORIGIN")]
+    allow_origin: Vec<String>,
+    /// Only allow clients from this IP address or CIDR range to connect to
+    /// the server. May be repeated.
+    #[clap(long, value_name = "CIDR")]
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    allow_ip: Vec<IpRange>,
+    /// Do not allow clients from this IP address or CIDR range to connect
+    /// to the server, even if allowed by --allow-ip. May be repeated.
+    #[clap(long, value_name = "CIDR")]
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    deny_ip: Vec<IpRange>,
+    /// Take client addresses for --allow-ip and --deny-ip from the
+    /// X-Forwarded-For header set by a reverse proxy. Only use this if all
+    /// connections come through the proxy. Implied by --tunnel.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    trust_proxy: Option<bool>,
+    /// Make the server reachable from outside via a tunnel, and use its
+    /// public address instead of --publish-addr. With a public address,
+    /// the redirect to the registration URL at / and its QR code at /qr are
+    /// only served to clients on this machine.
+    #[clap(long, value_enum)]
+    tunnel: Option<TunnelKind>,
+    /// Command for --tunnel custom. {port} and {url} are replaced with the
+    /// local port and URL of the server.
+    #[clap(long)]
+    tunnel_command: Option<String>,
+    /// Offer permessage-deflate compression to websocket clients, to reduce
+    /// bandwidth of engine output over slow links.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    ws_compression: Option<bool>,
+    /// How often to ping websocket clients to keep the connection alive
+    /// through routers that drop idle connections [default: 10s].
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    ws_ping_interval: Option<humantime::Duration>,
+    /// End the session, stopping any search, when a websocket client does
+    /// not answer a ping within this time [default: --ws-ping-interval].
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    ws_timeout: Option<humantime::Duration>,
+    /// Answer unknown or malformed commands from websocket clients with an
+    /// error message and ignore them, instead of ending the session.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    strict_uci: Option<bool>,
+    /// Tell websocket clients the round trip time to the server, with info
+    /// strings like "latency 25 ms", so that they can account for it.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    report_latency: Option<bool>,
+    /// Keep sessions running for this long (for example 30s) after the
+    /// connection to the client was lost, so that the client can reconnect
+    /// and continue where it left off, receiving the output of the engine
+    /// in the meantime. Sessions send "info string resume TOKEN" when they
+    /// start, and clients reconnect with the same secret and resume=TOKEN.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    resume_window: Option<humantime::Duration>,
+    /// Profile for sessions that do not choose one with the profile
+    /// parameter of the websocket URL, like /socket?profile=deep.
     #[clap(long)]
-    publish_addr_tls: bool,
+    default_profile: Option<String>,
+    /// Profiles by name, defined in the config file as sections like
+    /// `[profile.deep]` with threads, hash, max-multipv and options.
+    #[clap(skip)]
+    profile: BTreeMap<String, Profile>,
+    /// When clients search with clock times, leave this much time per move
+    /// (for example 100ms) in addition to the round trip time measured on
+    /// the websocket, so that bots do not lose on time. Sets Move Overhead
+    /// if the engine supports it, and otherwise shortens the clock times.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    move_overhead: Option<humantime::Duration>,
+    /// Send info lines to websocket clients at most once per this many
+    /// milliseconds. Lines that are superseded in the meantime are dropped.
+    #[clap(long)]
+    info_interval_ms: Option<u64>,
     /// Overwrite engine name.
     #[clap(long)]
     name: Option<String>,
+    /// Restrict what locally started engines can access. Only supported on
+    /// Linux [default: off].
+    #[clap(long, value_enum)]
+    sandbox: Option<SandboxMode>,
+    /// Niceness of locally started engines on Unix, from -20 (highest
+    /// priority) to 19 (lowest), so that analysis in the background does not
+    /// slow down other work.
+    #[clap(long, allow_hyphen_values = true)]
+    engine_nice: Option<i32>,
+    /// Priority class of locally started engines on Windows [default:
+    /// normal].
+    #[clap(long, value_enum)]
+    engine_priority: Option<PriorityClass>,
+    /// Run locally started engines only on these processors, like 0-27, to
+    /// keep others free for the system and the server. Also limits the
+    /// number of threads. Only supported on Linux.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    engine_cpus: Option<CpuSet>,
+    /// Placement of engines on machines with multiple NUMA nodes: auto to
+    /// let engines like Stockfish handle it, interleave to spread memory over
+    /// all nodes, or a node like node0 to run only on its processors and
+    /// memory, with threads and hash limited accordingly. --engine-cpus takes
+    /// precedence. Only supported on Linux.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    numa: Option<NumaPolicy>,
+    /// Lower limits while running on battery, like threads=4,hash=512.
+    /// Clients see them when they reconnect.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    on_battery: Option<BatteryLimits>,
+    /// Reduce engine threads while the processor is hotter than this (°C),
+    /// until it has cooled down by 5 °C.
+    #[clap(long)]
+    thermal_limit: Option<f32>,
+    /// Number of threads while the processor is too hot [default: 1].
+    #[clap(long)]
+    thermal_threads: Option<u32>,
+    /// Protocol spoken by the engines [default: uci].
+    #[clap(long, value_enum)]
+    protocol: Option<Protocol>,
     /// Limit number of threads.
     #[clap(long)]
     max_threads: Option<u32>,
     /// Limit size of hash table (MiB).
     #[clap(long)]
     max_hash: Option<u32>,
+    /// Limit depth of searches requested by clients. Infinite searches
+    /// stop at this depth.
+    #[clap(long)]
+    max_depth: Option<u32>,
+    /// Limit number of nodes of searches requested by clients.
+    #[clap(long)]
+    max_nodes: Option<u64>,
+    /// Limit time of searches requested by clients (for example 30s).
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    max_movetime: Option<humantime::Duration>,
+    /// Limit number of lines that clients may analyse at the same time
+    /// with MultiPV.
+    #[clap(long)]
+    max_multipv: Option<NonZeroU32>,
+    /// Number of lines to analyse at the start of each session, unless the
+    /// client asks for more or less [default: engine default].
+    #[clap(long)]
+    default_multipv: Option<NonZeroU32>,
+    /// Allow clients to set this UCI option, in addition to common options
+    /// like Hash, Threads and MultiPV. May be repeated.
+    #[clap(long, value_name = "NAME")]
+    allow_option: Vec<String>,
+    /// Do not allow clients to set this UCI option, even if it is allowed
+    /// by default. May be repeated.
+    #[clap(long, value_name = "NAME")]
+    deny_option: Vec<String>,
+    /// Only serve these variants, by lichess or engine name (for example
+    /// crazyhouse,atomic). Lichess names are translated to the names used
+    /// by the engine.
+    #[clap(long, value_delimiter = ',', value_name = "VARIANTS")]
+    variants: Vec<String>,
+    /// Serve a variant with another engine, for example Fairy-Stockfish for
+    /// crazyhouse. Sessions switch to that engine when the client selects
+    /// the variant. May be repeated.
+    #[clap(long, value_name = "VARIANT=PATH")]
+    variant_engine: Vec<VariantEngine>,
+    /// Cut principal variations at the first illegal move before sending
+    /// them to clients. Some engines emit illegal moves after hash
+    /// collisions.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    validate_pv: Option<bool>,
+    /// Report win/draw/loss statistics with scores. Enables UCI_ShowWDL if
+    /// the engine supports it, and otherwise estimates them from centipawn
+    /// scores with the win rate model of Stockfish.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    wdl: Option<bool>,
+    /// Rescale centipawn scores of older Stockfish releases, so that 100
+    /// centipawns mean a 50% win rate like in current releases. Scores of
+    /// other engines are not changed.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    normalize_scores: Option<bool>,
+    /// Keep the final output of up to this many searches with a fixed
+    /// depth, and answer repeated searches of the same positions without
+    /// the engine [default: 10000 with --analysis-cache-file].
+    #[clap(long, value_name = "ENTRIES")]
+    analysis_cache: Option<usize>,
+    /// Store cached analysis in this file, so that it survives restarts.
+    #[clap(long)]
+    analysis_cache_file: Option<PathBuf>,
+    /// Look up positions with up to 7 pieces that are not covered by
+    /// --syzygy-path in the lichess tablebase, and send the exact result
+    /// to clients as info string before the engine output.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    online_tb: Option<bool>,
+    /// Polyglot opening book. For standard chess positions in the book,
+    /// book moves are reported to clients.
+    #[clap(long)]
+    book: Option<PathBuf>,
+    /// Consult the book only up to this many moves into the game.
+    #[clap(long, value_name = "MOVES")]
+    book_depth: Option<u32>,
+    /// Whether to report book moves as info string before the engine
+    /// output, or to answer searches with book moves as lines of the
+    /// analysis without the engine [default: advisory].
+    #[clap(long, value_enum)]
+    book_mode: Option<BookMode>,
+    /// Keep statistics of the work done for clients in this file, so that
+    /// they add up over restarts. Show them with `remote-uci stats`, or
+    /// along with statistics of recent sessions at /stats.
+    #[clap(long)]
+    stats_file: Option<PathBuf>,
+    /// Number of processes to start for each engine, so that several
+    /// sessions can be served at the same time. The hash table limit is
+    /// split among them [default: 1].
+    #[clap(long)]
+    pool_size: Option<NonZeroUsize>,
+    /// Keep a second engine process started and initialized, so that a
+    /// session continues without delay if the engine process crashes or
+    /// stops responding. Needs memory for a second hash table.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    standby: Option<bool>,
+    /// Run this engine in addition, on the same positions, and send its
+    /// lines to clients as info string, tagged with its name, to compare
+    /// evaluations.
+    #[clap(long)]
+    engine_b: Option<PathBuf>,
+    /// Start this many additional engine processes, and search MultiPV
+    /// analysis with one line in each process, restricted to a share of
+    /// the root moves. The best lines of all processes are sent as one
+    /// MultiPV analysis. The hash table limit is split among them.
+    #[clap(long, value_name = "PROCESSES")]
+    split_multipv: Option<usize>,
+    /// Working directory of the engine process, for engines that look for
+    /// their files in the current directory.
+    #[clap(long)]
+    engine_cwd: Option<PathBuf>,
+    /// Pass a command line argument to the engine process. May be repeated.
+    #[clap(long, value_name = "ARG", allow_hyphen_values = true)]
+    engine_arg: Vec<String>,
+    /// Set an environment variable for the engine process. May be repeated.
+    #[clap(long, value_name = "KEY=VALUE")]
+    engine_env: Vec<EngineEnv>,
+    /// Set a UCI option when starting the engine. May be repeated.
+    #[clap(long, value_name = "NAME=VALUE")]
+    uci_option: Vec<UciOptionArg>,
+    /// Neural network weights file, passed to the engine as WeightsFile
+    /// (for example for Lc0).
+    #[clap(long)]
+    weights: Option<PathBuf>,
+    /// NNUE network file, passed to the engine as EvalFile (for example for
+    /// Stockfish). An official network can be given by name, like
+    /// nn-6877cd24400e.nnue, or by the SHA-256 prefix in its name, and is
+    /// downloaded unless the file exists.
+    #[clap(long)]
+    eval_file: Option<PathBuf>,
+    /// Directory with Syzygy tablebases, passed to the engine as
+    /// SyzygyPath. May be repeated. Defaults to tablebases installed with
+    /// `remote-uci download-tablebases`.
+    #[clap(long)]
+    syzygy_path: Vec<PathBuf>,
     /// Provide file with secret token to use instead of a random one.
+    /// Further lines may add named secrets for individual clients, like
+    /// `phone 0123456789abcdef max-threads=4 max-hash=256 variants=chess`.
     #[clap(long)]
     secret_file: Option<PathBuf>,
-    /// Promise that the selected engine is a recent official Stockfish
-    /// release.
+    /// Keep the secret in the credential store of the operating system
+    /// instead of a file: the Secret Service (requires secret-tool), the
+    /// macOS Keychain or the Windows Credential Manager. It is generated on
+    /// first run.
+    #[clap(
+        long,
+        conflicts_with = "secret-file",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    secret_keyring: Option<bool>,
+    /// Replace the secret with a new random one at this interval (for
+    /// example 24h). The previous secret is still accepted for an hour.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    secret_rotate_interval: Option<humantime::Duration>,
+    /// Ban clients for a while after this many wrong secrets in a row.
+    /// Clients are always slowed down after a few wrong secrets.
+    #[clap(long)]
+    secret_ban_after: Option<u32>,
+    /// How long to ban clients for --secret-ban-after [default: 1h].
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    secret_ban_duration: Option<humantime::Duration>,
+    /// Enable the admin API under /admin, authenticated with the secret in
+    /// this file as a bearer token. The file is created if it does not
+    /// exist.
+    #[clap(long)]
+    admin_secret_file: Option<PathBuf>,
+    /// Also serve analysis requests using the lichess external engine API,
+    /// registering engines with this OAuth token (scope engine:write).
+    #[clap(long, env = "LICHESS_TOKEN", hide_env_values = true)]
+    lichess_token: Option<String>,
+    /// Base URL of the lichess API.
+    #[clap(long, hide = true)]
+    lichess_url: Option<String>,
+    /// Base URL of the lichess external engine work API.
     #[clap(long, hide = true)]
-    promise_official_stockfish: bool,
+    lichess_engine_url: Option<String>,
+    /// What to do when another client connects while the engine is in use
+    /// [default: preempt].
+    #[clap(long, value_enum)]
+    session_policy: Option<SessionPolicy>,
+    /// When to clear the hash table of the engine. Keeping it makes
+    /// repeated analysis faster, clearing it makes evaluations reproducible
+    /// [default: clear-per-game].
+    #[clap(long, value_enum)]
+    hash_policy: Option<HashPolicy>,
+    /// Make analysis reproducible: use a single thread, clear the hash
+    /// table before every search, and reject options that change search
+    /// results, like Threads, Hash and strength limits.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    deterministic: Option<bool>,
+    /// Accept sessions only at these times, like 22:00-07:00,sat,sun for
+    /// nights and weekends. Outside of them, clients are told when to come
+    /// back and engine processes are stopped.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    available: Option<Schedule>,
+    /// Pause searching engines while the local user is active, until keyboard
+    /// and mouse have not been used for this long (for example 5m). Requires
+    /// xprintidle on X11, or GNOME on Wayland.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    only_when_idle: Option<humantime::Duration>,
+    /// Restart engines that produce no output for this long (for example
+    /// 60s) while searching or answering isready. Clients are informed.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    engine_timeout: Option<humantime::Duration>,
+    /// Give up starting an engine if it is not ready after this long (for
+    /// example 2m), like when loading large networks from a slow disk
+    /// [default: no limit].
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    init_timeout: Option<humantime::Duration>,
+    /// Try this many more times to start an engine that did not get ready
+    /// in time or exited during initialization [default: 0].
+    #[clap(long)]
+    init_retries: Option<u32>,
+    /// Stop the engine process after it has not been used for this long
+    /// (for example 300s). It is started again when needed.
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    idle_timeout: Option<humantime::Duration>,
+    /// On shutdown, wait this long for running searches to finish and
+    /// clients to disconnect [default: 10s].
+    #[clap(long)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    shutdown_timeout: Option<humantime::Duration>,
+    /// Format of log output on stderr [default: pretty]. Not read from the
+    /// config file.
+    #[clap(long, value_enum)]
+    #[serde(skip)]
+    log_format: Option<LogFormat>,
+    /// Log filter, for example info, debug or remote_uci=trace. Defaults to
+    /// $REMOTE_UCI_LOG or info. Not read from the config file.
+    #[clap(long)]
+    #[serde(skip)]
+    log_level: Option<String>,
+    /// Write a transcript of the UCI traffic of each session to a file in
+    /// this directory.
+    #[clap(long)]
+    session_log_dir: Option<PathBuf>,
+    /// Open the registration URL in the browser after startup. This is the
+    /// default when running in an interactive terminal. Not read from the
+    /// config file.
+    #[clap(long)]
+    #[serde(skip)]
+    open: bool,
+    /// Do not open the registration URL in the browser.
+    #[clap(long, conflicts_with = "open")]
+    #[serde(skip)]
+    no_open: bool,
+    /// Also print the registration URL as a QR code, for registering from a
+    /// phone. The QR code is always served as an image at /qr, only to
+    /// clients on this machine if the server has a public address. Not read
+    /// from the config file.
+    #[clap(long)]
+    #[serde(skip)]
+    qr: bool,
+    /// Show a dashboard with the state of the engines in the terminal,
+    /// instead of printing log lines. Not read from the config file.
+    #[clap(long)]
+    #[serde(skip)]
+    tui: bool,
+    /// Show an icon in the system tray, with a menu to copy the
+    /// registration URL, pause serving, restart the engine, and quit. Not
+    /// read from the config file.
+    #[cfg(feature = "tray")]
+    #[clap(long)]
+    #[serde(skip)]
+    tray: bool,
+    /// Show desktop notifications when a client starts or ends a session,
+    /// and when an engine crashes.
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    notify: Option<bool>,
+    /// Promise that the selected engine is a recent official Stockfish
+    /// release. The promise is only announced if the name and bench
+    /// signature of the engine match a known release.
+    #[clap(
+        long,
+        hide = true,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    promise_official_stockfish: Option<bool>,
+}
+
+impl Opts {
+    fn or(self, other: Opts) -> Opts {
+        Opts {
+            command: self.command.or(other.command),
+            config: self.config.or(other.config),
+            engine: self.engine.or(other.engine),
+            engine_spec: or_vec(self.engine_spec, other.engine_spec),
+            bind: or_vec(self.bind, other.bind),
+            bind_unix: self.bind_unix.or(other.bind_unix),
+            publish_addr: self.publish_addr.or(other.publish_addr),
+            publish_addr_tls: self.publish_addr_tls.or(other.publish_addr_tls),
+            advertise_url: self.advertise_url.or(other.advertise_url),
+            allow_origin: or_vec(self.allow_origin, other.allow_origin),
+            allow_ip: or_vec(self.allow_ip, other.allow_ip),
+            deny_ip: or_vec(self.deny_ip, other.deny_ip),
+            trust_proxy: self.trust_proxy.or(other.trust_proxy),
+            tunnel: self.tunnel.or(other.tunnel),
+            tunnel_command: self.tunnel_command.or(other.tunnel_command),
+            ws_compression: self.ws_compression.or(other.ws_compression),
+            ws_ping_interval: self.ws_ping_interval.or(other.ws_ping_interval),
+            ws_timeout: self.ws_timeout.or(other.ws_timeout),
+            strict_uci: self.strict_uci.or(other.strict_uci),
+            report_latency: self.report_latency.or(other.report_latency),
+            resume_window: self.resume_window.or(other.resume_window),
+            default_profile: self.default_profile.or(other.default_profile),
+            profile: {
+                let mut profile = other.profile;
+                profile.extend(self.profile);
+                profile
+            },
+            move_overhead: self.move_overhead.or(other.move_overhead),
+            info_interval_ms: self.info_interval_ms.or(other.info_interval_ms),
+            name: self.name.or(other.name),
+            sandbox: self.sandbox.or(other.sandbox),
+            engine_nice: self.engine_nice.or(other.engine_nice),
+            engine_priority: self.engine_priority.or(other.engine_priority),
+            engine_cpus: self.engine_cpus.or(other.engine_cpus),
+            numa: self.numa.or(other.numa),
+            on_battery: self.on_battery.or(other.on_battery),
+            thermal_limit: self.thermal_limit.or(other.thermal_limit),
+            thermal_threads: self.thermal_threads.or(other.thermal_threads),
+            protocol: self.protocol.or(other.protocol),
+            max_threads: self.max_threads.or(other.max_threads),
+            max_hash: self.max_hash.or(other.max_hash),
+            max_depth: self.max_depth.or(other.max_depth),
+            max_nodes: self.max_nodes.or(other.max_nodes),
+            max_movetime: self.max_movetime.or(other.max_movetime),
+            allow_option: or_vec(self.allow_option, other.allow_option),
+            deny_option: or_vec(self.deny_option, other.deny_option),
+            variants: or_vec(self.variants, other.variants),
+            variant_engine: or_vec(self.variant_engine, other.variant_engine),
+            validate_pv: self.validate_pv.or(other.validate_pv),
+            wdl: self.wdl.or(other.wdl),
+            normalize_scores: self.normalize_scores.or(other.normalize_scores),
+            analysis_cache: self.analysis_cache.or(other.analysis_cache),
+            analysis_cache_file: self.analysis_cache_file.or(other.analysis_cache_file),
+            online_tb: self.online_tb.or(other.online_tb),
+            book: self.book.or(other.book),
+            book_depth: self.book_depth.or(other.book_depth),
+            book_mode: self.book_mode.or(other.book_mode),
+            stats_file: self.stats_file.or(other.stats_file),
+            pool_size: self.pool_size.or(other.pool_size),
+            standby: self.standby.or(other.standby),
+            engine_b: self.engine_b.or(other.engine_b),
+            split_multipv: self.split_multipv.or(other.split_multipv),
+            engine_cwd: self.engine_cwd.or(other.engine_cwd),
+            engine_arg: or_vec(self.engine_arg, other.engine_arg),
+            engine_env: or_vec(self.engine_env, other.engine_env),
+            uci_option: or_vec(self.uci_option, other.uci_option),
+            weights: self.weights.or(other.weights),
+            eval_file: self.eval_file.or(other.eval_file),
+            syzygy_path: or_vec(self.syzygy_path, other.syzygy_path),
+            secret_file: self.secret_file.or(other.secret_file),
+            secret_keyring: self.secret_keyring.or(other.secret_keyring),
+            secret_rotate_interval: self.secret_rotate_interval.or(other.secret_rotate_interval),
+            secret_ban_after: self.secret_ban_after.or(other.secret_ban_after),
+            secret_ban_duration: self.secret_ban_duration.or(other.secret_ban_duration),
+            admin_secret_file: self.admin_secret_file.or(other.admin_secret_file),
+            lichess_token: self.lichess_token.or(other.lichess_token),
+            lichess_url: self.lichess_url.or(other.lichess_url),
+            lichess_engine_url: self.lichess_engine_url.or(other.lichess_engine_url),
+            session_policy: self.session_policy.or(other.session_policy),
+            hash_policy: self.hash_policy.or(other.hash_policy),
+            deterministic: self.deterministic.or(other.deterministic),
+            available: self.available.or(other.available),
+            only_when_idle: self.only_when_idle.or(other.only_when_idle),
+            engine_timeout: self.engine_timeout.or(other.engine_timeout),
+            max_multipv: self.max_multipv.or(other.max_multipv),
+            default_multipv: self.default_multipv.or(other.default_multipv),
+            init_timeout: self.init_timeout.or(other.init_timeout),
+            init_retries: self.init_retries.or(other.init_retries),
+            idle_timeout: self.idle_timeout.or(other.idle_timeout),
+            shutdown_timeout: self.shutdown_timeout.or(other.shutdown_timeout),
+            log_format: self.log_format.or(other.log_format),
+            log_level: self.log_level.or(other.log_level),
+            session_log_dir: self.session_log_dir.or(other.session_log_dir),
+            open: self.open || other.open,
+            no_open: self.no_open || other.no_open,
+            qr: self.qr || other.qr,
+            tui: self.tui || other.tui,
+            #[cfg(feature = "tray")]
+            tray: self.tray || other.tray,
+            notify: self.notify.or(other.notify),
+            promise_official_stockfish: self
+                .promise_official_stockfish
+                .or(other.promise_official_stockfish),
+        }
+    }
+
+    pub fn command(&self) -> Option<Command> {
+        self.command.clone()
+    }
+
+    /// Whether to open registration URLs in the browser.
+    pub fn open(&self) -> bool {
+        self.open || (!self.no_open && io::stdout().is_terminal())
+    }
+
+    /// Whether to print registration URLs as QR codes.
+    pub fn qr(&self) -> bool {
+        self.qr
+    }
+
+    /// Whether to show an icon in the system tray.
+    #[cfg(feature = "tray")]
+    pub fn tray(&self) -> bool {
+        self.tray
+    }
+
+    /// Installs the log subscriber. With --tui, log lines are collected in
+    /// the returned buffer for the dashboard.
+    pub fn init_logging(&self) -> Result<Option<LogBuffer>, Box<dyn Error>> {
+        let dashboard = (self.tui && io::stdout().is_terminal()).then(LogBuffer::default);
+        logging::init(
+            self.log_format.unwrap_or_default(),
+            self.log_level.as_deref(),
+            dashboard.clone(),
+        )?;
+        if self.tui && dashboard.is_none() {
+            tracing::warn!("Not a terminal, ignoring --tui");
+        }
+        Ok(dashboard)
+    }
+
+    fn with_config_file(self) -> Result<Opts, Box<dyn Error>> {
+        let path = match self.config {
+            Some(ref path) => path,
+            None => return Ok(self),
+        };
+        let file = fs::read_to_string(path).map_err(|err| {
+            tracing::error!("Could not read config file {path:?}: {err}");
+            err
+        })?;
+        let file = toml::from_str(&file).map_err(|err| {
+            tracing::error!("Could not parse config file {path:?}: {err}");
+            err
+        })?;
+        tracing::debug!("Loaded config file {path:?}");
+        Ok(self.or(file))
+    }
+
+    /// UCI options to set when starting engines, including WeightsFile and
+    /// SyzygyPath. Also returns the maximum number of pieces of the
+    /// tablebases, if any.
+    fn engine_options(&self) -> Result<(UciOptions, Option<usize>), Box<dyn Error>> {
+        let mut options: Vec<_> = self
+            .uci_option
+            .iter()
+            .map(|option| (option.name.clone(), option.value.clone()))
+            .collect();
+        if let Some(ref weights) = self.weights {
+            options.push((
+                UciOptionName("WeightsFile".to_owned()),
+                Some(weights.to_string_lossy().into_owned()),
+            ));
+        }
+        if let Some(ref eval_file) = self.eval_file {
+            let path = nnue::locate(eval_file)
+                .ok_or_else(|| format!("network file {eval_file:?} does not exist"))?;
+            options.push((
+                UciOptionName("EvalFile".to_owned()),
+                Some(path.to_string_lossy().into_owned()),
+            ));
+        }
+        let syzygy_paths = self.syzygy_paths();
+        if syzygy_paths.is_empty() {
+            return Ok((options, None));
+        }
+        let tablebases = Tablebases::scan(&syzygy_paths)
+            .and_then(|tablebases| {
+                options.push((
+                    UciOptionName("SyzygyPath".to_owned()),
+                    Some(syzygy::option_value(&syzygy_paths)?),
+                ));
+                Ok(tablebases)
+            })
+            .map_err(|err| {
+                tracing::error!("Could not use tablebases: {err}");
+                err
+            })?;
+        tracing::info!(
+            "Found {} WDL and {} DTZ tablebase files for up to {} pieces",
+            tablebases.wdl,
+            tablebases.dtz,
+            tablebases.max_pieces
+        );
+        Ok((options, Some(tablebases.max_pieces)))
+    }
+
+    fn launch(&self) -> Launch {
+        Launch {
+            cwd: self.engine_cwd.clone(),
+            args: self.engine_arg.clone(),
+            env: self
+                .engine_env
+                .iter()
+                .map(|EngineEnv { key, value }| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Directories with Syzygy tablebases for the engine.
+    fn syzygy_paths(&self) -> Vec<PathBuf> {
+        if self.syzygy_path.is_empty() {
+            syzygy::installed().into_iter().collect()
+        } else {
+            self.syzygy_path.clone()
+        }
+    }
+
+    /// Where to keep the secrets of the default engine.
+    fn secret_storage(&self) -> Result<Option<SecretStorage>, Box<dyn Error>> {
+        match (self.secret_keyring.unwrap_or(false), &self.secret_file) {
+            (true, Some(_)) => {
+                Err("--secret-keyring and --secret-file are mutually exclusive".into())
+            }
+            (true, None) => Ok(Some(SecretStorage::Keyring("secret".to_owned()))),
+            (false, secret_file) => Ok(secret_file.clone().map(SecretStorage::File)),
+        }
+    }
+
+    /// Parameters of an engine that is used locally, like for replaying
+    /// transcripts, rather than served to clients.
+    fn local_engine_parameters(self, gpu: Option<GpuBackend>) -> EngineParameters {
+        EngineParameters {
+            max_threads: self.max_threads(),
+            max_hash: self.max_hash(),
+            launch: self.launch(),
+            limits: self.search_limits(),
+            option_policy: self.option_policy(),
+            priority: self.engine_priority(),
+            cpus: self.engine_cpus.clone(),
+            numa: self.numa,
+            battery: self.on_battery,
+            thermal: None,
+            engine_timeout: self.engine_timeout.map(Into::into),
+            move_overhead: self.move_overhead.map(Into::into),
+            max_multipv: self.max_multipv.map(NonZeroU32::get),
+            default_multipv: self.default_multipv.map(NonZeroU32::get),
+            init_timeout: self.init_timeout.map(Into::into),
+            init_retries: self.init_retries.unwrap_or(0),
+            options: self
+                .uci_option
+                .into_iter()
+                .map(|option| (option.name, option.value))
+                .collect(),
+            gpu,
+            protocol: self.protocol.unwrap_or_default(),
+            sandbox: SandboxPolicy {
+                mode: self.sandbox.unwrap_or_default(),
+                paths: self.syzygy_path,
+            },
+            variants: self.variants,
+            validate_pv: self.validate_pv.unwrap_or(false),
+            notify: false,
+            wdl: self.wdl.unwrap_or(false),
+            normalize_scores: self.normalize_scores.unwrap_or(false),
+            cache: None,
+            compare: None,
+            standby: false,
+            split_multipv: None,
+            book: None,
+            online_tb: None,
+            stats: None,
+            hash_policy: self.hash_policy.unwrap_or_default(),
+            deterministic: self.deterministic.unwrap_or(false),
+        }
+    }
+
+    fn max_threads(&self) -> u32 {
+        if self.deterministic.unwrap_or(false) {
+            return 1;
+        }
+        let cpus = match (&self.engine_cpus, self.numa_node()) {
+            (Some(cpus), _) => cpus.count(),
+            (None, Some(node)) => node.cpus.count(),
+            (None, None) => usize::MAX,
+        };
+        min(
+            min(self.max_threads.unwrap_or(u32::MAX), available_threads()),
+            u32::try_from(cpus).unwrap_or(u32::MAX),
+        )
+    }
+
+    fn max_hash(&self) -> u32 {
+        let memory = self
+            .numa_node()
+            .map_or_else(available_memory, |node| node.memory);
+        min(
+            self.max_hash.unwrap_or(u32::MAX),
+            u32::try_from(memory).unwrap_or(u32::MAX),
+        )
+    }
+
+    /// The single node to run engines on, if any.
+    fn numa_node(&self) -> Option<NumaNode> {
+        match self.numa {
+            Some(NumaPolicy::Node(id)) => numa::node(id),
+            _ => None,
+        }
+    }
+
+    fn engine_priority(&self) -> ProcessPriority {
+        ProcessPriority {
+            nice: self.engine_nice,
+            class: self.engine_priority,
+        }
+    }
+
+    fn search_limits(&self) -> SearchLimits {
+        SearchLimits {
+            depth: self.max_depth,
+            nodes: self.max_nodes,
+            movetime: self.max_movetime.map(Into::into),
+        }
+    }
+
+    fn option_policy(&self) -> OptionPolicy {
+        OptionPolicy {
+            allow: self
+                .allow_option
+                .iter()
+                .cloned()
+                .map(UciOptionName)
+                .collect(),
+            deny: self
+                .deny_option
+                .iter()
+                .cloned()
+                .map(UciOptionName)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Subcommand)]
+pub enum Command {
+    /// Interactively test an engine and write a config file.
+    Setup,
+    /// Replay the client side of a transcript written with
+    /// --session-log-dir against the engine, and show differences in the
+    /// responses.
+    Replay {
+        transcript: PathBuf,
+        /// Also compare info lines other than info string, which usually
+        /// depend on timing.
+        #[clap(long)]
+        include_info: bool,
+    },
+    /// Run each position of the games in a PGN file through the engine, and
+    /// write the games with evaluations and best moves.
+    Annotate {
+        pgn: PathBuf,
+        /// Search depth per position. Defaults to 20 unless --movetime is
+        /// given.
+        #[clap(long)]
+        depth: Option<u32>,
+        /// Search time per position.
+        #[clap(long)]
+        movetime: Option<humantime::Duration>,
+        /// Write the annotated PGN to a file instead of standard output.
+        #[clap(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+    /// Show how much work the engines did for clients, from --stats-file.
+    Stats,
+    /// Download the latest official Stockfish release for this platform and
+    /// CPU, and use it unless another engine is configured.
+    DownloadStockfish,
+    /// Download Syzygy tablebases into the first --syzygy-path, or else
+    /// into a directory that is used unless --syzygy-path is given.
+    /// Interrupted downloads are resumed.
+    DownloadTablebases {
+        /// Maximum number of pieces, from 3 to 6. The tables for up to 5
+        /// pieces take about 1 GB, for 6 pieces about 150 GB.
+        #[clap(long, default_value_t = 5)]
+        pieces: usize,
+    },
+    /// Switch the running server to another engine executable, like a new
+    /// build, once it initializes and runs `bench`. Sessions in progress
+    /// keep the old engine until they end. Requires --admin-secret-file.
+    Upgrade { path: PathBuf },
+    /// Manage remote-uci as a Windows Service.
+    #[cfg(windows)]
+    Service {
+        #[clap(subcommand)]
+        action: ServiceAction,
+    },
+    /// Write a systemd user unit that runs remote-uci with the options
+    /// given before `systemd-install`, and restarts it if an engine stops
+    /// responding.
+    #[cfg(target_os = "linux")]
+    SystemdInstall,
+}
+
+/// Filters engine builds, ordered from the most to the least specialized,
+/// by whether the CPU supports them. Each build also requires the features
+/// of all less specialized builds.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn supported<const N: usize>(builds: [(Option<PathBuf>, bool); N]) -> Vec<PathBuf> {
+    let mut available = true;
+    let mut candidates: Vec<PathBuf> = builds
+        .into_iter()
+        .rev()
+        .filter_map(|(path, features)| {
+            available &= features;
+            path.filter(|_| available)
+        })
+        .collect();
+    candidates.reverse();
+    candidates
 }
 
-#[derive(Debug, Parser)]
+fn or_vec<T>(vec: Vec<T>, other: Vec<T>) -> Vec<T> {
+    if vec.is_empty() {
+        other
+    } else {
+        vec
+    }
+}
+
+#[derive(Debug, Default, Clone, Parser, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
 pub struct EngineOpts {
     /// UCI engine executable to use if the CPU supports the x86-64 feature
     /// VNNI512.
@@ -95,51 +1132,437 @@ pub struct EngineOpts {
     /// x86-64 features SSE3 and POPCNT.
     #[clap(long, display_order = 6)]
     engine_x86_64_sse3_popcnt: Option<PathBuf>,
-    /// Or else, the UCI engine executable to use.
-    #[clap(long, display_order = 7)]
-    engine: PathBuf,
+    /// UCI engine executable to use if the CPU supports the AArch64
+    /// feature DotProd, which includes Apple Silicon.
+    #[clap(long, display_order = 8)]
+    engine_aarch64_dotprod: Option<PathBuf>,
+    /// Or else, the UCI engine executable to use if the CPU supports the
+    /// AArch64 feature NEON.
+    #[clap(long, display_order = 9)]
+    engine_aarch64_neon: Option<PathBuf>,
+    /// Or else, the UCI engine executable to use. Defaults to Stockfish
+    /// installed with `remote-uci download-stockfish`.
+    #[clap(long, display_order = 10)]
+    engine: Option<PathBuf>,
+    /// UCI engine executable to use instead of all of the above if a CUDA
+    /// or OpenCL capable GPU is detected, for example Lc0.
+    #[clap(long, display_order = 11)]
+    engine_gpu: Option<PathBuf>,
+    /// Connect to an engine that is served over TCP on HOST:PORT, instead
+    /// of starting any of the above. The connection is reestablished if it
+    /// is lost.
+    #[clap(long, display_order = 12, value_name = "HOST:PORT")]
+    engine_tcp: Option<String>,
+    /// Start the engine on another machine over SSH, instead of starting
+    /// any of the above locally. Authentication must work without prompts,
+    /// for example with a key loaded into ssh-agent.
+    #[clap(long, display_order = 13, value_name = "[USER@]HOST:PATH")]
+    engine_ssh: Option<SshEngine>,
+    /// Start a Windows engine executable under wine, instead of any of the
+    /// above, for engines without builds for this platform.
+    #[clap(long, display_order = 14)]
+    engine_wine: Option<PathBuf>,
+    /// Run the engine from a Docker image, instead of any of the above,
+    /// isolated from the network and the files of this machine. Processors
+    /// and memory of the container are limited by --max-threads and
+    /// --max-hash.
+    #[clap(long, display_order = 15, value_name = "IMAGE[:TAG]")]
+    engine_docker: Option<DockerImage>,
+    /// Forward sessions to the websocket endpoint of another remote-uci
+    /// instance, instead of using any of the above. May be repeated to pool
+    /// several machines behind this server. Each session goes to the worker
+    /// that is serving the fewest sessions.
+    #[clap(
+        long,
+        display_order = 16,
+        value_name = "ws://HOST:PORT/PATH?secret=SECRET"
+    )]
+    engine_worker: Vec<WorkerUrl>,
+    /// Use a built-in demo engine instead of any of the above. It plays
+    /// weak moves, but does not need an engine to be installed.
+    #[clap(
+        long,
+        display_order = 17,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    demo: Option<bool>,
+    /// Run a short benchmark of each engine build that the CPU supports,
+    /// and use the fastest, instead of trusting CPU features alone.
+    #[clap(
+        long,
+        display_order = 18,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    auto_select_bench: Option<bool>,
 }
 
 impl EngineOpts {
+    fn or(self, other: EngineOpts) -> EngineOpts {
+        EngineOpts {
+            engine_x86_64_vnni512: self.engine_x86_64_vnni512.or(other.engine_x86_64_vnni512),
+            engine_x86_64_avx512: self.engine_x86_64_avx512.or(other.engine_x86_64_avx512),
+            engine_x86_64_bmi2: self.engine_x86_64_bmi2.or(other.engine_x86_64_bmi2),
+            engine_x86_64_avx2: self.engine_x86_64_avx2.or(other.engine_x86_64_avx2),
+            engine_x86_64_sse41_popcnt: self
+                .engine_x86_64_sse41_popcnt
+                .or(other.engine_x86_64_sse41_popcnt),
+            engine_x86_64_ssse3: self.engine_x86_64_ssse3.or(other.engine_x86_64_ssse3),
+            engine_x86_64_sse3_popcnt: self
+                .engine_x86_64_sse3_popcnt
+                .or(other.engine_x86_64_sse3_popcnt),
+            engine_aarch64_dotprod: self.engine_aarch64_dotprod.or(other.engine_aarch64_dotprod),
+            engine_aarch64_neon: self.engine_aarch64_neon.or(other.engine_aarch64_neon),
+            engine: self.engine.or(other.engine),
+            engine_gpu: self.engine_gpu.or(other.engine_gpu),
+            engine_tcp: self.engine_tcp.or(other.engine_tcp),
+            engine_ssh: self.engine_ssh.or(other.engine_ssh),
+            engine_wine: self.engine_wine.or(other.engine_wine),
+            engine_docker: self.engine_docker.or(other.engine_docker),
+            engine_worker: or_vec(self.engine_worker, other.engine_worker),
+            demo: self.demo.or(other.demo),
+            auto_select_bench: self.auto_select_bench.or(other.auto_select_bench),
+        }
+    }
+
+    async fn best(mut self, gpu: Option<GpuBackend>) -> Option<EngineAddr> {
+        if self.demo.unwrap_or(false) {
+            return Some(EngineAddr::Mock);
+        }
+        if let Some(addr) = self.engine_tcp.take() {
+            return Some(EngineAddr::Tcp(addr));
+        }
+        if let Some(SshEngine { destination, path }) = self.engine_ssh.take() {
+            return Some(EngineAddr::Ssh { destination, path });
+        }
+        if let Some(path) = self.engine_wine.take() {
+            return Some(EngineAddr::Wine(path));
+        }
+        if let Some(DockerImage(image)) = self.engine_docker.take() {
+            return Some(EngineAddr::Docker(image));
+        }
+        if !self.engine_worker.is_empty() {
+            return Some(EngineAddr::Cluster(mem::take(&mut self.engine_worker)));
+        }
+        if let Some(path) = self.engine_gpu.take().filter(|_| gpu.is_some()) {
+            return Some(EngineAddr::Process(path));
+        }
+        let bench = self.auto_select_bench.unwrap_or(false);
+        let mut candidates = self.cpu_candidates();
+        if candidates.is_empty() {
+            candidates.extend(stockfish::installed());
+        }
+        if bench && candidates.len() > 1 {
+            bench::fastest(candidates).await
+        } else {
+            candidates.into_iter().next()
+        }
+        .map(EngineAddr::Process)
+    }
+
+    /// Engine builds that the CPU supports, from the most to the least
+    /// specialized.
     #[cfg(target_arch = "x86_64")]
-    fn best(self) -> PathBuf {
-        self.engine_x86_64_vnni512
-            .filter(|_| {
+    fn cpu_candidates(self) -> Vec<PathBuf> {
+        supported([
+            (
+                self.engine_x86_64_vnni512,
                 is_x86_feature_detected!("avx512dq")
                     && is_x86_feature_detected!("avx512vl")
-                    && is_x86_feature_detected!("avx512vnni")
-            })
-            .or(self.engine_x86_64_avx512)
-            .filter(|_| is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw"))
-            .or(self.engine_x86_64_bmi2)
-            .filter(|_| {
-                is_x86_feature_detected!("bmi2") && {
-                    // AMD was using slow software emulation for PEXT for a
-                    // long time. The Zen 3 family (0x19) is the first to
-                    // implement it in hardware.
-                    let cpuid = raw_cpuid::CpuId::new();
-                    cpuid
-                        .get_vendor_info()
-                        .map_or(true, |v| v.as_str() != "AuthenticAMD")
-                        || cpuid
-                            .get_feature_info()
-                            .map_or(false, |f| f.family_id() >= 0x19)
-                }
-            })
-            .or(self.engine_x86_64_avx2)
-            .filter(|_| is_x86_feature_detected!("avx2"))
-            .or(self.engine_x86_64_sse41_popcnt)
-            .filter(|_| is_x86_feature_detected!("sse4.1"))
-            .or(self.engine_x86_64_ssse3)
-            .filter(|_| is_x86_feature_detected!("ssse3"))
-            .or(self.engine_x86_64_sse3_popcnt)
-            .filter(|_| is_x86_feature_detected!("sse3") && is_x86_feature_detected!("popcnt"))
-            .unwrap_or(self.engine)
+                    && is_x86_feature_detected!("avx512vnni"),
+            ),
+            (
+                self.engine_x86_64_avx512,
+                is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw"),
+            ),
+            (self.engine_x86_64_bmi2, has_fast_pext()),
+            (self.engine_x86_64_avx2, is_x86_feature_detected!("avx2")),
+            (
+                self.engine_x86_64_sse41_popcnt,
+                is_x86_feature_detected!("sse4.1"),
+            ),
+            (self.engine_x86_64_ssse3, is_x86_feature_detected!("ssse3")),
+            (
+                self.engine_x86_64_sse3_popcnt,
+                is_x86_feature_detected!("sse3") && is_x86_feature_detected!("popcnt"),
+            ),
+            (self.engine, true),
+        ])
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn cpu_candidates(self) -> Vec<PathBuf> {
+        supported([
+            (
+                self.engine_aarch64_dotprod,
+                // All Apple Silicon chips support DotProd, but feature
+                // detection is not available on every macOS version.
+                cfg!(target_os = "macos") || std::arch::is_aarch64_feature_detected!("dotprod"),
+            ),
+            (
+                self.engine_aarch64_neon,
+                std::arch::is_aarch64_feature_detected!("neon"),
+            ),
+            (self.engine, true),
+        ])
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn cpu_candidates(self) -> Vec<PathBuf> {
+        self.engine.into_iter().collect()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn has_fast_pext() -> bool {
+    is_x86_feature_detected!("bmi2") && {
+        // AMD was using slow software emulation for PEXT for a
+        // long time. The Zen 3 family (0x19) is the first to
+        // implement it in hardware. Hygon licensed Zen 1 (family
+        // 0x18), so it has the same problem.
+        let cpuid = raw_cpuid::CpuId::new();
+        cpuid.get_vendor_info().map_or(true, |v| {
+            !matches!(v.as_str(), "AuthenticAMD" | "HygonGenuine")
+        }) || cpuid
+            .get_feature_info()
+            .map_or(false, |f| f.family_id() >= 0x19)
+    }
+}
+
+/// CPU features that are relevant for choosing an engine build, as in the
+/// names of the --engine-* flags.
+#[cfg(target_arch = "x86_64")]
+fn cpu_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if is_x86_feature_detected!("avx512dq")
+        && is_x86_feature_detected!("avx512vl")
+        && is_x86_feature_detected!("avx512vnni")
+    {
+        features.push("vnni512");
+    }
+    if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
+        features.push("avx512");
+    }
+    if has_fast_pext() {
+        features.push("bmi2");
+    }
+    if is_x86_feature_detected!("avx2") {
+        features.push("avx2");
+    }
+    if is_x86_feature_detected!("sse4.1") {
+        features.push("sse41");
+    }
+    if is_x86_feature_detected!("ssse3") {
+        features.push("ssse3");
+    }
+    if is_x86_feature_detected!("sse3") {
+        features.push("sse3");
+    }
+    if is_x86_feature_detected!("popcnt") {
+        features.push("popcnt");
     }
+    features
+}
+
+#[cfg(target_arch = "aarch64")]
+fn cpu_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(target_os = "macos") || std::arch::is_aarch64_feature_detected!("dotprod") {
+        features.push("dotprod");
+    }
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        features.push("neon");
+    }
+    features
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn cpu_features() -> Vec<&'static str> {
+    Vec::new()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EngineSpec {
+    name: String,
+    path: PathBuf,
+}
+
+impl FromStr for EngineSpec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<EngineSpec, &'static str> {
+        let (name, path) = s.split_once('=').ok_or("expected NAME=PATH")?;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err("engine name must be alphanumeric");
+        }
+        if path.is_empty() {
+            return Err("expected NAME=PATH");
+        }
+        Ok(EngineSpec {
+            name: name.to_owned(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SshEngine {
+    destination: String,
+    path: String,
+}
+
+impl FromStr for SshEngine {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<SshEngine, &'static str> {
+        let (destination, path) = s.split_once(':').ok_or("expected [USER@]HOST:PATH")?;
+        if destination.is_empty() || destination.starts_with('-') || path.is_empty() {
+            return Err("expected [USER@]HOST:PATH");
+        }
+        Ok(SshEngine {
+            destination: destination.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl TryFrom<String> for SshEngine {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<SshEngine, &'static str> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DockerImage(String);
+
+impl FromStr for DockerImage {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<DockerImage, &'static str> {
+        if s.is_empty() || s.starts_with('-') || s.contains(char::is_whitespace) {
+            return Err("expected IMAGE[:TAG]");
+        }
+        Ok(DockerImage(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for DockerImage {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<DockerImage, &'static str> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UciOptionArg {
+    name: UciOptionName,
+    value: Option<String>,
+}
+
+impl FromStr for UciOptionArg {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<UciOptionArg, &'static str> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim().to_owned())),
+            None => (s, None),
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("expected NAME=VALUE");
+        }
+        Ok(UciOptionArg {
+            name: UciOptionName(name.to_owned()),
+            value,
+        })
+    }
+}
+
+impl TryFrom<String> for UciOptionArg {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<UciOptionArg, &'static str> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EngineEnv {
+    key: String,
+    value: String,
+}
+
+impl FromStr for EngineEnv {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<EngineEnv, &'static str> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(EngineEnv {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => Err("expected KEY=VALUE"),
+        }
+    }
+}
+
+impl TryFrom<String> for EngineEnv {
+    type Error = &'static str;
 
-    #[cfg(not(target_arch = "x86_64"))]
-    fn best(self) -> PathBuf {
-        self.engine
+    fn try_from(s: String) -> Result<EngineEnv, &'static str> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for EngineSpec {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<EngineSpec, &'static str> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct VariantEngine {
+    variant: String,
+    path: PathBuf,
+}
+
+impl FromStr for VariantEngine {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<VariantEngine, &'static str> {
+        match s.split_once('=') {
+            Some((variant, path)) if !variant.is_empty() && !path.is_empty() => Ok(VariantEngine {
+                variant: variant.to_owned(),
+                path: PathBuf::from(path),
+            }),
+            _ => Err("expected VARIANT=PATH"),
+        }
+    }
+}
+
+impl TryFrom<String> for VariantEngine {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<VariantEngine, &'static str> {
+        s.parse()
     }
 }
 
@@ -158,15 +1581,54 @@ pub struct ExternalWorkerOpts {
     #[serde_as(as = "DisplayFromStr")]
     #[serde(skip_serializing_if = "Not::not")]
     official_stockfish: bool,
+    /// Maximum number of pieces covered by tablebases.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tablebases: Option<usize>,
+    /// File name of the NNUE network the engine evaluates with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network: Option<String>,
 }
 
 impl ExternalWorkerOpts {
     pub fn registration_url(&self) -> String {
         format!(
             "https://lichess.org/analysis/external?{}",
-            serde_urlencoded::to_string(&self).expect("serialize spec"),
+            serde_urlencoded::to_string(self).expect("serialize spec"),
         )
     }
+
+    fn with_secret(&self, secret: Secret) -> ExternalWorkerOpts {
+        ExternalWorkerOpts {
+            secret,
+            ..self.clone()
+        }
+    }
+
+    /// Announces only what a client with a named secret may use.
+    fn restricted(mut self, permissions: &Permissions) -> ExternalWorkerOpts {
+        if let Some(max_threads) = permissions.max_threads {
+            self.max_threads = min(self.max_threads, i64::from(max_threads));
+        }
+        if let Some(max_hash) = permissions.max_hash {
+            self.max_hash = min(self.max_hash, i64::from(max_hash));
+        }
+        if !permissions.variants.is_empty() {
+            self.variants.retain(|variant| {
+                permissions
+                    .variants
+                    .iter()
+                    .any(|allowed| variant::same(allowed, variant))
+            });
+        }
+        self
+    }
+}
+
+fn available_threads() -> u32 {
+    thread::available_parallelism()
+        .ok()
+        .and_then(|threads| u32::try_from(usize::from(threads)).ok())
+        .unwrap_or(u32::MAX)
 }
 
 fn available_memory() -> u64 {
@@ -181,113 +1643,372 @@ fn get_external_protocol(tls: bool) -> String {
     }
 }
 
-pub async fn make_server(
-    opts: Opts,
-    mut listen_fds: ListenFd,
-) -> Result<
-    (
-        ExternalWorkerOpts,
-        hyper::Server<AddrIncoming, IntoMakeService<Router>>,
-    ),
-    Box<dyn Error>,
-> {
-    let secret = match opts.secret_file {
-        Some(path) => match fs::read_to_string(&path) {
-            Ok(secret) if secret.len() >= 8 => {
-                log::debug!("Loaded secret file {path:?}");
-                Secret(secret)
-            }
-            Ok(_) => {
-                log::error!("Ignoring secret file {path:?} (too short)");
-                Secret::random()
+/// Websocket base URL from --advertise-url, which may also be given as an
+/// HTTP URL, and with or without the /socket path.
+fn advertised_base_url(url: &str) -> Result<String, String> {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix("/socket").unwrap_or(url);
+    [
+        ("wss://", "wss://"),
+        ("ws://", "ws://"),
+        ("https://", "wss://"),
+        ("http://", "ws://"),
+    ]
+    .into_iter()
+    .find_map(|(scheme, ws_scheme)| {
+        url.strip_prefix(scheme)
+            .map(|rest| format!("{ws_scheme}{rest}"))
+    })
+    .ok_or_else(|| format!("{url} does not start with ws://, wss://, http:// or https://"))
+}
+
+fn load_secret(secret_file: Option<&Path>) -> Secret {
+    match load_secrets(
+        secret_file
+            .map(|path| SecretStorage::File(path.to_owned()))
+            .as_ref(),
+    ) {
+        Ok((secret, _)) => secret,
+        Err(err) => {
+            tracing::error!("{err}");
+            Secret::random()
+        }
+    }
+}
+
+/// Loads the secret of an engine endpoint, and named secrets for individual
+/// clients.
+fn load_secrets(storage: Option<&SecretStorage>) -> Result<(Secret, Vec<NamedSecret>), String> {
+    Ok(match storage {
+        Some(storage) => match storage.read() {
+            Ok(content) => {
+                let (secret, named) = parse_secret_file(&content)
+                    .map_err(|err| format!("Invalid {storage}: {err}"))?;
+                if secret.0.len() >= 8 {
+                    tracing::debug!("Loaded {storage}");
+                    (secret, named)
+                } else {
+                    tracing::error!("Ignoring {storage} (too short)");
+                    (Secret::random(), Vec::new())
+                }
             }
             Err(err) if err.kind() == io::ErrorKind::NotFound => {
                 let secret = Secret::random();
-                match fs::write(&path, &secret.0) {
-                    Ok(()) => log::warn!("Created new secret file {path:?}"),
-                    Err(err) => log::error!("Failed to create secret file {path:?}: {err}"),
+                match storage.write(&secret.0) {
+                    Ok(()) => tracing::warn!("Created new {storage}"),
+                    Err(err) => tracing::error!("Failed to create {storage}: {err}"),
                 }
-                secret
+                (secret, Vec::new())
             }
             Err(err) => {
-                log::error!("Failed to load secret file {path:?}: {err}");
-                Secret::random()
+                tracing::error!("Failed to load {storage}: {err}");
+                (Secret::random(), Vec::new())
             }
         },
-        None => Secret::random(),
-    };
-
-    let listener = opts
-        .bind
-        .map(TcpListener::bind)
-        .or_else(|| listen_fds.take_tcp_listener(0).transpose())
-        .unwrap_or_else(|| TcpListener::bind("localhost:9670"))
-        .map_err(|err| {
-            log::error!("Could not bind server: {err}");
-            err
-        })?;
+        None => (Secret::random(), Vec::new()),
+    })
+}
 
-    let engine = Engine::new(
-        opts.engine.best(),
-        EngineParameters {
-            max_threads: min(
-                opts.max_threads.unwrap_or(u32::MAX),
-                u32::try_from(usize::from(
-                    thread::available_parallelism().expect("available threads"),
-                ))
-                .unwrap_or(u32::MAX),
-            ),
-            max_hash: min(
-                opts.max_hash.unwrap_or(u32::MAX),
-                u32::try_from(available_memory()).unwrap_or(u32::MAX),
-            ),
-        },
-    )
-    .await
-    .map_err(|err| {
-        log::error!("Could not start engine: {err}");
-        err
-    })?;
-    
-    let spec = ExternalWorkerOpts {
-        url: format!(
-                 "{}://{}/socket",
-                 get_external_protocol(opts.publish_addr_tls),
-                 opts.publish_addr.unwrap_or(listener.local_addr().expect("local addr").to_string())
-        ),
-        secret: secret.clone(),
-        max_threads: engine.max_threads(),
-        max_hash: engine.max_hash(),
-        variants: engine.variants().to_vec(),
-        name: engine.name().unwrap_or("remote-uci").to_owned(),
-        official_stockfish: opts.promise_official_stockfish,
-    };
-
-    let engine = Arc::new(SharedEngine::new(engine));
-
-    let app = Router::new()
-        .route(
-            "/",
-            get({
-                let spec = spec.clone();
-                move || redirect(spec)
-            }),
-        )
-        .route(
-            "/socket",
-            get({
-                let engine = Arc::clone(&engine);
-                let secret = secret;
-                move |params, socket| ws::handler(engine, secret, params, socket)
-            }),
+async fn start_pool(
+    addr: EngineAddr,
+    params: &EngineParameters,
+    pool_size: usize,
+) -> Result<Vec<Engine>, RemoteUciError> {
+    let mut engines = Vec::with_capacity(pool_size);
+    for _ in 0..pool_size {
+        engines.push(
+            Engine::new(addr.clone(), params.clone())
+                .await
+                .map_err(|source| RemoteUciError::EngineSpawn {
+                    engine: addr.to_string(),
+                    source,
+                })?,
+        );
+    }
+    Ok(engines)
+}
+
+/// Periodically replaces the secret of an engine. Registration URLs with the
+/// new secret are available from the redirect route.
+async fn rotate_secret(spec: ExternalWorkerOpts, secrets: Arc<SecretStore>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let secret = secrets.rotate().await;
+        tracing::warn!(
+            "Rotated secret, new registration URL: {}",
+            spec.with_secret(secret).registration_url()
         );
+    }
+}
+
+/// Routes for an engine endpoint under `prefix`, which is empty for the
+/// default engine.
+fn route_engine(app: Router, prefix: &str, endpoint: &Endpoint, socket_opts: SocketOpts) -> Router {
+    let local_pages = socket_opts.local_pages;
+    let (spec, redirect_secrets) = (endpoint.spec.clone(), Arc::clone(&endpoint.secrets));
+    let (qr_spec, qr_secrets) = (endpoint.spec.clone(), Arc::clone(&endpoint.secrets));
+    let (api_engine, api_secrets, api_opts) = (
+        Arc::clone(&endpoint.engine),
+        Arc::clone(&endpoint.secrets),
+        socket_opts.clone(),
+    );
+    let (watch_engine, watch_secrets, watch_opts) = (
+        Arc::clone(&endpoint.engine),
+        Arc::clone(&endpoint.secrets),
+        socket_opts.clone(),
+    );
+    let (options_engine, options_secrets, options_opts) = (
+        Arc::clone(&endpoint.engine),
+        Arc::clone(&endpoint.secrets),
+        socket_opts.clone(),
+    );
+    let (engine, secrets) = (Arc::clone(&endpoint.engine), Arc::clone(&endpoint.secrets));
+    let socket_route =
+        get(move |params, req| ws::handler(engine, secrets, socket_opts, params, req));
+    let api_route =
+        post(move |params, req| api::analyse(api_engine, api_secrets, api_opts, params, req));
+    let watch_route =
+        get(move |params, req| api::watch(watch_engine, watch_secrets, watch_opts, params, req));
+    let options_route = get(move |params, req| {
+        api::options(options_engine, options_secrets, options_opts, params, req)
+    });
+    app.route(
+        if prefix.is_empty() { "/" } else { prefix },
+        get(move |req: Request<Body>| redirect(spec, redirect_secrets, local_pages, req)),
+    )
+    .route(
+        &format!("{prefix}/qr"),
+        get(move |req: Request<Body>| qr_code(qr_spec, qr_secrets, local_pages, req)),
+    )
+    .route(&endpoint.path, socket_route)
+    .route(&format!("{prefix}/api/analyse"), api_route)
+    .route(&format!("{prefix}/watch"), watch_route)
+    .route(&format!("{prefix}/options"), options_route)
+}
+
+/// The registration URL as a QR code.
+async fn qr_code(
+    spec: ExternalWorkerOpts,
+    secrets: Arc<SecretStore>,
+    local_only: bool,
+    req: Request<Body>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if local_only && !ipfilter::is_local(&req) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    qr::handler(spec.with_secret(secrets.active()).registration_url()).await
+}
 
-    Ok((
-        spec,
-        axum::Server::from_tcp(listener)?.serve(app.into_make_service()),
+/// Redirects to the registration URL, which includes the secret.
+async fn redirect(
+    spec: ExternalWorkerOpts,
+    secrets: Arc<SecretStore>,
+    local_only: bool,
+    req: Request<Body>,
+) -> Result<Redirect, StatusCode> {
+    if local_only && !ipfilter::is_local(&req) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Redirect::to(
+        &spec.with_secret(secrets.active()).registration_url(),
     ))
 }
 
-async fn redirect(spec: ExternalWorkerOpts) -> Redirect {
-    Redirect::to(&spec.registration_url())
+#[cfg(test)]
+mod tests {
+    use axum::{extract::ConnectInfo, http::header};
+
+    use super::*;
+    use crate::listen::PeerAddr;
+
+    fn spec() -> ExternalWorkerOpts {
+        ExternalWorkerOpts {
+            url: "wss://example.com/socket".to_owned(),
+            secret: Secret("placeholder".to_owned()),
+            name: "Stockfish".to_owned(),
+            max_threads: 1,
+            max_hash: 16,
+            variants: Vec::new(),
+            official_stockfish: false,
+            tablebases: None,
+            network: None,
+        }
+    }
+
+    fn request(peer: &str, forwarded: bool) -> Request<Body> {
+        let mut req = Request::builder();
+        if forwarded {
+            req = req.header("x-forwarded-for", "203.0.113.9");
+        }
+        let mut req = req.body(Body::empty()).expect("request");
+        req.extensions_mut()
+            .insert(ConnectInfo(PeerAddr(Some(peer.parse().expect("peer")))));
+        req
+    }
+
+    async fn location(
+        secrets: &Arc<SecretStore>,
+        local_only: bool,
+        req: Request<Body>,
+    ) -> Option<String> {
+        let res = redirect(spec(), Arc::clone(secrets), local_only, req)
+            .await
+            .into_response();
+        res.headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().expect("location").to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_redirect_local_only() {
+        let secrets = Arc::new(SecretStore::new(
+            Secret("s3cr3t".to_owned()),
+            Vec::new(),
+            None,
+        ));
+        let local = location(&secrets, true, request("127.0.0.1:1234", false)).await;
+        assert!(local.expect("redirect").contains("secret=s3cr3t"));
+        assert_eq!(
+            location(&secrets, true, request("127.0.0.1:1234", true)).await,
+            None,
+            "through tunnel"
+        );
+        assert_eq!(
+            location(&secrets, true, request("192.0.2.1:1234", false)).await,
+            None
+        );
+        assert!(location(&secrets, false, request("192.0.2.1:1234", false))
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rotated_secret_stays_local() {
+        let secrets = Arc::new(SecretStore::new(
+            Secret("s3cr3t".to_owned()),
+            Vec::new(),
+            None,
+        ));
+        let rotated = secrets.rotate().await;
+        assert!(secrets.verify(&Secret("s3cr3t".to_owned())).is_some());
+        assert_eq!(
+            location(&secrets, true, request("127.0.0.1:1234", true)).await,
+            None,
+            "through tunnel"
+        );
+        let local = location(&secrets, true, request("127.0.0.1:1234", false)).await;
+        assert!(local
+            .expect("redirect")
+            .contains(&format!("secret={}", rotated.0)));
+    }
+
+    #[tokio::test]
+    async fn test_qr_code_local_only() {
+        let secrets = Arc::new(SecretStore::new(
+            Secret("s3cr3t".to_owned()),
+            Vec::new(),
+            None,
+        ));
+        let status = |local_only, req| {
+            let secrets = Arc::clone(&secrets);
+            async move {
+                qr_code(spec(), secrets, local_only, req)
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        assert_eq!(
+            status(true, request("127.0.0.1:1234", false)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(true, request("127.0.0.1:1234", true)).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(false, request("127.0.0.1:1234", true)).await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_cli_overrides_config_flag() {
+        let file: Opts = toml::from_str(
+            r#"
+            publish-addr-tls = true
+            trust-proxy = true
+            strict-uci = true
+            wdl = true
+            standby = true
+            deterministic = true
+            demo = true
+            "#,
+        )
+        .expect("config");
+        let opts = |args: &[&str]| {
+            Opts::try_parse_from(["remote-uci"].iter().chain(args))
+                .expect("arguments")
+                .or(file.clone())
+        };
+
+        let defaults = opts(&[]);
+        assert_eq!(defaults.publish_addr_tls, Some(true));
+        assert_eq!(defaults.trust_proxy, Some(true));
+        assert_eq!(defaults.strict_uci, Some(true));
+        assert_eq!(defaults.wdl, Some(true));
+        assert_eq!(defaults.standby, Some(true));
+        assert_eq!(defaults.deterministic, Some(true));
+        assert_eq!(defaults.engine.demo, Some(true));
+        assert_eq!(opts(&["--publish-addr-tls"]).publish_addr_tls, Some(true));
+
+        let overridden = opts(&[
+            "--publish-addr-tls=false",
+            "--trust-proxy=false",
+            "--strict-uci=false",
+            "--wdl=false",
+            "--standby=false",
+            "--deterministic=false",
+            "--demo=false",
+        ]);
+        assert_eq!(overridden.publish_addr_tls, Some(false));
+        assert_eq!(overridden.trust_proxy, Some(false));
+        assert_eq!(overridden.strict_uci, Some(false));
+        assert_eq!(overridden.wdl, Some(false));
+        assert_eq!(overridden.standby, Some(false));
+        assert_eq!(overridden.deterministic, Some(false));
+        assert_eq!(overridden.engine.demo, Some(false));
+
+        assert_eq!(Opts::default().or(Opts::default()).publish_addr_tls, None);
+    }
+
+    #[test]
+    fn test_merge_profiles() {
+        let base: Opts = toml::from_str(
+            r#"
+            [profile.quick]
+            threads = 1
+
+            [profile.deep]
+            threads = 8
+            "#,
+        )
+        .expect("base");
+        let overrides: Opts = toml::from_str(
+            r#"
+            [profile.deep]
+            threads = 16
+            "#,
+        )
+        .expect("overrides");
+        let merged = overrides.or(base);
+        assert_eq!(
+            merged
+                .profile
+                .iter()
+                .map(|(name, profile)| (name.as_str(), profile.threads))
+                .collect::<Vec<_>>(),
+            vec![("deep", Some(16)), ("quick", Some(1))]
+        );
+    }
 }
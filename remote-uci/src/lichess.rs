@@ -0,0 +1,470 @@
+use std::{io, sync::Arc, time::Duration};
+
+use futures_util::stream;
+use reqwest::{Body, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time::sleep};
+
+use crate::{
+    engine::{Engine, Session},
+    uci::{UciIn, UciOut},
+    ws::{Lease, Secret, SharedEngine},
+    ExternalWorkerOpts,
+};
+
+/// Depth of searches that lichess does not request to be infinite.
+const DEFAULT_DEPTH: u32 = 24;
+
+/// Client for the lichess external engine API. Instead of waiting for
+/// websocket connections, engines are registered with lichess using an OAuth
+/// token, and analysis is requested by long polling.
+pub struct LichessClient {
+    http: Client,
+    token: String,
+    api_url: String,
+    engine_url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Registration<'a> {
+    name: &'a str,
+    max_threads: i64,
+    max_hash: i64,
+    default_depth: u32,
+    variants: Vec<&'a str>,
+    provider_secret: &'a Secret,
+}
+
+#[derive(Deserialize)]
+struct RegisteredEngine {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Acquire<'a> {
+    provider_secret: &'a Secret,
+}
+
+#[derive(Deserialize)]
+struct Job {
+    id: String,
+    work: Work,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Work {
+    session_id: String,
+    threads: u32,
+    hash: u32,
+    infinite: bool,
+    multi_pv: u32,
+    variant: String,
+    initial_fen: String,
+    moves: Vec<String>,
+}
+
+impl LichessClient {
+    pub fn new(token: String, api_url: String, engine_url: String) -> LichessClient {
+        LichessClient {
+            http: Client::new(),
+            token,
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            engine_url: engine_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Registers the engine with lichess, or updates the registration with
+    /// the same name. Returns the engine id.
+    async fn register(&self, spec: &ExternalWorkerOpts) -> reqwest::Result<String> {
+        let registration = Registration {
+            name: &spec.name,
+            max_threads: spec.max_threads,
+            max_hash: spec.max_hash,
+            default_depth: DEFAULT_DEPTH,
+            variants: if spec.variants.is_empty() {
+                vec!["chess"]
+            } else {
+                spec.variants.iter().map(String::as_str).collect()
+            },
+            provider_secret: &spec.secret,
+        };
+        let url = format!("{}/api/external-engine", self.api_url);
+        let engines: Vec<RegisteredEngine> = self
+            .http
+            .get(&url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match engines.into_iter().find(|engine| engine.name == spec.name) {
+            Some(engine) => {
+                self.http
+                    .put(format!("{url}/{}", engine.id))
+                    .bearer_auth(&self.token)
+                    .json(&registration)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(engine.id)
+            }
+            None => Ok(self
+                .http
+                .post(&url)
+                .bearer_auth(&self.token)
+                .json(&registration)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<RegisteredEngine>()
+                .await?
+                .id),
+        }
+    }
+
+    async fn acquire(&self, secret: &Secret) -> reqwest::Result<Option<Job>> {
+        let res = self
+            .http
+            .post(format!("{}/api/external-engine/work", self.engine_url))
+            .json(&Acquire {
+                provider_secret: secret,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        if res.status() == StatusCode::NO_CONTENT {
+            Ok(None)
+        } else {
+            res.json().await.map(Some)
+        }
+    }
+
+    /// Waits for the next analysis request. Errors are logged and retried
+    /// after a delay.
+    async fn next_job(&self, secret: &Secret) -> Option<Job> {
+        match self.acquire(secret).await {
+            Ok(job) => job,
+            Err(err) => {
+                tracing::error!("Failed to acquire work from lichess: {err}");
+                sleep(Duration::from_secs(10)).await;
+                None
+            }
+        }
+    }
+
+    /// Streams lines of engine output to lichess, until the sender is
+    /// dropped.
+    fn submit(&self, job_id: &str) -> mpsc::Sender<String> {
+        let (tx, rx) = mpsc::channel::<String>(16);
+        let body = Body::wrap_stream(stream::unfold(rx, |mut rx| async move {
+            rx.recv()
+                .await
+                .map(|line| (Ok::<_, io::Error>(line + "\n"), rx))
+        }));
+        let req = self
+            .http
+            .post(format!(
+                "{}/api/external-engine/work/{job_id}",
+                self.engine_url
+            ))
+            .body(body);
+        tokio::spawn(async move {
+            if let Err(err) = req.send().await.and_then(|res| res.error_for_status()) {
+                tracing::warn!("Failed to submit analysis to lichess: {err}");
+            }
+        });
+        tx
+    }
+}
+
+struct Analysis<'a> {
+    session: Session,
+    engine: Lease<'a>,
+    lines: mpsc::Sender<String>,
+}
+
+enum Event {
+    Job(Option<Job>),
+    Engine(io::Result<UciOut>),
+    Disconnected,
+    CheckSession,
+    Shutdown,
+}
+
+/// Serves analysis requests from lichess with the given engine.
+pub async fn provide(
+    client: Arc<LichessClient>,
+    spec: ExternalWorkerOpts,
+    shared_engine: Arc<SharedEngine>,
+) {
+    let mut shutdown = match shared_engine.shutdown_guard() {
+        Some(shutdown) => shutdown,
+        None => return,
+    };
+
+    match client.register(&spec).await {
+        Ok(id) => tracing::warn!("Registered engine {} with lichess as {id}", spec.name),
+        Err(err) => {
+            tracing::error!(
+                "Failed to register engine {} with lichess: {err}",
+                spec.name
+            );
+            return;
+        }
+    }
+
+    let mut next_job = Box::pin(client.next_job(&spec.secret));
+    let mut analysis: Option<Analysis> = None;
+
+    loop {
+        let event = if let Some(Analysis {
+            session,
+            ref mut engine,
+            ref lines,
+        }) = analysis
+        {
+            let notified = engine.notified();
+            tokio::select! {
+                job = &mut next_job => Event::Job(job),
+                engine_out = engine.recv(session) => Event::Engine(engine_out),
+                _ = lines.closed() => Event::Disconnected,
+                _ = notified => Event::CheckSession,
+                _ = shutdown.requested() => Event::Shutdown,
+            }
+        } else {
+            tokio::select! {
+                job = &mut next_job => Event::Job(job),
+                _ = shutdown.requested() => Event::Shutdown,
+            }
+        };
+
+        let res = match event {
+            Event::Job(job) => {
+                next_job = Box::pin(client.next_job(&spec.secret));
+                match job {
+                    Some(_) if shared_engine.unavailable_until().is_some() => {
+                        tracing::warn!("Ignoring work from lichess outside of schedule");
+                        Ok(())
+                    }
+                    Some(job) => {
+                        if let Some(analysis) = analysis.take() {
+                            finish(analysis).await;
+                        }
+                        start(&client, &shared_engine, job)
+                            .await
+                            .map(|started| analysis = Some(started))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Event::Engine(Ok(command)) => {
+                let done = matches!(command, UciOut::Bestmove { .. });
+                if let Some(ref analysis) = analysis {
+                    let _ = analysis.lines.send(command.to_string()).await;
+                }
+                if done {
+                    analysis = None;
+                }
+                Ok(())
+            }
+            Event::Engine(Err(err)) => {
+                analysis = None;
+                Err(err)
+            }
+            Event::Disconnected => {
+                if let Some(analysis) = analysis.take() {
+                    tracing::warn!("{}: lichess stopped receiving analysis", analysis.session.0);
+                    finish(analysis).await;
+                }
+                Ok(())
+            }
+            Event::CheckSession => {
+                match analysis.take() {
+                    Some(current)
+                        if current.engine.is_preempted(current.session)
+                            || current.engine.is_killed(current.session) =>
+                    {
+                        finish(current).await
+                    }
+                    current => analysis = current,
+                }
+                Ok(())
+            }
+            Event::Shutdown => {
+                if let Some(analysis) = analysis.take() {
+                    finish(analysis).await;
+                }
+                break;
+            }
+        };
+        if let Err(err) = res {
+            tracing::error!("Failed to analyse for lichess: {err}");
+        }
+    }
+}
+
+async fn start<'a>(
+    client: &LichessClient,
+    shared_engine: &'a SharedEngine,
+    job: Job,
+) -> io::Result<Analysis<'a>> {
+    let (session, mut engine) = shared_engine.for_variant(&job.work.variant).acquire().await;
+    tracing::warn!(
+        "{}: analysing for lichess session {}",
+        session.0,
+        job.work.session_id
+    );
+    engine.start_session(session).await?;
+    send_work(&mut engine, session, &job.work).await?;
+
+    Ok(Analysis {
+        session,
+        engine,
+        lines: client.submit(&job.id),
+    })
+}
+
+/// Configures the engine and starts the search for a job. Commands go
+/// through the same checks as commands of clients, including search limits
+/// and deterministic mode.
+async fn send_work(engine: &mut Engine, session: Session, work: &Work) -> io::Result<()> {
+    let mut commands = vec![
+        format!("setoption name Threads value {}", work.threads),
+        format!("setoption name Hash value {}", work.hash),
+        format!("setoption name MultiPV value {}", work.multi_pv),
+    ];
+    if !engine.variants().is_empty() {
+        commands.push(format!("setoption name UCI_Variant value {}", work.variant));
+    }
+    commands.push(if work.moves.is_empty() {
+        format!("position fen {}", work.initial_fen)
+    } else {
+        format!(
+            "position fen {} moves {}",
+            work.initial_fen,
+            work.moves.join(" ")
+        )
+    });
+    commands.push(if work.infinite {
+        "go infinite".to_owned()
+    } else {
+        format!("go depth {DEFAULT_DEPTH}")
+    });
+    for command in commands {
+        if let Some(command) = UciIn::from_line(&command)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        {
+            engine.send(session, command).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Stops the search and submits the remaining output.
+async fn finish(mut analysis: Analysis<'_>) {
+    let session = analysis.session;
+    let res = async {
+        if analysis.engine.is_searching() {
+            analysis.engine.send(session, UciIn::Stop).await?;
+        }
+        while !analysis.engine.is_idle() {
+            let command = analysis.engine.recv(session).await?;
+            let _ = analysis.lines.send(command.to_string()).await;
+        }
+        Ok::<_, io::Error>(())
+    }
+    .await;
+    if let Err(err) = res {
+        tracing::error!("{}: failed to stop analysis: {err}", session.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        engine::{EngineAddr, EngineParameters},
+        uci::{SearchLimits, UciOptionName},
+    };
+
+    fn work(threads: u32, infinite: bool) -> Work {
+        Work {
+            session_id: "test".to_owned(),
+            threads,
+            hash: 16,
+            infinite,
+            multi_pv: 1,
+            variant: "chess".to_owned(),
+            initial_fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_owned(),
+            moves: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_limits_apply_to_work() {
+        let mut engine = Engine::new(
+            EngineAddr::Mock,
+            EngineParameters {
+                max_threads: 1,
+                max_hash: 16,
+                limits: SearchLimits {
+                    depth: Some(2),
+                    ..SearchLimits::default()
+                },
+                ..EngineParameters::default()
+            },
+        )
+        .await
+        .expect("engine");
+        let session = Session(1);
+        send_work(&mut engine, session, &work(1, true))
+            .await
+            .expect("send");
+
+        // The infinite search is capped, so it ends without stop.
+        let mut max_depth = 0;
+        loop {
+            match timeout(Duration::from_secs(5), engine.recv(session))
+                .await
+                .expect("search ended")
+                .expect("recv")
+            {
+                UciOut::Info {
+                    depth: Some(depth), ..
+                } => max_depth = max_depth.max(depth),
+                UciOut::Bestmove { .. } => break,
+                _ => (),
+            }
+        }
+        assert_eq!(max_depth, 2);
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_mode_applies_to_work() {
+        let mut engine = Engine::new(
+            EngineAddr::Mock,
+            EngineParameters {
+                max_threads: 4,
+                max_hash: 16,
+                deterministic: true,
+                ..EngineParameters::default()
+            },
+        )
+        .await
+        .expect("engine");
+        send_work(&mut engine, Session(1), &work(4, false))
+            .await
+            .expect("send");
+        assert_ne!(
+            engine.spin_value(&UciOptionName("Threads".to_owned())),
+            Some(4)
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::extract::connect_info::Connected;
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream},
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Binds a listener on the address. With `only_v6`, IPv6 listeners do not
+/// also accept IPv4 connections, so that the same port can be bound for
+/// IPv4 separately.
+pub fn bind(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    // Like std, allow binding while old connections are in TIME_WAIT.
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// Binds a unix domain socket, for example for a reverse proxy on the same
+/// machine. A socket file left over from a previous run is replaced.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> io::Result<Listener> {
+    use std::{fs, os::unix::fs::FileTypeExt as _};
+
+    if fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    tokio::net::UnixListener::from_std(listener).map(Listener::Unix)
+}
+
+#[cfg(not(unix))]
+pub fn bind_unix(_path: &Path) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix domain sockets are not supported on this platform",
+    ))
+}
+
+/// A socket to accept connections on.
+pub enum Listener {
+    Tcp(AddrIncoming),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    pub fn tcp(listener: TcpListener) -> io::Result<Listener> {
+        listener.set_nonblocking(true)?;
+        AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)
+            .map(Listener::Tcp)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Conn>>> {
+        match self {
+            Listener::Tcp(incoming) => Pin::new(incoming)
+                .poll_accept(cx)
+                .map(|conn| conn.map(|conn| conn.map(Conn::Tcp))),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .poll_accept(cx)
+                .map(|conn| Some(conn.map(|(stream, _)| Conn::Unix(stream)))),
+        }
+    }
+}
+
+/// Accepts connections from several listeners, to serve the same router on
+/// all of them.
+pub struct Incoming {
+    listeners: Vec<Listener>,
+    next: usize,
+}
+
+impl Incoming {
+    pub fn new(listeners: Vec<Listener>) -> Incoming {
+        Incoming { listeners, next: 0 }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Conn;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Conn>>> {
+        // Start with a different listener each time, so that a busy
+        // listener can not starve the others.
+        let len = self.listeners.len();
+        for offset in 0..len {
+            let index = (self.next + offset) % len;
+            if let Poll::Ready(conn) = self.listeners[index].poll_accept(cx) {
+                self.next = index + 1;
+                return Poll::Ready(conn);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// An accepted connection.
+pub enum Conn {
+    Tcp(AddrStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Conn::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Conn::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Conn::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Conn::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Conn::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Address of the client, if connected over TCP.
+#[derive(Copy, Clone, Debug)]
+pub struct PeerAddr(pub Option<SocketAddr>);
+
+impl Connected<&Conn> for PeerAddr {
+    fn connect_info(conn: &Conn) -> PeerAddr {
+        PeerAddr(match conn {
+            Conn::Tcp(stream) => Some(stream.remote_addr()),
+            #[cfg(unix)]
+            Conn::Unix(_) => None,
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use std::{env, error::Error};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::tui::LogBuffer;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Pretty,
+    /// One JSON object per line, including the fields of the current span.
+    Json,
+}
+
+/// Installs the global log subscriber. The level is a filter directive like
+/// `debug` or `remote_uci=trace`, falling back to the `REMOTE_UCI_LOG`
+/// environment variable and then `info`. Lines are written to the buffer
+/// of the dashboard instead of stderr, if given.
+pub fn init(
+    format: LogFormat,
+    level: Option<&str>,
+    dashboard: Option<LogBuffer>,
+) -> Result<(), Box<dyn Error>> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => {
+            EnvFilter::try_from_env("REMOTE_UCI_LOG").unwrap_or_else(|_| EnvFilter::new("info"))
+        }
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false);
+    if let Some(dashboard) = dashboard {
+        return builder
+            .with_ansi(false)
+            .with_writer(dashboard)
+            .try_init()
+            .map_err(|err| err as Box<dyn Error>);
+    }
+    let builder = builder.with_writer(std::io::stderr);
+    match format {
+        LogFormat::Pretty => builder
+            .with_ansi(env::var("REMOTE_UCI_LOG_STYLE").map_or(true, |style| style != "never"))
+            .try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    }
+    .map_err(|err| err as Box<dyn Error>)
+}
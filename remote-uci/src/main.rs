@@ -1,30 +1,125 @@
-use std::error::Error;
+use std::{error::Error, process::ExitCode};
 
 use clap::Parser;
 use listenfd::ListenFd;
-use remote_uci::{make_server, Opts};
+use remote_uci::{
+    annotate, download_stockfish, download_tablebases, make_server, open_browser, replay, setup,
+    stats, terminal_qr_code, upgrade, Command, EngineHandle, Opts,
+};
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::Builder::from_env(
-        env_logger::Env::new()
-            .filter("REMOTE_UCI_LOG")
-            .default_filter_or("info")
-            .write_style("REMOTE_UCI_LOG_STYLE"),
-    )
-    .format_target(false)
-    .format_module_path(false)
-    .init();
-
-    let (spec, server) = make_server(Opts::parse(), ListenFd::from_env()).await?;
-    println!("{}", spec.registration_url());
-    server.with_graceful_shutdown(shutdown_signal()).await?;
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    let opts = Opts::parse();
+    let dashboard = opts.init_logging()?;
+
+    match opts.command() {
+        Some(Command::Setup) => return setup(opts).await,
+        Some(Command::Replay {
+            transcript,
+            include_info,
+        }) => return replay(opts, &transcript, include_info).await,
+        Some(Command::Annotate {
+            pgn,
+            depth,
+            movetime,
+            output,
+        }) => {
+            return annotate(
+                opts,
+                &pgn,
+                output.as_deref(),
+                depth,
+                movetime.map(Into::into),
+            )
+            .await
+        }
+        Some(Command::Stats) => return stats(opts),
+        Some(Command::DownloadStockfish) => return download_stockfish().await,
+        Some(Command::DownloadTablebases { pieces }) => {
+            return download_tablebases(opts, pieces).await
+        }
+        Some(Command::Upgrade { path }) => return upgrade(opts, &path).await,
+        #[cfg(windows)]
+        Some(Command::Service { action }) => return remote_uci::service(opts, action),
+        #[cfg(target_os = "linux")]
+        Some(Command::SystemdInstall) => return remote_uci::systemd_install(opts),
+        None => (),
+    }
+
+    let open = opts.open();
+    let qr = opts.qr();
+    #[cfg(feature = "tray")]
+    let tray = opts.tray();
+    #[cfg(not(feature = "tray"))]
+    let tray = false;
+    let (server, handle) = make_server(opts, ListenFd::from_env()).await?;
+    for spec in handle.specs() {
+        let url = spec.registration_url();
+        println!("{url}");
+        if qr {
+            println!("{}", terminal_qr_code(&url)?);
+        }
+        if open {
+            open_browser(&url);
+        }
+    }
+    let server = server.with_graceful_shutdown(async {
+        tokio::select! {
+            _ = shutdown_signal() => (),
+            _ = quit_from_tray(&handle), if tray => {
+                println!("Quit from system tray, shutting down gracefully...");
+            }
+        }
+        handle.request_shutdown();
+    });
+    match dashboard {
+        Some(logs) => tokio::join!(server, handle.dashboard(logs)).0?,
+        None => server.await?,
+    }
+    handle.shutdown().await;
     Ok(())
 }
 
+#[cfg(feature = "tray")]
+async fn quit_from_tray(handle: &EngineHandle) {
+    handle.tray().await;
+}
+
+#[cfg(not(feature = "tray"))]
+async fn quit_from_tray(_handle: &EngineHandle) {
+    std::future::pending().await
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Expect shutdown signal handler");
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => {
+            res.expect("Expect shutdown signal handler");
+            println!("\nRecieved SIGINT, shutting down gracefully...");
+        }
+        _ = terminate.recv() => {
+            println!("Recieved SIGTERM, shutting down gracefully...");
+        }
+    }
+}
+
+#[cfg(not(unix))]
 async fn shutdown_signal() {
     tokio::signal::ctrl_c()
         .await
         .expect("Expect shutdown signal handler");
     println!("\nRecieved SIGINT, shutting down gracefully...");
-}
\ No newline at end of file
+}
@@ -0,0 +1,140 @@
+use std::{
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use axum::{http::header, response::IntoResponse};
+use sysinfo::{Pid, PidExt, ProcessExt, RefreshKind, System, SystemExt};
+
+#[derive(Default, Debug)]
+pub struct EngineMetrics {
+    pub sessions: AtomicU64,
+    pub analyses: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub restarts: AtomicU64,
+    pub nps: AtomicU64,
+    pub pid: AtomicU32,
+    pub secret_failures: AtomicU64,
+    /// Average round trip time to the client of the current session.
+    pub latency_ms: AtomicU64,
+}
+
+pub struct Metrics {
+    started: Instant,
+    engines: Vec<(String, Vec<Arc<EngineMetrics>>)>,
+}
+
+impl Metrics {
+    /// Metrics of each engine path. Values of pooled engine processes are
+    /// summed up.
+    pub fn new(engines: Vec<(String, Vec<Arc<EngineMetrics>>)>) -> Metrics {
+        Metrics {
+            started: Instant::now(),
+            engines,
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut sys = System::new_with_specifics(RefreshKind::new());
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP remote_uci_uptime_seconds Time since the server was started."
+        );
+        let _ = writeln!(out, "# TYPE remote_uci_uptime_seconds gauge");
+        let _ = writeln!(
+            out,
+            "remote_uci_uptime_seconds {}",
+            self.started.elapsed().as_secs()
+        );
+
+        self.family(
+            &mut out,
+            "remote_uci_active_sessions",
+            "gauge",
+            "Connected websocket sessions.",
+            |m| m.sessions.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_analyses_total",
+            "counter",
+            "Searches started on behalf of clients.",
+            |m| m.analyses.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_analysis_cache_hits_total",
+            "counter",
+            "Searches answered from the analysis cache.",
+            |m| m.cache_hits.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_engine_restarts_total",
+            "counter",
+            "Engine processes restarted after exiting unexpectedly.",
+            |m| m.restarts.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_secret_failures_total",
+            "counter",
+            "Websocket connections rejected because of a wrong secret.",
+            |m| m.secret_failures.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_engine_nps",
+            "gauge",
+            "Nodes per second reported in the last info line.",
+            |m| m.nps.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_session_latency_milliseconds",
+            "gauge",
+            "Average round trip time to websocket clients of current sessions.",
+            |m| m.latency_ms.load(Ordering::Relaxed),
+        );
+        self.family(
+            &mut out,
+            "remote_uci_engine_rss_bytes",
+            "gauge",
+            "Resident set size of the engine process.",
+            |m| {
+                let pid = Pid::from_u32(m.pid.load(Ordering::Relaxed));
+                if sys.refresh_process(pid) {
+                    sys.process(pid).map_or(0, |p| p.memory() * 1024)
+                } else {
+                    0
+                }
+            },
+        );
+        out
+    }
+
+    fn family<F>(&self, out: &mut String, name: &str, kind: &str, help: &str, mut value: F)
+    where
+        F: FnMut(&EngineMetrics) -> u64,
+    {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (path, metrics) in &self.engines {
+            let sum: u64 = metrics.iter().map(|m| value(m)).sum();
+            let _ = writeln!(out, "{name}{{path=\"{path}\"}} {sum}");
+        }
+    }
+}
+
+pub async fn handler(metrics: Arc<Metrics>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
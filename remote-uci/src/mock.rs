@@ -0,0 +1,377 @@
+use std::{
+    cmp::{max, min},
+    collections::HashMap,
+    io,
+    time::Duration,
+};
+
+use shakmaty::{uci::Uci, CastlingMode, Chess, Color, Move, Position, Role, Square};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream},
+    time::{interval, Instant, MissedTickBehavior},
+};
+
+use crate::uci::{Eval, Score, UciIn, UciOption, UciOptionName, UciOut};
+
+/// Time for each iteration of the pretend search.
+const ITERATION: Duration = Duration::from_millis(100);
+/// Depth of searches that are neither infinite nor otherwise limited.
+const DEFAULT_DEPTH: u32 = 20;
+/// Iterations stop at this depth, but infinite searches still wait for stop.
+const MAX_DEPTH: u32 = 99;
+const MAX_MULTIPV: i64 = 5;
+const MAX_PV_LEN: u32 = 12;
+/// Number of moves that are considered for the principal variations.
+const CANDIDATES: usize = 8;
+/// Pretend nodes per second.
+const NPS: u64 = 1_000_000;
+
+/// Built-in engine that does not need an external binary, for testing the
+/// server end-to-end and for demonstrations. It picks moves with a trivial
+/// heuristic, but emits plausible `info` and `bestmove` output with the
+/// usual timing.
+pub struct MockEngine {
+    position: Chess,
+    multipv: u32,
+    search: Option<Search>,
+}
+
+struct Search {
+    started: Instant,
+    depth: u32,
+    max_depth: u32,
+    max_nodes: Option<u64>,
+    deadline: Option<Instant>,
+    infinite: bool,
+    pondering: bool,
+    bestmove: Option<Uci>,
+    ponder: Option<Uci>,
+}
+
+impl Default for MockEngine {
+    fn default() -> MockEngine {
+        MockEngine {
+            position: Chess::default(),
+            multipv: 1,
+            search: None,
+        }
+    }
+}
+
+impl MockEngine {
+    /// Runs a new engine on a background task. Returns the other end of its
+    /// standard input and output.
+    pub fn spawn() -> DuplexStream {
+        let (client, engine) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            let (input, output) = tokio::io::split(engine);
+            if let Err(err) = MockEngine::default().run(input, output).await {
+                tracing::error!("mock engine failed: {err}");
+            }
+        });
+        client
+    }
+
+    /// Reads commands from `input` and writes responses to `output`, until
+    /// `input` is closed.
+    pub async fn run<R, W>(mut self, input: R, mut output: W) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(input).lines();
+        let mut ticks = interval(ITERATION);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let responses = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => self.handle(&line),
+                    None => return Ok(()),
+                },
+                _ = ticks.tick(), if self.search.is_some() => self.iterate(),
+            };
+            for response in responses {
+                output.write_all(format!("{response}\n").as_bytes()).await?;
+            }
+            output.flush().await?;
+        }
+    }
+
+    fn handle(&mut self, line: &str) -> Vec<UciOut> {
+        let command = match UciIn::from_line(line) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Vec::new(),
+            Err(err) => return vec![UciOut::info_string(format!("{err}: {line}"))],
+        };
+        match command {
+            UciIn::Uci => vec![
+                UciOut::IdName("remote-uci demo".to_owned()),
+                UciOut::IdAuthor("remote-uci".to_owned()),
+                spin("Threads", 1, 1, 512),
+                spin("Hash", 16, 1, 33_554_432),
+                spin("MultiPV", 1, 1, MAX_MULTIPV),
+                UciOut::Uciok,
+            ],
+            UciIn::Isready => vec![UciOut::Readyok],
+            UciIn::Setoption { name, value } => {
+                if name == "MultiPV" {
+                    if let Some(multipv) = value.and_then(|v| v.parse().ok()) {
+                        self.multipv = multipv;
+                    }
+                }
+                Vec::new()
+            }
+            UciIn::Ucinewgame => {
+                self.position = Chess::default();
+                Vec::new()
+            }
+            UciIn::Position { fen, moves } => {
+                let mut position = match fen {
+                    Some(fen) => match fen.into_position(CastlingMode::Standard) {
+                        Ok(position) => position,
+                        Err(err) => return vec![UciOut::info_string(err.to_string())],
+                    },
+                    None => Chess::default(),
+                };
+                for uci in moves {
+                    match uci.to_move(&position) {
+                        Ok(m) => position.play_unchecked(&m),
+                        Err(err) => return vec![UciOut::info_string(err.to_string())],
+                    }
+                }
+                self.position = position;
+                Vec::new()
+            }
+            UciIn::Go {
+                ponder,
+                wtime,
+                btime,
+                winc,
+                binc,
+                movestogo,
+                depth,
+                nodes,
+                mate,
+                movetime,
+                infinite,
+                ..
+            } => {
+                let started = Instant::now();
+                let (time, inc) = match self.position.turn() {
+                    Color::White => (wtime, winc),
+                    Color::Black => (btime, binc),
+                };
+                let budget = time.map(|time| {
+                    time / movestogo.map_or(30, |n| max(n, 1)) + inc.unwrap_or_default()
+                });
+                let deadline = match (movetime, budget) {
+                    (Some(a), Some(b)) => Some(min(a, b)),
+                    (a, b) => a.or(b),
+                };
+                self.search = Some(Search {
+                    started,
+                    depth: 0,
+                    max_depth: depth
+                        .or_else(|| mate.map(|n| n * 2))
+                        .map_or(DEFAULT_DEPTH, |depth| depth.clamp(1, MAX_DEPTH)),
+                    max_nodes: nodes,
+                    deadline: deadline.map(|d| started + d),
+                    infinite,
+                    pondering: ponder,
+                    bestmove: None,
+                    ponder: None,
+                });
+                Vec::new()
+            }
+            UciIn::Stop => self.finish(),
+            UciIn::Ponderhit => {
+                if let Some(ref mut search) = self.search {
+                    search.pondering = false;
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    fn iterate(&mut self) -> Vec<UciOut> {
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None => return Vec::new(),
+        };
+        let mut responses = Vec::new();
+        if search.depth < search.max_depth {
+            search.depth += 1;
+            let time = search.started.elapsed();
+            let nodes = pretend_nodes(time);
+            for (i, (pv, eval)) in lines(&self.position, search.depth, self.multipv)
+                .into_iter()
+                .enumerate()
+            {
+                if i == 0 {
+                    search.bestmove = pv.first().cloned();
+                    search.ponder = pv.get(1).cloned();
+                }
+                responses.push(UciOut::Info {
+                    multipv: u32::try_from(i + 1).ok().and_then(|n| n.try_into().ok()),
+                    depth: Some(search.depth),
+                    seldepth: Some(search.depth + search.depth / 2),
+                    time: Some(time),
+                    nodes: Some(nodes),
+                    score: Some(Score::new(eval)),
+                    wdl: None,
+                    currmove: None,
+                    currmovenumber: None,
+                    hashfull: Some(min(search.depth * 40, 1000)),
+                    nps: Some(NPS),
+                    tbhits: None,
+                    sbhits: None,
+                    cpuload: None,
+                    ebf: None,
+                    refutation: HashMap::new(),
+                    currline: HashMap::new(),
+                    pv: Some(pv),
+                    string: None,
+                });
+            }
+        }
+        let done = search.depth >= search.max_depth
+            || search
+                .deadline
+                .map_or(false, |deadline| deadline <= Instant::now())
+            || search.max_nodes.map_or(false, |max_nodes| {
+                pretend_nodes(search.started.elapsed()) >= max_nodes
+            });
+        if done && !search.infinite && !search.pondering {
+            responses.extend(self.finish());
+        }
+        responses
+    }
+
+    fn finish(&mut self) -> Vec<UciOut> {
+        match self.search.take() {
+            Some(search) => {
+                let (m, ponder) = match search.bestmove {
+                    Some(m) => (Some(m), search.ponder),
+                    None => (
+                        lines(&self.position, 1, 1)
+                            .into_iter()
+                            .next()
+                            .and_then(|(pv, _)| pv.into_iter().next()),
+                        None,
+                    ),
+                };
+                vec![UciOut::Bestmove { m, ponder }]
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+fn spin(name: &str, default: i64, min: i64, max: i64) -> UciOut {
+    UciOut::Option {
+        name: UciOptionName(name.to_owned()),
+        option: UciOption::Spin { default, min, max },
+    }
+}
+
+fn pretend_nodes(time: Duration) -> u64 {
+    u64::try_from(time.as_millis()).unwrap_or(u64::MAX) * (NPS / 1000)
+}
+
+fn value(role: Role) -> i64 {
+    match role {
+        Role::Pawn => 100,
+        Role::Knight | Role::Bishop => 300,
+        Role::Rook => 500,
+        Role::Queen => 900,
+        Role::King => 0,
+    }
+}
+
+/// Greedy move ordering: mates, captures of valuable pieces with cheap
+/// pieces, promotions, checks and moves towards the center.
+fn priority(position: &Chess, m: &Move) -> i64 {
+    let mut after = position.clone();
+    after.play_unchecked(m);
+    if after.is_checkmate() {
+        return i64::MAX;
+    }
+    let center = [Square::D4, Square::E4, Square::D5, Square::E5]
+        .iter()
+        .map(|sq| m.to().distance(*sq))
+        .min()
+        .unwrap_or_default();
+    m.capture()
+        .map_or(0, |role| value(role) * 10 - value(m.role()))
+        + m.promotion().map_or(0, value) * 10
+        + if after.is_check() { 50 } else { 0 }
+        - i64::from(center) * 5
+}
+
+/// Material balance from the point of view of the side to move.
+fn material(position: &Chess) -> i64 {
+    let material = position.board().material();
+    let side = |color| {
+        Role::ALL
+            .iter()
+            .map(|role| i64::from(*material.get(color).get(*role)) * value(*role))
+            .sum::<i64>()
+    };
+    let balance = side(Color::White) - side(Color::Black);
+    match position.turn() {
+        Color::White => balance,
+        Color::Black => -balance,
+    }
+}
+
+/// Principal variations for the best `multipv` moves, by playing greedy
+/// moves for both sides.
+fn lines(position: &Chess, depth: u32, multipv: u32) -> Vec<(Vec<Uci>, Eval)> {
+    let mut moves: Vec<Move> = position.legal_moves().into_iter().collect();
+    moves.sort_by_key(|m| -priority(position, m));
+    let mut lines: Vec<_> = moves
+        .into_iter()
+        .take(max(
+            CANDIDATES,
+            usize::try_from(multipv).unwrap_or(usize::MAX),
+        ))
+        .map(|first| {
+            let mut pv = Vec::new();
+            let mut current = position.clone();
+            let mut m = Some(first);
+            while let Some(next) = m.take() {
+                pv.push(Uci::from_move(&next, CastlingMode::Standard));
+                current.play_unchecked(&next);
+                if pv.len() < min(depth, MAX_PV_LEN) as usize {
+                    m = current
+                        .legal_moves()
+                        .into_iter()
+                        .max_by_key(|m| priority(&current, m));
+                }
+            }
+            let plies = i32::try_from(pv.len()).unwrap_or(i32::MAX);
+            let eval = if current.is_checkmate() {
+                // Odd number of plies means the side to move delivers mate.
+                if plies % 2 == 1 {
+                    Eval::Mate((plies + 1) / 2)
+                } else {
+                    Eval::Mate(-plies / 2)
+                }
+            } else {
+                let balance = material(&current);
+                // Make evaluations fluctuate a little between iterations.
+                let noise = i64::from(depth % 5) * 3 - 6;
+                Eval::Cp(if plies % 2 == 1 {
+                    -balance + noise
+                } else {
+                    balance + noise
+                })
+            };
+            (pv, eval)
+        })
+        .collect();
+    lines.sort_by_key(|(_, eval)| eval.sort_key());
+    lines.reverse();
+    lines.truncate(usize::try_from(multipv).unwrap_or(usize::MAX));
+    lines
+}
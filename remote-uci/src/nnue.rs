@@ -0,0 +1,73 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ring::digest::{digest, SHA256};
+
+use crate::stockfish::{data_dir, hex};
+
+/// Networks of official Stockfish, by name.
+const NETWORK_URL: &str = "https://tests.stockfishchess.org/api/nn/";
+
+/// Name of an official network, if the --eval-file is given as one, like
+/// nn-6877cd24400e.nnue, or just as the SHA-256 prefix in its name.
+fn network_name(eval_file: &Path) -> Option<String> {
+    let name = eval_file.to_str()?;
+    let prefix = name
+        .strip_prefix("nn-")
+        .and_then(|name| name.strip_suffix(".nnue"))
+        .unwrap_or(name);
+    (prefix.len() == 12
+        && prefix
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+    .then(|| format!("nn-{prefix}.nnue"))
+}
+
+/// Where downloaded networks are kept.
+fn download_path(name: &str) -> Option<PathBuf> {
+    Some(data_dir()?.join("remote-uci").join("networks").join(name))
+}
+
+/// The network file for --eval-file: the file itself, or a previously
+/// downloaded official network.
+pub fn locate(eval_file: &Path) -> Option<PathBuf> {
+    if eval_file.is_file() {
+        return Some(eval_file.to_owned());
+    }
+    download_path(&network_name(eval_file)?).filter(|path| path.is_file())
+}
+
+/// Downloads the official network named by --eval-file, unless the file
+/// exists. The hash of the download must match the prefix in the name.
+pub async fn fetch(eval_file: &Path) -> Result<(), Box<dyn Error>> {
+    if locate(eval_file).is_some() {
+        return Ok(());
+    }
+    let name = network_name(eval_file).ok_or_else(|| {
+        format!("{eval_file:?} does not exist, and does not name an official network")
+    })?;
+    let path = download_path(&name).ok_or("could not determine data directory")?;
+    tracing::info!("Downloading network {name} ...");
+    let network = reqwest::Client::builder()
+        .user_agent(concat!("remote-uci/", env!("CARGO_PKG_VERSION")))
+        .build()?
+        .get(format!("{NETWORK_URL}{name}"))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let hash = hex(digest(&SHA256, &network).as_ref());
+    if name[3..15] != hash[..12] {
+        return Err(format!("hash of downloaded network {name} does not match: {hash}").into());
+    }
+    fs::create_dir_all(path.parent().expect("network path has parent"))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &network)?;
+    fs::rename(tmp, &path)?;
+    tracing::info!("Saved network to {path:?}");
+    Ok(())
+}
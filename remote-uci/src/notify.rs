@@ -0,0 +1,108 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::browser::is_headless;
+
+/// Whether the program that shows notifications is not installed. Noted
+/// once, so that the log is not repeated for every notification.
+static NOTIFIER_MISSING: AtomicBool = AtomicBool::new(false);
+
+/// Shows a desktop notification in the background, if there is a graphical
+/// session. Failures are logged.
+pub fn notify(title: &str, body: &str) {
+    if is_headless() || NOTIFIER_MISSING.load(Ordering::Relaxed) {
+        tracing::debug!("Can not show notifications, not showing: {title}");
+        return;
+    }
+    let (title, body) = (title.to_owned(), body.to_owned());
+    tokio::spawn(async move {
+        match imp::notify(&title, &body).await {
+            Ok(output) if !output.status.success() => tracing::warn!(
+                "Could not show notification: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if !NOTIFIER_MISSING.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(
+                        "Could not show notification: {} is not installed",
+                        imp::PROGRAM
+                    );
+                }
+            }
+            Err(err) => tracing::warn!("Could not show notification: {err}"),
+        }
+    });
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::{io, process::Output};
+
+    use tokio::process::Command;
+
+    pub const PROGRAM: &str = "notify-send";
+
+    pub async fn notify(title: &str, body: &str) -> io::Result<Output> {
+        Command::new(PROGRAM)
+            .args(["--app-name", "remote-uci", title, body])
+            .output()
+            .await
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::{io, process::Output};
+
+    use tokio::process::Command;
+
+    pub const PROGRAM: &str = "osascript";
+
+    pub async fn notify(title: &str, body: &str) -> io::Result<Output> {
+        // Arguments are passed separately, so that they need no quoting.
+        Command::new(PROGRAM)
+            .args([
+                "-e",
+                "on run argv",
+                "-e",
+                "display notification (item 2 of argv) with title (item 1 of argv)",
+                "-e",
+                "end run",
+                title,
+                body,
+            ])
+            .output()
+            .await
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{io, process::Output};
+
+    use tokio::process::Command;
+
+    /// Shows a balloon tip from the notification area. Title and body are
+    /// passed in the environment, so that they need no quoting.
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Windows.Forms; \
+        $icon = New-Object System.Windows.Forms.NotifyIcon; \
+        $icon.Icon = [System.Drawing.SystemIcons]::Information; \
+        $icon.Visible = $true; \
+        $icon.ShowBalloonTip(5000, $env:REMOTE_UCI_TITLE, $env:REMOTE_UCI_BODY, 'Info'); \
+        Start-Sleep -Seconds 6; \
+        $icon.Dispose()";
+
+    pub const PROGRAM: &str = "powershell";
+
+    pub async fn notify(title: &str, body: &str) -> io::Result<Output> {
+        Command::new(PROGRAM)
+            .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+            .env("REMOTE_UCI_TITLE", title)
+            .env("REMOTE_UCI_BODY", body)
+            .output()
+            .await
+    }
+}
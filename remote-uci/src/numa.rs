@@ -0,0 +1,273 @@
+use std::{fmt, io, str::FromStr};
+
+use tokio::process::Command;
+
+use crate::affinity::CpuSet;
+
+/// How to place engines on machines with multiple NUMA nodes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Let engines that support it, like Stockfish, distribute their
+    /// threads and memory over all nodes.
+    Auto,
+    /// Spread memory evenly over all nodes.
+    Interleave,
+    /// Run only on the processors and memory of a single node.
+    Node(usize),
+}
+
+impl NumaPolicy {
+    /// Value for the NumaPolicy option of Stockfish. Placement is left to
+    /// the operating system if it is managed here.
+    pub fn engine_option(self) -> &'static str {
+        match self {
+            NumaPolicy::Auto => "auto",
+            NumaPolicy::Interleave | NumaPolicy::Node(_) => "none",
+        }
+    }
+}
+
+impl FromStr for NumaPolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<NumaPolicy, &'static str> {
+        match s {
+            "auto" => Ok(NumaPolicy::Auto),
+            "interleave" => Ok(NumaPolicy::Interleave),
+            _ => s
+                .strip_prefix("node")
+                .and_then(|node| node.parse().ok())
+                .map(NumaPolicy::Node)
+                .ok_or("expected auto, interleave or nodeN"),
+        }
+    }
+}
+
+impl fmt::Display for NumaPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumaPolicy::Auto => f.write_str("auto"),
+            NumaPolicy::Interleave => f.write_str("interleave"),
+            NumaPolicy::Node(node) => write!(f, "node{node}"),
+        }
+    }
+}
+
+/// Processors and memory of a NUMA node.
+#[derive(Clone, Debug)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: CpuSet,
+    /// Available memory (MiB).
+    pub memory: u64,
+}
+
+/// Arranges for the engine process to be placed according to the policy.
+pub fn apply(command: &mut Command, policy: NumaPolicy) -> io::Result<()> {
+    imp::apply(command, policy)
+}
+
+/// NUMA nodes of this machine, or nothing if the topology is unknown.
+pub fn nodes() -> Vec<NumaNode> {
+    imp::nodes()
+}
+
+/// Finds a node by its number.
+pub fn node(id: usize) -> Option<NumaNode> {
+    nodes().into_iter().find(|node| node.id == id)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{fs, io, mem};
+
+    use tokio::process::Command;
+
+    use super::{NumaNode, NumaPolicy};
+
+    const MPOL_PREFERRED: i32 = 1;
+    const MPOL_INTERLEAVE: i32 = 3;
+
+    /// Bits of unsigned longs, for node numbers up to 1023.
+    type NodeMask = [usize; 16];
+
+    const NODE_DIR: &str = "/sys/devices/system/node";
+
+    pub fn nodes() -> Vec<NumaNode> {
+        let mut nodes: Vec<NumaNode> = match fs::read_dir(NODE_DIR) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let id = entry
+                        .file_name()
+                        .to_str()?
+                        .strip_prefix("node")?
+                        .parse()
+                        .ok()?;
+                    read_node(id)
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        nodes.sort_by_key(|node| node.id);
+        nodes
+    }
+
+    fn read_node(id: usize) -> Option<NumaNode> {
+        let cpus = fs::read_to_string(format!("{NODE_DIR}/node{id}/cpulist")).ok()?;
+        let meminfo = fs::read_to_string(format!("{NODE_DIR}/node{id}/meminfo")).ok()?;
+        // Free memory and page cache that can be reclaimed.
+        let available_kib = field(&meminfo, "MemFree:")? + field(&meminfo, "Inactive(file):")?;
+        Some(NumaNode {
+            id,
+            cpus: cpus.trim().parse().ok()?,
+            memory: (available_kib / 1024).next_power_of_two() / 2,
+        })
+    }
+
+    /// Reads a value from lines like: Node 0 MemFree: 123456 kB
+    fn field(meminfo: &str, name: &str) -> Option<u64> {
+        meminfo
+            .lines()
+            .find_map(|line| line.split_once(name))
+            .and_then(|(_, value)| value.trim().trim_end_matches("kB").trim().parse().ok())
+    }
+
+    pub fn apply(command: &mut Command, policy: NumaPolicy) -> io::Result<()> {
+        let nodes = nodes();
+        let (mode, ids) = match policy {
+            NumaPolicy::Auto => return Ok(()),
+            NumaPolicy::Interleave => (
+                MPOL_INTERLEAVE,
+                nodes.iter().map(|node| node.id).collect::<Vec<_>>(),
+            ),
+            NumaPolicy::Node(id) => {
+                let node = nodes.iter().find(|node| node.id == id).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no NUMA node {id}"))
+                })?;
+                crate::affinity::apply(command, &node.cpus);
+                (MPOL_PREFERRED, vec![id])
+            }
+        };
+        if nodes.len() < 2 {
+            tracing::warn!("Ignoring --numa {policy}: not a NUMA machine");
+            return Ok(());
+        }
+        let mask = node_mask(&ids);
+        let bits = 8 * mem::size_of::<usize>();
+        unsafe {
+            command.pre_exec(move || {
+                if libc::syscall(
+                    libc::SYS_set_mempolicy,
+                    mode,
+                    mask.as_ptr(),
+                    bits * mask.len() + 1,
+                ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Nodes as a bit mask for set_mempolicy. Larger node numbers are
+    /// ignored.
+    fn node_mask(ids: &[usize]) -> NodeMask {
+        let mut mask: NodeMask = [0; 16];
+        let bits = 8 * mem::size_of::<usize>();
+        for &id in ids {
+            if id < bits * mask.len() {
+                mask[id / bits] |= 1 << (id % bits);
+            }
+        }
+        mask
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_field() {
+            let meminfo = "Node 0 MemTotal:       32768000 kB\n\
+                           Node 0 MemFree:         1048576 kB\n\
+                           Node 0 Inactive(file):   524288 kB\n";
+            assert_eq!(field(meminfo, "MemFree:"), Some(1_048_576));
+            assert_eq!(field(meminfo, "Inactive(file):"), Some(524_288));
+            assert_eq!(field(meminfo, "Active(file):"), None);
+        }
+
+        #[test]
+        fn test_node_mask() {
+            let bits = 8 * mem::size_of::<usize>();
+            let mask = node_mask(&[0, 3, bits + 1, 1024]);
+            assert_eq!(mask[0], 0b1001);
+            assert_eq!(mask[1], 0b10);
+            assert!(mask[2..].iter().all(|&word| word == 0));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    use tokio::process::Command;
+
+    use super::{NumaNode, NumaPolicy};
+
+    pub fn nodes() -> Vec<NumaNode> {
+        Vec::new()
+    }
+
+    pub fn apply(_command: &mut Command, policy: NumaPolicy) -> io::Result<()> {
+        if policy != NumaPolicy::Auto {
+            tracing::warn!("Ignoring --numa {policy}: not supported on this platform");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        for (s, policy) in [
+            ("auto", NumaPolicy::Auto),
+            ("interleave", NumaPolicy::Interleave),
+            ("node0", NumaPolicy::Node(0)),
+            ("node12", NumaPolicy::Node(12)),
+        ] {
+            assert_eq!(s.parse(), Ok(policy));
+            assert_eq!(policy.to_string(), s);
+        }
+        for invalid in ["", "node", "node-1", "nodex", "1", "Auto", "interleaved"] {
+            assert!(invalid.parse::<NumaPolicy>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_engine_option() {
+        assert_eq!(NumaPolicy::Auto.engine_option(), "auto");
+        assert_eq!(NumaPolicy::Interleave.engine_option(), "none");
+        assert_eq!(NumaPolicy::Node(1).engine_option(), "none");
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut command = Command::new("true");
+        assert!(apply(&mut command, NumaPolicy::Auto).is_ok());
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                apply(&mut command, NumaPolicy::Node(1023))
+                    .expect_err("no such node")
+                    .kind(),
+                io::ErrorKind::NotFound
+            );
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{bench, engine::EngineAddr};
+
+/// Official Stockfish releases that can be promised, with their bench
+/// signature: the number of nodes searched by `bench` with default
+/// settings, as published with the release. Any change to the search or
+/// evaluation changes the signature. Newer releases need to be added here
+/// before they can be promised.
+const RELEASES: &[(&str, u64)] = &[
+    ("15", 8_129_754),
+    ("15.1", 3_467_381),
+    ("16", 2_593_605),
+    ("17", 1_484_730),
+];
+
+/// Whether the release of the given version can be promised.
+pub fn is_known(version: &str) -> bool {
+    RELEASES.iter().any(|(known, _)| *known == version)
+}
+
+/// Checks the promise that the engine is an official Stockfish release, by
+/// the name it reports and by its bench signature. Returns the version.
+pub async fn verify(addr: &EngineAddr, name: Option<&str>) -> Result<&'static str, String> {
+    let path = match addr {
+        EngineAddr::Process(path) => path,
+        _ => return Err(format!("can not run bench for {addr}")),
+    };
+    let name = name.ok_or("engine did not report a name")?;
+    let (version, expected) = RELEASES
+        .iter()
+        .find(|(version, _)| name == format!("Stockfish {version}"))
+        .ok_or_else(|| {
+            let known: Vec<&str> = RELEASES.iter().map(|(version, _)| *version).collect();
+            format!(
+                "{name} is not a known official Stockfish release (known: {})",
+                known.join(", ")
+            )
+        })?;
+    tracing::info!("Running bench to verify {name} ...");
+    let signature = bench::signature(path)
+        .await
+        .map_err(|err| format!("could not run bench: {err}"))?;
+    if signature == *expected {
+        Ok(version)
+    } else {
+        Err(format!(
+            "bench signature {signature} does not match {expected} of Stockfish {version}"
+        ))
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use reqwest::Client;
+use serde::Deserialize;
+use shakmaty::{fen::Fen, Chess, EnPassantMode, Position};
+
+/// Tablebase of lichess, with exact results for up to 7 pieces.
+const TABLEBASE_URL: &str = "https://tablebase.lichess.ovh/standard";
+
+/// Most pieces covered by the online tablebase.
+const MAX_PIECES: usize = 7;
+
+/// Number of probe results to keep.
+const CAPACITY: usize = 10_000;
+
+/// How long the engine waits for a probe before it starts searching.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct Probe {
+    category: String,
+    dtz: Option<i64>,
+    dtm: Option<i64>,
+    moves: Vec<ProbeMove>,
+}
+
+#[derive(Deserialize)]
+struct ProbeMove {
+    uci: String,
+}
+
+/// Results from the lichess tablebase API for positions that local
+/// tablebases do not cover.
+pub struct OnlineTablebase {
+    http: Client,
+    /// Most pieces covered by local tablebases.
+    local_pieces: usize,
+    results: Mutex<Results>,
+}
+
+#[derive(Default)]
+struct Results {
+    by_fen: HashMap<String, Option<String>>,
+    /// Positions in order of insertion, to evict the oldest result first.
+    order: VecDeque<String>,
+}
+
+impl OnlineTablebase {
+    pub fn new(local_pieces: Option<usize>) -> OnlineTablebase {
+        OnlineTablebase {
+            http: Client::builder()
+                .user_agent(concat!("remote-uci/", env!("CARGO_PKG_VERSION")))
+                .timeout(PROBE_TIMEOUT)
+                .build()
+                .expect("tablebase client"),
+            local_pieces: local_pieces.unwrap_or(0),
+            results: Mutex::default(),
+        }
+    }
+
+    /// Exact result of the position as an info string, like
+    /// `lichess tablebase: win, dtz 13, dtm 21, best move e2e4`, unless the
+    /// position is covered by local tablebases or not by the online
+    /// tablebase.
+    pub async fn probe(&self, pos: &Chess) -> Option<String> {
+        let pieces = pos.board().occupied().count();
+        if pieces <= self.local_pieces || pieces > MAX_PIECES || pos.castles().any() {
+            return None;
+        }
+        let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+        if let Some(result) = self
+            .results
+            .lock()
+            .expect("tablebase results")
+            .by_fen
+            .get(&fen)
+        {
+            return result.clone();
+        }
+        let result = match self.request(&fen).await {
+            Ok(result) => result,
+            Err(err) => {
+                // Not cached, to try again next time.
+                tracing::warn!("Could not probe online tablebase: {err}");
+                return None;
+            }
+        };
+        let mut results = self.results.lock().expect("tablebase results");
+        if results.by_fen.insert(fen.clone(), result.clone()).is_none() {
+            results.order.push_back(fen);
+        }
+        while results.order.len() > CAPACITY {
+            if let Some(oldest) = results.order.pop_front() {
+                results.by_fen.remove(&oldest);
+            }
+        }
+        result
+    }
+
+    async fn request(&self, fen: &str) -> reqwest::Result<Option<String>> {
+        let probe: Probe = self
+            .http
+            .get(TABLEBASE_URL)
+            .query(&[("fen", fen)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if probe.category == "unknown" {
+            return Ok(None);
+        }
+        let mut result = format!("lichess tablebase: {}", probe.category);
+        if let Some(dtz) = probe.dtz {
+            result.push_str(&format!(", dtz {dtz}"));
+        }
+        if let Some(dtm) = probe.dtm {
+            result.push_str(&format!(", dtm {dtm}"));
+        }
+        if let Some(best) = probe.moves.first() {
+            result.push_str(&format!(", best move {}", best.uci));
+        }
+        Ok(Some(result))
+    }
+}
@@ -0,0 +1,216 @@
+use std::net::IpAddr;
+
+use axum::http::{header, HeaderMap};
+
+/// Origins of web pages that may open websockets, in addition to pages
+/// served from localhost.
+const DEFAULT_ORIGINS: [&str; 1] = ["https://lichess.org"];
+
+/// Protects websockets against cross-site websocket hijacking and DNS
+/// rebinding. Clients other than browsers usually do not send an Origin
+/// header, and are not affected.
+#[derive(Debug, Default)]
+pub struct OriginPolicy {
+    origins: Vec<String>,
+    any_origin: bool,
+    hosts: Vec<String>,
+}
+
+impl OriginPolicy {
+    /// Allows the default origins and `extra_origins`, where `*` allows
+    /// any origin. Allows Host headers with IP addresses, localhost and
+    /// the host of the public URL.
+    pub fn new(extra_origins: &[String], public_url: &str) -> OriginPolicy {
+        OriginPolicy {
+            origins: DEFAULT_ORIGINS
+                .iter()
+                .map(|origin| (*origin).to_owned())
+                .chain(
+                    extra_origins
+                        .iter()
+                        .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase()),
+                )
+                .collect(),
+            any_origin: extra_origins.iter().any(|origin| origin == "*"),
+            hosts: hostname(
+                public_url
+                    .split_once("://")
+                    .map_or(public_url, |(_, rest)| rest)
+                    .split('/')
+                    .next()
+                    .unwrap_or_default(),
+            )
+            .into_iter()
+            .map(str::to_ascii_lowercase)
+            .collect(),
+        }
+    }
+
+    /// Checks the Origin and Host headers of a request. Returns a reason if
+    /// the request should be rejected.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), String> {
+        if let Some(origin) = headers.get(header::ORIGIN) {
+            let origin = origin.to_str().unwrap_or_default().to_ascii_lowercase();
+            if !self.any_origin && !self.origins.contains(&origin) && !is_local_origin(&origin) {
+                return Err(format!("origin {origin} is not allowed"));
+            }
+        }
+        if let Some(host) = headers.get(header::HOST) {
+            let host = host.to_str().unwrap_or_default();
+            let allowed = hostname(host).map_or(false, |name| {
+                is_local_host(name)
+                    || self
+                        .hosts
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            });
+            if !allowed {
+                return Err(format!("host {host} is not allowed"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Host name or IP address of a Host header or URL authority, without the
+/// port.
+fn hostname(authority: &str) -> Option<&str> {
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split_once(']').map(|(ip, _)| ip);
+    }
+    let name = authority.split(':').next().unwrap_or_default();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Host names that can not be taken over by DNS rebinding: IP addresses
+/// and localhost.
+fn is_local_host(name: &str) -> bool {
+    name.parse::<IpAddr>().is_ok()
+        || name.eq_ignore_ascii_case("localhost")
+        || name.to_ascii_lowercase().ends_with(".localhost")
+}
+
+/// Pages served from the same machine, like a local lichess instance.
+fn is_local_origin(origin: &str) -> bool {
+    ["http://", "https://"]
+        .iter()
+        .find_map(|scheme| origin.strip_prefix(scheme))
+        .and_then(hostname)
+        .map_or(false, |name| {
+            name == "localhost" || name.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(origin: Option<&'static str>, host: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        }
+        if let Some(host) = host {
+            headers.insert(header::HOST, HeaderValue::from_static(host));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_hostname() {
+        assert_eq!(hostname("example.com"), Some("example.com"));
+        assert_eq!(hostname("example.com:9670"), Some("example.com"));
+        assert_eq!(hostname("user:pass@example.com:9670"), Some("example.com"));
+        assert_eq!(hostname("user@evil.com@example.com"), Some("example.com"));
+        assert_eq!(hostname("[::1]"), Some("::1"));
+        assert_eq!(hostname("[::1]:9670"), Some("::1"));
+        assert_eq!(hostname("[2001:db8::1"), None);
+        assert_eq!(hostname(""), None);
+        assert_eq!(hostname(":9670"), None);
+        assert_eq!(hostname("user@"), None);
+    }
+
+    #[test]
+    fn test_local_origin() {
+        assert!(is_local_origin("http://localhost"));
+        assert!(is_local_origin("http://localhost:8080"));
+        assert!(is_local_origin("https://127.0.0.1:9663"));
+        assert!(is_local_origin("http://[::1]:8080"));
+        assert!(!is_local_origin("http://localhost.evil.com"));
+        assert!(!is_local_origin("http://127.0.0.1.evil.com"));
+        assert!(!is_local_origin("http://evil.com"));
+        assert!(!is_local_origin("http://192.0.2.1"));
+        assert!(!is_local_origin("file://localhost"));
+        assert!(!is_local_origin("null"));
+    }
+
+    #[test]
+    fn test_origin() {
+        let policy = OriginPolicy::new(
+            &["https://Example.com/".to_owned()],
+            "wss://engine.example.org",
+        );
+        assert!(policy.check(&headers(None, None)).is_ok(), "not a browser");
+        assert!(policy
+            .check(&headers(Some("https://lichess.org"), None))
+            .is_ok());
+        assert!(
+            policy
+                .check(&headers(Some("https://example.com"), None))
+                .is_ok(),
+            "trailing slash and case of extra origin"
+        );
+        assert!(policy
+            .check(&headers(Some("http://localhost:8080"), None))
+            .is_ok());
+        assert!(policy
+            .check(&headers(Some("https://evil.com"), None))
+            .is_err());
+        assert!(policy
+            .check(&headers(Some("http://localhost.evil.com"), None))
+            .is_err());
+        assert!(policy
+            .check(&headers(Some("https://lichess.org.evil.com"), None))
+            .is_err());
+
+        let any = OriginPolicy::new(&["*".to_owned()], "wss://engine.example.org");
+        assert!(any.check(&headers(Some("https://evil.com"), None)).is_ok());
+    }
+
+    #[test]
+    fn test_host() {
+        let policy = OriginPolicy::new(&[], "wss://Engine.example.org:9670/socket");
+        for host in [
+            "engine.example.org",
+            "ENGINE.example.org:443",
+            "localhost:9670",
+            "app.localhost",
+            "127.0.0.1:9670",
+            "192.0.2.1",
+            "[::1]:9670",
+        ] {
+            assert!(policy.check(&headers(None, Some(host))).is_ok(), "{host}");
+        }
+        for host in [
+            "evil.com",
+            "engine.example.org.evil.com",
+            "localhost.evil.com",
+            "",
+        ] {
+            assert!(policy.check(&headers(None, Some(host))).is_err(), "{host}");
+        }
+        assert!(
+            policy
+                .check(&headers(Some("https://lichess.org"), Some("evil.com")))
+                .is_err(),
+            "allowed origin does not make up for rebound host"
+        );
+    }
+}
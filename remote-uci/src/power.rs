@@ -0,0 +1,104 @@
+use std::{fmt, str::FromStr};
+
+/// Lower limits for threads and hash that apply while the machine is
+/// running on battery, like threads=4,hash=512.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatteryLimits {
+    pub threads: Option<u32>,
+    /// Size of hash table (MiB).
+    pub hash: Option<u32>,
+}
+
+impl FromStr for BatteryLimits {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<BatteryLimits, &'static str> {
+        let mut limits = BatteryLimits::default();
+        for part in s.split(',') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or("expected limits like threads=4,hash=512")?;
+            let value = value.trim().parse().map_err(|_| "expected number")?;
+            match key.trim() {
+                "threads" => limits.threads = Some(value),
+                "hash" => limits.hash = Some(value),
+                _ => return Err("expected threads or hash"),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+impl fmt::Display for BatteryLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(threads) = self.threads {
+            parts.push(format!("threads={threads}"));
+        }
+        if let Some(hash) = self.hash {
+            parts.push(format!("hash={hash}"));
+        }
+        f.write_str(&parts.join(","))
+    }
+}
+
+/// Whether the machine is running on battery rather than external power.
+/// Machines without batteries, or where the power source can not be
+/// detected, are considered to be plugged in.
+pub fn on_battery() -> bool {
+    imp::on_battery()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs;
+
+    pub fn on_battery() -> bool {
+        let supplies = match fs::read_dir("/sys/class/power_supply") {
+            Ok(supplies) => supplies,
+            Err(_) => return false,
+        };
+        supplies.filter_map(Result::ok).any(|supply| {
+            let path = supply.path();
+            let read = |name: &str| {
+                fs::read_to_string(path.join(name))
+                    .map(|content| content.trim().to_owned())
+                    .unwrap_or_default()
+            };
+            read("type") == "Battery" && read("status") == "Discharging"
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::process::Command;
+
+    pub fn on_battery() -> bool {
+        Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .map_or(false, |output| {
+                String::from_utf8_lossy(&output.stdout).contains("'Battery Power'")
+            })
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::mem;
+
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    pub fn on_battery() -> bool {
+        let mut status = unsafe { mem::zeroed::<SYSTEM_POWER_STATUS>() };
+        unsafe { GetSystemPowerStatus(&mut status) != 0 && status.ACLineStatus == 0 }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    pub fn on_battery() -> bool {
+        false
+    }
+}
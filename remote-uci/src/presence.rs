@@ -0,0 +1,145 @@
+use std::{io, time::Duration};
+
+/// How long the local user has not used keyboard or mouse, if this can be
+/// determined.
+pub async fn user_idle_time() -> Option<Duration> {
+    imp::user_idle_time().await
+}
+
+/// Suspends or resumes the process, without it noticing.
+pub fn pause_process(pid: u32, paused: bool) -> io::Result<()> {
+    imp::pause_process(pid, paused)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::{io, time::Duration};
+
+    use tokio::process::Command;
+
+    pub async fn user_idle_time() -> Option<Duration> {
+        // X11, with xprintidle installed.
+        if let Ok(output) = Command::new("xprintidle").output().await {
+            if let Ok(millis) = String::from_utf8_lossy(&output.stdout).trim().parse() {
+                return Some(Duration::from_millis(millis));
+            }
+        }
+        // GNOME on Wayland. Prints: (uint64 12345,)
+        let output = Command::new("gdbus")
+            .args([
+                "call",
+                "--session",
+                "--dest",
+                "org.gnome.Mutter.IdleMonitor",
+                "--object-path",
+                "/org/gnome/Mutter/IdleMonitor/Core",
+                "--method",
+                "org.gnome.Mutter.IdleMonitor.GetIdletime",
+            ])
+            .output()
+            .await
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .strip_prefix("(uint64 ")?
+            .strip_suffix(",)")?
+            .parse()
+            .ok()
+            .map(Duration::from_millis)
+    }
+
+    pub fn pause_process(pid: u32, paused: bool) -> io::Result<()> {
+        super::signal(pid, paused)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::{io, time::Duration};
+
+    use tokio::process::Command;
+
+    pub async fn user_idle_time() -> Option<Duration> {
+        // Prints lines like: "HIDIdleTime" = 1234567890
+        let output = Command::new("ioreg")
+            .args(["-c", "IOHIDSystem", "-d", "4"])
+            .output()
+            .await
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.split_once("\"HIDIdleTime\" ="))
+            .and_then(|(_, nanos)| nanos.trim().parse().ok())
+            .map(Duration::from_nanos)
+    }
+
+    pub fn pause_process(pid: u32, paused: bool) -> io::Result<()> {
+        super::signal(pid, paused)
+    }
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, paused: bool) -> io::Result<()> {
+    let pid = i32::try_from(pid).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let signal = if paused { libc::SIGSTOP } else { libc::SIGCONT };
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{io, mem, time::Duration};
+
+    use winapi::{
+        shared::ntdef::{HANDLE, NTSTATUS},
+        um::{
+            handleapi::CloseHandle,
+            processthreadsapi::OpenProcess,
+            sysinfoapi::GetTickCount,
+            winnt::PROCESS_SUSPEND_RESUME,
+            winuser::{GetLastInputInfo, LASTINPUTINFO},
+        },
+    };
+
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn NtSuspendProcess(process: HANDLE) -> NTSTATUS;
+        fn NtResumeProcess(process: HANDLE) -> NTSTATUS;
+    }
+
+    pub async fn user_idle_time() -> Option<Duration> {
+        let mut info = LASTINPUTINFO {
+            cbSize: mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if unsafe { GetLastInputInfo(&mut info) } == 0 {
+            return None;
+        }
+        let now = unsafe { GetTickCount() };
+        Some(Duration::from_millis(now.wrapping_sub(info.dwTime).into()))
+    }
+
+    pub fn pause_process(pid: u32, paused: bool) -> io::Result<()> {
+        let process = unsafe { OpenProcess(PROCESS_SUSPEND_RESUME, 0, pid) };
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let status = unsafe {
+            if paused {
+                NtSuspendProcess(process)
+            } else {
+                NtResumeProcess(process)
+            }
+        };
+        unsafe { CloseHandle(process) };
+        if status < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("could not suspend or resume process: {status:#x}"),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// Scheduling priority of engine processes on Windows.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PriorityClass {
+    Idle,
+    BelowNormal,
+    #[default]
+    Normal,
+    AboveNormal,
+    High,
+}
+
+/// How much processor time engine processes get compared to other
+/// processes on the same machine.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProcessPriority {
+    /// Niceness on Unix, from -20 (highest priority) to 19 (lowest).
+    pub nice: Option<i32>,
+    /// Priority class on Windows.
+    pub class: Option<PriorityClass>,
+}
+
+/// Arranges for the engine process to start with the given priority.
+pub fn apply(command: &mut Command, priority: ProcessPriority) {
+    imp::apply(command, priority);
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::io;
+
+    use tokio::process::Command;
+
+    use super::ProcessPriority;
+
+    pub fn apply(command: &mut Command, priority: ProcessPriority) {
+        if priority.class.is_some() {
+            tracing::warn!("Ignoring --engine-priority, use --engine-nice on this platform");
+        }
+        if let Some(nice) = priority.nice {
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use tokio::process::Command;
+    use winapi::um::winbase::{
+        ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS,
+        IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+    };
+
+    use super::{PriorityClass, ProcessPriority};
+
+    pub fn apply(command: &mut Command, priority: ProcessPriority) {
+        if priority.nice.is_some() {
+            tracing::warn!("Ignoring --engine-nice, use --engine-priority on this platform");
+        }
+        if let Some(class) = priority.class {
+            command.creation_flags(match class {
+                PriorityClass::Idle => IDLE_PRIORITY_CLASS,
+                PriorityClass::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+                PriorityClass::Normal => NORMAL_PRIORITY_CLASS,
+                PriorityClass::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+                PriorityClass::High => HIGH_PRIORITY_CLASS,
+            });
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use tokio::process::Command;
+
+    use super::ProcessPriority;
+
+    pub fn apply(_command: &mut Command, priority: ProcessPriority) {
+        if priority.nice.is_some() || priority.class.is_some() {
+            tracing::warn!("Ignoring engine priority: not supported on this platform");
+        }
+    }
+}
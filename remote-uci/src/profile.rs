@@ -0,0 +1,54 @@
+use std::{cmp::min, collections::BTreeMap};
+
+use serde::Deserialize;
+
+use crate::{
+    tokens::Permissions,
+    uci::{UciIn, UciOptionName},
+};
+
+/// Settings for a kind of analysis, like quick checks or correspondence
+/// games, that clients choose when they connect. Defined in the config file
+/// as `[profile.NAME]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// Threads to use, and the most that clients may set.
+    pub threads: Option<u32>,
+    /// Hash table size in MiB, and the most that clients may set.
+    pub hash: Option<u32>,
+    /// Most lines that clients may analyse at the same time.
+    pub max_multipv: Option<u32>,
+    /// UCI options to set when a session starts.
+    pub options: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// Lowers the limits of a client to those of the profile.
+    pub fn restrict(&self, permissions: &mut Permissions) {
+        permissions.max_threads = lower(permissions.max_threads, self.threads);
+        permissions.max_hash = lower(permissions.max_hash, self.hash);
+        permissions.max_multipv = lower(permissions.max_multipv, self.max_multipv);
+    }
+
+    /// Options to set at the start of a session.
+    pub fn session_commands(&self) -> Vec<UciIn> {
+        let spins = [("Threads", self.threads), ("Hash", self.hash)]
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_owned(), value?.to_string())));
+        spins
+            .chain(self.options.clone())
+            .map(|(name, value)| UciIn::Setoption {
+                name: UciOptionName(name),
+                value: Some(value),
+            })
+            .collect()
+    }
+}
+
+fn lower(limit: Option<u32>, other: Option<u32>) -> Option<u32> {
+    match (limit, other) {
+        (Some(limit), Some(other)) => Some(min(limit, other)),
+        (limit, other) => limit.or(other),
+    }
+}
@@ -0,0 +1,56 @@
+use std::error::Error;
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use qrcode::{render::unicode::Dense1x2, Color, QrCode};
+
+/// Size of a module in the PNG, in pixels.
+const SCALE: usize = 8;
+
+/// Width of the quiet zone around the code, in modules.
+const QUIET_ZONE: usize = 4;
+
+/// Renders the data as a QR code with unicode half blocks, to be printed in
+/// a terminal with a dark background.
+pub fn terminal_qr_code(data: &str) -> Result<String, Box<dyn Error>> {
+    Ok(QrCode::new(data)?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+fn png(data: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let code = QrCode::new(data)?;
+    let width = code.width();
+    let size = (width + 2 * QUIET_ZONE) * SCALE;
+    let mut pixels = vec![0xff; size * size];
+    for y in 0..width {
+        for x in 0..width {
+            if code[(x, y)] == Color::Dark {
+                for row in 0..SCALE {
+                    let offset = ((y + QUIET_ZONE) * SCALE + row) * size + (x + QUIET_ZONE) * SCALE;
+                    pixels[offset..offset + SCALE].fill(0);
+                }
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let size = u32::try_from(size)?;
+    let mut encoder = png::Encoder::new(&mut out, size, size);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(out)
+}
+
+pub async fn handler(data: String) -> Result<impl IntoResponse, StatusCode> {
+    let png = png(&data).map_err(|err| {
+        tracing::error!("Failed to render QR code: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
@@ -0,0 +1,61 @@
+use std::{error::Error, sync::Arc};
+
+use crate::{
+    engine::Reconfiguration,
+    ws::{SecretStore, SharedEngine},
+    Opts,
+};
+
+/// Applies changes of the config file and secret files at runtime, without
+/// interrupting running sessions or the listener.
+pub struct Reloader {
+    /// Options from the command line, which take precedence over the config
+    /// file.
+    opts: Opts,
+    endpoints: Vec<(Arc<SharedEngine>, Arc<SecretStore>)>,
+}
+
+impl Reloader {
+    pub fn new(opts: Opts, endpoints: Vec<(Arc<SharedEngine>, Arc<SecretStore>)>) -> Reloader {
+        Reloader { opts, endpoints }
+    }
+
+    /// Reads the config file again. Search limits, allowed options and UCI
+    /// options apply to new sessions. Secrets apply to new connections.
+    /// Other options require a restart.
+    pub async fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts.clone().with_config_file()?;
+        let (options, _) = opts.engine_options()?;
+        let reconfiguration = Reconfiguration {
+            options,
+            limits: opts.search_limits(),
+            option_policy: opts.option_policy(),
+        };
+        for (engine, secrets) in &self.endpoints {
+            engine.reconfigure(reconfiguration.clone());
+            secrets.reload().await;
+        }
+        tracing::warn!("Reloaded configuration for new sessions");
+        Ok(())
+    }
+}
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(reloader: Arc<Reloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            tracing::error!("Could not handle SIGHUP: {err}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        tracing::warn!("Received SIGHUP, reloading configuration ...");
+        if let Err(err) = reloader.reload().await {
+            tracing::error!("Keeping previous configuration: {err}");
+        }
+    }
+}
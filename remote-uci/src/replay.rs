@@ -0,0 +1,159 @@
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use tokio::time::{sleep_until, timeout, Instant};
+
+use crate::{
+    engine::{Engine, Session},
+    gpu::GpuBackend,
+    uci::UciIn,
+    Opts,
+};
+
+/// How long to wait for the engine to finish after the end of the
+/// recording.
+const GRACE: Duration = Duration::from_secs(5);
+
+struct Entry {
+    at: SystemTime,
+    client: bool,
+    line: String,
+}
+
+/// Parses a transcript written with --session-log-dir.
+fn parse(transcript: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for (i, line) in transcript.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || format!("invalid transcript line {}: {line}", i + 1);
+        let mut parts = line.splitn(3, ' ');
+        let at = humantime::parse_rfc3339_weak(parts.next().ok_or_else(invalid)?)
+            .map_err(|_| invalid())?;
+        let client = match parts.next() {
+            Some(">") => true,
+            Some("<") => false,
+            _ => return Err(invalid().into()),
+        };
+        entries.push(Entry {
+            at,
+            client,
+            line: parts.next().unwrap_or_default().to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Replays the client side of a recorded session against the configured
+/// engine, with the original timing, and compares the responses.
+pub async fn replay(
+    opts: Opts,
+    transcript: &Path,
+    include_info: bool,
+) -> Result<(), Box<dyn Error>> {
+    let entries = parse(&fs::read_to_string(transcript)?)?;
+    let first = entries
+        .first()
+        .map_or_else(SystemTime::now, |entry| entry.at);
+    let last = entries.last().map_or(first, |entry| entry.at);
+
+    let opts = opts.with_config_file()?;
+    let gpu = GpuBackend::detect();
+    let addr = opts
+        .engine
+        .clone()
+        .best(gpu)
+        .await
+        .ok_or("no engine configured")?;
+    let mut engine = Engine::new(addr, opts.local_engine_parameters(gpu)).await?;
+
+    let session = Session(1);
+    engine.ensure_newgame(session).await?;
+
+    let start = Instant::now();
+    let offset = |at: SystemTime| at.duration_since(first).unwrap_or_default();
+    let mut actual = Vec::new();
+    for entry in entries.iter().filter(|entry| entry.client) {
+        let deadline = start + offset(entry.at);
+        loop {
+            tokio::select! {
+                command = engine.recv(session) => actual.push(command?.to_string()),
+                _ = sleep_until(deadline) => break,
+            }
+        }
+        if let Some(command) = UciIn::from_line(&entry.line)? {
+            engine.send(session, command).await?;
+        }
+    }
+    let end = start + offset(last);
+    loop {
+        tokio::select! {
+            command = engine.recv(session) => actual.push(command?.to_string()),
+            _ = sleep_until(end) => break,
+        }
+    }
+    while !engine.is_idle() {
+        match timeout(GRACE, engine.recv(session)).await {
+            Ok(command) => actual.push(command?.to_string()),
+            Err(_) => break,
+        }
+    }
+
+    let relevant = |line: &&str| {
+        include_info || !line.starts_with("info ") || line.starts_with("info string ")
+    };
+    let expected: Vec<&str> = entries
+        .iter()
+        .filter(|entry| !entry.client)
+        .map(|entry| entry.line.as_str())
+        .filter(relevant)
+        .collect();
+    let actual: Vec<&str> = actual.iter().map(String::as_str).filter(relevant).collect();
+
+    let changes = diff(&expected, &actual);
+    if changes.iter().all(|(change, _)| *change == ' ') {
+        println!("Responses match ({} lines)", expected.len());
+        return Ok(());
+    }
+    for (change, line) in changes {
+        println!("{change} {line}");
+    }
+    Err("responses differ".into())
+}
+
+/// Line based diff of the longest common subsequence. Lines are prefixed
+/// with ' ' if unchanged, '-' if only expected and '+' if only actual.
+fn diff<'a>(expected: &[&'a str], actual: &[&'a str]) -> Vec<(char, &'a str)> {
+    let (n, m) = (expected.len(), actual.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            changes.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(('-', expected[i]));
+            i += 1;
+        } else {
+            changes.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+    changes
+}
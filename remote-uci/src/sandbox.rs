@@ -0,0 +1,324 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// Restrictions for engine processes, which are driven by remote clients.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxMode {
+    /// No restrictions.
+    #[default]
+    Off,
+    /// No network access and no writing to the filesystem.
+    Relaxed,
+    /// Like relaxed, and reading only from the engine directory, system
+    /// libraries, tablebases and weights. Refuse to start if this can not be
+    /// enforced.
+    Strict,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SandboxPolicy {
+    pub mode: SandboxMode,
+    /// Additional files and directories that the engine may read in strict
+    /// mode.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Arranges for the engine executable to be sandboxed before it starts.
+pub fn apply(command: &mut Command, executable: &Path, policy: &SandboxPolicy) -> io::Result<()> {
+    if policy.mode == SandboxMode::Off {
+        return Ok(());
+    }
+    imp::apply(command, executable, policy)
+}
+
+/// For engines that can not be sandboxed, like under wine, which needs to
+/// write to its prefix. Fails only in strict mode.
+pub fn skip(policy: &SandboxPolicy, what: &str) -> io::Result<()> {
+    if policy.mode == SandboxMode::Off {
+        return Ok(());
+    }
+    unsupported(policy, what)
+}
+
+fn unsupported(policy: &SandboxPolicy, what: &str) -> io::Result<()> {
+    if policy.mode == SandboxMode::Strict {
+        tracing::error!("Can not enforce strict sandbox: {what} not supported");
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{what} not supported"),
+        ))
+    } else {
+        tracing::warn!("Sandbox is incomplete: {what} not supported");
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        fs::File,
+        io, mem,
+        os::unix::io::{AsRawFd, FromRawFd, RawFd},
+        path::{Path, PathBuf},
+    };
+
+    use libc::{sock_filter, sock_fprog};
+    use tokio::process::Command;
+
+    use super::{unsupported, SandboxMode, SandboxPolicy};
+
+    // Landlock system calls have the same numbers on all architectures.
+    const SYS_LANDLOCK_CREATE_RULESET: i32 = 444;
+    const SYS_LANDLOCK_ADD_RULE: i32 = 445;
+    const SYS_LANDLOCK_RESTRICT_SELF: i32 = 446;
+    const LANDLOCK_RULE_PATH_BENEATH: i32 = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// All access rights of the first Landlock ABI.
+    const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+    const ACCESS_FS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    /// Directories that dynamically linked executables commonly need.
+    const SYSTEM_PATHS: [&str; 6] = [
+        "/usr",
+        "/lib",
+        "/lib64",
+        "/etc/ld.so.cache",
+        "/proc",
+        "/sys/devices/system/cpu",
+    ];
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: RawFd,
+    }
+
+    /// Everything that needs to be prepared before forking, so that the
+    /// child only has to make system calls.
+    struct Sandbox {
+        ruleset: Option<File>,
+        filter: Option<Vec<sock_filter>>,
+    }
+
+    impl Sandbox {
+        fn enter(&self) -> io::Result<()> {
+            unsafe {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some(ref ruleset) = self.ruleset {
+                    if libc::syscall(SYS_LANDLOCK_RESTRICT_SELF.into(), ruleset.as_raw_fd(), 0) != 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(ref filter) = self.filter {
+                    let prog = sock_fprog {
+                        len: filter.len() as u16,
+                        filter: filter.as_ptr() as *mut sock_filter,
+                    };
+                    if libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &prog as *const sock_fprog,
+                    ) != 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub fn apply(
+        command: &mut Command,
+        executable: &Path,
+        policy: &SandboxPolicy,
+    ) -> io::Result<()> {
+        let sandbox = Sandbox {
+            ruleset: match ruleset(executable, policy) {
+                Ok(ruleset) => Some(ruleset),
+                Err(err) => {
+                    unsupported(policy, &format!("landlock ({err})"))?;
+                    None
+                }
+            },
+            filter: match network_filter() {
+                Some(filter) => Some(filter),
+                None => {
+                    unsupported(policy, "seccomp on this architecture")?;
+                    None
+                }
+            },
+        };
+        unsafe {
+            command.pre_exec(move || sandbox.enter());
+        }
+        Ok(())
+    }
+
+    fn ruleset(executable: &Path, policy: &SandboxPolicy) -> io::Result<File> {
+        let attr = RulesetAttr {
+            handled_access_fs: ACCESS_FS_ALL,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET.into(),
+                &attr as *const RulesetAttr,
+                mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = unsafe { File::from_raw_fd(fd as RawFd) };
+
+        // Device files like /dev/null are always writable.
+        add_rule(
+            &ruleset,
+            Path::new("/dev"),
+            ACCESS_FS_READ | ACCESS_FS_WRITE_FILE,
+        )?;
+        match policy.mode {
+            SandboxMode::Off | SandboxMode::Relaxed => {
+                add_rule(&ruleset, Path::new("/"), ACCESS_FS_READ)?;
+            }
+            SandboxMode::Strict => {
+                let engine_dir = executable.parent().unwrap_or(executable);
+                for path in SYSTEM_PATHS
+                    .iter()
+                    .map(Path::new)
+                    .chain(Some(engine_dir))
+                    .chain(policy.paths.iter().map(PathBuf::as_path))
+                {
+                    add_rule(&ruleset, path, ACCESS_FS_READ)?;
+                }
+            }
+        }
+        Ok(ruleset)
+    }
+
+    fn add_rule(ruleset: &File, path: &Path, access: u64) -> io::Result<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let access = if file.metadata()?.is_dir() {
+            access
+        } else {
+            // Directory rights are invalid for files.
+            access & !ACCESS_FS_READ_DIR
+        };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: file.as_raw_fd(),
+        };
+        let res = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE.into(),
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Seccomp filter that prevents creating internet sockets.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn network_filter() -> Option<Vec<sock_filter>> {
+        #[cfg(target_arch = "x86_64")]
+        const AUDIT_ARCH: u32 = 0xc000_003e;
+        #[cfg(target_arch = "aarch64")]
+        const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+        // System call numbers at or above this belong to another ABI (x32).
+        #[cfg(target_arch = "x86_64")]
+        const SYSCALL_LIMIT: u32 = 0x4000_0000;
+        #[cfg(target_arch = "aarch64")]
+        const SYSCALL_LIMIT: u32 = u32::MAX;
+
+        const LD_W_ABS: u16 = 0x20;
+        const JEQ_K: u16 = 0x15;
+        const JGE_K: u16 = 0x35;
+        const RET_K: u16 = 0x06;
+
+        // Offsets in struct seccomp_data.
+        const NR: u32 = 0;
+        const ARCH: u32 = 4;
+        const ARG0: u32 = 16;
+
+        fn stmt(code: u16, k: u32) -> sock_filter {
+            sock_filter {
+                code,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+
+        fn jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+            sock_filter { code, jt, jf, k }
+        }
+
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EACCES as u32;
+        Some(vec![
+            stmt(LD_W_ABS, ARCH),
+            jump(JEQ_K, AUDIT_ARCH, 1, 0),
+            stmt(RET_K, deny),
+            stmt(LD_W_ABS, NR),
+            jump(JGE_K, SYSCALL_LIMIT, 5, 0),
+            jump(JEQ_K, libc::SYS_socket as u32, 0, 3),
+            stmt(LD_W_ABS, ARG0),
+            jump(JEQ_K, libc::AF_INET as u32, 2, 0),
+            jump(JEQ_K, libc::AF_INET6 as u32, 1, 0),
+            stmt(RET_K, libc::SECCOMP_RET_ALLOW),
+            stmt(RET_K, deny),
+        ])
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn network_filter() -> Option<Vec<sock_filter>> {
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::{io, path::Path};
+
+    use tokio::process::Command;
+
+    use super::{unsupported, SandboxPolicy};
+
+    pub fn apply(
+        _command: &mut Command,
+        _executable: &Path,
+        policy: &SandboxPolicy,
+    ) -> io::Result<()> {
+        unsupported(policy, "sandboxing on this platform")
+    }
+}
@@ -0,0 +1,244 @@
+use std::{fmt, str::FromStr};
+
+const DAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Times during which sessions are accepted, like 22:00-07:00,sat,sun for
+/// nights and weekends.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Ranges of minutes of the day, from 0 to 24:00. Ranges that end
+    /// before they start continue on the next day.
+    ranges: Vec<(u16, u16)>,
+    /// Whole days of the week, starting with Sunday.
+    days: Vec<u8>,
+}
+
+impl Schedule {
+    fn is_available_at(&self, day: u8, minute: u16) -> bool {
+        self.days.contains(&day)
+            || self.ranges.iter().any(|&(start, end)| {
+                if start < end {
+                    start <= minute && minute < end
+                } else {
+                    start <= minute || minute < end
+                }
+            })
+    }
+
+    /// When the next window starts, like "22:00" or "Sat 00:00", or nothing
+    /// if sessions are accepted now.
+    pub fn unavailable_until(&self) -> Option<String> {
+        let (day, minute) = imp::now();
+        self.unavailable_until_at(day, minute)
+    }
+
+    fn unavailable_until_at(&self, day: u8, minute: u16) -> Option<String> {
+        if self.is_available_at(day, minute) {
+            return None;
+        }
+        let start = u32::from(day) * u32::from(MINUTES_PER_DAY) + u32::from(minute);
+        (1..=7 * u32::from(MINUTES_PER_DAY)).find_map(|offset| {
+            let time = start + offset;
+            let next_day = (time / u32::from(MINUTES_PER_DAY) % 7) as u8;
+            let next_minute = (time % u32::from(MINUTES_PER_DAY)) as u16;
+            self.is_available_at(next_day, next_minute).then(|| {
+                let clock = format!("{:02}:{:02}", next_minute / 60, next_minute % 60);
+                if next_day == day && offset < u32::from(MINUTES_PER_DAY) {
+                    clock
+                } else {
+                    let name = &DAYS[usize::from(next_day)][..3];
+                    format!("{}{} {clock}", name[..1].to_uppercase(), &name[1..])
+                }
+            })
+        })
+    }
+}
+
+/// Parses names of days, like sat or saturday.
+fn parse_day(s: &str) -> Option<u8> {
+    let s = s.to_ascii_lowercase();
+    DAYS.iter()
+        .position(|day| s.len() >= 3 && day.starts_with(&s))
+        .map(|day| day as u8)
+}
+
+/// Parses times of the day, like 07:00 or 24:00.
+fn parse_time(s: &str) -> Option<u16> {
+    let (hours, minutes) = s.split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    let time = hours.checked_mul(60)?.checked_add(minutes)?;
+    (minutes < 60 && time <= MINUTES_PER_DAY).then_some(time)
+}
+
+impl FromStr for Schedule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Schedule, &'static str> {
+        let mut schedule = Schedule::default();
+        for item in s.split(',').map(str::trim) {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+            if let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) {
+                if start % MINUTES_PER_DAY == end % MINUTES_PER_DAY {
+                    return Err("expected time range like 22:00-07:00");
+                }
+                schedule.ranges.push((start, end));
+            } else if let (Some(start), Some(end)) = (parse_day(start), parse_day(end)) {
+                // Day ranges like mon-fri may wrap around the weekend.
+                let mut day = start;
+                loop {
+                    schedule.days.push(day);
+                    if day == end {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            } else {
+                return Err("expected times like 22:00-07:00 or days like sat");
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges = self.ranges.iter().map(|(start, end)| {
+            format!(
+                "{:02}:{:02}-{:02}:{:02}",
+                start / 60,
+                start % 60,
+                end / 60,
+                end % 60
+            )
+        });
+        let days = self
+            .days
+            .iter()
+            .map(|&day| DAYS[usize::from(day)][..3].to_owned());
+        f.write_str(&ranges.chain(days).collect::<Vec<_>>().join(","))
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{mem, ptr};
+
+    /// Day of the week (starting with Sunday) and minute of the day, in
+    /// local time.
+    pub fn now() -> (u8, u16) {
+        unsafe {
+            let time = libc::time(ptr::null_mut());
+            let mut tm = mem::zeroed::<libc::tm>();
+            if libc::localtime_r(&time, &mut tm).is_null() {
+                return (0, 0);
+            }
+            (tm.tm_wday as u8, (tm.tm_hour * 60 + tm.tm_min) as u16)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::mem;
+
+    use winapi::um::{minwinbase::SYSTEMTIME, sysinfoapi::GetLocalTime};
+
+    /// Day of the week (starting with Sunday) and minute of the day, in
+    /// local time.
+    pub fn now() -> (u8, u16) {
+        let mut time = unsafe { mem::zeroed::<SYSTEMTIME>() };
+        unsafe { GetLocalTime(&mut time) };
+        (time.wDayOfWeek as u8, time.wHour * 60 + time.wMinute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MON: u8 = 1;
+    const FRI: u8 = 5;
+    const SAT: u8 = 6;
+
+    fn schedule(s: &str) -> Schedule {
+        s.parse().expect("schedule")
+    }
+
+    fn at(hours: u16, minutes: u16) -> u16 {
+        hours * 60 + minutes
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            schedule("22:00-07:00, Sat,sunday").to_string(),
+            "22:00-07:00,sat,sun"
+        );
+        assert_eq!(schedule("fri-mon").to_string(), "fri,sat,sun,mon");
+        assert_eq!(schedule("18:30-24:00").to_string(), "18:30-24:00");
+        for invalid in [
+            "",
+            "22:00",
+            "22:00-22:00",
+            "00:00-24:00",
+            "25:00-07:00",
+            "22:60-07:00",
+            "22-07",
+            "mo",
+            "mon-someday",
+            "22:00-07:00,",
+        ] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_wrap_past_midnight() {
+        let nights = schedule("22:00-07:00");
+        assert!(nights.is_available_at(MON, at(22, 0)));
+        assert!(nights.is_available_at(MON, at(23, 59)));
+        assert!(nights.is_available_at(MON, at(0, 0)));
+        assert!(nights.is_available_at(MON, at(6, 59)));
+        assert!(!nights.is_available_at(MON, at(7, 0)));
+        assert!(!nights.is_available_at(MON, at(21, 59)));
+
+        let evenings = schedule("18:00-24:00");
+        assert!(evenings.is_available_at(MON, at(23, 59)));
+        assert!(!evenings.is_available_at(MON, at(0, 0)));
+    }
+
+    #[test]
+    fn test_unavailable_until() {
+        let nights = schedule("22:00-07:00");
+        assert_eq!(nights.unavailable_until_at(MON, at(23, 0)), None);
+        assert_eq!(
+            nights.unavailable_until_at(MON, at(12, 0)),
+            Some("22:00".to_owned())
+        );
+
+        let weekends = schedule("sat,sun");
+        assert_eq!(
+            weekends.unavailable_until_at(MON, at(12, 0)),
+            Some("Sat 00:00".to_owned())
+        );
+        assert_eq!(weekends.unavailable_until_at(SAT, at(12, 0)), None);
+
+        // Tomorrow, but at an earlier time of the day.
+        let mornings = schedule("06:00-08:00");
+        assert_eq!(
+            mornings.unavailable_until_at(FRI, at(9, 0)),
+            Some("Sat 06:00".to_owned())
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    env,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Subcommand;
+use windows_service::{
+    service::{
+        Service, ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceState,
+        ServiceType,
+    },
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+use crate::{load_secret, Opts};
+
+/// Name under which remote-uci-service.exe registers with the service
+/// control manager.
+const SERVICE_NAME: &str = "remote_uci";
+
+/// Event log source that remote-uci-service.exe writes to.
+const EVENT_SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\remote-uci";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Subcommand)]
+pub enum ServiceAction {
+    /// Register remote-uci-service.exe as a Windows Service that starts
+    /// with Windows, with the options given before `service`. Paths must be
+    /// absolute. Requires administrator rights.
+    Install,
+    /// Stop and remove the Windows Service.
+    Uninstall,
+    /// Start the Windows Service.
+    Start,
+    /// Stop the Windows Service.
+    Stop,
+}
+
+/// Manages the Windows Service.
+pub fn service(opts: Opts, action: ServiceAction) -> Result<(), Box<dyn Error>> {
+    match action {
+        ServiceAction::Install => install(opts),
+        ServiceAction::Uninstall => uninstall(),
+        ServiceAction::Start => {
+            open(ServiceAccess::START)?.start(&[] as &[&OsStr])?;
+            println!("Started service {SERVICE_NAME}");
+            Ok(())
+        }
+        ServiceAction::Stop => {
+            let service = open(ServiceAccess::STOP | ServiceAccess::QUERY_STATUS)?;
+            service.stop()?;
+            wait_stopped(&service)?;
+            println!("Stopped service {SERVICE_NAME}");
+            Ok(())
+        }
+    }
+}
+
+fn install(opts: Opts) -> Result<(), Box<dyn Error>> {
+    let executable_path = env::current_exe()?.with_file_name("remote-uci-service.exe");
+    if !executable_path.exists() {
+        return Err(format!("{} not found", executable_path.display()).into());
+    }
+
+    // Pass on all options, but not `service install` itself.
+    let mut launch_arguments: Vec<OsString> = env::args_os().skip(1).collect();
+    launch_arguments.truncate(launch_arguments.len().saturating_sub(2));
+
+    let opts = opts.with_config_file()?;
+    if opts.secret_keyring {
+        // The service does not run as the current user, and could not read
+        // the entry from the current user's Credential Manager.
+        return Err("--secret-keyring is not supported for the service, use --secret-file".into());
+    }
+    let secret_file = match opts.secret_file {
+        Some(path) => path,
+        None => {
+            let dir = PathBuf::from(
+                env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into()),
+            )
+            .join("remote-uci");
+            fs::create_dir_all(&dir)?;
+            let path = dir.join("secret");
+            launch_arguments.push("--secret-file".into());
+            launch_arguments.push(path.clone().into());
+            path
+        }
+    };
+    load_secret(Some(&secret_file));
+    restrict_to_administrators(&secret_file)?;
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let service = manager.create_service(
+        &ServiceInfo {
+            name: SERVICE_NAME.into(),
+            display_name: "remote-uci".into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path,
+            launch_arguments,
+            dependencies: Vec::new(),
+            account_name: None, // LocalSystem
+            account_password: None,
+        },
+        ServiceAccess::CHANGE_CONFIG,
+    )?;
+    service.set_description("External engine provider for lichess.org")?;
+
+    // Messages of EventCreate.exe consist of just the inserted string.
+    let system_root = env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_owned());
+    let (key, _) = RegKey::predef(HKEY_LOCAL_MACHINE).create_subkey(EVENT_SOURCE_KEY)?;
+    key.set_value(
+        "EventMessageFile",
+        &format!(r"{system_root}\System32\EventCreate.exe"),
+    )?;
+    key.set_value("TypesSupported", &7u32)?;
+
+    println!("Installed service {SERVICE_NAME}");
+    println!("Secret is stored in {}", secret_file.display());
+    println!("Start it with: remote-uci service start");
+    Ok(())
+}
+
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    let service = open(ServiceAccess::STOP | ServiceAccess::QUERY_STATUS | ServiceAccess::DELETE)?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+        wait_stopped(&service)?;
+    }
+    service.delete()?;
+    let _ = RegKey::predef(HKEY_LOCAL_MACHINE).delete_subkey_all(EVENT_SOURCE_KEY);
+    println!("Uninstalled service {SERVICE_NAME}");
+    Ok(())
+}
+
+fn open(access: ServiceAccess) -> Result<Service, Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    Ok(manager.open_service(SERVICE_NAME, access)?)
+}
+
+fn wait_stopped(service: &Service) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    while service.query_status()?.current_state != ServiceState::Stopped {
+        if started.elapsed() > Duration::from_secs(60) {
+            return Err("service did not stop".into());
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    Ok(())
+}
+
+/// Allows only SYSTEM and administrators to access the file. Uses SIDs,
+/// because account names are localized.
+fn restrict_to_administrators(path: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("icacls")
+        .arg(path)
+        .args([
+            "/inheritance:r",
+            "/grant:r",
+            "*S-1-5-18:F",
+            "*S-1-5-32-544:F",
+        ])
+        .status()?;
+    if !status.success() {
+        return Err(format!("could not restrict access to {}", path.display()).into());
+    }
+    Ok(())
+}
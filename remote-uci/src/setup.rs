@@ -0,0 +1,224 @@
+use std::{
+    cmp::{max, min},
+    error::Error,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::{
+    available_memory, available_threads, cpu_features,
+    engine::{Engine, EngineAddr, EngineParameters, HashPolicy, Launch},
+    gpu::GpuBackend,
+    load_secret,
+    priority::ProcessPriority,
+    sandbox::SandboxPolicy,
+    uci::{OptionPolicy, SearchLimits},
+    xboard::Protocol,
+    ExternalWorkerOpts, Opts,
+};
+
+/// Config file written by the setup wizard.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct SetupConfig {
+    engine: PathBuf,
+    max_threads: u32,
+    max_hash: u32,
+    secret_file: PathBuf,
+}
+
+/// Interactively tests an engine, chooses limits and writes a config file.
+pub async fn setup(opts: Opts) -> Result<(), Box<dyn Error>> {
+    println!("Welcome to remote-uci. This will create a config file for your engine.");
+    println!();
+
+    let features = cpu_features();
+    println!(
+        "CPU features: {}",
+        if features.is_empty() {
+            "none detected".to_owned()
+        } else {
+            features.join(", ")
+        }
+    );
+    if let Some(gpu) = GpuBackend::detect() {
+        println!("GPU: {gpu} (suitable for neural network engines like Lc0)");
+    }
+    println!("Pick the fastest engine build that supports these features.");
+    println!();
+
+    let (path, engine) = loop {
+        let default = opts
+            .engine
+            .engine
+            .as_ref()
+            .map(|path| path.display().to_string());
+        let path = PathBuf::from(prompt(
+            "Path to the UCI engine executable",
+            default.as_deref(),
+        )?);
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        println!("Testing {} ...", path.display());
+        match test_engine(&path).await {
+            Ok(engine) => {
+                println!(
+                    "Found {} (up to {} threads, {} MiB hash)",
+                    engine.name().unwrap_or("unnamed engine"),
+                    engine.max_threads(),
+                    engine.max_hash()
+                );
+                break (path, engine);
+            }
+            Err(err) => println!("Could not use this engine: {err}"),
+        }
+    };
+    println!();
+
+    let available_threads = available_threads();
+    let max_threads = prompt_number(
+        "Maximum number of threads",
+        min(
+            max(available_threads - 1, 1),
+            u32::try_from(engine.max_threads()).unwrap_or(u32::MAX),
+        ),
+    )?;
+    let max_hash = prompt_number(
+        "Maximum hash table size (MiB)",
+        min(
+            max(
+                u32::try_from(available_memory() / 2).unwrap_or(u32::MAX),
+                16,
+            ),
+            u32::try_from(engine.max_hash()).unwrap_or(u32::MAX),
+        ),
+    )?;
+    println!();
+
+    let config_path = loop {
+        let default = opts.config.as_ref().map_or_else(
+            || "remote-uci.toml".to_owned(),
+            |path| path.display().to_string(),
+        );
+        let config_path = PathBuf::from(prompt("Write config file to", Some(&default))?);
+        if !config_path.exists()
+            || prompt("File exists. Overwrite? [y/N]", None)?.eq_ignore_ascii_case("y")
+        {
+            break config_path;
+        }
+    };
+    let secret_file = config_path.with_extension("secret");
+    let secret = load_secret(Some(&secret_file));
+    let config = SetupConfig {
+        engine: fs::canonicalize(&path).unwrap_or(path),
+        max_threads,
+        max_hash,
+        secret_file: fs::canonicalize(&secret_file).unwrap_or(secret_file),
+    };
+    fs::write(&config_path, toml::to_string(&config)?)?;
+    println!("Wrote {}", config_path.display());
+
+    let spec = ExternalWorkerOpts {
+        url: "ws://localhost:9670/socket".to_owned(),
+        secret,
+        name: engine.name().unwrap_or("remote-uci").to_owned(),
+        max_threads: min(engine.max_threads(), i64::from(max_threads)),
+        max_hash: min(engine.max_hash(), i64::from(max_hash)),
+        variants: engine.variants(),
+        official_stockfish: false,
+        tablebases: None,
+        network: engine.network(),
+    };
+    println!();
+    println!("Start the server with:");
+    println!();
+    println!("    remote-uci --config {}", config_path.display());
+    println!();
+    println!("Then open this URL to register the engine with lichess:");
+    println!();
+    println!("    {}", spec.registration_url());
+    Ok(())
+}
+
+async fn test_engine(path: &Path) -> io::Result<Engine> {
+    timeout(
+        Duration::from_secs(10),
+        Engine::new(
+            EngineAddr::Process(path.to_owned()),
+            EngineParameters {
+                max_threads: u32::MAX,
+                max_hash: u32::MAX,
+                options: Vec::new(),
+                gpu: None,
+                limits: SearchLimits::default(),
+                option_policy: OptionPolicy::default(),
+                variants: Vec::new(),
+                validate_pv: false,
+                notify: false,
+                wdl: false,
+                normalize_scores: false,
+                cache: None,
+                compare: None,
+                standby: false,
+                split_multipv: None,
+                book: None,
+                online_tb: None,
+                stats: None,
+                hash_policy: HashPolicy::default(),
+                deterministic: false,
+                protocol: Protocol::default(),
+                sandbox: SandboxPolicy::default(),
+                launch: Launch::default(),
+                priority: ProcessPriority::default(),
+                cpus: None,
+                numa: None,
+                battery: None,
+                thermal: None,
+                engine_timeout: None,
+                move_overhead: None,
+                max_multipv: None,
+                default_multipv: None,
+                init_timeout: None,
+                init_retries: 0,
+            },
+        ),
+    )
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "engine did not respond to uci"))?
+}
+
+fn prompt(question: &str, default: Option<&str>) -> io::Result<String> {
+    match default {
+        Some(default) => print!("{question} [{default}]: "),
+        None => print!("{question}: "),
+    }
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "setup aborted",
+        ));
+    }
+    let line = line.trim();
+    Ok(if line.is_empty() {
+        default.unwrap_or_default().to_owned()
+    } else {
+        line.to_owned()
+    })
+}
+
+fn prompt_number(question: &str, default: u32) -> io::Result<u32> {
+    loop {
+        match prompt(question, Some(&default.to_string()))?.parse() {
+            Ok(number) if number > 0 => return Ok(number),
+            _ => println!("Please enter a positive number."),
+        }
+    }
+}
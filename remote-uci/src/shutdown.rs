@@ -0,0 +1,77 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::{
+    sync::{mpsc, watch},
+    time::timeout,
+};
+
+/// Coordinates ending websocket sessions when the server shuts down.
+/// Upgraded connections are not tracked by the HTTP server itself.
+pub struct Shutdown {
+    signal: watch::Sender<bool>,
+    requested: watch::Receiver<bool>,
+    sessions: Mutex<Option<mpsc::Sender<()>>>,
+    done: tokio::sync::Mutex<mpsc::Receiver<()>>,
+    timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(timeout: Duration) -> Shutdown {
+        let (signal, requested) = watch::channel(false);
+        let (sessions, done) = mpsc::channel(1);
+        Shutdown {
+            signal,
+            requested,
+            sessions: Mutex::new(Some(sessions)),
+            done: tokio::sync::Mutex::new(done),
+            timeout,
+        }
+    }
+
+    /// Registers a session, or returns `None` if the server is already
+    /// shutting down.
+    pub fn session(&self) -> Option<SessionGuard> {
+        let sessions = self.sessions.lock().expect("shutdown sessions");
+        sessions.as_ref().map(|sessions| SessionGuard {
+            _alive: sessions.clone(),
+            requested: self.requested.clone(),
+        })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Asks all sessions to stop their searches, deliver remaining engine
+    /// output, and close. Streams to spectators end right away, so that
+    /// the HTTP server does not wait for them.
+    pub fn request(&self) {
+        let _ = self.signal.send(true);
+    }
+
+    /// Requests shutdown, and waits until all sessions are done or the
+    /// shutdown timeout elapses.
+    pub async fn drain(&self) {
+        self.request();
+        self.sessions.lock().expect("shutdown sessions").take();
+        let mut done = self.done.lock().await;
+        if timeout(self.timeout, done.recv()).await.is_err() {
+            tracing::warn!("Shutdown timeout elapsed, closing remaining sessions");
+        }
+    }
+}
+
+pub struct SessionGuard {
+    _alive: mpsc::Sender<()>,
+    requested: watch::Receiver<bool>,
+}
+
+impl SessionGuard {
+    pub async fn requested(&mut self) {
+        while !*self.requested.borrow() {
+            if self.requested.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
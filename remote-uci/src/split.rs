@@ -0,0 +1,340 @@
+use std::{
+    cmp::{min, Reverse},
+    collections::{BTreeMap, VecDeque},
+    io,
+    num::NonZeroU32,
+};
+
+use shakmaty::uci::Uci;
+use tokio::sync::mpsc;
+
+use crate::{
+    engine::{Engine, EngineAddr, EngineParameters, Session},
+    uci::{UciIn, UciOptionName, UciOut},
+};
+
+/// Engine processes that each search a disjoint set of root moves, so that
+/// MultiPV analysis is spread across processes instead of one engine
+/// splitting its own hash table.
+pub struct SplitPool {
+    helpers: Vec<mpsc::UnboundedSender<UciIn>>,
+    output: mpsc::UnboundedReceiver<(usize, io::Result<UciOut>)>,
+    search: Option<SplitSearch>,
+}
+
+/// Lines of the current search, by process and position in the output of
+/// that process.
+struct SplitSearch {
+    multipv: u32,
+    lines: BTreeMap<(usize, u32), UciOut>,
+    /// Position of each of the best lines in the last info sent for it.
+    ranks: BTreeMap<(usize, u32), u32>,
+    bestmoves: Vec<Option<UciOut>>,
+    pending: VecDeque<UciOut>,
+}
+
+impl SplitPool {
+    /// Starts the processes in the background. They get the configured
+    /// options except MultiPV, and share the hash table limit.
+    pub fn spawn(addr: EngineAddr, params: &EngineParameters, processes: usize) -> SplitPool {
+        let params = EngineParameters {
+            options: params
+                .options
+                .iter()
+                .filter(|(name, _)| *name != "MultiPV")
+                .cloned()
+                .collect(),
+            max_hash: (params.max_hash / u32::try_from(processes).unwrap_or(u32::MAX)).max(1),
+            default_multipv: None,
+            compare: None,
+            standby: false,
+            cache: None,
+            book: None,
+            online_tb: None,
+            stats: None,
+            notify: false,
+            ..params.clone()
+        };
+        let (output_tx, output) = mpsc::unbounded_channel();
+        let helpers = (0..processes)
+            .map(|index| {
+                let (commands, commands_rx) = mpsc::unbounded_channel();
+                tokio::spawn(run(
+                    index,
+                    addr.clone(),
+                    params.clone(),
+                    commands_rx,
+                    output_tx.clone(),
+                ));
+                commands
+            })
+            .collect();
+        SplitPool {
+            helpers,
+            output,
+            search: None,
+        }
+    }
+
+    pub fn processes(&self) -> usize {
+        self.helpers.len()
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Passes on a command that was sent to the engine, if it is relevant
+    /// for the processes. Resources and the number of lines stay as
+    /// configured.
+    pub fn send(&self, command: &UciIn) {
+        let relevant = match command {
+            UciIn::Position { .. } | UciIn::Stop | UciIn::Ponderhit | UciIn::Ucinewgame => true,
+            UciIn::Setoption { name, .. } => {
+                *name != "MultiPV" && *name != "Threads" && *name != "Hash"
+            }
+            _ => false,
+        };
+        if relevant {
+            for helper in &self.helpers {
+                let _ = helper.send(command.clone());
+            }
+        }
+    }
+
+    /// Starts searching each set of root moves in another process. Each
+    /// process searches as many lines as requested, because the best lines
+    /// may all start with moves of the same set.
+    pub fn search(&mut self, go: &UciIn, multipv: u32, partitions: Vec<Vec<Uci>>) {
+        // Output of helpers that failed after the previous search.
+        while self.output.try_recv().is_ok() {}
+        let mut go = go.clone();
+        for (helper, moves) in self.helpers.iter().zip(&partitions) {
+            let lines = min(multipv, u32::try_from(moves.len()).unwrap_or(u32::MAX));
+            let _ = helper.send(UciIn::Setoption {
+                name: UciOptionName("MultiPV".to_owned()),
+                value: Some(lines.to_string()),
+            });
+            if let UciIn::Go {
+                ref mut searchmoves,
+                ..
+            } = go
+            {
+                *searchmoves = Some(moves.clone());
+            }
+            let _ = helper.send(go.clone());
+        }
+        self.search = Some(SplitSearch::new(multipv, partitions.len()));
+    }
+
+    /// Next line of the merged MultiPV output. The search ends with the best
+    /// move of the best line, once all processes have finished.
+    pub async fn recv(&mut self) -> io::Result<UciOut> {
+        loop {
+            let search = self
+                .search
+                .as_mut()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "not searching"))?;
+            if let Some(out) = search.pending.pop_front() {
+                if let UciOut::Bestmove { .. } = out {
+                    self.search = None;
+                }
+                return Ok(out);
+            }
+            match self.output.recv().await {
+                Some((index, Ok(out))) if index < search.bestmoves.len() => {
+                    search.update(index, out)
+                }
+                Some((_, Ok(_))) => (),
+                Some((index, Err(err))) => {
+                    self.search = None;
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("split search process {index} failed: {err}"),
+                    ));
+                }
+                None => {
+                    self.search = None;
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+            }
+        }
+    }
+}
+
+impl SplitSearch {
+    fn new(multipv: u32, processes: usize) -> SplitSearch {
+        SplitSearch {
+            multipv,
+            lines: BTreeMap::new(),
+            ranks: BTreeMap::new(),
+            bestmoves: vec![None; processes],
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn update(&mut self, index: usize, out: UciOut) {
+        match out {
+            UciOut::Info { multipv, .. } => {
+                let key = (index, multipv.map_or(1, NonZeroU32::get));
+                self.lines.insert(key, out);
+                let mut order: Vec<(usize, u32)> = self.lines.keys().copied().collect();
+                order.sort_by_key(|key| {
+                    Reverse(match self.lines[key] {
+                        UciOut::Info {
+                            score: Some(ref score),
+                            ..
+                        } => score.eval.sort_key(),
+                        _ => i64::MIN,
+                    })
+                });
+                order.truncate(usize::try_from(self.multipv).unwrap_or(usize::MAX));
+                // Lines that dropped out of the best can come back later.
+                self.ranks.retain(|line, _| order.contains(line));
+                // Send lines that moved to another position again, so that
+                // the positions stay unique.
+                for (line, rank) in order.into_iter().zip(1..) {
+                    if line == key || self.ranks.get(&line) != Some(&rank) {
+                        self.ranks.insert(line, rank);
+                        let mut info = self.lines[&line].clone();
+                        if let UciOut::Info {
+                            ref mut multipv, ..
+                        } = info
+                        {
+                            *multipv = NonZeroU32::new(rank);
+                        }
+                        self.pending.push_back(info);
+                    }
+                }
+            }
+            UciOut::Bestmove { .. } => {
+                self.bestmoves[index] = Some(out);
+                if self.bestmoves.iter().all(Option::is_some) {
+                    let best = self
+                        .ranks
+                        .iter()
+                        .find_map(|(&(index, _), &rank)| (rank == 1).then_some(index))
+                        .unwrap_or_default();
+                    if let Some(bestmove) = self.bestmoves[best].take() {
+                        self.pending.push_back(bestmove);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+async fn run(
+    index: usize,
+    addr: EngineAddr,
+    params: EngineParameters,
+    mut commands: mpsc::UnboundedReceiver<UciIn>,
+    output: mpsc::UnboundedSender<(usize, io::Result<UciOut>)>,
+) {
+    let session = Session(0);
+    let mut engine = match Engine::new(addr.clone(), params).await {
+        Ok(engine) => engine,
+        Err(err) => {
+            tracing::error!("Could not start engine {addr} for split search: {err}");
+            let _ = output.send((index, Err(err)));
+            return;
+        }
+    };
+    loop {
+        let res = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) if engine.is_searching() && !matches!(command, UciIn::Stop | UciIn::Ponderhit) => {
+                    // Still finishing the previous search.
+                    match engine.ensure_idle(session).await {
+                        Ok(()) => engine.send(session, command).await,
+                        Err(err) => Err(err),
+                    }
+                }
+                Some(command) => engine.send(session, command).await,
+                None => return,
+            },
+            out = engine.recv(session) => match out {
+                Ok(out @ (UciOut::Info { score: Some(_), pv: Some(_), string: None, .. } | UciOut::Bestmove { .. })) => {
+                    if output.send((index, Ok(out))).is_err() {
+                        return;
+                    }
+                    Ok(())
+                }
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = res {
+            tracing::error!("Engine {addr} for split search failed: {err}");
+            let _ = output.send((index, Err(err)));
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(s: &str) -> UciOut {
+        UciOut::from_line(s).expect("info").expect("not empty")
+    }
+
+    #[test]
+    fn test_merge_best_lines() {
+        let mut search = SplitSearch::new(2, 2);
+        search.update(0, info("info depth 10 multipv 1 score cp 50 pv e2e4"));
+        search.update(0, info("info depth 10 multipv 2 score cp 10 pv g1f3"));
+        search.update(1, info("info depth 10 multipv 1 score cp 30 pv d2d4"));
+        search.update(1, info("info depth 10 multipv 2 score cp 5 pv c2c4"));
+
+        // The latest info for each position.
+        let mut merged = BTreeMap::new();
+        for out in search.pending.drain(..) {
+            if let UciOut::Info {
+                multipv: Some(multipv),
+                ..
+            } = out
+            {
+                merged.insert(multipv.get(), out.to_string());
+            }
+        }
+        assert_eq!(
+            merged.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    1,
+                    info("info depth 10 multipv 1 score cp 50 pv e2e4").to_string()
+                ),
+                (
+                    2,
+                    info("info depth 10 multipv 2 score cp 30 pv d2d4").to_string()
+                ),
+            ]
+        );
+
+        // Both of the best lines started with moves of the same set.
+        search.update(0, info("info depth 11 multipv 2 score cp 40 pv g1f3"));
+        assert_eq!(
+            search
+                .pending
+                .drain(..)
+                .map(|out| out.to_string())
+                .collect::<Vec<_>>(),
+            vec![info("info depth 11 multipv 2 score cp 40 pv g1f3").to_string()]
+        );
+
+        search.update(1, info("bestmove d2d4"));
+        assert!(search.pending.is_empty());
+        search.update(0, info("bestmove e2e4"));
+        assert_eq!(
+            search
+                .pending
+                .drain(..)
+                .map(|out| out.to_string())
+                .collect::<Vec<_>>(),
+            vec!["bestmove e2e4"]
+        );
+    }
+}
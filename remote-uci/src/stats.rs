@@ -0,0 +1,217 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::{engine::Session, Opts};
+
+/// Number of ended sessions to show in /stats.
+const RECENT_SESSIONS: usize = 20;
+
+/// Work done by engines on behalf of clients.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Work {
+    /// Searches that ended with a best move, not counting cached analysis.
+    pub positions: u64,
+    pub nodes: u64,
+    pub max_depth: u32,
+    /// Wall-clock time spent searching.
+    pub engine_time_ms: u64,
+}
+
+impl Work {
+    fn add(&mut self, other: &Work) {
+        self.positions += other.positions;
+        self.nodes += other.nodes;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.engine_time_ms += other.engine_time_ms;
+    }
+}
+
+/// Work over the lifetime of the server, or of all servers using the same
+/// --stats-file.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lifetime {
+    pub sessions: u64,
+    #[serde(flatten)]
+    pub work: Work,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionStats {
+    pub session: u64,
+    #[serde(flatten)]
+    pub work: Work,
+}
+
+/// Response of GET /stats.
+#[derive(Serialize)]
+pub struct Report {
+    pub lifetime: Lifetime,
+    /// Sessions that are using engines right now.
+    pub active: Vec<SessionStats>,
+    /// Ended sessions, most recent first.
+    pub recent: Vec<SessionStats>,
+}
+
+/// The current search of an engine, counted once it ends.
+pub struct Progress {
+    started: Instant,
+    nodes: u64,
+    depth: u32,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            started: Instant::now(),
+            nodes: 0,
+            depth: 0,
+        }
+    }
+
+    /// Takes note of an info line of the search.
+    pub fn update(&mut self, nodes: Option<u64>, depth: Option<u32>) {
+        self.nodes = nodes.unwrap_or(self.nodes);
+        self.depth = self.depth.max(depth.unwrap_or(0));
+    }
+}
+
+#[derive(Default)]
+struct State {
+    lifetime: Lifetime,
+    active: HashMap<u64, Work>,
+    recent: VecDeque<SessionStats>,
+}
+
+/// Statistics of sessions, shared by all engines.
+pub struct Stats {
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+impl Stats {
+    /// Starts with the lifetime statistics from the file, if given. They are
+    /// written back whenever a session ends.
+    pub fn new(path: Option<PathBuf>) -> io::Result<Stats> {
+        let lifetime = match path {
+            Some(ref path) => load(path)?,
+            None => Lifetime::default(),
+        };
+        Ok(Stats {
+            path,
+            state: Mutex::new(State {
+                lifetime,
+                ..State::default()
+            }),
+        })
+    }
+
+    pub fn start_session(&self, session: Session) {
+        let mut state = self.state.lock().expect("stats");
+        state.active.entry(session.0).or_default();
+    }
+
+    /// Counts a search that ended.
+    pub fn searched(&self, session: Session, progress: Progress) {
+        let elapsed = progress.started.elapsed();
+        let mut state = self.state.lock().expect("stats");
+        state.active.entry(session.0).or_default().add(&Work {
+            positions: 1,
+            nodes: progress.nodes,
+            max_depth: progress.depth,
+            engine_time_ms: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        });
+    }
+
+    /// Adds the work of a session to the lifetime statistics.
+    pub fn end_session(&self, session: Session) {
+        let mut state = self.state.lock().expect("stats");
+        let work = state.active.remove(&session.0).unwrap_or_default();
+        state.lifetime.sessions += 1;
+        state.lifetime.work.add(&work);
+        state.recent.push_front(SessionStats {
+            session: session.0,
+            work,
+        });
+        state.recent.truncate(RECENT_SESSIONS);
+        if let Some(ref path) = self.path {
+            if let Err(err) = store(path, &state.lifetime) {
+                tracing::error!("Failed to write statistics to {path:?}: {err}");
+            }
+        }
+    }
+
+    fn report(&self) -> Report {
+        let state = self.state.lock().expect("stats");
+        let mut active: Vec<SessionStats> = state
+            .active
+            .iter()
+            .map(|(session, work)| SessionStats {
+                session: *session,
+                work: work.clone(),
+            })
+            .collect();
+        active.sort_by_key(|stats| stats.session);
+        // Lifetime statistics include the sessions in progress.
+        let mut lifetime = state.lifetime.clone();
+        for stats in &active {
+            lifetime.work.add(&stats.work);
+        }
+        Report {
+            lifetime,
+            active,
+            recent: state.recent.iter().cloned().collect(),
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<Lifetime> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            toml::from_str(&content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Lifetime::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn store(path: &Path, lifetime: &Lifetime) -> io::Result<()> {
+    let content =
+        toml::to_string(lifetime).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+pub async fn handler(stats: Arc<Stats>) -> Json<Report> {
+    Json(stats.report())
+}
+
+/// Prints the lifetime statistics from the --stats-file.
+pub fn print(opts: Opts) -> Result<(), Box<dyn Error>> {
+    let opts = opts.with_config_file()?;
+    let path = opts
+        .stats_file
+        .ok_or("no statistics to show (use --stats-file)")?;
+    let lifetime = load(&path)?;
+    let engine_time = Duration::from_secs(lifetime.work.engine_time_ms / 1000);
+    println!("Sessions:        {}", lifetime.sessions);
+    println!("Positions:       {}", lifetime.work.positions);
+    println!("Nodes:           {}", lifetime.work.nodes);
+    println!("Deepest search:  {}", lifetime.work.max_depth);
+    println!(
+        "Engine time:     {}",
+        humantime::format_duration(engine_time)
+    );
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{cpu_features, official};
+
+/// Latest release of official Stockfish, from the GitHub API.
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/official-stockfish/Stockfish/releases/latest";
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+    /// Like sha256:0123...
+    digest: Option<String>,
+}
+
+/// Where `download-stockfish` puts the engine.
+fn install_path() -> Option<PathBuf> {
+    let exe = if cfg!(windows) {
+        "stockfish.exe"
+    } else {
+        "stockfish"
+    };
+    Some(data_dir()?.join("remote-uci").join(exe))
+}
+
+/// Directory for files of the user that are not configuration, like
+/// downloaded engines.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn data_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => Some(home::home_dir()?.join(".local").join("share")),
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn data_dir() -> Option<PathBuf> {
+    Some(
+        home::home_dir()?
+            .join("Library")
+            .join("Application Support"),
+    )
+}
+
+#[cfg(windows)]
+pub fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+}
+
+/// Stockfish installed with `download-stockfish`, if any.
+pub fn installed() -> Option<PathBuf> {
+    install_path().filter(|path| path.is_file())
+}
+
+/// Names of the release archives that can run here, without extension, from
+/// the most to the least specialized.
+fn asset_stems() -> Vec<String> {
+    let os = if cfg!(windows) {
+        "windows"
+    } else if cfg!(target_os = "macos") {
+        "macos"
+    } else if cfg!(target_os = "linux") {
+        "ubuntu"
+    } else {
+        return Vec::new();
+    };
+    if cfg!(target_arch = "aarch64") {
+        return if os == "macos" {
+            vec!["stockfish-macos-m1-apple-silicon".to_owned()]
+        } else {
+            Vec::new()
+        };
+    }
+    if !cfg!(target_arch = "x86_64") {
+        return Vec::new();
+    }
+    let features = cpu_features();
+    let mut builds: Vec<&str> = ["vnni512", "avx512", "bmi2", "avx2"]
+        .into_iter()
+        .filter(|build| features.contains(build))
+        .collect();
+    if features.contains(&"sse41") && features.contains(&"popcnt") {
+        builds.push("sse41-popcnt");
+    }
+    builds
+        .into_iter()
+        .map(|build| format!("stockfish-{os}-x86-64-{build}"))
+        .chain([format!("stockfish-{os}-x86-64")])
+        .collect()
+}
+
+/// Downloads the latest official Stockfish release for this platform and
+/// CPU, verifies its checksum, and installs it as the default engine.
+pub async fn download() -> Result<(), Box<dyn Error>> {
+    let target = install_path().ok_or("could not determine data directory")?;
+    let stems = asset_stems();
+    if stems.is_empty() {
+        return Err("no official Stockfish build for this platform".into());
+    }
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("remote-uci/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let release: Release = http
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let (stem, asset) = stems
+        .iter()
+        .find_map(|stem| {
+            release
+                .assets
+                .iter()
+                .find(|asset| {
+                    asset.name.strip_prefix(stem.as_str()).map_or(false, |ext| {
+                        matches!(ext, ".tar" | ".zip" | ".tar.gz" | ".tgz")
+                    })
+                })
+                .map(|asset| (stem, asset))
+        })
+        .ok_or_else(|| format!("no suitable build in Stockfish {}", release.tag_name))?;
+    let expected = asset
+        .digest
+        .as_deref()
+        .and_then(|digest| digest.strip_prefix("sha256:"))
+        .ok_or_else(|| format!("no published checksum for {}", asset.name))?;
+
+    println!("Downloading {} ({}) ...", asset.name, release.tag_name);
+    let archive = http
+        .get(&asset.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let actual = hex(digest(&SHA256, &archive).as_ref());
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "checksum mismatch for {}: expected {expected}, got {actual}",
+            asset.name
+        )
+        .into());
+    }
+
+    let dir = target.parent().expect("install path has parent");
+    let work_dir = dir.join("download");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir)?;
+    let extracted = match extract(&work_dir, &asset.name, &archive, stem).await {
+        Ok(extracted) => extracted,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&work_dir);
+            return Err(err);
+        }
+    };
+    fs::rename(extracted, &target)?;
+    let _ = fs::remove_dir_all(&work_dir);
+
+    println!(
+        "Installed Stockfish {} to {}",
+        release.tag_name,
+        target.display()
+    );
+    println!("It is used unless another engine is configured.");
+    let version = release
+        .tag_name
+        .strip_prefix("sf_")
+        .unwrap_or(&release.tag_name);
+    if !official::is_known(version) {
+        println!("--promise-official-stockfish does not know this release yet.");
+    }
+    Ok(())
+}
+
+/// Unpacks the archive with the tar of the system, which also handles zip
+/// files on Windows and macOS, and finds the engine executable in it.
+async fn extract(
+    work_dir: &Path,
+    name: &str,
+    archive: &[u8],
+    stem: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let archive_path = work_dir.join(name);
+    fs::write(&archive_path, archive)?;
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(&archive_path)
+        .arg("-C")
+        .arg(work_dir)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(format!(
+            "could not extract {name}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let exe = if cfg!(windows) {
+        format!("{stem}.exe")
+    } else {
+        stem.to_owned()
+    };
+    find_file(work_dir, &exe).ok_or_else(|| format!("no {exe} in {name}").into())
+}
+
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name) {
+                return Some(found);
+            }
+        } else if entry.file_name() == name {
+            return Some(path);
+        }
+    }
+    None
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,195 @@
+use std::{
+    env,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt, io::AsRawFd, net::UnixDatagram},
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::time::sleep;
+
+use crate::{health::Health, load_secret, Opts};
+
+/// Name of the user unit.
+const UNIT_NAME: &str = "remote-uci.service";
+
+/// Watchdog timeout of the unit. Long enough for busy engines to answer
+/// isready.
+const WATCHDOG_SEC: u64 = 60;
+
+/// Writes a systemd user unit that runs remote-uci with the options given
+/// before `systemd-install`.
+pub fn install(opts: Opts) -> Result<(), Box<dyn Error>> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => home::home_dir()
+            .ok_or("could not determine home directory")?
+            .join(".config"),
+    };
+
+    // Pass on all options, but not `systemd-install` itself.
+    let mut args: Vec<OsString> = env::args_os().skip(1).collect();
+    args.truncate(args.len().saturating_sub(1));
+
+    let opts = opts.with_config_file()?;
+    let secret_file = match opts.secret_file {
+        Some(path) => Some(path),
+        None if opts.secret_keyring.unwrap_or(false) => None,
+        None => {
+            let dir = config_dir.join("remote-uci");
+            fs::create_dir_all(&dir)?;
+            let path = dir.join("secret");
+            args.push("--secret-file".into());
+            args.push(path.clone().into());
+            Some(path)
+        }
+    };
+    if let Some(ref secret_file) = secret_file {
+        load_secret(Some(secret_file));
+        fs::set_permissions(secret_file, fs::Permissions::from_mode(0o600))?;
+    }
+
+    let mut exec_start = quote(env::current_exe()?.as_os_str());
+    for arg in &args {
+        exec_start.push(' ');
+        exec_start.push_str(&quote(arg));
+    }
+    let unit = format!(
+        "[Unit]
+Description=External engine provider for lichess.org
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+WorkingDirectory={working_directory}
+Restart=on-failure
+RestartSec=5
+WatchdogSec={WATCHDOG_SEC}
+
+[Install]
+WantedBy=default.target
+",
+        // Unlike ExecStart, WorkingDirectory is not unquoted.
+        working_directory = env::current_dir()?.display().to_string().replace('%', "%%"),
+    );
+
+    let unit_dir = config_dir.join("systemd").join("user");
+    fs::create_dir_all(&unit_dir)?;
+    let unit_file = unit_dir.join(UNIT_NAME);
+    fs::write(&unit_file, unit)?;
+
+    println!("Wrote {}", unit_file.display());
+    match secret_file {
+        Some(secret_file) => println!("Secret is stored in {}", secret_file.display()),
+        None => println!("Secret is stored in the keyring"),
+    }
+    println!(
+        "Start it with: systemctl --user daemon-reload && systemctl --user enable --now remote-uci"
+    );
+    println!("To keep it running after logging out: loginctl enable-linger");
+    Ok(())
+}
+
+/// Quotes a word of a command line in a unit file, escaping specifiers and
+/// variable expansion.
+fn quote(arg: &OsStr) -> String {
+    let mut quoted = String::from('"');
+    for ch in arg.to_string_lossy().chars() {
+        match ch {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(ch);
+            }
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Tells the service manager about state changes, if started with
+/// `Type=notify`.
+pub fn notify(state: &str) {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    if let Err(err) = send(Path::new(&path), state) {
+        tracing::error!("Failed to notify systemd: {err}");
+    }
+}
+
+fn send(path: &Path, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.first() != Some(&b'@') {
+        socket.send_to(state.as_bytes(), path)?;
+        return Ok(());
+    }
+
+    // Abstract socket address, starting with a null byte instead of @.
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as _;
+    if bytes.len() > addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "NOTIFY_SOCKET too long",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes).skip(1) {
+        *dst = *src as _;
+    }
+    let len = std::mem::size_of_val(&addr.sun_family) + bytes.len();
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            state.as_ptr().cast(),
+            state.len(),
+            0,
+            (&addr as *const libc::sockaddr_un).cast(),
+            len as _,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Pings the systemd watchdog while all idle engines answer isready, so that
+/// systemd restarts the service if an engine wedges. Does nothing unless
+/// the unit has `WatchdogSec` set.
+pub async fn watchdog(health: Arc<Health>) {
+    let usec: u64 = match env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse().ok()) {
+        Some(usec) if usec > 0 => usec,
+        _ => return,
+    };
+    if let Some(pid) = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+    {
+        if pid != process::id() {
+            return;
+        }
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    tracing::info!("Pinging systemd watchdog every {interval:?}");
+    loop {
+        sleep(interval).await;
+        match health.ping(interval / 2).await {
+            Ok(()) => notify("WATCHDOG=1"),
+            Err(err) => tracing::error!("Not pinging systemd watchdog: {err}"),
+        }
+    }
+}
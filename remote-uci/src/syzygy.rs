@@ -0,0 +1,226 @@
+use std::{
+    cmp::max,
+    env,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use reqwest::{header::RANGE, StatusCode};
+use ring::digest::{Context, SHA256};
+
+use crate::{
+    stockfish::{data_dir, hex},
+    Opts,
+};
+
+/// Mirror of the Syzygy tablebases.
+const MIRROR_URL: &str = "https://tablebase.sesse.net/syzygy/";
+
+/// Published SHA-256 checksums of all Syzygy tablebase files.
+const CHECKSUMS_URL: &str =
+    "https://raw.githubusercontent.com/niklasf/syzygy-tables.info/main/checksums/SHA256SUM";
+
+/// Syzygy tablebase files found in the configured directories.
+#[derive(Default, Debug)]
+pub struct Tablebases {
+    pub wdl: usize,
+    pub dtz: usize,
+    pub max_pieces: usize,
+}
+
+impl Tablebases {
+    /// Counts tablebase files. Fails if a directory does not exist.
+    pub fn scan(paths: &[PathBuf]) -> io::Result<Tablebases> {
+        let mut tablebases = Tablebases::default();
+        for path in paths {
+            for entry in fs::read_dir(path)
+                .map_err(|err| io::Error::new(err.kind(), format!("syzygy path {path:?}: {err}")))?
+            {
+                tablebases.add(&entry?.path());
+            }
+        }
+        Ok(tablebases)
+    }
+
+    fn add(&mut self, path: &Path) {
+        let (stem, extension) = match (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|s| s.to_str()),
+        ) {
+            (Some(stem), Some(extension)) => (stem, extension),
+            _ => return,
+        };
+        match extension {
+            "rtbw" => {
+                self.wdl += 1;
+                self.max_pieces = max(self.max_pieces, pieces(stem));
+            }
+            "rtbz" => self.dtz += 1,
+            _ => (),
+        }
+    }
+}
+
+/// Number of pieces of a table, like 3 for KRvK.
+fn pieces(stem: &str) -> usize {
+    stem.chars().filter(|c| *c != 'v').count()
+}
+
+/// Value of the SyzygyPath option, using the separator expected by engines
+/// on this platform.
+pub fn option_value(paths: &[PathBuf]) -> io::Result<String> {
+    env::join_paths(paths)
+        .ok()
+        .and_then(|joined| joined.into_string().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "syzygy paths must be valid unicode and must not contain the path separator",
+            )
+        })
+}
+
+/// Where `download-tablebases` puts the tables, unless --syzygy-path is
+/// given.
+fn install_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("remote-uci").join("syzygy"))
+}
+
+/// Tablebases installed with `download-tablebases`, if any.
+pub fn installed() -> Option<PathBuf> {
+    install_path().filter(|path| path.is_dir())
+}
+
+/// Downloads the WDL and DTZ tables for up to the given number of pieces
+/// into the first --syzygy-path, or else into the directory that is used
+/// when no --syzygy-path is given. Interrupted downloads are resumed, and
+/// each file is verified against its published checksum.
+pub async fn download(opts: Opts, max_pieces: usize) -> Result<(), Box<dyn Error>> {
+    if !(3..=6).contains(&max_pieces) {
+        return Err("--pieces must be between 3 and 6".into());
+    }
+    let opts = opts.with_config_file()?;
+    let dir = match opts.syzygy_path.first() {
+        Some(dir) => dir.clone(),
+        None => install_path().ok_or("could not determine data directory")?,
+    };
+    fs::create_dir_all(&dir)?;
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("remote-uci/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let checksums = http
+        .get(CHECKSUMS_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let tables: Vec<(&str, &str)> = checksums
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            let name = name.trim_start().trim_start_matches('*');
+            let stem = name
+                .strip_suffix(".rtbw")
+                .or_else(|| name.strip_suffix(".rtbz"))?;
+            (is_standard(stem) && pieces(stem) <= max_pieces).then_some((name, hash))
+        })
+        .collect();
+    if tables.is_empty() {
+        return Err("no tables in published checksums".into());
+    }
+
+    for (i, (name, hash)) in tables.iter().enumerate() {
+        let path = dir.join(name);
+        if path.is_file() {
+            continue;
+        }
+        println!("Downloading {name} ({}/{}) ...", i + 1, tables.len());
+        fetch(&http, name, &path, hash).await?;
+    }
+
+    println!(
+        "Installed {} tablebase files for up to {max_pieces} pieces to {}",
+        tables.len(),
+        dir.display()
+    );
+    if opts.syzygy_path.is_empty() {
+        println!("They are used unless another --syzygy-path is configured.");
+    }
+    Ok(())
+}
+
+/// Whether the table is for standard chess, like KRPvKR.
+fn is_standard(stem: &str) -> bool {
+    stem.split_once('v').map_or(false, |(white, black)| {
+        [white, black].into_iter().all(|side| {
+            side.strip_prefix('K')
+                .map_or(false, |rest| rest.bytes().all(|b| b"QRBNP".contains(&b)))
+        })
+    })
+}
+
+/// Downloads a table, continuing from a partial download of a previous run
+/// if the mirror supports it.
+async fn fetch(
+    http: &reqwest::Client,
+    name: &str,
+    path: &Path,
+    expected: &str,
+) -> Result<(), Box<dyn Error>> {
+    let directory = if pieces(&name[..name.len() - 5]) <= 5 {
+        "3-4-5"
+    } else if name.ends_with(".rtbw") {
+        "6-WDL"
+    } else {
+        "6-DTZ"
+    };
+    let partial = path.with_file_name(format!("{name}.part"));
+    let offset = fs::metadata(&partial).map_or(0, |meta| meta.len());
+    let mut request = http.get(format!("{MIRROR_URL}{directory}/{name}"));
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut response = request.send().await?;
+    // The range is not satisfiable if a previous run got the whole file,
+    // but was interrupted before verifying it.
+    if response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+        response = response.error_for_status()?;
+        let resume = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(&partial)?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()?;
+    }
+
+    let actual = sha256(&partial)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        fs::remove_file(&partial)?;
+        return Err(
+            format!("checksum mismatch for {name}: expected {expected}, got {actual}").into(),
+        );
+    }
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hex(context.finish().as_ref())),
+            n => context.update(&buf[..n]),
+        }
+    }
+}
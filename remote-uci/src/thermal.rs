@@ -0,0 +1,89 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use sysinfo::{ComponentExt, RefreshKind, System, SystemExt};
+use tokio::time::sleep;
+
+/// How far the temperature has to drop below the limit before threads are
+/// restored, so that engines do not switch back and forth.
+const HYSTERESIS: f32 = 5.0;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reduces the threads of engines while the processor is too hot.
+pub struct ThermalGuard {
+    /// Temperature (°C) at which threads are reduced.
+    limit: f32,
+    threads: u32,
+    hot: AtomicBool,
+}
+
+impl ThermalGuard {
+    pub fn new(limit: f32, threads: u32) -> ThermalGuard {
+        ThermalGuard {
+            limit,
+            threads,
+            hot: AtomicBool::new(false),
+        }
+    }
+
+    /// Number of threads to use while the processor is too hot.
+    pub fn threads(&self) -> u32 {
+        self.threads
+    }
+
+    pub fn is_hot(&self) -> bool {
+        self.hot.load(Ordering::Relaxed)
+    }
+
+    /// Periodically checks the temperature of the processor.
+    pub async fn monitor(self: Arc<Self>) {
+        let mut sys = System::new_with_specifics(RefreshKind::new().with_components_list());
+        if package_temperature(&sys).is_none() {
+            tracing::warn!("No temperature sensors found, ignoring --thermal-limit");
+            return;
+        }
+        loop {
+            sleep(POLL_INTERVAL).await;
+            sys.refresh_components();
+            let temperature = match package_temperature(&sys) {
+                Some(temperature) => temperature,
+                None => continue,
+            };
+            if !self.is_hot() && temperature >= self.limit {
+                tracing::warn!("Processor at {temperature:.0} °C, reducing engine threads");
+                self.hot.store(true, Ordering::Relaxed);
+            } else if self.is_hot() && temperature < self.limit - HYSTERESIS {
+                tracing::info!("Processor at {temperature:.0} °C, restoring engine threads");
+                self.hot.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Temperature of the processor package, or of the hottest sensor if the
+/// package sensor is not known.
+fn package_temperature(sys: &System) -> Option<f32> {
+    let components = sys.components();
+    hottest(
+        components
+            .iter()
+            .filter(|component| {
+                let label = component.label();
+                label.contains("Package") || label.contains("Tctl") || label.contains("Tdie")
+            })
+            .map(ComponentExt::temperature),
+    )
+    .or_else(|| hottest(components.iter().map(ComponentExt::temperature)))
+}
+
+fn hottest(temperatures: impl Iterator<Item = f32>) -> Option<f32> {
+    temperatures
+        .filter(|temperature| temperature.is_finite())
+        .reduce(f32::max)
+}
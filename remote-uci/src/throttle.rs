@@ -0,0 +1,183 @@
+use std::{
+    cmp::min,
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Failed attempts that are not slowed down, to allow for typos.
+const FREE_ATTEMPTS: u32 = 3;
+/// Longest wait between attempts, without a ban.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Failures are forgotten after this long without another failure.
+const FORGET_AFTER: Duration = Duration::from_secs(3600);
+
+/// Slows down guessing of secrets, by making clients wait exponentially
+/// longer after each failed attempt, and optionally banning them for a
+/// while. Clients are identified by IP address. Clients with unknown
+/// addresses share a single entry.
+#[derive(Debug, Default)]
+pub struct SecretThrottle {
+    ban_after: Option<u32>,
+    ban_duration: Duration,
+    clients: Mutex<HashMap<Option<IpAddr>, Failures>>,
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Instant,
+}
+
+impl SecretThrottle {
+    pub fn new(ban_after: Option<u32>, ban_duration: Duration) -> SecretThrottle {
+        SecretThrottle {
+            ban_after,
+            ban_duration,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks if the client may try a secret now. Otherwise returns how
+    /// long it has to wait.
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let clients = self.clients.lock().expect("throttle");
+        match clients.get(&ip) {
+            Some(failures) => {
+                if failures.blocked_until > now {
+                    Err(failures.blocked_until - now)
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records a wrong secret. Once banned, each further failure bans the
+    /// client again, until the failures are forgotten.
+    pub fn failed(&self, ip: Option<IpAddr>) {
+        self.failed_at(ip, Instant::now());
+    }
+
+    fn failed_at(&self, ip: Option<IpAddr>, now: Instant) {
+        let mut clients = self.clients.lock().expect("throttle");
+        clients.retain(|_, failures| {
+            failures.blocked_until > now || now.duration_since(failures.last) < FORGET_AFTER
+        });
+        let failures = clients.entry(ip).or_insert(Failures {
+            count: 0,
+            last: now,
+            blocked_until: now,
+        });
+        failures.count += 1;
+        failures.last = now;
+        let client = ip.map_or_else(|| "unknown address".to_owned(), |ip| ip.to_string());
+        if self.ban_after.map_or(false, |n| failures.count >= n) {
+            tracing::warn!(
+                "Banning {client} for {:?} after {} wrong secrets",
+                self.ban_duration,
+                failures.count
+            );
+            failures.blocked_until = now + self.ban_duration;
+        } else if failures.count > FREE_ATTEMPTS {
+            let backoff = min(
+                Duration::from_secs(1 << min(failures.count - FREE_ATTEMPTS - 1, 16)),
+                MAX_BACKOFF,
+            );
+            tracing::warn!(
+                "{} wrong secrets from {client}, blocking it for {backoff:?}",
+                failures.count
+            );
+            failures.blocked_until = now + backoff;
+        }
+    }
+
+    /// Forgets previous failures of a client that presented the right
+    /// secret.
+    pub fn succeeded(&self, ip: Option<IpAddr>) {
+        self.clients.lock().expect("throttle").remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1)));
+
+    fn fail(throttle: &SecretThrottle, now: Instant, times: u32) {
+        for _ in 0..times {
+            throttle.failed_at(CLIENT, now);
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let throttle = SecretThrottle::new(None, Duration::ZERO);
+        let now = Instant::now();
+        fail(&throttle, now, FREE_ATTEMPTS);
+        assert_eq!(throttle.check_at(CLIENT, now), Ok(()));
+        for backoff in [1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300] {
+            fail(&throttle, now, 1);
+            assert_eq!(
+                throttle.check_at(CLIENT, now),
+                Err(Duration::from_secs(backoff))
+            );
+        }
+        assert_eq!(
+            throttle.check_at(CLIENT, now + MAX_BACKOFF),
+            Ok(()),
+            "waited"
+        );
+        assert_eq!(throttle.check_at(None, now), Ok(()), "other client");
+    }
+
+    #[test]
+    fn test_ban() {
+        let ban = Duration::from_secs(600);
+        let throttle = SecretThrottle::new(Some(5), ban);
+        let now = Instant::now();
+        fail(&throttle, now, 5);
+        assert_eq!(throttle.check_at(CLIENT, now), Err(ban));
+
+        // The next failure after the ban bans again, instead of starting
+        // over with free attempts.
+        let later = now + ban;
+        assert_eq!(throttle.check_at(CLIENT, later), Ok(()));
+        fail(&throttle, later, 1);
+        assert_eq!(throttle.check_at(CLIENT, later), Err(ban));
+    }
+
+    #[test]
+    fn test_forget() {
+        let throttle = SecretThrottle::new(Some(5), Duration::from_secs(60));
+        let now = Instant::now();
+        fail(&throttle, now, 4);
+        assert!(throttle.check_at(CLIENT, now).is_err());
+
+        // Another client fails much later, which cleans up old entries.
+        let later = now + FORGET_AFTER;
+        throttle.failed_at(None, later);
+        fail(&throttle, later, 1);
+        assert_eq!(throttle.check_at(CLIENT, later), Ok(()), "free attempt");
+    }
+
+    #[test]
+    fn test_succeeded() {
+        let throttle = SecretThrottle::new(Some(5), Duration::from_secs(60));
+        let now = Instant::now();
+        fail(&throttle, now, 5);
+        assert!(throttle.check_at(CLIENT, now).is_err());
+        throttle.succeeded(CLIENT);
+        assert_eq!(throttle.check_at(CLIENT, now), Ok(()));
+        fail(&throttle, now, FREE_ATTEMPTS);
+        assert_eq!(throttle.check_at(CLIENT, now), Ok(()), "count reset");
+    }
+}
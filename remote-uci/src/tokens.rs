@@ -0,0 +1,373 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::Engine,
+    uci::{UciIn, UciOption, UciOptionName, UciOut},
+    variant,
+    ws::Secret,
+};
+
+/// Limits for a client that connects with a named secret. Unset limits
+/// are those of the engine endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Permissions {
+    pub max_threads: Option<u32>,
+    pub max_hash: Option<u32>,
+    pub max_multipv: Option<u32>,
+    /// Variants the client may analyse. Empty to allow all variants.
+    pub variants: Vec<String>,
+}
+
+impl Permissions {
+    fn allows_variant(&self, variant: &str) -> bool {
+        self.variants.is_empty()
+            || self
+                .variants
+                .iter()
+                .any(|allowed| variant::same(allowed, variant))
+    }
+
+    fn spin_limit(&self, name: &UciOptionName) -> Option<u32> {
+        if *name == "Threads" {
+            self.max_threads
+        } else if *name == "Hash" {
+            self.max_hash
+        } else if *name == "MultiPV" {
+            self.max_multipv
+        } else {
+            None
+        }
+    }
+
+    /// Limits a command from the client. Returns a reason if the command
+    /// is not allowed at all.
+    pub fn restrict_in(&self, command: &mut UciIn) -> Result<(), String> {
+        if let UciIn::Setoption { name, value } = command {
+            if let (Some(limit), Some(requested)) = (
+                self.spin_limit(name),
+                value.as_deref().and_then(|v| v.parse::<i64>().ok()),
+            ) {
+                if requested > i64::from(limit) {
+                    tracing::info!("limited option {name} to {limit}");
+                    *value = Some(limit.to_string());
+                }
+            } else if *name == "UCI_Variant" {
+                let variant = value.as_deref().unwrap_or_default();
+                if !self.allows_variant(variant) {
+                    return Err(format!("variant {variant} is not allowed"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Announces only what the client may use.
+    pub fn restrict_out(&self, command: &mut UciOut) {
+        if let UciOut::Option { name, option } = command {
+            if let Some(limit) = self.spin_limit(name) {
+                option.limit_max(limit.into());
+            } else if *name == "UCI_Variant" {
+                if let UciOption::Combo { default, var } = option {
+                    var.retain(|variant| self.allows_variant(variant));
+                    if !var.contains(default) {
+                        *default = var.first().cloned().unwrap_or_default();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Options to set at the start of a session, if the previous session
+    /// left them above the limits.
+    pub fn session_commands(&self, engine: &Engine) -> Vec<UciIn> {
+        ["Threads", "Hash", "MultiPV"]
+            .iter()
+            .map(|name| UciOptionName((*name).to_owned()))
+            .filter_map(|name| {
+                let limit = self.spin_limit(&name)?;
+                (engine.spin_value(&name)? > i64::from(limit)).then(|| UciIn::Setoption {
+                    name,
+                    value: Some(limit.to_string()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Who connected to an engine endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Grant {
+    /// Name of the secret, or `None` for the secret of the endpoint.
+    pub name: Option<String>,
+    pub permissions: Permissions,
+}
+
+/// Secret for a single client, like a device or family member, which can
+/// be revoked without affecting other clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedSecret {
+    pub name: String,
+    pub secret: Secret,
+    pub permissions: Permissions,
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl FromStr for NamedSecret {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<NamedSecret, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or("missing name")?;
+        if !is_valid_name(name) {
+            return Err(format!("invalid name {name}"));
+        }
+        let secret = words.next().ok_or("missing secret")?;
+        if secret.len() < 8 {
+            return Err(format!("secret of {name} is too short"));
+        }
+        let mut permissions = Permissions::default();
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {word}"))?;
+            match key {
+                "max-threads" => {
+                    permissions.max_threads =
+                        Some(value.parse().map_err(|err| format!("{key}: {err}"))?)
+                }
+                "max-hash" => {
+                    permissions.max_hash =
+                        Some(value.parse().map_err(|err| format!("{key}: {err}"))?)
+                }
+                "max-multipv" => {
+                    permissions.max_multipv =
+                        Some(value.parse().map_err(|err| format!("{key}: {err}"))?)
+                }
+                "variants" => {
+                    permissions.variants = value.split(',').map(ToOwned::to_owned).collect()
+                }
+                _ => return Err(format!("unknown permission {key}")),
+            }
+        }
+        Ok(NamedSecret {
+            name: name.to_owned(),
+            secret: Secret(secret.to_owned()),
+            permissions,
+        })
+    }
+}
+
+impl fmt::Display for NamedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.secret.0)?;
+        if let Some(max_threads) = self.permissions.max_threads {
+            write!(f, " max-threads={max_threads}")?;
+        }
+        if let Some(max_hash) = self.permissions.max_hash {
+            write!(f, " max-hash={max_hash}")?;
+        }
+        if let Some(max_multipv) = self.permissions.max_multipv {
+            write!(f, " max-multipv={max_multipv}")?;
+        }
+        if !self.permissions.variants.is_empty() {
+            write!(f, " variants={}", self.permissions.variants.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a secret file. The first line is the secret of the endpoint, used
+/// for registration URLs. Further lines are named secrets, like
+/// `phone 0123456789abcdef max-threads=4 max-hash=256 variants=chess,atomic`.
+pub fn parse_secret_file(content: &str) -> Result<(Secret, Vec<NamedSecret>), String> {
+    let mut lines = content.lines();
+    let active = Secret(lines.next().unwrap_or_default().to_owned());
+    let mut named: Vec<NamedSecret> = Vec::new();
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let secret: NamedSecret = line
+            .parse()
+            .map_err(|err| format!("line {}: {err}", i + 2))?;
+        if named.iter().any(|other| other.name == secret.name) {
+            return Err(format!("line {}: duplicate name {}", i + 2, secret.name));
+        }
+        named.push(secret);
+    }
+    Ok((active, named))
+}
+
+pub fn format_secret_file(active: &Secret, named: &[NamedSecret]) -> String {
+    let mut content = active.0.clone();
+    for secret in named {
+        content.push('\n');
+        content.push_str(&secret.to_string());
+    }
+    if !named.is_empty() {
+        content.push('\n');
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions(line: &str) -> Permissions {
+        line.parse::<NamedSecret>()
+            .expect("named secret")
+            .permissions
+    }
+
+    fn restrict_in(permissions: &Permissions, line: &str) -> Result<String, String> {
+        let mut command = UciIn::from_line(line).expect("command").expect("not empty");
+        permissions.restrict_in(&mut command)?;
+        Ok(command.to_string())
+    }
+
+    fn restrict_out(permissions: &Permissions, line: &str) -> String {
+        let mut out = UciOut::from_line(line).expect("output").expect("not empty");
+        permissions.restrict_out(&mut out);
+        out.to_string()
+    }
+
+    #[test]
+    fn test_named_secret() {
+        for line in [
+            "phone 0123456789abcdef",
+            "laptop 0123456789abcdef max-threads=4 max-hash=256 max-multipv=3",
+            "kid 0123456789abcdef variants=chess,atomic",
+        ] {
+            let secret: NamedSecret = line.parse().expect("named secret");
+            assert_eq!(secret.to_string(), line);
+            assert_eq!(secret.to_string().parse::<NamedSecret>(), Ok(secret));
+        }
+        assert_eq!(
+            permissions("phone 0123456789abcdef max-hash=64"),
+            Permissions {
+                max_hash: Some(64),
+                ..Permissions::default()
+            }
+        );
+
+        assert!("".parse::<NamedSecret>().is_err());
+        assert!("phone".parse::<NamedSecret>().is_err());
+        assert!("ph/one 0123456789abcdef".parse::<NamedSecret>().is_err());
+        assert!("phone 0123456".parse::<NamedSecret>().is_err());
+        assert!("phone 0123456789abcdef max-threads"
+            .parse::<NamedSecret>()
+            .is_err());
+        assert!("phone 0123456789abcdef max-threads=-1"
+            .parse::<NamedSecret>()
+            .is_err());
+        assert!("phone 0123456789abcdef admin=true"
+            .parse::<NamedSecret>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let content = "0123456789abcdef\n\nphone 1111111111111111 max-threads=2\n  \nlaptop 2222222222222222\n";
+        let (active, named) = parse_secret_file(content).expect("secret file");
+        assert_eq!(active, Secret("0123456789abcdef".to_owned()));
+        assert_eq!(
+            named.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(),
+            vec!["phone", "laptop"]
+        );
+        assert_eq!(
+            format_secret_file(&active, &named),
+            "0123456789abcdef\nphone 1111111111111111 max-threads=2\nlaptop 2222222222222222\n"
+        );
+        assert_eq!(
+            parse_secret_file("0123456789abcdef"),
+            Ok((Secret("0123456789abcdef".to_owned()), Vec::new()))
+        );
+
+        assert_eq!(
+            parse_secret_file(
+                "0123456789abcdef\nphone 1111111111111111\n\nphone 2222222222222222\n"
+            ),
+            Err("line 4: duplicate name phone".to_owned())
+        );
+        assert_eq!(
+            parse_secret_file("0123456789abcdef\n\n\nphone short\n"),
+            Err("line 4: secret of phone is too short".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_restrict_in() {
+        let permissions =
+            permissions("phone 0123456789abcdef max-threads=4 max-hash=256 variants=chess,atomic");
+        assert_eq!(
+            restrict_in(&permissions, "setoption name Threads value 64"),
+            Ok("setoption name Threads value 4".to_owned())
+        );
+        assert_eq!(
+            restrict_in(&permissions, "setoption name Threads value 2"),
+            Ok("setoption name Threads value 2".to_owned())
+        );
+        assert_eq!(
+            restrict_in(&permissions, "setoption name Hash value 4096"),
+            Ok("setoption name Hash value 256".to_owned())
+        );
+        assert_eq!(
+            restrict_in(&permissions, "setoption name MultiPV value 500"),
+            Ok("setoption name MultiPV value 500".to_owned()),
+            "no limit"
+        );
+        assert_eq!(
+            restrict_in(&permissions, "setoption name UCI_Variant value atomic"),
+            Ok("setoption name UCI_Variant value atomic".to_owned())
+        );
+        assert!(restrict_in(&permissions, "setoption name UCI_Variant value crazyhouse").is_err());
+        assert_eq!(
+            restrict_in(&permissions, "go depth 20"),
+            Ok("go depth 20".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_restrict_out() {
+        let permissions =
+            permissions("phone 0123456789abcdef max-threads=4 variants=atomic,crazyhouse");
+        assert_eq!(
+            restrict_out(
+                &permissions,
+                "option name Threads type spin default 1 min 1 max 1024"
+            ),
+            "option name Threads type spin default 1 min 1 max 4"
+        );
+        assert_eq!(
+            restrict_out(
+                &permissions,
+                "option name UCI_Variant type combo default chess var chess var atomic var crazyhouse var horde"
+            ),
+            "option name UCI_Variant type combo default atomic var atomic var crazyhouse"
+        );
+        assert_eq!(
+            restrict_out(
+                &permissions,
+                "option name UCI_Variant type combo default crazyhouse var chess var atomic var crazyhouse"
+            ),
+            "option name UCI_Variant type combo default crazyhouse var atomic var crazyhouse"
+        );
+        assert_eq!(
+            restrict_out(
+                &Permissions::default(),
+                "option name Hash type spin default 16 min 1 max 33554432"
+            ),
+            "option name Hash type spin default 16 min 1 max 33554432"
+        );
+    }
+}
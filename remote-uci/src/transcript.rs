@@ -0,0 +1,58 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, LineWriter, Write as _},
+    path::Path,
+    time::SystemTime,
+};
+
+use crate::engine::Session;
+
+/// Record of all UCI traffic of a session, one line per command with a
+/// millisecond timestamp. Commands from the client are prefixed with `>`,
+/// commands from the engine with `<`.
+pub struct Transcript {
+    file: LineWriter<File>,
+}
+
+impl Transcript {
+    /// Creates a new transcript file in `dir`. Failure is logged, but does
+    /// not affect the session.
+    pub fn create(dir: &Path, session: Session) -> Option<Transcript> {
+        let started = humantime::format_rfc3339_seconds(SystemTime::now())
+            .to_string()
+            .replace(':', "-");
+        let path = dir.join(format!("{started}-session-{}.log", session.0));
+        match fs::create_dir_all(dir).and_then(|()| File::create(&path)) {
+            Ok(file) => {
+                tracing::info!("writing transcript to {}", path.display());
+                Some(Transcript {
+                    file: LineWriter::new(file),
+                })
+            }
+            Err(err) => {
+                tracing::error!("failed to create transcript {}: {err}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn client(&mut self, command: impl fmt::Display) {
+        self.write('>', command);
+    }
+
+    pub fn engine(&mut self, command: impl fmt::Display) {
+        self.write('<', command);
+    }
+
+    fn write(&mut self, direction: char, command: impl fmt::Display) {
+        let res: io::Result<()> = writeln!(
+            self.file,
+            "{} {direction} {command}",
+            humantime::format_rfc3339_millis(SystemTime::now())
+        );
+        if let Err(err) = res {
+            tracing::error!("failed to write transcript: {err}");
+        }
+    }
+}
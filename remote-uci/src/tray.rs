@@ -0,0 +1,241 @@
+//! Icon in the system tray, shown by a helper process of the platform:
+//! yad on Linux, JavaScript for Automation on macOS and PowerShell on
+//! Windows.
+//!
+//! Tray icon crates need an event loop of the GUI toolkit on the main
+//! thread, which is taken by the async runtime, and GTK libraries at build
+//! time on Linux. Helper processes avoid both, and the tray can fail
+//! without taking down the server.
+
+use std::{io, process::Stdio, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    process::{Child, Command},
+};
+
+use crate::{
+    ws::{SecretStore, SharedEngine},
+    ExternalWorkerOpts,
+};
+
+/// Shows an icon with a menu in the system tray, until the user picks Quit.
+///
+/// The icon is provided by a helper process of the platform, which prints
+/// the actions picked from the menu, one per line.
+pub async fn run(
+    specs: &[ExternalWorkerOpts],
+    secrets: &[Arc<SecretStore>],
+    engines: &[Arc<SharedEngine>],
+) -> io::Result<()> {
+    let mut helper = imp::spawn()?;
+    let stdout = helper.stdout.take().expect("piped stdout");
+    let mut actions = BufReader::new(stdout).lines();
+    let mut on_hold = false;
+    while let Some(action) = actions.next_line().await? {
+        match action.trim() {
+            "copy" => {
+                // With the secrets as they are now, in case they were
+                // rotated.
+                let urls: Vec<String> = specs
+                    .iter()
+                    .zip(secrets)
+                    .map(|(spec, secrets)| spec.with_secret(secrets.active()).registration_url())
+                    .collect();
+                match copy(&urls.join("\n")).await {
+                    Ok(()) => tracing::info!("Copied registration URL to clipboard"),
+                    Err(err) => tracing::error!("Failed to copy registration URL: {err}"),
+                }
+            }
+            "pause" => {
+                on_hold = !on_hold;
+                for engine in engines {
+                    engine.hold(on_hold);
+                }
+                if on_hold {
+                    tracing::warn!("Paused serving from the tray");
+                } else {
+                    tracing::warn!("Resumed serving from the tray");
+                }
+            }
+            "restart" => {
+                tracing::warn!("Restarting engines from the tray ...");
+                for engine in engines {
+                    if let Err(err) = engine.restart_engines().await {
+                        tracing::error!("Failed to restart engine: {err}");
+                    }
+                }
+            }
+            "quit" => return Ok(()),
+            other => tracing::debug!("Unknown tray action: {other}"),
+        }
+    }
+    let status = helper.wait().await?;
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!("tray icon closed ({status})"),
+    ))
+}
+
+/// Puts the text on the clipboard.
+async fn copy(text: &str) -> io::Result<()> {
+    let mut child = imp::clipboard()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    stdin.write_all(text.as_bytes()).await?;
+    drop(stdin);
+    let status = child.wait().await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("clipboard command failed ({status})"),
+        ))
+    }
+}
+
+fn helper(mut command: Command) -> io::Result<Child> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::io;
+
+    use tokio::process::{Child, Command};
+
+    /// Requires yad, which runs the command of a menu entry with its own
+    /// stdout.
+    pub fn spawn() -> io::Result<Child> {
+        let mut command = Command::new("yad");
+        command.args([
+            "--notification",
+            "--image=applications-games",
+            "--text=remote-uci",
+            "--command=echo copy",
+            "--menu=Copy registration URL!echo copy|Pause serving!echo pause|Restart engine!echo restart|Quit!echo quit",
+        ]);
+        super::helper(command)
+    }
+
+    pub fn clipboard() -> Command {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Command::new("wl-copy")
+        } else {
+            let mut command = Command::new("xclip");
+            command.args(["-selection", "clipboard"]);
+            command
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::io;
+
+    use tokio::process::{Child, Command};
+
+    /// Adds a status item to the menu bar, using the Objective-C bridge of
+    /// JavaScript for Automation.
+    const SCRIPT: &str = r#"
+        ObjC.import('Cocoa');
+        const app = $.NSApplication.sharedApplication;
+        app.setActivationPolicy($.NSApplicationActivationPolicyAccessory);
+        ObjC.registerSubclass({
+            name: 'RemoteUciTray',
+            methods: {
+                'act:': {
+                    types: ['void', ['id']],
+                    implementation: function (sender) {
+                        const action = sender.representedObject.js;
+                        if (action === 'pause') sender.state = sender.state ? 0 : 1;
+                        $.NSFileHandle.fileHandleWithStandardOutput.writeData(
+                            $(action + '\n').dataUsingEncoding($.NSUTF8StringEncoding));
+                    },
+                },
+            },
+        });
+        const target = $.RemoteUciTray.alloc.init;
+        const menu = $.NSMenu.alloc.init;
+        [
+            ['Copy registration URL', 'copy'],
+            ['Pause serving', 'pause'],
+            ['Restart engine', 'restart'],
+            ['Quit', 'quit'],
+        ].forEach(([title, action]) => {
+            const item = $.NSMenuItem.alloc.initWithTitleActionKeyEquivalent(title, 'act:', '');
+            item.target = target;
+            item.representedObject = $(action);
+            menu.addItem(item);
+        });
+        const status = $.NSStatusBar.systemStatusBar.statusItemWithLength($.NSVariableStatusItemLength);
+        status.button.title = 'UCI';
+        status.menu = menu;
+        app.run;
+    "#;
+
+    pub fn spawn() -> io::Result<Child> {
+        let mut command = Command::new("osascript");
+        command.args(["-l", "JavaScript", "-e", SCRIPT]);
+        super::helper(command)
+    }
+
+    pub fn clipboard() -> Command {
+        Command::new("pbcopy")
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+
+    use tokio::process::{Child, Command};
+
+    /// Shows an icon in the notification area. The icon is removed before
+    /// exiting on Quit, because Windows keeps showing icons of killed
+    /// processes until the mouse passes over them.
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Windows.Forms
+        $icon = New-Object System.Windows.Forms.NotifyIcon
+        $icon.Icon = [System.Drawing.SystemIcons]::Application
+        $icon.Text = 'remote-uci'
+        $menu = New-Object System.Windows.Forms.ContextMenuStrip
+        function Add-Action($title, $action) {
+            $item = $menu.Items.Add($title)
+            $item.add_Click({
+                [Console]::Out.WriteLine($action)
+                [Console]::Out.Flush()
+                if ($action -eq 'quit') {
+                    $icon.Visible = $false
+                    [System.Windows.Forms.Application]::Exit()
+                }
+            }.GetNewClosure())
+            $item
+        }
+        $null = Add-Action 'Copy registration URL' 'copy'
+        $pause = Add-Action 'Pause serving' 'pause'
+        $pause.CheckOnClick = $true
+        $null = Add-Action 'Restart engine' 'restart'
+        $null = $menu.Items.Add('-')
+        $null = Add-Action 'Quit' 'quit'
+        $icon.ContextMenuStrip = $menu
+        $icon.Visible = $true
+        [System.Windows.Forms.Application]::Run()
+        $icon.Dispose()";
+
+    pub fn spawn() -> io::Result<Child> {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT]);
+        super::helper(command)
+    }
+
+    pub fn clipboard() -> Command {
+        Command::new("clip")
+    }
+}
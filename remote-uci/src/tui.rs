@@ -0,0 +1,329 @@
+//! Dashboard for the terminal, drawn with ANSI escape codes.
+//!
+//! The dashboard only shows text and takes no input, and the whole screen is
+//! redrawn twice a second. A terminal UI library like ratatui would add
+//! dependencies without making that simpler.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, Write as _},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use shakmaty::{variant::Variant, CastlingMode, File, Position as _, Rank, Square};
+use sysinfo::{Pid, PidExt, ProcessExt, ProcessRefreshKind, RefreshKind, System, SystemExt};
+use tokio::{sync::broadcast, time::sleep};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    engine::{EngineStatus, Observation},
+    qr::terminal_qr_code,
+    shutdown::Shutdown,
+    uci::{UciIn, UciOut},
+    ws::{SecretStore, SharedEngine},
+    ExternalWorkerOpts,
+};
+
+/// Number of log lines shown below the dashboard.
+const LOG_LINES: usize = 12;
+
+/// Time between redraws of the dashboard.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Width of the column left of the QR code.
+const COLUMN_WIDTH: usize = 72;
+
+/// Last lines of the log, shown in the dashboard instead of being written
+/// to stderr.
+#[derive(Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<String>>>);
+
+impl LogBuffer {
+    fn lines(&self) -> Vec<String> {
+        self.0.lock().expect("log buffer").iter().cloned().collect()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        LogWriter {
+            buffer: self.clone(),
+            bytes: Vec::new(),
+        }
+    }
+}
+
+/// Collects a log event, which is added to the buffer once complete.
+pub struct LogWriter {
+    buffer: LogBuffer,
+    bytes: Vec<u8>,
+}
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let mut lines = self.buffer.0.lock().expect("log buffer");
+        for line in String::from_utf8_lossy(&self.bytes).lines() {
+            lines.push_back(line.to_owned());
+        }
+        while lines.len() > LOG_LINES {
+            lines.pop_front();
+        }
+    }
+}
+
+/// What the dashboard shows of an engine process.
+struct Slot {
+    observations: broadcast::Receiver<Observation>,
+    /// Latest info line of the main line, with a principal variation.
+    info: Option<UciOut>,
+}
+
+struct Endpoint {
+    spec: ExternalWorkerOpts,
+    secrets: Arc<SecretStore>,
+    /// Registration URL with the secret that was active at the last redraw,
+    /// and its QR code.
+    url: String,
+    qr: Option<String>,
+    engine: Arc<SharedEngine>,
+    slots: Vec<Slot>,
+}
+
+/// Shows the state of the engines in the terminal, until shutdown is
+/// requested.
+pub async fn run(
+    specs: &[ExternalWorkerOpts],
+    secrets: &[Arc<SecretStore>],
+    engines: &[Arc<SharedEngine>],
+    shutdown: &Shutdown,
+    logs: LogBuffer,
+) {
+    let mut endpoints: Vec<Endpoint> = specs
+        .iter()
+        .zip(secrets)
+        .zip(engines)
+        .map(|((spec, secrets), engine)| {
+            let url = spec.with_secret(secrets.active()).registration_url();
+            Endpoint {
+                spec: spec.clone(),
+                secrets: Arc::clone(secrets),
+                qr: terminal_qr_code(&url).ok(),
+                url,
+                engine: Arc::clone(engine),
+                slots: engine
+                    .observe()
+                    .into_iter()
+                    .map(|observations| Slot {
+                        observations,
+                        info: None,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    let mut sys = System::new_with_specifics(RefreshKind::new());
+
+    // Use the alternate screen, so that the previous contents of the
+    // terminal are restored on exit.
+    print!("\x1b[?1049h\x1b[?25l");
+    while !shutdown.is_requested() {
+        let mut screen = String::new();
+        for endpoint in &mut endpoints {
+            endpoint.update();
+            endpoint.render(&mut screen, &mut sys);
+        }
+        screen.push_str("\x1b[1mLog\x1b[0m\n");
+        for line in logs.lines() {
+            let _ = writeln!(screen, "{}", truncate(&line, 2 * COLUMN_WIDTH));
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "\x1b[H\x1b[2J{}", screen.replace('\n', "\r\n"));
+        let _ = stdout.flush();
+        drop(stdout);
+        sleep(REFRESH_INTERVAL).await;
+    }
+    print!("\x1b[?25h\x1b[?1049l");
+    let _ = io::stdout().flush();
+}
+
+impl Endpoint {
+    /// Takes note of the analysis and of the secret, if it was rotated,
+    /// since the last redraw.
+    fn update(&mut self) {
+        let url = self
+            .spec
+            .with_secret(self.secrets.active())
+            .registration_url();
+        if url != self.url {
+            self.qr = terminal_qr_code(&url).ok();
+            self.url = url;
+        }
+        for slot in &mut self.slots {
+            loop {
+                match slot.observations.try_recv() {
+                    Ok(Observation::Position(_)) => slot.info = None,
+                    Ok(Observation::Info(info)) => {
+                        if let UciOut::Info {
+                            multipv,
+                            pv: Some(_),
+                            ..
+                        } = info
+                        {
+                            if multipv.map_or(true, |multipv| multipv.get() == 1) {
+                                slot.info = Some(info);
+                            }
+                        }
+                    }
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => (),
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    fn render(&self, screen: &mut String, sys: &mut System) {
+        let mut column = String::new();
+        let _ = writeln!(column, "\x1b[1mRegistration URL\x1b[0m");
+        for chunk in self.url.as_bytes().chunks(COLUMN_WIDTH) {
+            let _ = writeln!(column, "{}", String::from_utf8_lossy(chunk));
+        }
+        let metrics = self.engine.metrics();
+        for ((status, slot), metrics) in self.engine.status().iter().zip(&self.slots).zip(metrics) {
+            column.push('\n');
+            let _ = writeln!(
+                column,
+                "\x1b[1m{}\x1b[0m",
+                status.name.as_deref().unwrap_or("Engine")
+            );
+            let _ = writeln!(column, "State:    {}", state(status));
+            if let Some(latency) = status.latency_ms {
+                let _ = writeln!(column, "Latency:  {latency} ms");
+            }
+            let pid = metrics.pid.load(std::sync::atomic::Ordering::Relaxed);
+            if pid != 0 {
+                let pid = Pid::from_u32(pid);
+                if sys.refresh_process_specifics(pid, ProcessRefreshKind::new().with_cpu()) {
+                    if let Some(process) = sys.process(pid) {
+                        let _ = writeln!(
+                            column,
+                            "Process:  pid {pid}, cpu {:.0}%, memory {} MiB",
+                            process.cpu_usage(),
+                            process.memory() / 1024
+                        );
+                    }
+                }
+            }
+            if let Some(UciOut::Info {
+                depth,
+                score,
+                nps,
+                pv: Some(pv),
+                ..
+            }) = &slot.info
+            {
+                let _ = writeln!(
+                    column,
+                    "Depth:    {}    Score: {}    Nodes/s: {}",
+                    depth.map_or("-".to_owned(), |depth| depth.to_string()),
+                    score
+                        .as_ref()
+                        .map_or("-".to_owned(), |score| score.to_string()),
+                    nps.map_or("-".to_owned(), |nps| nps.to_string()),
+                );
+                let pv: Vec<String> = pv.iter().map(ToString::to_string).collect();
+                let _ = writeln!(
+                    column,
+                    "PV:       {}",
+                    truncate(&pv.join(" "), COLUMN_WIDTH - 10)
+                );
+            }
+            if let Some(board) = status.position.as_deref().and_then(board) {
+                column.push('\n');
+                column.push_str(&board);
+            }
+        }
+
+        // Put the QR code to the right of the column.
+        let left: Vec<&str> = column.lines().collect();
+        let right: Vec<&str> = self
+            .qr
+            .as_deref()
+            .map_or(Vec::new(), |qr| qr.lines().collect());
+        for i in 0..left.len().max(right.len()) {
+            let line = left.get(i).copied().unwrap_or_default();
+            let padding = COLUMN_WIDTH.saturating_sub(visible_len(line)) + 2;
+            let _ = writeln!(
+                screen,
+                "{line}{:padding$}{}",
+                "",
+                right.get(i).copied().unwrap_or_default()
+            );
+        }
+        screen.push('\n');
+    }
+}
+
+fn state(status: &EngineStatus) -> String {
+    match status.session {
+        _ if status.paused => "paused while the user is active".to_owned(),
+        _ if status.suspended => "suspended".to_owned(),
+        Some(session) if status.pondering => format!("session {session}, pondering"),
+        Some(session) if status.searching => format!("session {session}, searching"),
+        Some(session) => format!("session {session}, idle"),
+        None => "waiting for clients".to_owned(),
+    }
+}
+
+/// Draws the position of a `position` command, if it is a standard chess
+/// position.
+fn board(position: &str) -> Option<String> {
+    let pos = UciIn::from_line(position)
+        .ok()
+        .flatten()?
+        .to_position(Variant::Chess, CastlingMode::Chess960)
+        .ok()
+        .flatten()?;
+    let mut board = String::new();
+    for rank in (0..8).rev() {
+        let _ = write!(board, "{} ", rank + 1);
+        for file in 0..8 {
+            let square = Square::from_coords(File::new(file), Rank::new(rank));
+            board.push(
+                pos.board()
+                    .piece_at(square)
+                    .map_or('.', |piece| piece.char()),
+            );
+            board.push(' ');
+        }
+        board.push('\n');
+    }
+    board.push_str("  a b c d e f g h\n");
+    Some(board)
+}
+
+/// Length without escape sequences.
+fn visible_len(line: &str) -> usize {
+    line.replace("\x1b[1m", "")
+        .replace("\x1b[0m", "")
+        .chars()
+        .count()
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
@@ -0,0 +1,213 @@
+use std::{io, net::SocketAddr, process::Stdio, time::Duration};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::mpsc,
+    time::timeout,
+};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TunnelKind {
+    /// Quick tunnel via cloudflared (trycloudflare.com).
+    Cloudflared,
+    /// Tunnel via ngrok. Requires a configured ngrok account.
+    Ngrok,
+    /// Run --tunnel-command and use the first https:// URL it prints.
+    Custom,
+}
+
+impl TunnelKind {
+    fn command(self, local: SocketAddr, custom: Option<&str>) -> io::Result<Vec<String>> {
+        let target = format!("http://{local}");
+        Ok(match self {
+            TunnelKind::Cloudflared => vec![
+                "cloudflared".to_owned(),
+                "tunnel".to_owned(),
+                "--url".to_owned(),
+                target,
+            ],
+            TunnelKind::Ngrok => vec![
+                "ngrok".to_owned(),
+                "http".to_owned(),
+                target,
+                "--log".to_owned(),
+                "stdout".to_owned(),
+                "--log-format".to_owned(),
+                "logfmt".to_owned(),
+            ],
+            TunnelKind::Custom => custom
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--tunnel custom requires --tunnel-command",
+                    )
+                })?
+                .split_whitespace()
+                .map(|arg| {
+                    arg.replace("{port}", &local.port().to_string())
+                        .replace("{url}", &target)
+                })
+                .collect(),
+        })
+    }
+
+    /// Finds the public URL in a line of output of the tunnel process.
+    fn public_url(self, line: &str) -> Option<&str> {
+        line.match_indices("https://")
+            .map(|(i, _)| {
+                let url = &line[i..];
+                url.split(|c: char| c.is_whitespace() || c == '"' || c == '|')
+                    .next()
+                    .unwrap_or(url)
+            })
+            .find(|url| match self {
+                // cloudflared also prints links to its documentation.
+                TunnelKind::Cloudflared => url.ends_with(".trycloudflare.com"),
+                TunnelKind::Ngrok | TunnelKind::Custom => true,
+            })
+    }
+}
+
+/// Starts the tunnel process and waits until it reports the public URL of
+/// the server. The process keeps running in the background.
+pub async fn start(
+    kind: TunnelKind,
+    local: SocketAddr,
+    custom_command: Option<&str>,
+) -> io::Result<String> {
+    let command = kind.command(local, custom_command)?;
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty tunnel command"))?;
+
+    tracing::info!("Starting tunnel {program:?} ...");
+    let mut process = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    if let Some(stdout) = process.stdout.take() {
+        tokio::spawn(forward_lines(stdout, tx.clone()));
+    }
+    if let Some(stderr) = process.stderr.take() {
+        tokio::spawn(forward_lines(stderr, tx));
+    }
+
+    let url = timeout(Duration::from_secs(30), async {
+        while let Some(line) = rx.recv().await {
+            if let Some(url) = kind.public_url(&line) {
+                return Some(url.trim_end_matches('/').to_owned());
+            }
+        }
+        None
+    })
+    .await
+    .map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            "tunnel did not report a public url",
+        )
+    })?
+    .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "tunnel exited"))?;
+
+    tokio::spawn(async move {
+        match process.wait().await {
+            Ok(status) => tracing::error!("Tunnel exited with {status}"),
+            Err(err) => tracing::error!("Tunnel failed: {err}"),
+        }
+    });
+
+    Ok(url)
+}
+
+async fn forward_lines<R: AsyncRead + Unpin>(reader: R, tx: mpsc::UnboundedSender<String>) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::debug!("tunnel: {line}");
+        let _ = tx.send(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_url(kind: TunnelKind, log: &str) -> Option<&str> {
+        log.lines().find_map(|line| kind.public_url(line))
+    }
+
+    #[test]
+    fn test_cloudflared_url() {
+        let log = "\
+2024-05-04T10:12:01Z INF Thank you for trying Cloudflare Tunnel. Doing so, without a Cloudflare account, is a quick way to experiment and try it out. However, be aware that these account-less Tunnels have no uptime guarantee. If you intend to use Tunnels in production you should use a pre-created named tunnel by following: https://developers.cloudflare.com/cloudflare-one/connections/connect-apps
+2024-05-04T10:12:01Z INF Requesting new quick Tunnel on trycloudflare.com...
+2024-05-04T10:12:04Z INF +--------------------------------------------------------------------------------------------+
+2024-05-04T10:12:04Z INF |  Your quick Tunnel has been created! Visit it at (it may take some time to be reachable):  |
+2024-05-04T10:12:04Z INF |  https://seasonal-deck-organisms-sf.trycloudflare.com                                      |
+2024-05-04T10:12:04Z INF +--------------------------------------------------------------------------------------------+
+2024-05-04T10:12:04Z INF Cannot determine default configuration path. No file [config.yml config.yaml] in [~/.cloudflared ~/.cloudflare-warp ~/cloudflare-warp /etc/cloudflared /usr/local/etc/cloudflared]
+";
+        assert_eq!(
+            first_url(TunnelKind::Cloudflared, log),
+            Some("https://seasonal-deck-organisms-sf.trycloudflare.com")
+        );
+        assert_eq!(
+            TunnelKind::Cloudflared.public_url(
+                "INF See https://developers.cloudflare.com/ or https://abc.trycloudflare.com"
+            ),
+            Some("https://abc.trycloudflare.com")
+        );
+        assert_eq!(
+            TunnelKind::Cloudflared
+                .public_url("INF https://developers.cloudflare.com/cloudflare-one/"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ngrok_url() {
+        let log = r#"t=2024-05-04T10:15:31+0200 lvl=info msg="no configuration paths supplied"
+t=2024-05-04T10:15:31+0200 lvl=info msg="starting web service" obj=web addr=127.0.0.1:4040 allow_hosts=[]
+t=2024-05-04T10:15:32+0200 lvl=info msg="client session established" obj=tunnels.session
+t=2024-05-04T10:15:32+0200 lvl=info msg="started tunnel" obj=tunnels name=command_line addr=http://127.0.0.1:9670 url=https://1a2b-203-0-113-9.ngrok-free.app
+"#;
+        assert_eq!(
+            first_url(TunnelKind::Ngrok, log),
+            Some("https://1a2b-203-0-113-9.ngrok-free.app")
+        );
+        assert_eq!(
+            TunnelKind::Custom.public_url(r#"{"url":"https://example.com/engine"}"#),
+            Some("https://example.com/engine")
+        );
+        assert_eq!(
+            TunnelKind::Custom.public_url("listening on http://127.0.0.1:9670"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_custom_command() {
+        let local: SocketAddr = "127.0.0.1:9670".parse().expect("address");
+        assert_eq!(
+            TunnelKind::Custom
+                .command(local, Some("bore local {port} --to bore.pub"))
+                .expect("command"),
+            vec!["bore", "local", "9670", "--to", "bore.pub"]
+        );
+        assert_eq!(
+            TunnelKind::Custom
+                .command(local, Some("tunnel {url}"))
+                .expect("command"),
+            vec!["tunnel", "http://127.0.0.1:9670"]
+        );
+        assert!(TunnelKind::Custom.command(local, None).is_err());
+    }
+}
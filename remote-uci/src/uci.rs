@@ -1,15 +1,28 @@
+//! Typed messages of the Universal Chess Interface, with a parser and
+//! serializer for both directions.
+//!
+//! Lines from a GUI to an engine are [`UciIn`], lines from an engine are
+//! [`UciOut`]. Both can be parsed with [`str::parse`] or leniently with
+//! `from_line` (which skips unknown engine output), and written with
+//! [`fmt::Display`].
+
 use std::{
+    cmp::min,
     collections::HashMap,
     fmt,
     hash::{Hash, Hasher},
     num::{NonZeroU32, ParseIntError},
+    str::FromStr,
     time::Duration,
 };
 
 use memchr::{memchr2, memchr2_iter};
+use serde::Serialize;
 use shakmaty::{
     fen::{Fen, ParseFenError},
     uci::{ParseUciError, Uci},
+    variant::{Variant, VariantPosition},
+    CastlingMode, Position as _, PositionError,
 };
 use thiserror::Error;
 
@@ -29,7 +42,35 @@ impl UciOptionName {
             || *self == "UCI_AnalyseMode"
             || *self == "UCI_Opponent"
             || *self == "UCI_Chess960"
+            || *self == "UCI_Variant"
             || *self == "Analysis Contempt"
+            || *self == "Clear Hash"
+    }
+
+    /// Whether searches give the same results regardless of the value of
+    /// the option. The number of threads and the size of the hash table
+    /// change search results, and strength limits add random choices.
+    pub fn is_deterministic(&self) -> bool {
+        !(*self == "Threads"
+            || *self == "Hash"
+            || *self == "UCI_LimitStrength"
+            || *self == "UCI_Elo"
+            || *self == "Skill Level"
+            || *self == "Temperature")
+    }
+}
+
+/// Operator overrides for the options that clients may set, on top of the
+/// options that are considered safe.
+#[derive(Clone, Debug, Default)]
+pub struct OptionPolicy {
+    pub allow: Vec<UciOptionName>,
+    pub deny: Vec<UciOptionName>,
+}
+
+impl OptionPolicy {
+    pub fn is_allowed(&self, name: &UciOptionName) -> bool {
+        !self.deny.contains(name) && (name.is_safe() || self.allow.contains(name))
     }
 }
 
@@ -60,7 +101,10 @@ impl fmt::Display for UciOptionName {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Type, default value and bounds of an option, as in
+/// `type spin default 1 min 1 max 512`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum UciOption {
     Check { default: bool },
     Spin { default: i64, min: i64, max: i64 },
@@ -73,8 +117,8 @@ impl UciOption {
     pub fn validate(&self, value: Option<String>) -> Result<UciOptionValue, ProtocolError> {
         Ok(match self {
             UciOption::Check { .. } => match value {
-                Some(v) if v == "true" => UciOptionValue::Check(true),
-                Some(v) if v == "false" => UciOptionValue::Check(false),
+                Some(v) if v.eq_ignore_ascii_case("true") => UciOptionValue::Check(true),
+                Some(v) if v.eq_ignore_ascii_case("false") => UciOptionValue::Check(false),
                 _ => return Err(ProtocolError::InvalidOptionValue),
             },
             UciOption::Spin { min, max, .. } => {
@@ -85,11 +129,14 @@ impl UciOption {
                 UciOptionValue::Spin(value)
             }
             UciOption::Combo { var, .. } => {
+                // Values are matched like option names, and spelled as the
+                // engine announced them.
                 let value = value.ok_or(ProtocolError::InvalidOptionValue)?;
-                if !var.contains(&value) {
-                    return Err(ProtocolError::InvalidOptionValue);
-                }
-                UciOptionValue::Combo(value)
+                let value = var
+                    .iter()
+                    .find(|v| v.eq_ignore_ascii_case(&value))
+                    .ok_or(ProtocolError::InvalidOptionValue)?;
+                UciOptionValue::Combo(value.clone())
             }
             UciOption::Button => {
                 if value.is_some() {
@@ -112,6 +159,18 @@ impl UciOption {
         }
     }
 
+    /// Default value, as it would be sent in `setoption`.
+    pub fn default_value(&self) -> Option<String> {
+        match self {
+            UciOption::Check { default } => Some(default.to_string()),
+            UciOption::Spin { default, .. } => Some(default.to_string()),
+            UciOption::Combo { default, .. } | UciOption::String { default } => {
+                Some(default.clone())
+            }
+            UciOption::Button => None,
+        }
+    }
+
     pub fn var(&self) -> Option<&[String]> {
         match self {
             UciOption::Combo { var, .. } => Some(var),
@@ -127,6 +186,19 @@ impl UciOption {
     }
 }
 
+impl FromStr for UciOption {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<UciOption, ProtocolError> {
+        let mut parser = Parser::new(s)?;
+        match parser.next() {
+            Some("type") => parser.parse_option_type(),
+            Some(_) => Err(ProtocolError::UnexpectedToken),
+            None => Err(ProtocolError::UnexpectedEndOfLine),
+        }
+    }
+}
+
 impl fmt::Display for UciOption {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -147,6 +219,7 @@ impl fmt::Display for UciOption {
     }
 }
 
+/// Value of an option, validated against its [`UciOption`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UciOptionValue {
     Check(bool),
@@ -156,6 +229,18 @@ pub enum UciOptionValue {
     String(String),
 }
 
+impl fmt::Display for UciOptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciOptionValue::Check(value) => value.fmt(f),
+            UciOptionValue::Spin(value) => value.fmt(f),
+            UciOptionValue::Combo(value) | UciOptionValue::String(value) => value.fmt(f),
+            UciOptionValue::Button => Ok(()),
+        }
+    }
+}
+
+/// A command from the GUI to the engine.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UciIn {
     Uci,
@@ -188,8 +273,105 @@ pub enum UciIn {
 }
 
 impl UciIn {
+    /// Parses a single line. Returns `None` for blank lines.
     pub fn from_line(s: &str) -> Result<Option<UciIn>, ProtocolError> {
-        Parser::new(s)?.parse_in()
+        let mut parser = Parser::new(s)?;
+        parser.check_tokens()?;
+        parser.parse_in()
+    }
+
+    /// Rewrites a `go` command so that the search stays within the limits.
+    /// Infinite searches become finite. Returns whether the command was
+    /// changed.
+    pub fn limit_search(&mut self, limits: &SearchLimits) -> bool {
+        let (depth, nodes, movetime, infinite) = match self {
+            UciIn::Go {
+                depth,
+                nodes,
+                movetime,
+                infinite,
+                ..
+            } => (depth, nodes, movetime, infinite),
+            _ => return false,
+        };
+        let mut changed = false;
+        if let Some(max_depth) = limits.depth {
+            changed |= cap(depth, max_depth);
+        }
+        if let Some(max_nodes) = limits.nodes {
+            changed |= cap(nodes, max_nodes);
+        }
+        if let Some(max_movetime) = limits.movetime {
+            changed |= cap(movetime, max_movetime);
+        }
+        if *infinite && !limits.is_unlimited() {
+            *infinite = false;
+            changed = true;
+        }
+        changed
+    }
+
+    /// Sets up the position of a `position` command. Checks that the FEN is
+    /// a legal position of the variant, and that the moves can be played
+    /// from it. Positions with impossible material or checks are accepted,
+    /// because engines handle them fine and they can be set up on lichess.
+    /// Returns `None` for other commands.
+    pub fn to_position(
+        &self,
+        variant: Variant,
+        castling_mode: CastlingMode,
+    ) -> Result<Option<VariantPosition>, ProtocolError> {
+        let (fen, moves) = match self {
+            UciIn::Position { fen, moves } => (fen, moves),
+            _ => return Ok(None),
+        };
+        let mut pos = match fen {
+            Some(fen) => VariantPosition::from_setup(variant, fen.0.clone(), castling_mode)
+                .or_else(PositionError::ignore_impossible_material)
+                .or_else(PositionError::ignore_impossible_check)
+                .map_err(|err| ProtocolError::IllegalPosition(err.to_string()))?,
+            None => VariantPosition::new(variant),
+        };
+        for (ply, uci) in moves.iter().enumerate() {
+            let m = uci.to_move(&pos).map_err(|_| ProtocolError::IllegalMove {
+                uci: uci.clone(),
+                ply: ply + 1,
+            })?;
+            pos.play_unchecked(&m);
+        }
+        Ok(Some(pos))
+    }
+}
+
+fn cap<T: Ord + Copy>(value: &mut Option<T>, max: T) -> bool {
+    match *value {
+        Some(v) if v <= max => false,
+        _ => {
+            *value = Some(max);
+            true
+        }
+    }
+}
+
+/// Caps on the resources a single search may use.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.depth.is_none() && self.nodes.is_none() && self.movetime.is_none()
+    }
+}
+
+impl FromStr for UciIn {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<UciIn, ProtocolError> {
+        UciIn::from_line(s)?.ok_or(ProtocolError::UnexpectedEndOfLine)
     }
 }
 
@@ -287,6 +469,18 @@ pub enum Eval {
     Mate(i32),
 }
 
+impl Eval {
+    /// Orders evaluations from the point of view of the side to move, with
+    /// the fastest mate first and the slowest mate against last.
+    pub fn sort_key(&self) -> i64 {
+        match *self {
+            Eval::Mate(n) if n > 0 => i64::MAX - i64::from(n),
+            Eval::Mate(n) => i64::MIN - i64::from(n),
+            Eval::Cp(cp) => cp,
+        }
+    }
+}
+
 impl fmt::Display for Eval {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -298,9 +492,19 @@ impl fmt::Display for Eval {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Score {
-    eval: Eval,
-    lowerbound: bool,
-    upperbound: bool,
+    pub eval: Eval,
+    pub lowerbound: bool,
+    pub upperbound: bool,
+}
+
+impl Score {
+    pub fn new(eval: Eval) -> Score {
+        Score {
+            eval,
+            lowerbound: false,
+            upperbound: false,
+        }
+    }
 }
 
 impl fmt::Display for Score {
@@ -316,6 +520,56 @@ impl fmt::Display for Score {
     }
 }
 
+/// Win, draw and loss probabilities in permille, from the point of view of
+/// the side to move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wdl {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.wins, self.draws, self.losses)
+    }
+}
+
+/// Effective branching factor of a search, in thousandths.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ebf(pub u32);
+
+impl FromStr for Ebf {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Ebf, ProtocolError> {
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(ProtocolError::UnexpectedToken);
+        }
+        // Further decimal places are not significant.
+        let frac: u32 = format!("{:0<3}", &frac[..min(frac.len(), 3)]).parse()?;
+        int.parse::<u32>()?
+            .checked_mul(1000)
+            .and_then(|int| int.checked_add(frac))
+            .map(Ebf)
+            .ok_or(ProtocolError::UnexpectedToken)
+    }
+}
+
+impl fmt::Display for Ebf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 % 1000 {
+            0 => write!(f, "{}", self.0 / 1000),
+            frac => {
+                let frac = format!("{frac:03}");
+                write!(f, "{}.{}", self.0 / 1000, frac.trim_end_matches('0'))
+            }
+        }
+    }
+}
+
+/// A message from the engine to the GUI.
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UciOut {
@@ -334,6 +588,7 @@ pub enum UciOut {
         time: Option<Duration>,
         nodes: Option<u64>,
         score: Option<Score>,
+        wdl: Option<Wdl>,
         currmove: Option<Uci>,
         currmovenumber: Option<u32>,
         hashfull: Option<u32>,
@@ -341,6 +596,7 @@ pub enum UciOut {
         tbhits: Option<u64>,
         sbhits: Option<u64>,
         cpuload: Option<u32>,
+        ebf: Option<Ebf>,
         refutation: HashMap<Uci, Vec<Uci>>,
         currline: HashMap<u32, Vec<Uci>>,
         pv: Option<Vec<Uci>>,
@@ -353,9 +609,80 @@ pub enum UciOut {
 }
 
 impl UciOut {
+    /// Parses a single line. Returns `None` for blank lines and unknown
+    /// messages, which engines are allowed to print.
     pub fn from_line(s: &str) -> Result<Option<UciOut>, ProtocolError> {
         Parser::new(s)?.parse_out()
     }
+
+    pub fn info_string(string: String) -> UciOut {
+        UciOut::Info {
+            multipv: None,
+            depth: None,
+            seldepth: None,
+            time: None,
+            nodes: None,
+            score: None,
+            wdl: None,
+            currmove: None,
+            currmovenumber: None,
+            hashfull: None,
+            nps: None,
+            tbhits: None,
+            sbhits: None,
+            cpuload: None,
+            ebf: None,
+            refutation: HashMap::new(),
+            currline: HashMap::new(),
+            pv: None,
+            string: Some(string),
+        }
+    }
+
+    /// Cuts the principal variation of an `info` line at the first move
+    /// that is illegal in the analysed position. Returns whether moves were
+    /// removed.
+    pub fn truncate_pv(&mut self, pos: &VariantPosition) -> bool {
+        let pv = match self {
+            UciOut::Info { pv: Some(pv), .. } => pv,
+            _ => return false,
+        };
+        let mut pos = pos.clone();
+        let legal = pv
+            .iter()
+            .take_while(|uci| match uci.to_move(&pos) {
+                Ok(m) => {
+                    pos.play_unchecked(&m);
+                    true
+                }
+                Err(_) => false,
+            })
+            .count();
+        if legal == pv.len() {
+            return false;
+        }
+        pv.truncate(legal);
+        if pv.is_empty() {
+            if let UciOut::Info { pv, .. } = self {
+                *pv = None;
+            }
+        }
+        true
+    }
+}
+
+impl FromStr for UciOut {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<UciOut, ProtocolError> {
+        match UciOut::from_line(s)? {
+            Some(out) => Ok(out),
+            None if s.trim_matches(is_separator).is_empty() => {
+                Err(ProtocolError::UnexpectedEndOfLine)
+            }
+            None => Err(ProtocolError::UnexpectedToken),
+        }
+    }
 }
 
 impl fmt::Display for UciOut {
@@ -382,6 +709,7 @@ impl fmt::Display for UciOut {
                 time,
                 nodes,
                 score,
+                wdl,
                 currmove,
                 currmovenumber,
                 hashfull,
@@ -389,6 +717,7 @@ impl fmt::Display for UciOut {
                 tbhits,
                 sbhits,
                 cpuload,
+                ebf,
                 refutation,
                 currline,
                 pv,
@@ -413,6 +742,9 @@ impl fmt::Display for UciOut {
                 if let Some(score) = score {
                     write!(f, " score {score}")?;
                 }
+                if let Some(wdl) = wdl {
+                    write!(f, " wdl {wdl}")?;
+                }
                 if let Some(currmove) = currmove {
                     write!(f, " currmove {currmove}")?;
                 }
@@ -434,6 +766,9 @@ impl fmt::Display for UciOut {
                 if let Some(cpuload) = cpuload {
                     write!(f, " cpuload {cpuload}")?;
                 }
+                if let Some(ebf) = ebf {
+                    write!(f, " ebf {ebf}")?;
+                }
                 for (refuted, refuted_by) in refutation {
                     write!(f, " refutation {refuted}")?;
                     for m in refuted_by {
@@ -468,6 +803,10 @@ pub enum ProtocolError {
     UnexpectedToken,
     #[error("unexpected line break in uci command")]
     UnexpectedLineBreak,
+    #[error("unexpected control character in uci command")]
+    UnexpectedControlCharacter,
+    #[error("token longer than {MAX_TOKEN_LEN} bytes")]
+    TokenTooLong,
     #[error("expected end of line")]
     ExpectedEndOfLine,
     #[error("unexpected end of line")]
@@ -480,8 +819,15 @@ pub enum ProtocolError {
     InvalidInteger(#[from] ParseIntError),
     #[error("invalid option value")]
     InvalidOptionValue,
+    #[error("{0}")]
+    IllegalPosition(String),
+    #[error("illegal move {uci} at ply {ply}")]
+    IllegalMove { uci: Uci, ply: usize },
 }
 
+/// Longest token accepted in commands, enough for paths in string options.
+const MAX_TOKEN_LEN: usize = 1024;
+
 struct Parser<'a> {
     s: &'a str,
 }
@@ -518,6 +864,21 @@ impl<'a> Parser<'a> {
         head
     }
 
+    /// Rejects input that no engine should have to deal with.
+    fn check_tokens(&self) -> Result<(), ProtocolError> {
+        if self.s.chars().any(|c| c.is_control() && !is_separator(c)) {
+            return Err(ProtocolError::UnexpectedControlCharacter);
+        }
+        if self
+            .s
+            .split(is_separator)
+            .any(|token| token.len() > MAX_TOKEN_LEN)
+        {
+            return Err(ProtocolError::TokenTooLong);
+        }
+        Ok(())
+    }
+
     fn end(&self) -> Result<(), ProtocolError> {
         match self.peek() {
             Some(_) => Err(ProtocolError::ExpectedEndOfLine),
@@ -704,7 +1065,14 @@ impl<'a> Parser<'a> {
             None => return Err(ProtocolError::UnexpectedEndOfLine),
         };
         self.next(); // type
-        let option = match self.next() {
+        Ok(UciOut::Option {
+            name,
+            option: self.parse_option_type()?,
+        })
+    }
+
+    fn parse_option_type(&mut self) -> Result<UciOption, ProtocolError> {
+        Ok(match self.next() {
             Some("check") => UciOption::Check {
                 default: match self.next() {
                     Some("default") => match self.next() {
@@ -794,8 +1162,7 @@ impl<'a> Parser<'a> {
             },
             Some(_) => return Err(ProtocolError::UnexpectedToken),
             None => return Err(ProtocolError::UnexpectedEndOfLine),
-        };
-        Ok(UciOut::Option { name, option })
+        })
     }
 
     fn parse_bestmove(&mut self) -> Result<UciOut, ProtocolError> {
@@ -852,6 +1219,20 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_wdl(&mut self) -> Result<Wdl, ProtocolError> {
+        let mut permille = || -> Result<u32, ProtocolError> {
+            Ok(self
+                .next()
+                .ok_or(ProtocolError::UnexpectedEndOfLine)?
+                .parse()?)
+        };
+        Ok(Wdl {
+            wins: permille()?,
+            draws: permille()?,
+            losses: permille()?,
+        })
+    }
+
     fn parse_info(&mut self) -> Result<UciOut, ProtocolError> {
         let mut multipv = None;
         let mut depth = None;
@@ -859,6 +1240,7 @@ impl<'a> Parser<'a> {
         let mut time = None;
         let mut nodes = None;
         let mut score = None;
+        let mut wdl = None;
         let mut currmove = None;
         let mut currmovenumber = None;
         let mut hashfull = None;
@@ -866,6 +1248,7 @@ impl<'a> Parser<'a> {
         let mut tbhits = None;
         let mut sbhits = None;
         let mut cpuload = None;
+        let mut ebf = None;
         let mut refutation = HashMap::new();
         let mut currline = HashMap::new();
         let mut pv = None;
@@ -908,6 +1291,7 @@ impl<'a> Parser<'a> {
                     )
                 }
                 Some("score") => score = Some(self.parse_score()?),
+                Some("wdl") => wdl = Some(self.parse_wdl()?),
                 Some("currmove") => {
                     currmove = Some(
                         self.next()
@@ -965,13 +1349,24 @@ impl<'a> Parser<'a> {
                         self.parse_moves(),
                     );
                 }
-                Some("currline") => {
-                    currline.insert(
+                Some("ebf") => {
+                    ebf = Some(
                         self.next()
                             .ok_or(ProtocolError::UnexpectedEndOfLine)?
                             .parse()?,
-                        self.parse_moves(),
-                    );
+                    )
+                }
+                Some("currline") => {
+                    // The CPU number may be omitted by engines that use only
+                    // one.
+                    let cpunr = match self.peek().and_then(|token| token.parse().ok()) {
+                        Some(cpunr) => {
+                            self.next();
+                            cpunr
+                        }
+                        None => 1,
+                    };
+                    currline.insert(cpunr, self.parse_moves());
                 }
                 Some("pv") => pv = Some(self.parse_moves()),
                 Some("string") => {
@@ -988,6 +1383,7 @@ impl<'a> Parser<'a> {
             time,
             nodes,
             score,
+            wdl,
             currmove,
             currmovenumber,
             hashfull,
@@ -995,6 +1391,7 @@ impl<'a> Parser<'a> {
             tbhits,
             sbhits,
             cpuload,
+            ebf,
             refutation,
             currline,
             pv,
@@ -1032,6 +1429,15 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Decodes a line of engine output, including its line ending. Bytes that
+/// are not valid UTF-8 (like author names in Latin-1) are replaced, and
+/// stray carriage returns are treated as separators.
+pub fn decode_line(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\r', '\n'])
+        .replace('\r', " ")
+}
+
 fn is_separator(c: char) -> bool {
     c == ' ' || c == '\t'
 }
@@ -1140,4 +1546,422 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_in_roundtrip() -> Result<(), ProtocolError> {
+        for line in [
+            "uci",
+            "isready",
+            "setoption name Skill Level value 10",
+            "setoption name Clear Hash",
+            "ucinewgame",
+            "position startpos",
+            "position startpos moves e2e4 e7e5",
+            "position fen 8/8/8/8/8/8/8/K1k5 w - - 0 1 moves a1a2",
+            "go searchmoves e2e4 d2d4 ponder wtime 1000 btime 2000 winc 10 binc 20 movestogo 30 depth 5 nodes 100 mate 3 movetime 500 infinite",
+            "go",
+            "stop",
+            "ponderhit",
+        ] {
+            assert_eq!(line.parse::<UciIn>()?.to_string(), line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_option_policy() {
+        let policy = OptionPolicy {
+            allow: vec![UciOptionName("Skill Level".to_owned())],
+            deny: vec![UciOptionName("multipv".to_owned())],
+        };
+        assert!(policy.is_allowed(&UciOptionName("Hash".to_owned())));
+        assert!(policy.is_allowed(&UciOptionName("skill level".to_owned())));
+        assert!(!policy.is_allowed(&UciOptionName("MultiPV".to_owned())));
+        assert!(!policy.is_allowed(&UciOptionName("Debug Log File".to_owned())));
+    }
+
+    #[test]
+    fn test_limit_search() -> Result<(), ProtocolError> {
+        let limits = SearchLimits {
+            depth: Some(20),
+            nodes: None,
+            movetime: Some(Duration::from_secs(5)),
+        };
+        for (line, limited) in [
+            ("go infinite", "go depth 20 movetime 5000"),
+            ("go depth 30 movetime 1000", "go depth 20 movetime 1000"),
+            (
+                "go wtime 1000 btime 1000",
+                "go wtime 1000 btime 1000 depth 20 movetime 5000",
+            ),
+        ] {
+            let mut command = line.parse::<UciIn>()?;
+            assert!(command.limit_search(&limits));
+            assert_eq!(command.to_string(), limited);
+        }
+
+        let mut command = "go depth 10 movetime 100".parse::<UciIn>()?;
+        assert!(!command.limit_search(&limits));
+        assert!(!"go infinite"
+            .parse::<UciIn>()?
+            .limit_search(&SearchLimits::default()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_roundtrip() -> Result<(), ProtocolError> {
+        for line in [
+            "id name Stockfish 15",
+            "id author the Stockfish developers",
+            "uciok",
+            "readyok",
+            "bestmove e2e4 ponder e7e5",
+            "bestmove e2e4",
+            "bestmove (none)",
+            "info multipv 2 depth 20 seldepth 28 time 1234 nodes 5678 score cp -17 upperbound wdl 40 900 60 hashfull 12 nps 4600 tbhits 3 pv e2e4 e7e5 g1f3",
+            "info depth 20 currmove e2e4 currmovenumber 1",
+            "info score mate -3 lowerbound",
+            "info sbhits 1 cpuload 950",
+            "info string NNUE evaluation enabled",
+            "option name Hash type spin default 16 min 1 max 33554432",
+            "option name Ponder type check default false",
+            "option name UCI_Variant type combo default chess var chess var atomic",
+            "option name Clear Hash type button",
+            "option name SyzygyPath type string default <empty>",
+        ] {
+            assert_eq!(line.parse::<UciOut>()?.to_string(), line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_info() -> Result<(), ProtocolError> {
+        let info = "info depth 24 seldepth 32 multipv 1 score cp 35 wdl 120 850 30 nodes 1000 nps 2000 hashfull 77 tbhits 0 time 500 pv e2e4 e7e5 currmove g1f3 string hello world".parse()?;
+        match info {
+            UciOut::Info {
+                multipv,
+                depth,
+                seldepth,
+                time,
+                nodes,
+                score,
+                wdl,
+                currmove,
+                hashfull,
+                nps,
+                tbhits,
+                pv,
+                string,
+                ..
+            } => {
+                assert_eq!(multipv, NonZeroU32::new(1));
+                assert_eq!(depth, Some(24));
+                assert_eq!(seldepth, Some(32));
+                assert_eq!(time, Some(Duration::from_millis(500)));
+                assert_eq!(nodes, Some(1000));
+                assert_eq!(score, Some(Score::new(Eval::Cp(35))));
+                assert_eq!(
+                    wdl,
+                    Some(Wdl {
+                        wins: 120,
+                        draws: 850,
+                        losses: 30
+                    })
+                );
+                assert_eq!(currmove, Some("g1f3".parse()?));
+                assert_eq!(hashfull, Some(77));
+                assert_eq!(nps, Some(2000));
+                assert_eq!(tbhits, Some(0));
+                assert_eq!(pv, Some(vec!["e2e4".parse()?, "e7e5".parse()?]));
+                assert_eq!(string, Some("hello world".to_owned()));
+            }
+            _ => panic!("expected info"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_info_roundtrip() -> Result<(), ProtocolError> {
+        for line in [
+            "info depth 20 score cp 15 lowerbound",
+            "info depth 20 score mate -3 upperbound",
+            "info nodes 123456 hashfull 500 cpuload 975 ebf 1.85",
+            "info refutation d1h5 g6h5",
+            "info refutation d1h5",
+            "info currline 2 e2e4 e7e5",
+            "info currmove e2e4 currmovenumber 1 sbhits 3",
+            "info string ",
+            "info depth 5 pv e2e4 string pv e2e4",
+        ] {
+            let info: UciOut = line.parse()?;
+            assert_eq!(info.to_string(), line);
+            assert_eq!(info.to_string().parse::<UciOut>()?, info);
+        }
+
+        match "info currline e2e4 ebf 2.5000".parse()? {
+            UciOut::Info { currline, ebf, .. } => {
+                assert_eq!(currline.get(&1), Some(&vec!["e2e4".parse()?]));
+                assert_eq!(ebf, Some(Ebf(2500)));
+                assert_eq!(Ebf(2500).to_string(), "2.5");
+                assert_eq!(Ebf(3000).to_string(), "3");
+                assert_eq!(Ebf(1005).to_string(), "1.005");
+            }
+            _ => panic!("expected info"),
+        }
+        assert!("info ebf .5".parse::<UciOut>().is_err());
+        assert!("info ebf 1.-5".parse::<UciOut>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reparse() -> Result<(), ProtocolError> {
+        for line in [
+            "setoption name Skill Level value 10",
+            "setoption name SyzygyPath value /home/user/My Tablebases",
+            "setoption name Clear Hash",
+            "position startpos moves e2e4 e7e5",
+            "position fen rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "go searchmoves e2e4 d2d4 ponder wtime 1000 btime 2000 winc 10 binc 20 movestogo 30 depth 5 nodes 100 mate 3 movetime 500 infinite",
+            "go wtime 300000 btime 300000 winc 2000 binc 2000",
+            "go movetime 1000",
+            "go nodes 1000000",
+            "go mate 5",
+            "go infinite",
+        ] {
+            let command: UciIn = line.parse()?;
+            assert_eq!(command.to_string().parse::<UciIn>()?, command);
+        }
+        for line in [
+            "id name Stockfish 15.1 NNUE",
+            "option name Skill Level type spin default 20 min 0 max 20",
+            "option name Debug Log File type string default <empty>",
+            "option name UCI_Variant type combo default chess var chess var king of the hill",
+            "info depth 20 currline 1 e2e4 e7e5 currline 2 d2d4 ebf 1.85",
+            "info currline e2e4 ebf 2",
+            "info refutation d1h5 g6h5 string best line d1h5",
+            "bestmove e2e4 ponder e7e5",
+        ] {
+            let out: UciOut = line.parse()?;
+            assert_eq!(out.to_string().parse::<UciOut>()?, out);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_pathological_output() -> Result<(), ProtocolError> {
+        // Overlong lines and tokens from the engine are kept. Only commands
+        // of clients are limited.
+        let pv = vec!["e2e4 e7e5 g1f3 b8c6 f1b5 g8f6 f3g1 f6g8"; 500].join(" ");
+        match UciOut::from_line(&format!("info depth 1 pv {pv}"))? {
+            Some(UciOut::Info { pv: Some(pv), .. }) => assert_eq!(pv.len(), 4000),
+            other => panic!("expected info with pv, got {other:?}"),
+        }
+        let long = "x".repeat(MAX_TOKEN_LEN * 10);
+        assert_eq!(
+            UciOut::from_line(&format!("info string {long}"))?,
+            Some(UciOut::info_string(long))
+        );
+
+        // Invalid UTF-8 is replaced.
+        assert_eq!(
+            UciOut::from_line(&decode_line(b"id name Sch\xe4ferhund 1.0\n"))?,
+            Some(UciOut::IdName("Sch\u{fffd}ferhund 1.0".to_owned()))
+        );
+        match UciOut::from_line(&decode_line(b"info depth 3 string \xc3\r\n"))? {
+            Some(UciOut::Info { depth, string, .. }) => {
+                assert_eq!(depth, Some(3));
+                assert_eq!(string.as_deref(), Some("\u{fffd}"));
+            }
+            other => panic!("expected info, got {other:?}"),
+        }
+
+        // Missing tokens after score.
+        for line in [
+            "info score",
+            "info depth 10 score",
+            "info score cp",
+            "info score mate",
+            "info score cp lowerbound",
+        ] {
+            assert!(UciOut::from_line(line).is_err(), "{line}");
+        }
+        assert!(UciOut::from_line("info score bogus 1").is_err());
+
+        // Carriage returns anywhere in the line.
+        assert_eq!(decode_line(b"readyok\r\r\n"), "readyok");
+        assert_eq!(
+            UciOut::from_line(&decode_line(b"bestmove\re2e4\r\n"))?,
+            Some(UciOut::Bestmove {
+                m: Some("e2e4".parse()?),
+                ponder: None,
+            })
+        );
+        assert_eq!(
+            UciOut::from_line(&decode_line(b"info depth 5\r score cp 20 pv e2e4\r\n"))?,
+            UciOut::from_line("info depth 5 score cp 20 pv e2e4")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_option_types() -> Result<(), ProtocolError> {
+        assert_eq!(
+            "type check default true".parse::<UciOption>()?,
+            UciOption::Check { default: true }
+        );
+        assert_eq!(
+            "type spin default 1 min 1 max 512".parse::<UciOption>()?,
+            UciOption::Spin {
+                default: 1,
+                min: 1,
+                max: 512
+            }
+        );
+        assert_eq!(
+            "type combo default Normal var Solid var Normal".parse::<UciOption>()?,
+            UciOption::Combo {
+                default: "Normal".to_owned(),
+                var: vec!["Solid".to_owned(), "Normal".to_owned()]
+            }
+        );
+        assert_eq!(
+            "type string default".parse::<UciOption>()?,
+            UciOption::String {
+                default: "".to_owned()
+            }
+        );
+        assert_eq!("type button".parse::<UciOption>()?, UciOption::Button);
+        assert!("type spin default 1".parse::<UciOption>().is_err());
+        assert!("type slider".parse::<UciOption>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_option_values() -> Result<(), ProtocolError> {
+        let spin: UciOption = "type spin default 1 min 1 max 512".parse()?;
+        assert_eq!(
+            spin.validate(Some("64".to_owned()))?,
+            UciOptionValue::Spin(64)
+        );
+        assert!(spin.validate(Some("1024".to_owned())).is_err());
+        assert!(spin.validate(None).is_err());
+
+        let check: UciOption = "type check default false".parse()?;
+        assert_eq!(check.validate(Some("true".to_owned()))?.to_string(), "true");
+        assert!(check.validate(Some("yes".to_owned())).is_err());
+        assert_eq!(
+            check.validate(Some("False".to_owned()))?.to_string(),
+            "false"
+        );
+
+        let combo: UciOption = "type combo default Both var Both var Off var White".parse()?;
+        assert_eq!(
+            combo.validate(Some("white".to_owned()))?,
+            UciOptionValue::Combo("White".to_owned())
+        );
+        assert!(combo.validate(Some("Black".to_owned())).is_err());
+        assert!(combo.validate(None).is_err());
+
+        let button: UciOption = "type button".parse()?;
+        assert_eq!(button.validate(None)?, UciOptionValue::Button);
+        assert!(button.validate(Some("x".to_owned())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_line() -> Result<(), ProtocolError> {
+        assert_eq!(decode_line(b"uciok\r\n"), "uciok");
+        assert_eq!(decode_line(b"readyok\r\r\n"), "readyok");
+        assert_eq!(decode_line(b"bestmove e2e4"), "bestmove e2e4");
+        assert_eq!(
+            UciOut::from_line(&decode_line(b"id author J\xf6rg M\xfcller\r\n"))?,
+            Some(UciOut::IdAuthor("J\u{fffd}rg M\u{fffd}ller".to_owned()))
+        );
+        assert_eq!(
+            UciOut::from_line(&decode_line(b"info string a\rb\xff\n"))?,
+            Some(UciOut::info_string("a b\u{fffd}".to_owned()))
+        );
+        assert_eq!(UciOut::from_line(&decode_line(b"\xfe\xff\r\n"))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            "".parse::<UciIn>(),
+            Err(ProtocolError::UnexpectedEndOfLine)
+        ));
+        assert!(matches!(
+            "quit now".parse::<UciIn>(),
+            Err(ProtocolError::UnexpectedToken)
+        ));
+        assert!(matches!(
+            "uci\nisready".parse::<UciIn>(),
+            Err(ProtocolError::UnexpectedLineBreak)
+        ));
+        assert!(matches!(
+            "setoption name Foo value a\0b".parse::<UciIn>(),
+            Err(ProtocolError::UnexpectedControlCharacter)
+        ));
+        assert!(matches!(
+            format!("setoption name Foo value {}", "x".repeat(MAX_TOKEN_LEN + 1)).parse::<UciIn>(),
+            Err(ProtocolError::TokenTooLong)
+        ));
+        assert!("setoption name Foo value\ta b".parse::<UciIn>().is_ok());
+        assert!(matches!(
+            "go depth deep".parse::<UciIn>(),
+            Err(ProtocolError::InvalidInteger(_))
+        ));
+        assert!(matches!(
+            "   ".parse::<UciOut>(),
+            Err(ProtocolError::UnexpectedEndOfLine)
+        ));
+        assert!(matches!(
+            "Stockfish 15 by the Stockfish developers".parse::<UciOut>(),
+            Err(ProtocolError::UnexpectedToken)
+        ));
+        assert!(matches!(UciOut::from_line("Stockfish 15"), Ok(None)));
+        assert!(matches!(
+            "info wdl 1 2".parse::<UciOut>(),
+            Err(ProtocolError::UnexpectedEndOfLine)
+        ));
+    }
+
+    #[test]
+    fn test_validate_position() -> Result<(), ProtocolError> {
+        let legal: UciIn = "position startpos moves e2e4 e7e5 g1f3".parse()?;
+        assert!(legal
+            .to_position(Variant::Chess, CastlingMode::Standard)
+            .is_ok());
+
+        let illegal: UciIn = "position startpos moves e2e4 e2e4".parse()?;
+        assert!(matches!(
+            illegal.to_position(Variant::Chess, CastlingMode::Standard),
+            Err(ProtocolError::IllegalMove { ply: 2, .. })
+        ));
+
+        let no_king: UciIn = "position fen 8/8/8/8/8/8/8/K7 w - - 0 1".parse()?;
+        assert!(matches!(
+            no_king.to_position(Variant::Chess, CastlingMode::Standard),
+            Err(ProtocolError::IllegalPosition(_))
+        ));
+
+        let drop: UciIn =
+            "position fen rnbqkbnr/ppp1pppp/8/8/8/8/PPPPPPPP/RNBQKBNR[Pp] w KQkq - 0 1 moves P@e4"
+                .parse()?;
+        assert!(drop
+            .to_position(Variant::Crazyhouse, CastlingMode::Standard)
+            .is_ok());
+        assert!(drop
+            .to_position(Variant::Chess, CastlingMode::Standard)
+            .is_err());
+
+        let pos = legal
+            .to_position(Variant::Chess, CastlingMode::Standard)?
+            .expect("position");
+        let mut info: UciOut = "info depth 3 pv b8c6 f1b5 a1a8 a7a6".parse()?;
+        assert!(info.truncate_pv(&pos));
+        assert_eq!(info.to_string(), "info depth 3 pv b8c6 f1b5");
+        Ok(())
+    }
 }
@@ -0,0 +1,56 @@
+use std::{
+    error::Error,
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::{tokens::parse_secret_file, Opts};
+
+#[derive(Serialize)]
+struct Upgrade<'a> {
+    path: &'a Path,
+}
+
+/// Asks the running server to switch to another engine executable, using
+/// the admin API.
+pub async fn request(opts: Opts, path: &Path) -> Result<(), Box<dyn Error>> {
+    let opts = opts.with_config_file()?;
+    let secret_file = opts
+        .admin_secret_file
+        .ok_or("admin API not enabled (use --admin-secret-file)")?;
+    let (secret, _) = parse_secret_file(&fs::read_to_string(&secret_file)?)
+        .map_err(|err| format!("Invalid {secret_file:?}: {err}"))?;
+    let host = match opts.bind.first() {
+        Some(addr) if addr.ip().is_unspecified() => {
+            let ip = if addr.is_ipv4() {
+                Ipv4Addr::LOCALHOST.into()
+            } else {
+                Ipv6Addr::LOCALHOST.into()
+            };
+            SocketAddr::new(ip, addr.port()).to_string()
+        }
+        Some(addr) => addr.to_string(),
+        None => "localhost:9670".to_owned(),
+    };
+    let path = fs::canonicalize(path)?;
+    let res = reqwest::Client::new()
+        .post(format!("http://{host}/admin/upgrade-engine"))
+        .bearer_auth(&secret.0)
+        .json(&Upgrade { path: &path })
+        .send()
+        .await?;
+    match res.status() {
+        StatusCode::NO_CONTENT => {
+            println!("Upgraded to {path:?}. Engines switch when idle.");
+            Ok(())
+        }
+        StatusCode::UNPROCESSABLE_ENTITY => {
+            Err(format!("{path:?} failed the checks, see the server log").into())
+        }
+        status => Err(format!("server responded with {status}").into()),
+    }
+}
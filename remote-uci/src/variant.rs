@@ -0,0 +1,39 @@
+/// Variants supported by lichess, with other names that engines use for
+/// the same variant in `UCI_Variant`.
+const ALIASES: [(&str, &[&str]); 8] = [
+    ("chess", &["standard", "normal"]),
+    ("crazyhouse", &["zh"]),
+    ("antichess", &["giveaway"]),
+    ("atomic", &[]),
+    ("horde", &[]),
+    ("kingofthehill", &["koth", "king-of-the-hill"]),
+    ("racingkings", &["racing-kings"]),
+    ("3check", &["threecheck", "three-check"]),
+];
+
+/// Lichess name of a variant, or the name itself if lichess does not know
+/// the variant.
+pub fn lichess_name(name: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(lichess, aliases)| {
+            lichess.eq_ignore_ascii_case(name)
+                || aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+        })
+        .map_or(name, |(lichess, _)| lichess)
+}
+
+/// Whether two names refer to the same variant.
+pub fn same(a: &str, b: &str) -> bool {
+    lichess_name(a).eq_ignore_ascii_case(lichess_name(b))
+}
+
+/// The `UCI_Variant` value of the engine for a variant requested by a
+/// client, which may use the lichess name.
+pub fn engine_name<'a>(name: &str, available: &'a [String]) -> Option<&'a str> {
+    available
+        .iter()
+        .find(|variant| *variant == name)
+        .or_else(|| available.iter().find(|variant| same(variant, name)))
+        .map(String::as_str)
+}
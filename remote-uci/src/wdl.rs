@@ -0,0 +1,156 @@
+use std::cmp::min;
+
+use shakmaty::{fen::Fen, Color};
+
+use crate::uci::{Eval, Wdl};
+
+/// Coefficients of the win rate model of Stockfish 15.1, fitted to games
+/// from fishtest. They transform the evaluation into the parameters of a
+/// logistic function, depending on the game ply.
+const AS: [f64; 4] = [0.38036525, -2.82015070, 23.17882135, 307.36768407];
+const BS: [f64; 4] = [-2.29434733, 13.27689788, -14.26828904, 63.45318330];
+
+/// Internal evaluation units of Stockfish that correspond to a pawn, which
+/// is a 50% win rate at ply 64.
+const NORMALIZE_TO_PAWN_VALUE: f64 = 328.0;
+
+/// Centipawns that Stockfish releases reported for a 50% win rate at ply
+/// 64, before scores were normalized to 100 centipawns in Stockfish 15.1.
+const PAWN_VALUES: [(&str, f64); 5] = [
+    ("12", 114.13),
+    ("13", 114.13),
+    ("14", 115.39),
+    ("14.1", 115.39),
+    ("15", 115.39),
+];
+
+/// Win rate in per mille.
+fn win_rate(cp: i64, ply: u32) -> u32 {
+    // The model only captures up to 240 plies.
+    let m = f64::from(min(ply, 240)) / 64.0;
+    let a = ((AS[0] * m + AS[1]) * m + AS[2]) * m + AS[3];
+    let b = ((BS[0] * m + BS[1]) * m + BS[2]) * m + BS[3];
+    let x = (cp as f64 * NORMALIZE_TO_PAWN_VALUE / 100.0).clamp(-4000.0, 4000.0);
+    (0.5 + 1000.0 / (1.0 + ((a - x) / b).exp())) as u32
+}
+
+/// Estimates win/draw/loss statistics in per mille, for engines that do
+/// not report them.
+pub fn estimate(eval: &Eval, ply: u32) -> Wdl {
+    match *eval {
+        Eval::Cp(cp) => {
+            let wins = win_rate(cp, ply);
+            let losses = win_rate(-cp, ply);
+            Wdl {
+                wins,
+                draws: 1000u32.saturating_sub(wins + losses),
+                losses,
+            }
+        }
+        Eval::Mate(mate) if mate > 0 => Wdl {
+            wins: 1000,
+            draws: 0,
+            losses: 0,
+        },
+        Eval::Mate(_) => Wdl {
+            wins: 0,
+            draws: 0,
+            losses: 1000,
+        },
+    }
+}
+
+/// Centipawns that correspond to a 50% win rate at ply 64, for engine
+/// versions that do not report normalized scores.
+pub fn pawn_value(engine_name: &str) -> Option<f64> {
+    let version = engine_name
+        .strip_prefix("Stockfish ")?
+        .split_whitespace()
+        .next()?;
+    PAWN_VALUES
+        .iter()
+        .find(|(release, _)| *release == version)
+        .map(|(_, pawn_value)| *pawn_value)
+}
+
+/// Rescales a centipawn score, so that 100 centipawns are a 50% win rate
+/// at ply 64.
+pub fn normalize(eval: &mut Eval, pawn_value: f64) {
+    if let Eval::Cp(ref mut cp) = eval {
+        *cp = (*cp as f64 * 100.0 / pawn_value).round() as i64;
+    }
+}
+
+/// Number of half-moves that were played in the game up to a position.
+pub fn game_ply(fen: Option<&Fen>, moves: usize) -> u32 {
+    let (fullmoves, turn) =
+        fen.map_or((1, Color::White), |fen| (fen.0.fullmoves.get(), fen.0.turn));
+    (fullmoves - 1) * 2 + turn.fold_wb(0, 1) + u32::try_from(moves).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        for ply in [0, 20, 64, 120, 240, 1000] {
+            for cp in [
+                -100_000, -2000, -350, -100, -35, -1, 0, 1, 35, 100, 350, 2000, 100_000,
+            ] {
+                let wdl = estimate(&Eval::Cp(cp), ply);
+                assert_eq!(wdl.wins + wdl.draws + wdl.losses, 1000, "{cp} at {ply}");
+                let mirrored = estimate(&Eval::Cp(-cp), ply);
+                assert_eq!(
+                    (wdl.wins, wdl.draws, wdl.losses),
+                    (mirrored.losses, mirrored.draws, mirrored.wins),
+                    "{cp} at {ply}"
+                );
+            }
+        }
+        let even = estimate(&Eval::Cp(0), 64);
+        assert_eq!(even.wins, even.losses);
+        assert!(even.draws > 900);
+        assert!(estimate(&Eval::Cp(350), 64).wins > estimate(&Eval::Cp(100), 64).wins);
+        assert!(estimate(&Eval::Cp(100_000), 64).wins >= 999);
+    }
+
+    #[test]
+    fn test_pawn_value() {
+        // Normalized scores are a 50% win rate at a pawn.
+        assert_eq!(win_rate(100, 64), 500);
+
+        let mut eval = Eval::Cp(231);
+        normalize(
+            &mut eval,
+            pawn_value("Stockfish 15 by the Stockfish developers").expect("15"),
+        );
+        assert_eq!(eval, Eval::Cp(200));
+        let mut mate = Eval::Mate(3);
+        normalize(&mut mate, 115.39);
+        assert_eq!(mate, Eval::Mate(3));
+        assert_eq!(pawn_value("Stockfish 16"), None);
+        assert_eq!(pawn_value("Komodo 14"), None);
+    }
+
+    #[test]
+    fn test_mate() {
+        for (mate, wdl) in [
+            (1, (1000, 0, 0)),
+            (12, (1000, 0, 0)),
+            (-1, (0, 0, 1000)),
+            (-7, (0, 0, 1000)),
+        ] {
+            let estimate = estimate(&Eval::Mate(mate), 40);
+            assert_eq!((estimate.wins, estimate.draws, estimate.losses), wdl);
+        }
+    }
+
+    #[test]
+    fn test_game_ply() {
+        assert_eq!(game_ply(None, 0), 0);
+        assert_eq!(game_ply(None, 3), 3);
+        let fen: Fen = "4k3/8/8/8/8/8/8/4K3 b - - 0 20".parse().expect("fen");
+        assert_eq!(game_ply(Some(&fen), 1), 40);
+    }
+}
@@ -1,49 +1,689 @@
 use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap, VecDeque},
+    ffi::OsString,
+    fmt, fs,
+    future::{self, Future},
     io,
     iter::zip,
+    mem,
+    net::IpAddr,
+    num::NonZeroU32,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    pin::Pin,
+    ptr,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use axum::{
-    extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Query,
-    },
-    http::StatusCode,
-    response::IntoResponse,
+    body::{self, Body, Empty},
+    extract::Query,
+    http::{header, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use futures_util::{
+    future::select_all,
+    stream::{SplitSink, SplitStream},
+    SinkExt as _, StreamExt as _,
 };
+use hyper::upgrade::Upgraded;
 use rand::random;
 use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::{Mutex, MutexGuard, Notify},
-    time::{interval, MissedTickBehavior},
+    sync::{broadcast, futures::Notified, mpsc, oneshot, Mutex, MutexGuard, Notify},
+    time::{interval, sleep, sleep_until, MissedTickBehavior, Sleep},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    WebSocketStream,
 };
+use tracing::{field, Instrument as _};
 
 use crate::{
-    engine::{Engine, Session},
+    bench,
+    deflate::{DeflateConfig, DeflateStream},
+    engine::{
+        AnnouncedOption, Engine, EngineAddr, EngineParameters, EngineStatus, Observation,
+        Reconfiguration, Session,
+    },
+    ipfilter::IpFilter,
+    keyring,
+    metrics::EngineMetrics,
+    origin::OriginPolicy,
+    presence,
+    profile::Profile,
+    schedule::Schedule,
+    shutdown::{SessionGuard, Shutdown},
+    throttle::SecretThrottle,
+    tokens::{format_secret_file, parse_secret_file, Grant, NamedSecret, Permissions},
+    transcript::Transcript,
     uci::{UciIn, UciOut},
+    variant,
 };
 
-pub struct SharedEngine {
-    session: AtomicU64,
+/// What to do when a client wants to use the engine while another session
+/// is active.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionPolicy {
+    /// Stop the active session and take over the engine.
+    #[default]
+    Preempt,
+    /// Wait until the active session ends.
+    Queue,
+    /// Refuse the new session.
+    Reject,
+}
+
+/// An engine process of the pool, and the state of the session using it.
+struct Slot {
+    killed: AtomicU64,
     notify: Notify,
+    status: Arc<std::sync::Mutex<EngineStatus>>,
+    metrics: Arc<EngineMetrics>,
+    observers: broadcast::Sender<Observation>,
+    reconfiguration: std::sync::Mutex<Option<Reconfiguration>>,
+    /// Engine to switch to when the engine is next idle.
+    upgrade: std::sync::Mutex<Option<EngineAddr>>,
     engine: Mutex<Engine>,
 }
 
+impl Slot {
+    /// Switches to the upgraded engine, if any.
+    async fn apply_upgrade(&self, engine: &mut Engine) -> io::Result<()> {
+        let upgrade = self.upgrade.lock().expect("upgrade").take();
+        match upgrade {
+            Some(addr) => engine.upgrade(addr).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct SharedEngine {
+    session: Arc<AtomicU64>,
+    latest_session: AtomicU64,
+    policy: SessionPolicy,
+    idle_timeout: Option<Duration>,
+    schedule: Option<Schedule>,
+    /// Whether the operator stopped accepting sessions for now.
+    on_hold: AtomicBool,
+    /// Whether engine processes are paused while the local user is active.
+    paused: AtomicBool,
+    shutdown: Arc<Shutdown>,
+    /// Parameters of the engines, to check upgrades.
+    params: EngineParameters,
+    slots: Vec<Slot>,
+    /// Pools of other engines, for the variants they are mapped to.
+    variant_engines: Vec<(Vec<String>, SharedEngine)>,
+    /// Sessions that reconnecting clients can take over, by the resume
+    /// token that was sent to the client, with the grant of the client.
+    resumable: std::sync::Mutex<HashMap<String, (Grant, mpsc::Sender<Resume>)>>,
+}
+
 impl SharedEngine {
-    pub fn new(engine: Engine) -> SharedEngine {
+    pub fn new(
+        engines: Vec<Engine>,
+        policy: SessionPolicy,
+        idle_timeout: Option<Duration>,
+        shutdown: Arc<Shutdown>,
+    ) -> SharedEngine {
+        assert!(!engines.is_empty(), "engine pool must not be empty");
         SharedEngine {
-            session: AtomicU64::new(0),
-            notify: Notify::new(),
-            engine: Mutex::new(engine),
+            session: Arc::new(AtomicU64::new(0)),
+            latest_session: AtomicU64::new(0),
+            policy,
+            idle_timeout,
+            schedule: None,
+            on_hold: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            shutdown,
+            params: engines[0].params().clone(),
+            slots: engines
+                .into_iter()
+                .map(|engine| Slot {
+                    killed: AtomicU64::new(0),
+                    notify: Notify::new(),
+                    status: engine.status(),
+                    metrics: engine.metrics(),
+                    observers: engine.observers(),
+                    reconfiguration: std::sync::Mutex::new(None),
+                    upgrade: std::sync::Mutex::new(None),
+                    engine: Mutex::new(engine),
+                })
+                .collect(),
+            variant_engines: Vec::new(),
+            resumable: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Lets sessions switch to another engine when the client selects one
+    /// of the given variants.
+    pub fn with_variant_engines(
+        mut self,
+        variant_engines: Vec<(Vec<String>, SharedEngine)>,
+    ) -> SharedEngine {
+        self.variant_engines = variant_engines
+            .into_iter()
+            .map(|(variants, mut engine)| {
+                engine.session = Arc::clone(&self.session);
+                (variants, engine)
+            })
+            .collect();
+        self
+    }
+
+    /// Accepts sessions only at the given times.
+    pub fn with_schedule(mut self, schedule: Option<Schedule>) -> SharedEngine {
+        self.schedule = schedule;
+        self
+    }
+
+    /// When sessions are accepted again, if they are not accepted now.
+    pub fn unavailable_until(&self) -> Option<String> {
+        if self.on_hold.load(Ordering::Relaxed) {
+            return Some("the operator resumes serving".to_owned());
+        }
+        self.schedule.as_ref().and_then(Schedule::unavailable_until)
+    }
+
+    /// Stops accepting sessions and ends the current ones, or starts
+    /// accepting sessions again.
+    pub fn hold(&self, on_hold: bool) {
+        self.on_hold.store(on_hold, Ordering::Relaxed);
+        for (_, engine) in &self.variant_engines {
+            engine.on_hold.store(on_hold, Ordering::Relaxed);
+        }
+        if on_hold {
+            self.kill_sessions();
+        }
+    }
+
+    /// Ends sessions and stops engine processes when the schedule does not
+    /// allow them. Engines are started again when needed.
+    pub async fn enforce_schedule(self: Arc<Self>) {
+        if self.schedule.is_none() {
+            return;
+        }
+        let mut was_available = true;
+        loop {
+            match self.unavailable_until() {
+                Some(until) => {
+                    if was_available {
+                        tracing::warn!("Not available until {until}, stopping engines ...");
+                        self.kill_sessions();
+                        was_available = false;
+                    }
+                    for slot in self.all_slots() {
+                        if let Ok(mut engine) = slot.engine.try_lock() {
+                            if let Err(err) = engine.suspend_if_idle(Duration::ZERO).await {
+                                tracing::error!("Failed to stop engine: {err}");
+                            }
+                        }
+                    }
+                }
+                None => {
+                    if !was_available {
+                        tracing::warn!("Available again");
+                        was_available = true;
+                    }
+                }
+            }
+            sleep(Duration::from_secs(10)).await;
+        }
+    }
+
+    /// Pauses the processes of searching engines while the local user is
+    /// active, and resumes them once the user has been idle for the given
+    /// time.
+    pub async fn pause_while_user_active(self: Arc<Self>, threshold: Duration) {
+        if presence::user_idle_time().await.is_none() {
+            tracing::warn!("Can not determine idle time of the user, ignoring --only-when-idle");
+            return;
+        }
+        let mut paused: Vec<u32> = Vec::new();
+        let mut warned = false;
+        loop {
+            let active = !self.shutdown.is_requested()
+                && presence::user_idle_time()
+                    .await
+                    .map_or(false, |idle| idle < threshold);
+            if active {
+                for slot in self.all_slots() {
+                    let pid = slot.metrics.pid.load(Ordering::Relaxed);
+                    let (searching, pausable) = {
+                        let status = slot.status.lock().expect("engine status");
+                        (status.searching, status.pausable)
+                    };
+                    if pid == 0 || !searching || paused.contains(&pid) {
+                        continue;
+                    }
+                    if !pausable {
+                        if !warned {
+                            tracing::warn!(
+                                "Only local engine processes are paused while user is active"
+                            );
+                            warned = true;
+                        }
+                        continue;
+                    }
+                    match presence::pause_process(pid, true) {
+                        Ok(()) => {
+                            tracing::warn!("Pausing engine process {pid} while user is active");
+                            slot.status.lock().expect("engine status").paused = true;
+                            paused.push(pid);
+                        }
+                        Err(err) => tracing::error!("Failed to pause engine process {pid}: {err}"),
+                    }
+                }
+            } else {
+                for slot in self.all_slots() {
+                    slot.status.lock().expect("engine status").paused = false;
+                }
+                for pid in paused.drain(..) {
+                    tracing::warn!("Resuming engine process {pid}");
+                    // The process may have exited in the meantime.
+                    if let Err(err) = presence::pause_process(pid, false) {
+                        tracing::debug!("Failed to resume engine process {pid}: {err}");
+                    }
+                }
+                if self.shutdown.is_requested() {
+                    break;
+                }
+            }
+            self.paused.store(!paused.is_empty(), Ordering::Relaxed);
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    /// The pool of engines for a variant.
+    pub fn for_variant(&self, variant: &str) -> &SharedEngine {
+        self.variant_engines
+            .iter()
+            .find(|(variants, _)| variants.iter().any(|mapped| variant::same(mapped, variant)))
+            .map_or(self, |(_, engine)| engine)
+    }
+
+    fn all_slots(&self) -> impl Iterator<Item = &Slot> {
+        self.slots.iter().chain(
+            self.variant_engines
+                .iter()
+                .flat_map(|(_, engine)| &engine.slots),
+        )
+    }
+
+    /// Whether new sessions take over the engine from the active session.
+    /// With a pool of engines, new sessions wait for an idle one instead.
+    fn preempts(&self) -> bool {
+        self.policy == SessionPolicy::Preempt && self.slots.len() == 1
+    }
+
+    /// Periodically stops engine processes while no session is using them.
+    /// Sessions holding an engine take care of this themselves.
+    pub async fn suspend_when_idle(self: Arc<Self>) {
+        let timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        loop {
+            sleep(min(timeout, Duration::from_secs(10))).await;
+            for slot in self.all_slots() {
+                if let Ok(mut engine) = slot.engine.try_lock() {
+                    if let Err(err) = engine.suspend_if_idle(timeout).await {
+                        tracing::error!("Failed to stop idle engine: {err}");
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<Arc<EngineMetrics>> {
+        self.all_slots()
+            .map(|slot| Arc::clone(&slot.metrics))
+            .collect()
+    }
+
+    /// Counts a websocket connection until the returned guard is dropped.
+    /// Connections are counted with the first engine of the pool.
+    fn count_connection(&self) -> ConnectionCount<'_> {
+        ConnectionCount::new(&self.slots[0].metrics.sessions)
+    }
+
+    /// Wrong secrets are counted with the first engine of the pool.
+    fn secret_failures(&self) -> &AtomicU64 {
+        &self.slots[0].metrics.secret_failures
+    }
+
+    pub fn status(&self) -> Vec<EngineStatus> {
+        self.all_slots()
+            .map(|slot| slot.status.lock().expect("engine status").clone())
+            .collect()
+    }
+
+    /// Options that the engines announce to clients.
+    pub fn options(&self) -> Vec<AnnouncedOption> {
+        self.slots[0]
+            .status
+            .lock()
+            .expect("engine status")
+            .announced
+            .clone()
+    }
+
+    /// Subscribes to the analysis of all engines, for spectators.
+    pub fn observe(&self) -> Vec<broadcast::Receiver<Observation>> {
+        self.all_slots()
+            .map(|slot| slot.observers.subscribe())
+            .collect()
+    }
+
+    /// Ends the sessions that are currently using engines, if any, and
+    /// closes their websockets.
+    pub fn kill_sessions(&self) -> Vec<u64> {
+        self.all_slots()
+            .filter_map(|slot| {
+                let session = slot.status.lock().expect("engine status").session?;
+                tracing::warn!("{}: killing session ...", session);
+                slot.killed.store(session, Ordering::SeqCst);
+                slot.notify.notify_one();
+                Some(session)
+            })
+            .collect()
+    }
+
+    /// Ends the current sessions and replaces the engine processes.
+    pub async fn restart_engines(&self) -> io::Result<()> {
+        self.kill_sessions();
+        for slot in self.all_slots() {
+            slot.engine.lock().await.restart_process().await?;
+        }
+        Ok(())
+    }
+
+    /// Switches to another engine executable, like a new build, after
+    /// checking that it initializes with the configured options and runs
+    /// `bench`. Idle engines switch right away, engines in use when their
+    /// session ends.
+    pub async fn upgrade_engines(&self, path: PathBuf) -> io::Result<()> {
+        let addr = EngineAddr::Process(path.clone());
+        tracing::info!("Checking upgrade to {addr} ...");
+        let params = EngineParameters {
+            compare: None,
+            standby: false,
+            stats: None,
+            notify: false,
+            ..self.params.clone()
+        };
+        drop(Engine::new(addr.clone(), params).await?);
+        let nps = bench::nps(&path).await?;
+        tracing::info!("{addr} searched {nps} nodes/second");
+        for slot in &self.slots {
+            *slot.upgrade.lock().expect("upgrade") = Some(addr.clone());
+            if let Ok(mut engine) = slot.engine.try_lock() {
+                slot.apply_upgrade(&mut engine).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes parameters of the engines, starting with their next session.
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        for slot in self.all_slots() {
+            *slot.reconfiguration.lock().expect("reconfiguration") = Some(reconfiguration.clone());
+        }
+    }
+
+    /// Checks that idle engines respond. Engines that are in use are checked
+    /// by their sessions.
+    pub async fn ping(&self, limit: Duration) -> io::Result<()> {
+        if self.paused.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        for slot in self.all_slots() {
+            if let Ok(mut engine) = slot.engine.try_lock() {
+                engine.ping(limit).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a new session could start without waiting or taking over.
+    pub fn has_capacity(&self) -> bool {
+        !self.shutdown.is_requested()
+            && self.unavailable_until().is_none()
+            && self
+                .slots
+                .iter()
+                .any(|slot| slot.status.lock().expect("engine status").session.is_none())
+    }
+
+    pub fn shutdown_guard(&self) -> Option<SessionGuard> {
+        self.shutdown.session()
+    }
+
+    pub fn new_session(&self) -> Session {
+        let session = self.session.fetch_add(1, Ordering::SeqCst) + 1;
+        self.latest_session.store(session, Ordering::SeqCst);
+        Session(session)
+    }
+
+    /// Lets clients that connect with the same grant and the random token
+    /// of the registration take over the session, until the registration is
+    /// dropped.
+    fn resumable(&self, grant: &Grant, window: Duration) -> Resumable<'_> {
+        let token = format!("{:032x}", random::<u128>());
+        let (sender, receiver) = mpsc::channel(1);
+        self.resumable
+            .lock()
+            .expect("resumable sessions")
+            .insert(token.clone(), (grant.clone(), sender));
+        Resumable {
+            shared_engine: self,
+            token,
+            window,
+            receiver,
+        }
+    }
+
+    /// Hands the websocket of a reconnecting client to the session it was
+    /// using before. Returns the outbox of the session, including output
+    /// buffered while the client was away, or `None` if the session ended
+    /// in the meantime. Gives back the websocket if there is no such
+    /// session.
+    async fn resume(
+        &self,
+        token: &str,
+        grant: &Grant,
+        stream: SplitStream<WebSocket>,
+    ) -> Result<Option<Arc<Outbox>>, SplitStream<WebSocket>> {
+        let sender = self
+            .resumable
+            .lock()
+            .expect("resumable sessions")
+            .get(token)
+            .filter(|(session_grant, _)| session_grant == grant)
+            .map(|(_, sender)| sender.clone());
+        let sender = match sender {
+            Some(sender) => sender,
+            None => return Err(stream),
+        };
+        let (reply, outbox) = oneshot::channel();
+        if let Err(mpsc::error::SendError(resume)) = sender.send(Resume { stream, reply }).await {
+            return Err(resume.stream);
+        }
+        Ok(outbox.await.ok())
+    }
+
+    /// Takes an idle engine from the pool, if any.
+    pub fn try_acquire(&self, session: Session) -> Option<Lease<'_>> {
+        self.slots.iter().find_map(|slot| {
+            slot.engine
+                .try_lock()
+                .ok()
+                .map(|engine| Lease::new(self, slot, engine, session))
+        })
+    }
+
+    /// Waits for the next engine of the pool to become idle. Depending on
+    /// the policy, the active session is asked to end.
+    pub async fn acquire_next(&self, session: Session) -> Lease<'_> {
+        if self.preempts() {
+            self.slots[0].notify.notify_one();
+        }
+        let (engine, index, _) =
+            select_all(self.slots.iter().map(|slot| Box::pin(slot.engine.lock()))).await;
+        Lease::new(self, &self.slots[index], engine, session)
+    }
+
+    /// Starts a new session and waits for an engine.
+    pub async fn acquire(&self) -> (Session, Lease<'_>) {
+        let session = self.new_session();
+        let lease = match self.try_acquire(session) {
+            Some(lease) => lease,
+            None => self.acquire_next(session).await,
+        };
+        (session, lease)
+    }
+}
+
+/// A websocket handed over to the session it reconnected to.
+struct Resume {
+    stream: SplitStream<WebSocket>,
+    reply: oneshot::Sender<Arc<Outbox>>,
+}
+
+/// Registration of a session that reconnecting clients can take over.
+struct Resumable<'a> {
+    shared_engine: &'a SharedEngine,
+    token: String,
+    /// How long the session waits for the client after losing the
+    /// connection.
+    window: Duration,
+    receiver: mpsc::Receiver<Resume>,
+}
+
+impl Resumable<'_> {
+    async fn next(&mut self) -> Resume {
+        match self.receiver.recv().await {
+            Some(resume) => resume,
+            None => future::pending().await,
+        }
+    }
+}
+
+impl Drop for Resumable<'_> {
+    fn drop(&mut self) {
+        self.shared_engine
+            .resumable
+            .lock()
+            .expect("resumable sessions")
+            .remove(&self.token);
+    }
+}
+
+/// Engine locked by a session. Keeps track of the session in the engine
+/// status.
+pub struct Lease<'a> {
+    shared_engine: &'a SharedEngine,
+    slot: &'a Slot,
+    engine: MutexGuard<'a, Engine>,
+}
+
+impl<'a> Lease<'a> {
+    fn new(
+        shared_engine: &'a SharedEngine,
+        slot: &'a Slot,
+        engine: MutexGuard<'a, Engine>,
+        session: Session,
+    ) -> Lease<'a> {
+        slot.status.lock().expect("engine status").session = Some(session.0);
+        Lease {
+            shared_engine,
+            slot,
+            engine,
+        }
+    }
+
+    /// Resolves when another session wants the engine, or an operator wants
+    /// to end the session.
+    pub fn notified(&self) -> Notified<'a> {
+        self.slot.notify.notified()
+    }
+
+    /// Prepares the engine for a new game of the session, after applying
+    /// reloaded parameters, if any.
+    pub async fn start_session(&mut self, session: Session) -> io::Result<()> {
+        let reconfiguration = self
+            .slot
+            .reconfiguration
+            .lock()
+            .expect("reconfiguration")
+            .take();
+        self.slot.apply_upgrade(&mut self.engine).await?;
+        if let Some(reconfiguration) = reconfiguration {
+            self.engine.reconfigure(session, reconfiguration).await?;
+        }
+        self.engine.start_session(session).await
+    }
+
+    pub fn is_killed(&self, session: Session) -> bool {
+        session.0 == self.slot.killed.load(Ordering::SeqCst)
+    }
+
+    pub fn is_preempted(&self, session: Session) -> bool {
+        self.shared_engine.preempts()
+            && session != Session(self.shared_engine.latest_session.load(Ordering::SeqCst))
+    }
+}
+
+impl Lease<'_> {
+    /// Publishes the round trip time to the client of the session.
+    fn report_latency(&self, latency: Duration) {
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        self.slot.status.lock().expect("engine status").latency_ms = Some(millis);
+        self.slot
+            .metrics
+            .latency_ms
+            .store(millis, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut status = self.slot.status.lock().expect("engine status");
+        let session = status.session.take();
+        status.latency_ms = None;
+        drop(status);
+        self.slot.metrics.latency_ms.store(0, Ordering::Relaxed);
+        if let Some(session) = session {
+            self.engine.end_session(Session(session));
         }
     }
 }
 
+impl Deref for Lease<'_> {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+impl DerefMut for Lease<'_> {
+    fn deref_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+}
+
 #[derive(Eq, Serialize, Deserialize, Clone, Debug)]
 pub struct Secret(pub String);
 
@@ -52,6 +692,9 @@ pub struct Params {
     secret: Secret,
     #[serde(rename = "session")]
     _session: String,
+    profile: Option<String>,
+    /// Token of a session to take over after reconnecting.
+    resume: Option<String>,
 }
 
 impl Secret {
@@ -68,58 +711,734 @@ impl PartialEq for Secret {
     }
 }
 
+/// How long the previous secret is still accepted after rotation, so that
+/// clients can reconnect until they pick up the new registration URL.
+const SECRET_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Where the secrets of an engine endpoint are kept across restarts.
+#[derive(Clone, Debug)]
+pub enum SecretStorage {
+    File(PathBuf),
+    /// Account name of an entry in the credential store of the operating
+    /// system.
+    Keyring(String),
+}
+
+impl SecretStorage {
+    pub fn read(&self) -> io::Result<String> {
+        match self {
+            SecretStorage::File(path) => fs::read_to_string(path),
+            SecretStorage::Keyring(account) => keyring::read(account),
+        }
+    }
+
+    pub fn write(&self, content: &str) -> io::Result<()> {
+        match self {
+            SecretStorage::File(path) => fs::write(path, content),
+            SecretStorage::Keyring(account) => keyring::write(account, content),
+        }
+    }
+
+    /// Storage for the secrets of a named engine, next to those of the
+    /// default engine.
+    pub fn scoped(&self, name: &str) -> SecretStorage {
+        match self {
+            SecretStorage::File(path) => {
+                let mut path = OsString::from(path);
+                path.push(".");
+                path.push(name);
+                SecretStorage::File(PathBuf::from(path))
+            }
+            SecretStorage::Keyring(account) => SecretStorage::Keyring(format!("{account}.{name}")),
+        }
+    }
+}
+
+impl fmt::Display for SecretStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretStorage::File(path) => write!(f, "secret file {path:?}"),
+            SecretStorage::Keyring(account) => write!(f, "keyring entry {account}"),
+        }
+    }
+}
+
+/// Secrets of an engine endpoint, which can be rotated, added and revoked
+/// while the server is running.
+pub struct SecretStore {
+    storage: Option<SecretStorage>,
+    secrets: std::sync::Mutex<Secrets>,
+    /// Held while writing to the storage, so that writes happen in the
+    /// order of the changes.
+    saving: Mutex<()>,
+}
+
+struct Secrets {
+    active: Secret,
+    previous: Option<(Secret, Instant)>,
+    named: Vec<NamedSecret>,
+}
+
+impl Secrets {
+    fn format(&self) -> String {
+        format_secret_file(&self.active, &self.named)
+    }
+}
+
+impl SecretStore {
+    pub fn new(
+        active: Secret,
+        named: Vec<NamedSecret>,
+        storage: Option<SecretStorage>,
+    ) -> SecretStore {
+        SecretStore {
+            storage,
+            secrets: std::sync::Mutex::new(Secrets {
+                active,
+                previous: None,
+                named,
+            }),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// The secret for new registration URLs.
+    pub fn active(&self) -> Secret {
+        self.secrets.lock().expect("secrets").active.clone()
+    }
+
+    /// Checks a secret presented by a client, and returns the permissions
+    /// that come with it.
+    pub fn verify(&self, secret: &Secret) -> Option<Grant> {
+        let secrets = self.secrets.lock().expect("secrets");
+        if *secret == secrets.active
+            || secrets
+                .previous
+                .as_ref()
+                .map_or(false, |(previous, rotated)| {
+                    rotated.elapsed() < SECRET_GRACE_PERIOD && secret == previous
+                })
+        {
+            return Some(Grant::default());
+        }
+        secrets
+            .named
+            .iter()
+            .find(|named| named.secret == *secret)
+            .map(|named| Grant {
+                name: Some(named.name.clone()),
+                permissions: named.permissions.clone(),
+            })
+    }
+
+    /// Replaces the active secret with a new random one, and writes it to
+    /// storage, if any. Named secrets are not affected.
+    pub async fn rotate(&self) -> Secret {
+        let saving = self.saving.lock().await;
+        let secret = Secret::random();
+        let content = {
+            let mut secrets = self.secrets.lock().expect("secrets");
+            let previous = mem::replace(&mut secrets.active, secret.clone());
+            secrets.previous = Some((previous, Instant::now()));
+            secrets.format()
+        };
+        self.save(saving, content).await;
+        secret
+    }
+
+    pub fn named(&self) -> Vec<NamedSecret> {
+        self.secrets.lock().expect("secrets").named.clone()
+    }
+
+    /// Issues a new random secret for a client. Returns `None` if the name
+    /// is already taken.
+    pub async fn add(&self, name: String, permissions: Permissions) -> Option<Secret> {
+        let saving = self.saving.lock().await;
+        let secret = Secret::random();
+        let content = {
+            let mut secrets = self.secrets.lock().expect("secrets");
+            if secrets.named.iter().any(|named| named.name == name) {
+                return None;
+            }
+            secrets.named.push(NamedSecret {
+                name,
+                secret: secret.clone(),
+                permissions,
+            });
+            secrets.format()
+        };
+        self.save(saving, content).await;
+        Some(secret)
+    }
+
+    /// Revokes the named secret of a client, for new connections. Returns
+    /// `false` if there is no such secret.
+    pub async fn revoke(&self, name: &str) -> bool {
+        let saving = self.saving.lock().await;
+        let content = {
+            let mut secrets = self.secrets.lock().expect("secrets");
+            let len = secrets.named.len();
+            secrets.named.retain(|named| named.name != name);
+            if secrets.named.len() == len {
+                return false;
+            }
+            secrets.format()
+        };
+        self.save(saving, content).await;
+        true
+    }
+
+    /// Writes all secrets to the storage, if any, so that they are kept
+    /// across restarts. Keyrings are written by other programs, so this
+    /// happens on a blocking thread.
+    async fn save(&self, _saving: MutexGuard<'_, ()>, content: String) {
+        if let Some(ref storage) = self.storage {
+            let write = {
+                let storage = storage.clone();
+                tokio::task::spawn_blocking(move || storage.write(&content))
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)))
+            };
+            match write {
+                Ok(()) => tracing::debug!("Wrote secrets to {storage}"),
+                Err(err) => tracing::error!("Failed to write {storage}: {err}"),
+            }
+        }
+    }
+
+    /// Reads the storage again, in case an operator changed it. The
+    /// old secret is no longer accepted for new connections.
+    pub async fn reload(&self) {
+        let storage = match self.storage {
+            Some(ref storage) => storage,
+            None => return,
+        };
+        let read = {
+            let storage = storage.clone();
+            tokio::task::spawn_blocking(move || storage.read())
+                .await
+                .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)))
+        };
+        match read.map(|content| parse_secret_file(&content)) {
+            Ok(Ok((active, named))) if active.0.len() >= 8 => {
+                let mut secrets = self.secrets.lock().expect("secrets");
+                if secrets.active != active {
+                    tracing::warn!("Loaded new secret from {storage}");
+                    secrets.active = active;
+                    secrets.previous = None;
+                }
+                if secrets.named != named {
+                    tracing::warn!("Loaded {} named secrets from {storage}", named.len());
+                    secrets.named = named;
+                }
+            }
+            Ok(Ok(_)) => tracing::error!("Ignoring {storage} (too short)"),
+            Ok(Err(err)) => tracing::error!("Ignoring invalid {storage}: {err}"),
+            Err(err) => tracing::error!("Failed to reload {storage}: {err}"),
+        }
+    }
+}
+
+type WebSocket = WebSocketStream<DeflateStream<Upgraded>>;
+
+/// Settings for websocket connections.
+#[derive(Clone, Debug, Default)]
+pub struct SocketOpts {
+    /// Offer permessage-deflate to clients.
+    pub compression: bool,
+    /// Minimum time between batches of info lines.
+    pub info_interval: Option<Duration>,
+    /// Directory for session transcripts.
+    pub session_log_dir: Option<Arc<Path>>,
+    /// Origins and hosts that may open websockets.
+    pub origin_policy: Arc<OriginPolicy>,
+    /// Client addresses that may open websockets.
+    pub ip_filter: Arc<IpFilter>,
+    /// Limits attempts to guess the secret.
+    pub throttle: Arc<SecretThrottle>,
+    /// Serve the pages that show the secret, like the redirect to the
+    /// registration URL, only to clients on this machine. Set when the
+    /// server is published under a public URL.
+    pub local_pages: bool,
+    /// Time between pings to the client.
+    pub ping_interval: Duration,
+    /// Time to wait for a pong before giving up on the client.
+    pub ping_timeout: Duration,
+    /// Answer invalid commands with an error, instead of ending the
+    /// session.
+    pub strict_uci: bool,
+    /// Tell clients the round trip time with info strings.
+    pub report_latency: bool,
+    /// How long sessions wait for clients to reconnect after losing the
+    /// connection.
+    pub resume_window: Option<Duration>,
+    /// Profiles that clients may choose, by name.
+    pub profiles: Arc<BTreeMap<String, Profile>>,
+    /// Profile of sessions that do not choose one.
+    pub default_profile: Option<String>,
+}
+
+/// Why a client was turned away.
+pub enum Denied {
+    Throttled { retry_after: Duration },
+    WrongSecret,
+}
+
+impl IntoResponse for Denied {
+    fn into_response(self) -> Response {
+        match self {
+            Denied::Throttled { retry_after } => Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, retry_after.as_secs() + 1)
+                .body(body::boxed(Empty::new()))
+                .expect("throttled response"),
+            Denied::WrongSecret => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+/// Checks the secret of a client, and slows down clients that are guessing.
+pub fn authorize(
+    engine: &SharedEngine,
+    secrets: &SecretStore,
+    opts: &SocketOpts,
+    ip: Option<IpAddr>,
+    secret: Option<&Secret>,
+) -> Result<Grant, Denied> {
+    if let Err(retry_after) = opts.throttle.check(ip) {
+        return Err(Denied::Throttled { retry_after });
+    }
+    match secret.and_then(|secret| secrets.verify(secret)) {
+        Some(grant) => {
+            opts.throttle.succeeded(ip);
+            Ok(grant)
+        }
+        None => {
+            engine.secret_failures().fetch_add(1, Ordering::Relaxed);
+            opts.throttle.failed(ip);
+            Err(Denied::WrongSecret)
+        }
+    }
+}
+
 pub async fn handler(
     engine: Arc<SharedEngine>,
-    secret: Secret,
+    secrets: Arc<SecretStore>,
+    opts: SocketOpts,
     Query(params): Query<Params>,
-    ws: WebSocketUpgrade,
-) -> Result<impl IntoResponse, StatusCode> {
-    if secret == params.secret {
-        Ok(ws.on_upgrade(move |socket| handle_socket(engine, socket)))
-    } else {
-        Err(StatusCode::FORBIDDEN)
+    mut req: Request<Body>,
+) -> Result<Response, StatusCode> {
+    if let Err(reason) = opts.origin_policy.check(req.headers()) {
+        tracing::warn!("rejected websocket: {reason}");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let ip = opts.ip_filter.client_ip(&req);
+    let mut grant = match authorize(&engine, &secrets, &opts, ip, Some(&params.secret)) {
+        Ok(grant) => grant,
+        Err(denied) => return Ok(denied.into_response()),
+    };
+    let profile = match params.profile.as_ref().or(opts.default_profile.as_ref()) {
+        Some(name) => match opts.profiles.get(name) {
+            Some(profile) => {
+                profile.restrict(&mut grant.permissions);
+                Some(profile.clone())
+            }
+            None => {
+                tracing::warn!("rejected websocket: unknown profile {name}");
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        },
+        None => None,
+    };
+
+    // The websocket handshake is done here rather than by axum, which does
+    // not support extensions.
+    let headers = req.headers();
+    let header_contains = |name, value: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(value))
+    };
+    if !header_contains(header::CONNECTION, "upgrade")
+        || !header_contains(header::UPGRADE, "websocket")
+        || !header_contains(header::SEC_WEBSOCKET_VERSION, "13")
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let accept = derive_accept_key(
+        headers
+            .get(header::SEC_WEBSOCKET_KEY)
+            .ok_or(StatusCode::BAD_REQUEST)?
+            .as_bytes(),
+    );
+    let deflate = headers
+        .get(header::SEC_WEBSOCKET_EXTENSIONS)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| opts.compression)
+        .and_then(DeflateConfig::negotiate);
+
+    let mut res = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept);
+    if let Some(ref deflate) = deflate {
+        res = res.header(header::SEC_WEBSOCKET_EXTENSIONS, deflate.response_header());
+    }
+
+    let span = tracing::info_span!("connection", client = field::Empty, session = field::Empty);
+    if let Some(ref name) = grant.name {
+        span.record("client", &name.as_str());
+    }
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(
+        async move {
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    if deflate.is_some() {
+                        tracing::debug!("using permessage-deflate");
+                    }
+                    let socket = WebSocketStream::from_raw_socket(
+                        DeflateStream::new(upgraded, deflate),
+                        Role::Server,
+                        None,
+                    )
+                    .await;
+                    handle_socket(engine, socket, opts, grant, profile, params.resume).await;
+                }
+                Err(err) => tracing::error!("websocket upgrade failed: {err}"),
+            }
+        }
+        .instrument(span),
+    );
+
+    Ok(res
+        .body(body::boxed(Empty::new()))
+        .expect("upgrade response"))
+}
+
+async fn handle_socket(
+    shared_engine: Arc<SharedEngine>,
+    socket: WebSocket,
+    opts: SocketOpts,
+    grant: Grant,
+    profile: Option<Profile>,
+    resume: Option<String>,
+) {
+    let (sink, stream) = socket.split();
+    let resumed = match resume {
+        Some(ref token) => shared_engine.resume(token, &grant, stream).await,
+        None => Err(stream),
+    };
+    let stream = match resumed {
+        Ok(outbox) => {
+            // The session reads from the websocket from now on.
+            tracing::warn!("resuming session ...");
+            let outbox = outbox.unwrap_or_else(|| {
+                let outbox = Arc::<Outbox>::default();
+                outbox.close(None);
+                outbox
+            });
+            write_socket(sink, &outbox, opts.info_interval).await;
+            return;
+        }
+        Err(stream) => {
+            if resume.is_some() {
+                tracing::warn!("no session to resume, starting new session");
+            }
+            stream
+        }
+    };
+
+    let _connection_count = shared_engine.count_connection();
+    let outbox = Arc::<Outbox>::default();
+    let read = async {
+        let mut connection = Connection {
+            stream: Some(stream),
+            outbox: Arc::clone(&outbox),
+        };
+        let close = match (
+            shared_engine.shutdown_guard(),
+            shared_engine.unavailable_until(),
+        ) {
+            (Some(_), Some(until)) => Some(unavailable_close_frame(until)),
+            (Some(guard), None) => handle_socket_inner(
+                &shared_engine,
+                &mut connection,
+                guard,
+                &opts,
+                &grant,
+                profile.as_ref(),
+            )
+            .await
+            .unwrap_or_else(|err| {
+                tracing::error!("handler: {}", err);
+                None
+            }),
+            (None, _) => Some(shutdown_close_frame()),
+        };
+        connection.outbox.close(close);
+    };
+    tokio::join!(read, write_socket(sink, &outbox, opts.info_interval));
+}
+
+/// Keeps a websocket connection counted in the metrics, even if its task
+/// ends early or panics.
+struct ConnectionCount<'a> {
+    sessions: &'a AtomicU64,
+}
+
+impl ConnectionCount<'_> {
+    fn new(sessions: &AtomicU64) -> ConnectionCount<'_> {
+        sessions.fetch_add(1, Ordering::Relaxed);
+        ConnectionCount { sessions }
+    }
+}
+
+impl Drop for ConnectionCount<'_> {
+    fn drop(&mut self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The websocket of a session. Replaced when the client reconnects, and
+/// missing while the session waits for that.
+struct Connection {
+    stream: Option<SplitStream<WebSocket>>,
+    outbox: Arc<Outbox>,
+}
+
+impl Connection {
+    /// Keeps output for the client until it reconnects.
+    fn parked() -> Connection {
+        Connection {
+            stream: None,
+            outbox: Arc::default(),
+        }
+    }
+}
+
+/// Identifies info lines that supersede each other: progress updates
+/// without pv, or lines with pv, for the same multipv and depth.
+type InfoKey = (Option<NonZeroU32>, Option<u32>, bool);
+
+struct Outgoing {
+    message: Message,
+    info: Option<InfoKey>,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    queue: VecDeque<Outgoing>,
+    closed: bool,
+}
+
+/// Messages waiting to be sent to the client. While the client is behind,
+/// info lines are coalesced, keeping only the latest one for each multipv
+/// and depth.
+#[derive(Default)]
+struct Outbox {
+    state: std::sync::Mutex<OutboxState>,
+    notify: Notify,
+}
+
+impl Outbox {
+    fn push(&self, message: Message, info: Option<InfoKey>) -> io::Result<()> {
+        let mut state = self.state.lock().expect("outbox");
+        if state.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "websocket closed",
+            ));
+        }
+        if info.is_some() {
+            let superseded = state
+                .queue
+                .iter()
+                .rposition(|queued| queued.info == info)
+                .filter(|i| {
+                    state
+                        .queue
+                        .iter()
+                        .skip(*i)
+                        .all(|queued| queued.info.is_some())
+                });
+            if let Some(i) = superseded {
+                state.queue.remove(i);
+            }
+        }
+        state.queue.push_back(Outgoing { message, info });
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Queues the close frame as the last message.
+    fn close(&self, frame: Option<CloseFrame<'static>>) {
+        let mut state = self.state.lock().expect("outbox");
+        if !state.closed {
+            state.queue.push_back(Outgoing {
+                message: Message::Close(frame),
+                info: None,
+            });
+            state.closed = true;
+        }
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    /// Called when sending failed. Further messages are rejected.
+    fn fail(&self) {
+        let mut state = self.state.lock().expect("outbox");
+        state.queue.clear();
+        state.closed = true;
+    }
+
+    /// Waits for queued messages. If only info lines are queued, they are
+    /// held back until the interval since the last batch of info lines has
+    /// passed.
+    async fn next_batch(
+        &self,
+        info_interval: Option<Duration>,
+        last_info: Option<Instant>,
+    ) -> Vec<Outgoing> {
+        loop {
+            let throttle = {
+                let mut state = self.state.lock().expect("outbox");
+                if state.queue.is_empty() {
+                    None
+                } else {
+                    match (info_interval, last_info) {
+                        (Some(interval), Some(last_info))
+                            if last_info.elapsed() < interval
+                                && state.queue.iter().all(|queued| queued.info.is_some()) =>
+                        {
+                            Some(interval - last_info.elapsed())
+                        }
+                        _ => return state.queue.drain(..).collect(),
+                    }
+                }
+            };
+            match throttle {
+                Some(delay) => tokio::select! {
+                    _ = sleep(delay) => (),
+                    _ = self.notify.notified() => (),
+                },
+                None => self.notify.notified().await,
+            }
+        }
     }
 }
 
-async fn handle_socket(shared_engine: Arc<SharedEngine>, mut socket: WebSocket) {
-    if let Err(err) = handle_socket_inner(&shared_engine, &mut socket).await {
-        log::error!("handler: {}", err);
+async fn write_socket(
+    mut sink: SplitSink<WebSocket, Message>,
+    outbox: &Outbox,
+    info_interval: Option<Duration>,
+) {
+    let mut last_info = None;
+    loop {
+        let batch = outbox.next_batch(info_interval, last_info).await;
+        let infos = batch.iter().any(|outgoing| outgoing.info.is_some());
+        let closing = batch
+            .iter()
+            .any(|outgoing| matches!(outgoing.message, Message::Close(_)));
+        let mut res = Ok(());
+        for outgoing in batch {
+            res = sink.feed(outgoing.message).await;
+            if res.is_err() {
+                break;
+            }
+        }
+        if let Err(err) = res.and(sink.flush().await) {
+            if !closing {
+                tracing::error!("failed to send: {err}");
+            }
+            outbox.fail();
+            break;
+        }
+        if infos {
+            last_info = Some(Instant::now());
+        }
+        if closing {
+            break;
+        }
     }
-    let _ = socket.send(Message::Close(None)).await;
 }
 
 #[allow(clippy::large_enum_variant)]
-enum Event {
-    Socket(Option<Result<Message, axum::Error>>),
+enum Event<'a> {
+    Socket(Option<Result<Message, tokio_tungstenite::tungstenite::Error>>),
     Engine(io::Result<UciOut>),
+    Acquired(Lease<'a>),
     CheckSession,
+    Shutdown,
     Tick,
+    PingTimeout,
+    Resumed(Resume),
+    ResumeExpired,
 }
 
+type Acquire<'a> = Pin<Box<dyn Future<Output = Lease<'a>> + Send + 'a>>;
+
 async fn handle_socket_inner(
     shared_engine: &SharedEngine,
-    socket: &mut WebSocket,
-) -> io::Result<()> {
-    let mut locked_engine: Option<MutexGuard<Engine>> = None;
+    connection: &mut Connection,
+    mut shutdown: SessionGuard,
+    opts: &SocketOpts,
+    grant: &Grant,
+    profile: Option<&Profile>,
+) -> io::Result<Option<CloseFrame<'static>>> {
+    let permissions = &grant.permissions;
+    let mut pool = shared_engine;
+    let mut client_options: Vec<UciIn> = Vec::new();
+    let mut locked_engine: Option<Lease> = None;
+    let mut acquiring: Option<Acquire> = None;
+    let mut queued = Vec::new();
     let mut session = Session(0);
+    let mut transcript: Option<Transcript> = None;
+    let mut resumable: Option<Resumable> = None;
+    let mut parked: Option<Pin<Box<Sleep>>> = None;
 
-    let mut missed_pong = false;
-    let mut timeout = interval(Duration::from_secs(10));
+    // Measure the round trip time right away, to compensate for it in
+    // searches with clock times.
+    connection.outbox.push(Message::Ping(Vec::new()), None)?;
+    let mut ping_sent = Instant::now();
+    let mut latency = Duration::ZERO;
+    let mut missed_pong = true;
+    let mut timeout = interval(opts.ping_interval);
     timeout.set_missed_tick_behavior(MissedTickBehavior::Delay);
     timeout.reset();
 
     loop {
+        let outbox = &*connection.outbox;
+
         // Try to end session if another session wants to take over.
         // We send a stop command, and keep the previous session the engine
         // is actually idle.
+        if let Some(ref mut engine) = locked_engine {
+            if engine.is_killed(session) {
+                finish(engine, session, outbox, &mut transcript).await?;
+                tracing::warn!("session killed");
+                break Ok(Some(match pool.unavailable_until() {
+                    Some(until) => unavailable_close_frame(until),
+                    None => CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "session ended by operator".into(),
+                    },
+                }));
+            }
+        }
         if let Some(mut engine) = locked_engine.take() {
-            if session != Session(shared_engine.session.load(Ordering::SeqCst)) {
-                log::warn!("{}: trying to end session ...", session.0);
+            if engine.is_preempted(session) {
+                tracing::warn!("trying to end session ...");
                 if engine.is_searching() {
                     engine.send(session, UciIn::Stop).await?;
                 }
                 if engine.is_idle() {
-                    log::warn!("{}: session ended", session.0);
+                    tracing::warn!("session ended");
                 } else {
                     locked_engine = Some(engine);
                 }
@@ -127,79 +1446,291 @@ async fn handle_socket_inner(
                 locked_engine = Some(engine);
             }
         }
+        if parked.is_some() && locked_engine.is_none() {
+            tracing::warn!("session ended before the client reconnected");
+            break Ok(None);
+        }
 
         // Select next event to handle.
+        let pong_deadline = sleep_until((ping_sent + opts.ping_timeout).into());
         let event = if let Some(ref mut engine) = locked_engine {
+            let notified = engine.notified();
             tokio::select! {
-                engine_in = socket.recv() => Event::Socket(engine_in),
+                engine_in = next_message(&mut connection.stream) => Event::Socket(engine_in),
                 engine_out = engine.recv(session) => Event::Engine(engine_out),
-                _ = shared_engine.notify.notified() => Event::CheckSession,
+                _ = notified => Event::CheckSession,
+                _ = shutdown.requested() => Event::Shutdown,
                 _ = timeout.tick() => Event::Tick,
+                _ = pong_deadline, if missed_pong => Event::PingTimeout,
+                resume = next_resume(&mut resumable) => Event::Resumed(resume),
+                _ = expired(&mut parked) => Event::ResumeExpired,
+            }
+        } else if let Some(ref mut acquire) = acquiring {
+            tokio::select! {
+                engine_in = next_message(&mut connection.stream) => Event::Socket(engine_in),
+                engine = acquire => Event::Acquired(engine),
+                _ = shutdown.requested() => Event::Shutdown,
+                _ = timeout.tick() => Event::Tick,
+                _ = pong_deadline, if missed_pong => Event::PingTimeout,
+                resume = next_resume(&mut resumable) => Event::Resumed(resume),
+                _ = expired(&mut parked) => Event::ResumeExpired,
             }
         } else {
             tokio::select! {
-                engine_in = socket.recv() => Event::Socket(engine_in),
+                engine_in = next_message(&mut connection.stream) => Event::Socket(engine_in),
+                _ = shutdown.requested() => Event::Shutdown,
                 _ = timeout.tick() => Event::Tick,
+                _ = pong_deadline, if missed_pong => Event::PingTimeout,
+                resume = next_resume(&mut resumable) => Event::Resumed(resume),
+                _ = expired(&mut parked) => Event::ResumeExpired,
             }
         };
 
         // Handle event.
+        if let Event::Socket(Some(Ok(ref message))) = event {
+            tracing::debug!("ws >> {:?}", message);
+        }
+        let lost = matches!(
+            event,
+            Event::PingTimeout | Event::Socket(None | Some(Err(_)))
+        );
+        if let (true, Some(resumable), Some(_)) = (lost, &resumable, &locked_engine) {
+            // Keep the session running and buffer its output, in case the
+            // client reconnects.
+            tracing::warn!(
+                "connection lost, waiting {:?} for client to reconnect ...",
+                resumable.window
+            );
+            connection.outbox.close(None);
+            *connection = Connection::parked();
+            parked = Some(Box::pin(sleep(resumable.window)));
+            missed_pong = false;
+            continue;
+        }
         match event {
             Event::CheckSession => continue,
 
+            Event::Shutdown => {
+                if let Some(ref mut engine) = locked_engine {
+                    finish(engine, session, outbox, &mut transcript).await?;
+                }
+                tracing::warn!("session closed for shutdown");
+                break Ok(Some(shutdown_close_frame()));
+            }
+
             Event::Tick => {
-                if missed_pong {
-                    log::error!("{}: ping timeout", session.0);
-                    if let Some(ref mut engine) = locked_engine {
-                        engine.ensure_idle(session).await?;
-                    }
-                    break Ok(());
-                } else {
-                    socket
-                        .send(Message::Ping(Vec::new()))
-                        .await
-                        .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
+                if let (Some(ref mut engine), Some(idle_timeout)) =
+                    (&mut locked_engine, pool.idle_timeout)
+                {
+                    engine.suspend_if_idle(idle_timeout).await?;
+                }
+                if let Some(ref mut engine) = locked_engine {
+                    engine.apply_thermal(session).await?;
+                }
+                if !missed_pong && connection.stream.is_some() {
+                    outbox.push(Message::Ping(Vec::new()), None)?;
+                    ping_sent = Instant::now();
                     missed_pong = true;
                 }
             }
 
+            Event::PingTimeout => {
+                // The peer is gone, possibly without the connection being
+                // closed. Stop analysing for nobody and free the engine for
+                // the next client.
+                tracing::error!("ping timeout, session dead");
+                if let Some(ref mut engine) = locked_engine {
+                    engine.ensure_idle(session).await?;
+                }
+                break Ok(None);
+            }
+
+            Event::Resumed(resume) => {
+                if parked.take().is_some() {
+                    tracing::warn!("client reconnected");
+                } else {
+                    tracing::warn!("client reconnected, closing previous connection");
+                    connection.outbox.close(Some(CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "session resumed by another connection".into(),
+                    }));
+                    connection.outbox = Arc::default();
+                }
+                connection.stream = Some(resume.stream);
+                let _ = resume.reply.send(Arc::clone(&connection.outbox));
+                connection.outbox.push(Message::Ping(Vec::new()), None)?;
+                ping_sent = Instant::now();
+                missed_pong = true;
+            }
+
+            Event::ResumeExpired => {
+                tracing::warn!("client did not reconnect, session ended");
+                if let Some(ref mut engine) = locked_engine {
+                    engine.ensure_idle(session).await?;
+                }
+                break Ok(None);
+            }
+
             Event::Socket(Some(Ok(Message::Text(text)))) => {
-                if let Some(command) = UciIn::from_line(&text)
-                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
-                {
-                    let mut engine = match locked_engine.take() {
-                        Some(engine) => engine,
-                        None if command == UciIn::Stop => {
-                            // No need to make a new session just to send a stop
-                            // command.
-                            continue;
+                let parsed = match UciIn::from_line(&text) {
+                    Err(err) if opts.strict_uci => {
+                        tracing::warn!("rejected invalid command: {err}");
+                        let reason = format!("rejected invalid command: {err}");
+                        send_text(outbox, &mut transcript, UciOut::info_string(reason))?;
+                        continue;
+                    }
+                    res => res.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                };
+                if let Some(mut command) = parsed {
+                    if let Err(reason) = permissions.restrict_in(&mut command) {
+                        tracing::warn!("rejected command: {reason}");
+                        send_text(outbox, &mut transcript, UciOut::info_string(reason))?;
+                        continue;
+                    }
+                    if let UciIn::Setoption {
+                        ref name,
+                        ref value,
+                    } = command
+                    {
+                        if *name == "UCI_Variant" {
+                            let target =
+                                shared_engine.for_variant(value.as_deref().unwrap_or_default());
+                            if !ptr::eq(target, pool) {
+                                // Continue the session with another engine,
+                                // bringing along the options of the client.
+                                tracing::warn!("switching engine for variant ...");
+                                if let Some(mut engine) = locked_engine.take() {
+                                    engine.ensure_idle(session).await?;
+                                }
+                                acquiring = None;
+                                queued.retain(|queued| !matches!(queued, UciIn::Setoption { .. }));
+                                queued.splice(0..0, client_options.iter().cloned());
+                                pool = target;
+                            }
+                        } else {
+                            client_options.retain(|option| {
+                                !matches!(option, UciIn::Setoption { name: other, .. } if other == name)
+                            });
+                            client_options.push(command.clone());
                         }
-                        None => {
-                            session =
-                                Session(shared_engine.session.fetch_add(1, Ordering::SeqCst) + 1);
-                            log::warn!("{}: starting or restarting session ...", session.0);
-                            shared_engine.notify.notify_one();
-                            let mut engine = shared_engine.engine.lock().await;
-                            log::warn!("{}: new session started", session.0);
-                            engine.ensure_newgame(session).await?;
-
-                            // TODO: Should track and restore options and
-                            // positions of the session. Not required for
-                            // lichess.org.
-                            engine
+                    }
+                    if let Some(ref mut engine) = locked_engine {
+                        if let Some(ref mut transcript) = transcript {
+                            transcript.client(&command);
                         }
-                    };
-
+                        send_validated(engine, session, command, latency, outbox, &mut transcript)
+                            .await?;
+                    } else if acquiring.is_some() {
+                        if let Some(ref mut transcript) = transcript {
+                            transcript.client(&command);
+                        }
+                        queued.push(command);
+                    } else if command == UciIn::Stop || command == UciIn::Ponderhit {
+                        // No need to make a new session just to send a stop
+                        // command, or to end pondering that was never started.
+                    } else {
+                        session = pool.new_session();
+                        tracing::Span::current().record("session", &session.0);
+                        transcript = opts
+                            .session_log_dir
+                            .as_deref()
+                            .and_then(|dir| Transcript::create(dir, session));
+                        if let Some(ref mut transcript) = transcript {
+                            transcript.client(&command);
+                        }
+                        tracing::warn!("starting or restarting session ...");
+                        acquiring = Some(match pool.try_acquire(session) {
+                            Some(engine) => Box::pin(future::ready(engine)),
+                            None if pool.preempts() => Box::pin(pool.acquire_next(session)),
+                            None if pool.policy == SessionPolicy::Reject => {
+                                tracing::warn!("rejected, engine is busy");
+                                send_text(
+                                    outbox,
+                                    &mut transcript,
+                                    UciOut::info_string("engine is busy".to_owned()),
+                                )?;
+                                break Ok(None);
+                            }
+                            None => {
+                                tracing::warn!("waiting for engine ...");
+                                send_text(
+                                    outbox,
+                                    &mut transcript,
+                                    UciOut::info_string(
+                                        "waiting for another session to end".to_owned(),
+                                    ),
+                                )?;
+                                Box::pin(pool.acquire_next(session))
+                            }
+                        });
+                        queued.push(command);
+                    }
+                }
+            }
+            Event::Acquired(mut engine) => {
+                acquiring = None;
+                tracing::warn!("new session started");
+                if let (None, Some(window)) = (&resumable, opts.resume_window) {
+                    // Not in the transcript, since it lets the reader take
+                    // over the session.
+                    let registration = shared_engine.resumable(grant, window);
+                    let token = UciOut::info_string(format!("resume {}", registration.token));
+                    outbox.push(Message::Text(token.to_string()), None)?;
+                    resumable = Some(registration);
+                }
+                engine.report_latency(latency);
+                engine.start_session(session).await?;
+                for command in permissions.session_commands(&engine) {
                     engine.send(session, command).await?;
-                    locked_engine = Some(engine);
                 }
+                for mut command in profile.map(Profile::session_commands).unwrap_or_default() {
+                    match permissions.restrict_in(&mut command) {
+                        Ok(()) => engine.send(session, command).await?,
+                        Err(reason) => tracing::warn!("ignoring option of profile: {reason}"),
+                    }
+                }
+
+                // TODO: Should track and restore options and
+                // positions of the session. Not required for
+                // lichess.org.
+                for command in queued.drain(..) {
+                    send_validated(
+                        &mut engine,
+                        session,
+                        command,
+                        latency,
+                        outbox,
+                        &mut transcript,
+                    )
+                    .await?;
+                }
+                locked_engine = Some(engine);
             }
-            Event::Socket(Some(Ok(Message::Pong(_)))) => missed_pong = false,
-            Event::Socket(Some(Ok(Message::Ping(data)))) => socket
-                .send(Message::Pong(data))
-                .await
-                .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?,
-            Event::Socket(Some(Ok(Message::Binary(_)))) => {
+            Event::Socket(Some(Ok(Message::Pong(_)))) => {
+                if missed_pong {
+                    // Rolling average, so that a single slow round trip
+                    // does not count too much.
+                    let rtt = ping_sent.elapsed();
+                    latency = if latency.is_zero() {
+                        rtt
+                    } else {
+                        (latency * 3 + rtt) / 4
+                    };
+                    tracing::debug!("round trip time: {rtt:?}, average: {latency:?}");
+                    if let Some(ref engine) = locked_engine {
+                        engine.report_latency(latency);
+                    }
+                    if opts.report_latency {
+                        let latency = format!("latency {} ms", latency.as_millis());
+                        send_text(outbox, &mut transcript, UciOut::info_string(latency))?;
+                    }
+                }
+                missed_pong = false;
+            }
+            Event::Socket(Some(Ok(Message::Ping(data)))) => {
+                outbox.push(Message::Pong(data), None)?
+            }
+            Event::Socket(Some(Ok(Message::Binary(_) | Message::Frame(_)))) => {
                 if let Some(ref mut engine) = locked_engine {
                     engine.ensure_idle(session).await?;
                 }
@@ -212,7 +1743,7 @@ async fn handle_socket_inner(
                 if let Some(ref mut engine) = locked_engine {
                     engine.ensure_idle(session).await?;
                 }
-                break Ok(());
+                break Ok(None);
             }
             Event::Socket(Some(Err(err))) => {
                 if let Some(ref mut engine) = locked_engine {
@@ -221,13 +1752,204 @@ async fn handle_socket_inner(
                 return Err(io::Error::new(io::ErrorKind::BrokenPipe, err));
             }
 
-            Event::Engine(Ok(command)) => {
-                socket
-                    .send(Message::Text(command.to_string()))
-                    .await
-                    .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
+            Event::Engine(Ok(mut command)) => {
+                permissions.restrict_out(&mut command);
+                send_text(outbox, &mut transcript, command)?
             }
             Event::Engine(Err(err)) => return Err(err),
         }
     }
 }
+
+async fn next_message(
+    stream: &mut Option<SplitStream<WebSocket>>,
+) -> Option<Result<Message, tokio_tungstenite::tungstenite::Error>> {
+    match stream {
+        Some(stream) => stream.next().await,
+        None => future::pending().await,
+    }
+}
+
+async fn next_resume(resumable: &mut Option<Resumable<'_>>) -> Resume {
+    match resumable {
+        Some(resumable) => resumable.next().await,
+        None => future::pending().await,
+    }
+}
+
+async fn expired(parked: &mut Option<Pin<Box<Sleep>>>) {
+    match parked {
+        Some(parked) => parked.as_mut().await,
+        None => future::pending().await,
+    }
+}
+
+/// Sends a command of the client to the engine, unless it sets up an
+/// illegal position. The client is told why the command was rejected.
+/// Searches with clock times leave time for the round trip to the client.
+async fn send_validated(
+    engine: &mut Engine,
+    session: Session,
+    mut command: UciIn,
+    latency: Duration,
+    outbox: &Outbox,
+    transcript: &mut Option<Transcript>,
+) -> io::Result<()> {
+    match engine.validate_position(&command) {
+        Ok(()) => {
+            engine
+                .compensate_latency(session, &mut command, latency)
+                .await?;
+            engine.send(session, command).await
+        }
+        Err(err) => {
+            tracing::warn!("rejected command: {err}");
+            send_text(outbox, transcript, UciOut::info_string(err.to_string()))
+        }
+    }
+}
+
+/// Stops a running search and lets the client receive the final bestmove.
+async fn finish(
+    engine: &mut Engine,
+    session: Session,
+    outbox: &Outbox,
+    transcript: &mut Option<Transcript>,
+) -> io::Result<()> {
+    if engine.is_searching() {
+        engine.send(session, UciIn::Stop).await?;
+    }
+    while !engine.is_idle() {
+        let command = engine.recv(session).await?;
+        send_text(outbox, transcript, command)?;
+    }
+    Ok(())
+}
+
+fn shutdown_close_frame() -> CloseFrame<'static> {
+    CloseFrame {
+        code: CloseCode::Away,
+        reason: "server shutting down".into(),
+    }
+}
+
+/// Tells clients when to try again, like: unavailable until 22:00
+fn unavailable_close_frame(until: String) -> CloseFrame<'static> {
+    CloseFrame {
+        code: CloseCode::Again,
+        reason: format!("unavailable until {until}").into(),
+    }
+}
+
+fn send_text(
+    outbox: &Outbox,
+    transcript: &mut Option<Transcript>,
+    command: UciOut,
+) -> io::Result<()> {
+    tracing::debug!("ws << {}", command);
+    if let Some(ref mut transcript) = transcript {
+        transcript.engine(&command);
+    }
+    let info = match command {
+        UciOut::Info {
+            multipv,
+            depth,
+            ref pv,
+            string: None,
+            ..
+        } => Some((multipv, depth, pv.is_some())),
+        _ => None,
+    };
+    outbox.push(Message::Text(command.to_string()), info)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn push(outbox: &Outbox, line: &str) {
+        let command = UciOut::from_line(line).expect("valid").expect("not empty");
+        send_text(outbox, &mut None, command).expect("open");
+    }
+
+    fn texts(batch: Vec<Outgoing>) -> Vec<String> {
+        batch
+            .into_iter()
+            .map(|outgoing| match outgoing.message {
+                Message::Text(text) => text,
+                other => panic!("unexpected message {other:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_outbox_drops_superseded_info() {
+        let outbox = Outbox::default();
+        push(&outbox, "info multipv 1 depth 10 score cp 20 pv e2e4");
+        push(&outbox, "info multipv 2 depth 10 score cp 10 pv d2d4");
+        push(&outbox, "info multipv 1 depth 10 score cp 25 pv e2e4 e7e5");
+        push(&outbox, "info multipv 1 depth 11 score cp 30 pv e2e4");
+        push(&outbox, "info depth 11 currmove g1f3 currmovenumber 3");
+        push(&outbox, "info depth 11 currmove b1c3 currmovenumber 4");
+        push(&outbox, "info string NNUE evaluation enabled");
+        push(&outbox, "info multipv 1 depth 11 score cp 35 pv e2e4");
+        assert_eq!(
+            texts(outbox.next_batch(None, None).await),
+            vec![
+                "info multipv 2 depth 10 score cp 10 pv d2d4",
+                "info multipv 1 depth 10 score cp 25 pv e2e4 e7e5",
+                "info multipv 1 depth 11 score cp 30 pv e2e4",
+                "info depth 11 currmove b1c3 currmovenumber 4",
+                "info string NNUE evaluation enabled",
+                "info multipv 1 depth 11 score cp 35 pv e2e4",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outbox_bestmove_flushes_info() {
+        let outbox = Outbox::default();
+        let interval = Some(Duration::from_secs(60));
+        let last_info = Some(Instant::now());
+        push(&outbox, "info depth 1 score cp 20 pv e2e4");
+        push(&outbox, "info depth 2 score cp 25 pv e2e4 e7e5");
+        assert!(
+            timeout(
+                Duration::from_millis(50),
+                outbox.next_batch(interval, last_info)
+            )
+            .await
+            .is_err(),
+            "info lines held back"
+        );
+        push(&outbox, "bestmove e2e4 ponder e7e5");
+        assert_eq!(
+            texts(outbox.next_batch(interval, last_info).await),
+            vec![
+                "info depth 1 score cp 20 pv e2e4",
+                "info depth 2 score cp 25 pv e2e4 e7e5",
+                "bestmove e2e4 ponder e7e5",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connection_count() {
+        let sessions = AtomicU64::new(0);
+        {
+            let _first = ConnectionCount::new(&sessions);
+            let _second = ConnectionCount::new(&sessions);
+            assert_eq!(sessions.load(Ordering::Relaxed), 2);
+        }
+        assert_eq!(sessions.load(Ordering::Relaxed), 0);
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _count = ConnectionCount::new(&sessions);
+            panic!("session task panicked");
+        });
+        assert!(panicked.is_err());
+        assert_eq!(sessions.load(Ordering::Relaxed), 0);
+    }
+}
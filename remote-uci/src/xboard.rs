@@ -0,0 +1,731 @@
+//! Adapter for engines that speak the Chess Engine Communication Protocol
+//! (CECP, also known as XBoard or WinBoard protocol) version 2 instead of
+//! UCI.
+//!
+//! UCI commands are translated to XBoard commands, and XBoard output is
+//! translated back to UCI, so that the rest of the server does not need to
+//! know which protocol the engine speaks.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::Uci,
+    variant::{Variant, VariantPosition},
+    CastlingMode, Color, Position,
+};
+
+use crate::uci::{Eval, Score, UciIn, UciOption, UciOptionName, UciOut};
+
+/// Protocol spoken by the engine process.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// Universal Chess Interface.
+    #[default]
+    Uci,
+    /// Chess Engine Communication Protocol version 2.
+    Xboard,
+}
+
+/// Names of variants in UCI_Variant and XBoard.
+const VARIANTS: [(&str, &str); 8] = [
+    ("chess", "normal"),
+    ("antichess", "suicide"),
+    ("atomic", "atomic"),
+    ("crazyhouse", "crazyhouse"),
+    ("3check", "3check"),
+    ("kingofthehill", "kingofthehill"),
+    ("horde", "horde"),
+    ("racingkings", "racingkings"),
+];
+
+/// XBoard scores at or beyond this are mate scores, with the number of moves
+/// to mate added.
+const MATE_SCORE: i64 = 100_000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Search {
+    /// Searching with the clock, until the engine moves.
+    Think,
+    /// Analyzing until told to stop.
+    Analyze,
+}
+
+/// Translation state for a single engine process.
+#[derive(Default)]
+pub struct Xboard {
+    pending: VecDeque<UciOut>,
+    features: Vec<UciOut>,
+    ping: bool,
+    pings: u64,
+    usermove: bool,
+    memory: bool,
+    smp: bool,
+    variants: Vec<&'static str>,
+    options: Vec<(UciOptionName, UciOption)>,
+    variant: Variant,
+    position: Option<VariantPosition>,
+    search: Option<Search>,
+    pv: Vec<Uci>,
+}
+
+impl Xboard {
+    /// Translates a UCI command to the lines to send to the engine.
+    pub fn write_in(&mut self, command: &UciIn) -> Vec<String> {
+        match command {
+            UciIn::Uci => {
+                self.features.clear();
+                vec!["xboard".to_owned(), "protover 2".to_owned()]
+            }
+            UciIn::Isready => {
+                if self.ping {
+                    self.pings += 1;
+                    vec![format!("ping {}", self.pings)]
+                } else {
+                    self.pending.push_back(UciOut::Readyok);
+                    Vec::new()
+                }
+            }
+            UciIn::Setoption { name, value } => self.setoption(name, value.as_deref()),
+            UciIn::Ucinewgame => vec!["new".to_owned(), "force".to_owned()],
+            UciIn::Position { fen, moves } => self.position(fen.as_ref(), moves),
+            UciIn::Go {
+                wtime,
+                btime,
+                winc,
+                binc,
+                movestogo,
+                depth,
+                movetime,
+                infinite,
+                ..
+            } => {
+                let mut lines = vec!["easy".to_owned(), "post".to_owned()];
+                if *infinite || (wtime.is_none() && btime.is_none() && movetime.is_none()) {
+                    if let Some(depth) = depth {
+                        lines.push(format!("sd {depth}"));
+                    }
+                    lines.push("analyze".to_owned());
+                    self.search = Some(Search::Analyze);
+                } else {
+                    let turn = self
+                        .position
+                        .as_ref()
+                        .map_or(Color::White, |pos| pos.turn());
+                    let (time, otim, inc) = match turn {
+                        Color::White => (wtime, btime, winc),
+                        Color::Black => (btime, wtime, binc),
+                    };
+                    if let Some(time) = time {
+                        let secs = time.as_secs();
+                        lines.push(format!(
+                            "level {} {}:{:02} {}",
+                            movestogo.unwrap_or(0),
+                            secs / 60,
+                            secs % 60,
+                            inc.unwrap_or_default().as_secs()
+                        ));
+                        lines.push(format!("time {}", centis(*time)));
+                    }
+                    if let Some(otim) = otim {
+                        lines.push(format!("otim {}", centis(*otim)));
+                    }
+                    if let Some(movetime) = movetime {
+                        lines.push(format!("st {}", movetime.as_secs().max(1)));
+                    }
+                    if let Some(depth) = depth {
+                        lines.push(format!("sd {depth}"));
+                    }
+                    lines.push("go".to_owned());
+                    self.search = Some(Search::Think);
+                }
+                self.pv.clear();
+                lines
+            }
+            UciIn::Stop => match self.search {
+                Some(Search::Think) => vec!["?".to_owned()],
+                Some(Search::Analyze) => {
+                    self.search = None;
+                    self.pending.push_back(UciOut::Bestmove {
+                        m: self.pv.first().cloned(),
+                        ponder: self.pv.get(1).cloned(),
+                    });
+                    vec!["exit".to_owned()]
+                }
+                None => Vec::new(),
+            },
+            UciIn::Ponderhit => Vec::new(),
+        }
+    }
+
+    fn setoption(&mut self, name: &UciOptionName, value: Option<&str>) -> Vec<String> {
+        let value = value.unwrap_or_default();
+        if *name == "Hash" {
+            vec![format!("memory {value}")]
+        } else if *name == "Threads" {
+            vec![format!("cores {value}")]
+        } else if *name == "UCI_Variant" {
+            self.variant = Variant::from_uci(value).unwrap_or_default();
+            Vec::new()
+        } else {
+            match self.options.iter().find(|(n, _)| n == name) {
+                Some((_, UciOption::Button)) => vec![format!("option {name}")],
+                Some((_, UciOption::Check { .. })) => vec![format!(
+                    "option {name}={}",
+                    if value == "true" { 1 } else { 0 }
+                )],
+                Some(_) => vec![format!("option {name}={value}")],
+                None => Vec::new(),
+            }
+        }
+    }
+
+    fn position(&mut self, fen: Option<&Fen>, moves: &[Uci]) -> Vec<String> {
+        let mut lines = vec!["new".to_owned()];
+        if self.variant != Variant::Chess {
+            lines.push(format!("variant {}", xboard_variant(self.variant)));
+        }
+        lines.push("force".to_owned());
+        if let Some(fen) = fen {
+            lines.push(format!("setboard {fen}"));
+        }
+        for m in moves {
+            lines.push(if self.usermove {
+                format!("usermove {m}")
+            } else {
+                m.to_string()
+            });
+        }
+
+        let mut pos = match fen {
+            Some(fen) => {
+                VariantPosition::from_setup(self.variant, fen.0.clone(), CastlingMode::Standard)
+                    .ok()
+            }
+            None => Some(VariantPosition::new(self.variant)),
+        };
+        for m in moves {
+            pos = pos.and_then(|pos| {
+                let m = m.to_move(&pos).ok()?;
+                pos.play(&m).ok()
+            });
+        }
+        self.position = pos;
+        lines
+    }
+
+    /// Translates a line of engine output. The results are available from
+    /// [`Xboard::pop`].
+    pub fn read_out(&mut self, line: &str) {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("feature") => self.feature(line.trim_start().trim_start_matches("feature")),
+            Some("pong") if tokens.next().and_then(|n| n.parse().ok()) == Some(self.pings) => {
+                self.pending.push_back(UciOut::Readyok);
+            }
+            Some("move") => {
+                let m = tokens.next().and_then(|m| self.parse_move(m));
+                if self.search.take().is_some() {
+                    self.pending.push_back(UciOut::Bestmove { m, ponder: None });
+                }
+            }
+            Some("resign" | "1-0" | "0-1" | "1/2-1/2") if self.search == Some(Search::Think) => {
+                // No legal moves, or the engine gave up.
+                self.search = None;
+                self.pending.push_back(UciOut::Bestmove {
+                    m: None,
+                    ponder: None,
+                });
+            }
+            Some("telluser" | "tellusererror" | "Error" | "Illegal") => {
+                self.pending.push_back(UciOut::info_string(line.to_owned()));
+            }
+            Some(ply) if ply.trim_end_matches('.').parse::<u32>().is_ok() => {
+                self.thinking(line);
+            }
+            _ => (),
+        }
+    }
+
+    /// Next translated output of the engine.
+    pub fn pop(&mut self) -> Option<UciOut> {
+        self.pending.pop_front()
+    }
+
+    fn feature(&mut self, features: &str) {
+        let mut rest = features.trim_start();
+        while let Some((key, tail)) = rest.split_once('=') {
+            let (value, tail) = match tail.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                None => tail.split_once(char::is_whitespace).unwrap_or((tail, "")),
+            };
+            rest = tail.trim_start();
+            match key.trim() {
+                "myname" => self.features.push(UciOut::IdName(value.to_owned())),
+                "ping" => self.ping = value == "1",
+                "usermove" => self.usermove = value == "1",
+                "memory" => self.memory = value == "1",
+                "smp" => self.smp = value == "1",
+                "variants" => {
+                    self.variants = value
+                        .split(',')
+                        .filter_map(|name| {
+                            VARIANTS
+                                .iter()
+                                .find(|(_, xboard)| *xboard == name.trim())
+                                .map(|(uci, _)| *uci)
+                        })
+                        .collect();
+                }
+                "option" => {
+                    if let Some((name, option)) = parse_option(value) {
+                        self.options.push((name.clone(), option.clone()));
+                        self.features.push(UciOut::Option { name, option });
+                    }
+                }
+                "done" if value == "1" => self.done(),
+                _ => (),
+            }
+        }
+    }
+
+    fn done(&mut self) {
+        let mut features = mem::take(&mut self.features);
+        if self.memory {
+            features.push(UciOut::Option {
+                name: UciOptionName("Hash".to_owned()),
+                option: UciOption::Spin {
+                    default: 16,
+                    min: 1,
+                    max: 33_554_432,
+                },
+            });
+        }
+        if self.smp {
+            features.push(UciOut::Option {
+                name: UciOptionName("Threads".to_owned()),
+                option: UciOption::Spin {
+                    default: 1,
+                    min: 1,
+                    max: 1024,
+                },
+            });
+        }
+        if self.variants.len() > 1 {
+            features.push(UciOut::Option {
+                name: UciOptionName("UCI_Variant".to_owned()),
+                option: UciOption::Combo {
+                    default: "chess".to_owned(),
+                    var: self.variants.iter().map(|v| (*v).to_owned()).collect(),
+                },
+            });
+        }
+        features.push(UciOut::Uciok);
+        self.pending.extend(features);
+    }
+
+    fn thinking(&mut self, line: &str) {
+        let mut tokens = line.split_whitespace();
+        let mut number = || {
+            tokens
+                .next()
+                .and_then(|t| t.trim_end_matches('.').parse::<i64>().ok())
+        };
+        let (depth, score, time, nodes) = match (number(), number(), number(), number()) {
+            (Some(depth), Some(score), Some(time), Some(nodes)) => (depth, score, time, nodes),
+            _ => return,
+        };
+
+        let mut pos = self.position.clone();
+        let mut pv = Vec::new();
+        for token in tokens {
+            let token = token.trim_end_matches(['!', '?']);
+            if token.is_empty() || token.ends_with('.') || token.starts_with(['(', '{', '<']) {
+                continue;
+            }
+            match self.parse_pv_move(pos.as_mut(), token) {
+                Some(m) => pv.push(m),
+                None => break,
+            }
+        }
+        if !pv.is_empty() {
+            self.pv = pv.clone();
+        }
+
+        let eval = if score.abs() >= MATE_SCORE {
+            let moves = i32::try_from(score.abs() - MATE_SCORE).unwrap_or(i32::MAX);
+            Eval::Mate(if score > 0 { moves } else { -moves })
+        } else {
+            Eval::Cp(score)
+        };
+        self.pending.push_back(UciOut::Info {
+            multipv: None,
+            depth: u32::try_from(depth).ok(),
+            seldepth: None,
+            time: u64::try_from(time)
+                .ok()
+                .map(|cs| Duration::from_millis(cs * 10)),
+            nodes: u64::try_from(nodes).ok(),
+            score: Some(Score::new(eval)),
+            wdl: None,
+            currmove: None,
+            currmovenumber: None,
+            hashfull: None,
+            nps: None,
+            tbhits: None,
+            sbhits: None,
+            cpuload: None,
+            ebf: None,
+            refutation: HashMap::new(),
+            currline: HashMap::new(),
+            pv: Some(pv).filter(|pv| !pv.is_empty()),
+            string: None,
+        });
+    }
+
+    /// Parses a move of a principal variation, in coordinate notation or
+    /// SAN, and plays it.
+    fn parse_pv_move(&self, pos: Option<&mut VariantPosition>, token: &str) -> Option<Uci> {
+        let pos = match pos {
+            Some(pos) => pos,
+            None => return token.parse().ok(),
+        };
+        let m = match token.parse::<Uci>() {
+            Ok(uci) => uci.to_move(pos).ok()?,
+            Err(_) => token.parse::<SanPlus>().ok()?.san.to_move(pos).ok()?,
+        };
+        let uci = Uci::from_move(&m, CastlingMode::Standard);
+        pos.play_unchecked(&m);
+        Some(uci)
+    }
+
+    fn parse_move(&self, token: &str) -> Option<Uci> {
+        self.parse_pv_move(self.position.clone().as_mut(), token)
+    }
+}
+
+fn xboard_variant(variant: Variant) -> &'static str {
+    VARIANTS
+        .iter()
+        .find(|(uci, _)| *uci == variant.uci())
+        .map_or("normal", |(_, xboard)| xboard)
+}
+
+fn centis(time: Duration) -> u128 {
+    time.as_millis() / 10
+}
+
+/// Parses an option feature like `Contempt -spin 0 -100 100`.
+fn parse_option(feature: &str) -> Option<(UciOptionName, UciOption)> {
+    let (name, rest) = feature.split_once(" -")?;
+    let mut tokens = rest.split_whitespace();
+    let kind = tokens.next()?;
+    let option = match kind {
+        "check" => UciOption::Check {
+            default: tokens.next() == Some("1"),
+        },
+        "spin" | "slider" => UciOption::Spin {
+            default: tokens.next()?.parse().ok()?,
+            min: tokens.next()?.parse().ok()?,
+            max: tokens.next()?.parse().ok()?,
+        },
+        "combo" => {
+            let choices: Vec<_> = rest[kind.len()..]
+                .split("///")
+                .map(|choice| choice.trim())
+                .collect();
+            let default = choices
+                .iter()
+                .find_map(|choice| choice.strip_prefix('*'))
+                .or_else(|| choices.first().copied())?
+                .to_owned();
+            UciOption::Combo {
+                default,
+                var: choices
+                    .iter()
+                    .map(|choice| choice.trim_start_matches('*').to_owned())
+                    .collect(),
+            }
+        }
+        "button" | "save" | "reset" => UciOption::Button,
+        "string" | "file" | "path" => UciOption::String {
+            default: rest[kind.len()..].trim().to_owned(),
+        },
+        _ => return None,
+    };
+    Some((UciOptionName(name.trim().to_owned()), option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_in(xboard: &mut Xboard, line: &str) -> Vec<String> {
+        xboard.write_in(&UciIn::from_line(line).expect("command").expect("not empty"))
+    }
+
+    fn read_out(xboard: &mut Xboard, lines: &[&str]) -> Vec<String> {
+        for line in lines {
+            xboard.read_out(line);
+        }
+        let mut output = Vec::new();
+        while let Some(out) = xboard.pop() {
+            // Output must be valid UCI, like that of other engines.
+            let line = out.to_string();
+            assert_eq!(
+                UciOut::from_line(&line)
+                    .expect("valid uci")
+                    .expect("not empty")
+                    .to_string(),
+                line
+            );
+            output.push(line);
+        }
+        output
+    }
+
+    /// Engine that announced its features.
+    fn engine() -> Xboard {
+        let mut xboard = Xboard::default();
+        assert_eq!(write_in(&mut xboard, "uci"), vec!["xboard", "protover 2"]);
+        read_out(
+            &mut xboard,
+            &[
+                r#"feature myname="Crafty 25.2" ping=1 usermove=1 memory=1 smp=1"#,
+                r#"feature variants="normal,atomic,suicide,fischerandom""#,
+                r#"feature option="Contempt -spin 0 -100 100" option="Ponder -check 1""#,
+                r#"feature option="Style -combo solid /// *normal /// risky" option="Clear Hash -button""#,
+                "feature done=1",
+            ],
+        );
+        xboard
+    }
+
+    #[test]
+    fn test_features() {
+        let mut xboard = Xboard::default();
+        write_in(&mut xboard, "uci");
+        assert_eq!(
+            read_out(
+                &mut xboard,
+                &[
+                    r#"feature myname="Crafty 25.2" ping=1 memory=1 smp=1"#,
+                    r#"feature variants="normal,atomic,suicide,fischerandom""#,
+                    r#"feature option="Contempt -spin 0 -100 100" option="Ponder -check 1""#,
+                    r#"feature option="Style -combo solid /// *normal /// risky""#,
+                ]
+            ),
+            Vec::<String>::new(),
+            "waiting for done=1"
+        );
+        assert_eq!(
+            read_out(&mut xboard, &["feature done=1"]),
+            vec![
+                "id name Crafty 25.2",
+                "option name Contempt type spin default 0 min -100 max 100",
+                "option name Ponder type check default true",
+                "option name Style type combo default normal var solid var normal var risky",
+                "option name Hash type spin default 16 min 1 max 33554432",
+                "option name Threads type spin default 1 min 1 max 1024",
+                "option name UCI_Variant type combo default chess var chess var atomic var antichess",
+                "uciok",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_isready() {
+        let mut xboard = Xboard::default();
+        assert!(write_in(&mut xboard, "isready").is_empty());
+        assert_eq!(read_out(&mut xboard, &[]), vec!["readyok"]);
+
+        let mut xboard = engine();
+        assert_eq!(write_in(&mut xboard, "isready"), vec!["ping 1"]);
+        assert!(read_out(&mut xboard, &["pong 0"]).is_empty(), "stale");
+        assert_eq!(read_out(&mut xboard, &["pong 1"]), vec!["readyok"]);
+        assert_eq!(write_in(&mut xboard, "isready"), vec!["ping 2"]);
+    }
+
+    #[test]
+    fn test_setoption() {
+        let mut xboard = engine();
+        for (command, lines) in [
+            ("setoption name Hash value 256", vec!["memory 256"]),
+            ("setoption name Threads value 4", vec!["cores 4"]),
+            (
+                "setoption name Contempt value -20",
+                vec!["option Contempt=-20"],
+            ),
+            ("setoption name Ponder value false", vec!["option Ponder=0"]),
+            ("setoption name Ponder value true", vec!["option Ponder=1"]),
+            (
+                "setoption name Style value risky",
+                vec!["option Style=risky"],
+            ),
+            ("setoption name Clear Hash", vec!["option Clear Hash"]),
+            ("setoption name UCI_Variant value atomic", vec![]),
+            ("setoption name Unknown value 1", vec![]),
+        ] {
+            assert_eq!(write_in(&mut xboard, command), lines, "{command}");
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let mut xboard = engine();
+        assert_eq!(
+            write_in(&mut xboard, "position startpos moves e2e4 e7e5"),
+            vec!["new", "force", "usermove e2e4", "usermove e7e5"]
+        );
+        assert_eq!(
+            write_in(
+                &mut xboard,
+                "position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1 moves e1g1"
+            ),
+            vec![
+                "new",
+                "force",
+                "setboard 4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+                "usermove e1g1"
+            ]
+        );
+
+        write_in(&mut xboard, "setoption name UCI_Variant value antichess");
+        assert_eq!(
+            write_in(&mut xboard, "position startpos"),
+            vec!["new", "variant suicide", "force"]
+        );
+
+        let mut plain = Xboard::default();
+        assert_eq!(
+            write_in(&mut plain, "position startpos moves g1f3"),
+            vec!["new", "force", "g1f3"]
+        );
+    }
+
+    #[test]
+    fn test_go() {
+        let mut xboard = engine();
+        write_in(&mut xboard, "position startpos moves e2e4");
+        assert_eq!(
+            write_in(
+                &mut xboard,
+                "go wtime 60000 btime 185000 winc 1000 binc 2000 movestogo 20"
+            ),
+            vec![
+                "easy",
+                "post",
+                "level 20 3:05 2",
+                "time 18500",
+                "otim 6000",
+                "go"
+            ]
+        );
+        assert_eq!(read_out(&mut xboard, &["move e7e5"]), vec!["bestmove e7e5"]);
+
+        assert_eq!(
+            write_in(&mut xboard, "go movetime 500 depth 12"),
+            vec!["easy", "post", "st 1", "sd 12", "go"]
+        );
+        assert_eq!(read_out(&mut xboard, &["resign"]), vec!["bestmove (none)"]);
+
+        assert_eq!(
+            write_in(&mut xboard, "go depth 20"),
+            vec!["easy", "post", "sd 20", "analyze"]
+        );
+        assert_eq!(
+            write_in(&mut xboard, "go infinite"),
+            vec!["easy", "post", "analyze"]
+        );
+    }
+
+    #[test]
+    fn test_thinking() {
+        let mut xboard = engine();
+        write_in(&mut xboard, "position startpos moves e2e4");
+        write_in(&mut xboard, "go infinite");
+        assert_eq!(
+            read_out(
+                &mut xboard,
+                &[
+                    "9 -35 120 123456 e5 Nf3 Nc6",
+                    "10. -20 250 234567 1. ... c5 2. Nf3 d6!?",
+                    "11 -25 400 345678 e7e5 g1f3 {book}",
+                    "12 100005 600 456789 e7e5 Qh5? zz9",
+                    "13 -100003 700 567890",
+                    "telluser hello",
+                    "# debug output",
+                ]
+            ),
+            vec![
+                "info depth 9 time 1200 nodes 123456 score cp -35 pv e7e5 g1f3 b8c6",
+                "info depth 10 time 2500 nodes 234567 score cp -20 pv c7c5 g1f3 d7d6",
+                "info depth 11 time 4000 nodes 345678 score cp -25 pv e7e5 g1f3",
+                "info depth 12 time 6000 nodes 456789 score mate 5 pv e7e5 d1h5",
+                "info depth 13 time 7000 nodes 567890 score mate -3",
+                "info string telluser hello",
+            ]
+        );
+        assert_eq!(write_in(&mut xboard, "stop"), vec!["exit"]);
+        assert_eq!(
+            read_out(&mut xboard, &[]),
+            vec!["bestmove e7e5 ponder d1h5"]
+        );
+
+        write_in(&mut xboard, "position startpos");
+        write_in(&mut xboard, "go infinite");
+        read_out(&mut xboard, &["5 20 10 1000 Nf3 d5 d4"]);
+        write_in(&mut xboard, "stop");
+        assert_eq!(
+            read_out(&mut xboard, &[]),
+            vec!["bestmove g1f3 ponder d7d5"]
+        );
+    }
+
+    #[test]
+    fn test_san_move() {
+        let mut xboard = engine();
+        write_in(&mut xboard, "position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        write_in(&mut xboard, "go wtime 1000 btime 1000");
+        assert_eq!(read_out(&mut xboard, &["move O-O"]), vec!["bestmove e1g1"]);
+        assert!(
+            read_out(&mut xboard, &["move Kd7"]).is_empty(),
+            "not searching"
+        );
+    }
+
+    #[test]
+    fn test_parse_option() {
+        assert_eq!(
+            parse_option("Book File -file book.bin"),
+            Some((
+                UciOptionName("Book File".to_owned()),
+                UciOption::String {
+                    default: "book.bin".to_owned()
+                }
+            ))
+        );
+        assert_eq!(
+            parse_option("Aggression -slider 50 0 200"),
+            Some((
+                UciOptionName("Aggression".to_owned()),
+                UciOption::Spin {
+                    default: 50,
+                    min: 0,
+                    max: 200
+                }
+            ))
+        );
+        assert_eq!(parse_option("Contempt -spin x 0 1"), None);
+        assert_eq!(parse_option("Contempt"), None);
+        assert_eq!(parse_option("Contempt -unknown"), None);
+    }
+}